@@ -0,0 +1,928 @@
+//! Admin HTTP API
+//!
+//! - `POST /upstreams/{name}/drain` — перевод upstream'а в drain режим
+//! - `POST /upstreams/{name}/undrain` — возврат upstream'а в ротацию
+//! - `GET /upstreams/{name}/histogram` — гистограмма латентности
+//!   (HdrHistogram V2 + deflate, base64) для слияния между инстансами
+//! - `GET /snapshots` — количество snapshot'ов и настройки их хранения
+//! - `GET /snapshots/diff/{a}/{b}` — изменения конфигурации между snapshot'ами
+//! - `GET /debug/explain?host=&path=&method=&intent=` — разбор маршрутизации
+//!   без проксирования (тот же матчинг и scoring, что и у живых запросов)
+//! - `GET /events/metrics` — SSE поток снимков резонанс-метрик upstream'ов
+//! - `GET /routes[?format=text]` — действующая таблица маршрутов (после
+//!   include, подстановки env и hot-reload) с состоянием upstream'ов
+//!
+//! Доступ — bearer токеном или HMAC подписью запроса (см. `signature`).
+
+use crate::signature::{RequestSigner, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::Admin;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bytes::Bytes;
+use dao_core::align::{Align, IntentClassifier};
+use dao_core::config::{AdminAuthConfig, MatchContext};
+use dao_core::sense::MetricsFeed;
+use dao_core::{constant_time_eq, Intent};
+use http::{header, HeaderMap, Method, Request, Response, StatusCode};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, LengthLimitError, Limited, StreamBody};
+use hyper::body::{Body, Frame};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Тело ответа admin API
+pub type AdminBody = BoxBody<Bytes, Infallible>;
+
+/// Предел тела запроса, читаемого для проверки подписи
+const MAX_SIGNED_BODY_BYTES: usize = 1024 * 1024;
+
+/// HTTP API управления DAO
+pub struct AdminApi {
+    admin: Arc<Admin>,
+    align: Arc<Align>,
+    token: Option<String>,
+    signer: Option<RequestSigner>,
+    feed: Option<MetricsFeed>,
+}
+
+impl AdminApi {
+    pub fn new(admin: Arc<Admin>, align: Arc<Align>, token: Option<String>) -> Self {
+        Self {
+            admin,
+            align,
+            token,
+            signer: None,
+            feed: None,
+        }
+    }
+
+    /// Доступ по HMAC подписи запроса (в дополнение к токену, если задан)
+    pub fn with_hmac_auth(mut self, config: &AdminAuthConfig) -> Self {
+        self.signer = Some(RequestSigner::new(config));
+        self
+    }
+
+    /// Источник снимков для `GET /events/metrics`
+    pub fn with_metrics_feed(mut self, feed: MetricsFeed) -> Self {
+        self.feed = Some(feed);
+        self
+    }
+
+    /// Обслуживание API на готовом listener'е
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> anyhow::Result<()> {
+        tracing::info!("Admin API listening on {}", listener.local_addr()?);
+
+        loop {
+            let (stream, peer_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::error!("Admin accept error: {}", e);
+                    continue;
+                }
+            };
+            let api = self.clone();
+
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let api = api.clone();
+                    async move { Ok::<_, Infallible>(api.handle(req).await) }
+                });
+
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!("Admin connection from {} failed: {}", peer_addr, e);
+                }
+            });
+        }
+    }
+
+    /// Обработка запроса к API
+    pub async fn handle<B>(&self, req: Request<B>) -> Response<AdminBody>
+    where
+        B: Body<Data = Bytes>,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        // Тело читается только для проверки подписи — API его не использует.
+        // Без подписи или с устаревшим временем тело не читается, объявленное
+        // сверх предела отклоняется до чтения
+        let (parts, body) = req.into_parts();
+        let body = if self.signature_pending(&parts.headers) {
+            let declared = parts
+                .headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<usize>().ok());
+            if declared.is_some_and(|length| length > MAX_SIGNED_BODY_BYTES) {
+                return json_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    json!({ "error": "body_too_large" }),
+                );
+            }
+            match Limited::new(body, MAX_SIGNED_BODY_BYTES).collect().await {
+                Ok(collected) => collected.to_bytes(),
+                Err(e) if e.is::<LengthLimitError>() => {
+                    return json_response(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        json!({ "error": "body_too_large" }),
+                    )
+                }
+                Err(_) => {
+                    return json_response(
+                        StatusCode::BAD_REQUEST,
+                        json!({ "error": "invalid_body" }),
+                    )
+                }
+            }
+        } else {
+            Bytes::new()
+        };
+        let req = Request::from_parts(parts, ());
+
+        if !self.authorized(&req, &body) {
+            return json_response(StatusCode::UNAUTHORIZED, json!({ "error": "unauthorized" }));
+        }
+
+        let path = req.uri().path().trim_matches('/');
+        let segments: Vec<&str> = path.split('/').collect();
+
+        match (req.method(), segments.as_slice()) {
+            (&Method::POST, ["upstreams", name, "drain"]) => self.set_draining(name, true),
+            (&Method::POST, ["upstreams", name, "undrain"]) => self.set_draining(name, false),
+            (&Method::GET, ["upstreams", name, "histogram"]) => self.histogram(name),
+            (&Method::GET, ["snapshots"]) => self.snapshots(),
+            (&Method::GET, ["snapshots", "diff", from, to]) => self.snapshot_diff(from, to),
+            (&Method::GET, ["debug", "explain"]) => self.explain(req.uri().query().unwrap_or("")),
+            (&Method::GET, ["routes"]) => self.routes(req.uri().query().unwrap_or("")),
+            (&Method::GET, ["events", "metrics"]) => self.metrics_events(),
+            _ => json_response(StatusCode::NOT_FOUND, json!({ "error": "not_found" })),
+        }
+    }
+
+    fn set_draining(&self, name: &str, draining: bool) -> Response<AdminBody> {
+        if self.admin.set_upstream_draining(name, draining) {
+            json_response(
+                StatusCode::OK,
+                json!({ "upstream": name, "draining": draining }),
+            )
+        } else {
+            json_response(
+                StatusCode::NOT_FOUND,
+                json!({ "error": "unknown_upstream", "upstream": name }),
+            )
+        }
+    }
+
+    fn histogram(&self, name: &str) -> Response<AdminBody> {
+        let Some(upstream) = self.admin.upstreams().get(name) else {
+            return json_response(
+                StatusCode::NOT_FOUND,
+                json!({ "error": "unknown_upstream", "upstream": name }),
+            );
+        };
+        let stats = upstream.get_stats();
+        let since = stats
+            .histogram_since
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        json_response(
+            StatusCode::OK,
+            json!({
+                "upstream": name,
+                "encoding": "hdrhistogram-v2-deflate",
+                "unit": "us",
+                "histogram": BASE64_STANDARD.encode(stats.encode_histogram()),
+                "samples": stats.histogram_samples(),
+                "since_ms": since.as_millis() as u64,
+                "window_secs": stats.histogram_since.elapsed().unwrap_or_default().as_secs(),
+            }),
+        )
+    }
+
+    fn snapshots(&self) -> Response<AdminBody> {
+        let (count, retention) = self.admin.snapshot_status();
+        json_response(
+            StatusCode::OK,
+            json!({ "count": count, "retention": retention }),
+        )
+    }
+
+    fn snapshot_diff(&self, from: &str, to: &str) -> Response<AdminBody> {
+        let (Ok(from), Ok(to)) = (from.parse::<usize>(), to.parse::<usize>()) else {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({ "error": "invalid_snapshot_index" }),
+            );
+        };
+
+        match self.admin.diff_snapshots(from, to) {
+            Some(diff) => json_response(
+                StatusCode::OK,
+                json!({ "changes": diff.changes, "summary": diff.summary() }),
+            ),
+            None => json_response(
+                StatusCode::NOT_FOUND,
+                json!({ "error": "unknown_snapshot" }),
+            ),
+        }
+    }
+
+    /// Матчинг маршрута и разбор выбора upstream'а для образца запроса
+    fn explain(&self, query: &str) -> Response<AdminBody> {
+        let mut host = None;
+        let mut path = "/".to_string();
+        let mut method = "GET".to_string();
+        let mut intent = None;
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "host" => host = Some(value.into_owned()),
+                "path" => path = value.into_owned(),
+                "method" => method = value.into_owned(),
+                "intent" => intent = Some(Intent::new(value.into_owned())),
+                _ => {}
+            }
+        }
+
+        let mut builder = Request::builder()
+            .method(method.as_str())
+            .uri(path.as_str());
+        if let Some(host) = &host {
+            builder = builder.header(header::HOST, host.as_str());
+        }
+        let Ok(sample) = builder.body(()) else {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({ "error": "invalid_sample_request" }),
+            );
+        };
+
+        let config = self.admin.get_current_config();
+        let Some(route) = config
+            .routes
+            .find_route(&MatchContext::from_request(&sample))
+        else {
+            return json_response(StatusCode::OK, json!({ "route": null }));
+        };
+
+        let request_intent = intent.or_else(|| {
+            IntentClassifier::new(&config.intent_rules).classify_or(&sample, route.intent())
+        });
+        let candidates = self.admin.upstreams().route_upstreams(route);
+        let explanation =
+            self.align
+                .explain_route_selection(route, &candidates, request_intent.as_ref());
+
+        json_response(
+            StatusCode::OK,
+            json!({ "route": route.name, "explanation": explanation }),
+        )
+    }
+
+    /// Таблица маршрутов из конфигурации в памяти и состояние upstream'ов
+    fn routes(&self, query: &str) -> Response<AdminBody> {
+        let config = self.admin.get_current_config();
+        let registry = self.admin.upstreams().load();
+
+        let routes: Vec<_> = config
+            .routes
+            .rule
+            .iter()
+            .map(|route| {
+                let upstreams: Vec<_> = route
+                    .upstreams
+                    .iter()
+                    .map(|upstream| {
+                        // Нет в реестре — upstream еще не подхвачен после reload
+                        let live = registry.iter().find(|u| u.name == upstream.name);
+                        json!({
+                            "name": upstream.name,
+                            "url": upstream.url,
+                            "weight": upstream.weight,
+                            "present": live.is_some(),
+                            "draining": live.is_some_and(|u| u.is_draining()),
+                            "in_flight": live.map_or(0, |u| u.in_flight()),
+                            "error_rate": live.map_or(0.0, |u| u.get_stats().error_rate()),
+                        })
+                    })
+                    .collect();
+                json!({
+                    "name": route.name,
+                    "match": route.match_rule,
+                    "priority": route.priority,
+                    "policy": route.policy,
+                    "default": config.routes.default.as_deref() == Some(route.name.as_str()),
+                    "upstreams": upstreams,
+                })
+            })
+            .collect();
+
+        let text = form_urlencoded::parse(query.as_bytes())
+            .any(|(key, value)| key == "format" && value == "text");
+        if text {
+            return text_response(StatusCode::OK, render_routes(&routes));
+        }
+        json_response(StatusCode::OK, json!({ "routes": routes }))
+    }
+
+    /// SSE: снимок метрик на событие. Подписка живет, пока клиент читает
+    /// поток; при отключении hyper бросает тело вместе с подпиской.
+    fn metrics_events(&self) -> Response<AdminBody> {
+        let Some(feed) = &self.feed else {
+            return json_response(StatusCode::NOT_FOUND, json!({ "error": "not_found" }));
+        };
+
+        let events = futures::stream::unfold(feed.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(snapshot) => {
+                        let data = serde_json::to_string(&*snapshot).unwrap_or_default();
+                        let frame = Frame::data(Bytes::from(format!("data: {}\n\n", data)));
+                        return Some((Ok(frame), receiver));
+                    }
+                    // Отставший клиент пропускает старые снимки
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(StreamBody::new(events).boxed())
+            .unwrap()
+    }
+
+    /// Проверка доступа: верный токен или подпись. Без настроенной
+    /// аутентификации API закрыт (конфигурация такой не пропускает)
+    fn authorized(&self, req: &Request<()>, body: &[u8]) -> bool {
+        self.token_valid(req.headers()) || self.signature_valid(req, body)
+    }
+
+    /// Проверка `Authorization: Bearer <token>`
+    fn token_valid(&self, headers: &HeaderMap) -> bool {
+        let Some(expected) = &self.token else {
+            return false;
+        };
+
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+    }
+
+    /// Запрос проверяется подписью: токена нет или он неверен, подпись
+    /// есть и время ее свежее — только тогда читается тело
+    fn signature_pending(&self, headers: &HeaderMap) -> bool {
+        let Some(signer) = &self.signer else {
+            return false;
+        };
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        !self.token_valid(headers)
+            && header(SIGNATURE_HEADER).is_some()
+            && header(TIMESTAMP_HEADER)
+                .is_some_and(|timestamp| signer.timestamp_fresh(timestamp, unix_now()))
+    }
+
+    /// Проверка `X-DAO-Signature` / `X-DAO-Timestamp`
+    fn signature_valid(&self, req: &Request<()>, body: &[u8]) -> bool {
+        let Some(signer) = &self.signer else {
+            return false;
+        };
+        let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+        let (Some(timestamp), Some(signature)) =
+            (header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER))
+        else {
+            return false;
+        };
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+        signer.verify(
+            req.method().as_str(),
+            path,
+            timestamp,
+            signature,
+            body,
+            unix_now(),
+        )
+    }
+}
+
+/// Текущее время, unix секунды
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Таблица маршрутов для `?format=text`: строка на upstream
+fn render_routes(routes: &[serde_json::Value]) -> String {
+    let mut rows = vec![["ROUTE", "MATCH", "POLICY", "UPSTREAM", "URL", "STATE"].map(String::from)];
+    for route in routes {
+        // Условия match — только заданные поля
+        let conditions: Vec<String> = route["match"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| match value.as_str() {
+                Some(text) => format!("{}={}", key, text),
+                None => format!("{}={}", key, value),
+            })
+            .collect();
+        let mut name = route["name"].as_str().unwrap_or_default().to_string();
+        if route["default"] == true {
+            name.push_str(" (default)");
+        }
+        for (i, upstream) in route["upstreams"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let state = if upstream["present"] != true {
+                "absent"
+            } else if upstream["draining"] == true {
+                "draining"
+            } else {
+                "active"
+            };
+            let first = i == 0;
+            rows.push([
+                if first { name.clone() } else { String::new() },
+                if first {
+                    conditions.join(" ")
+                } else {
+                    String::new()
+                },
+                if first {
+                    route["policy"].as_str().unwrap_or_default().to_string()
+                } else {
+                    String::new()
+                },
+                upstream["name"].as_str().unwrap_or_default().to_string(),
+                upstream["url"].as_str().unwrap_or_default().to_string(),
+                state.to_string(),
+            ]);
+        }
+    }
+
+    let mut widths = [0usize; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+fn text_response(status: StatusCode, body: String) -> Response<AdminBody> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from(body)).boxed())
+        .unwrap()
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<AdminBody> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())).boxed())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dao_core::config::DaoConfig;
+    use dao_core::memory::Memory;
+    use dao_core::sense::Sense;
+    use dao_core::upstream::UpstreamRegistry;
+    use http_body_util::BodyExt;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn test_config() -> DaoConfig {
+        toml::from_str(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+            [routes.rule.match]
+            host = "api.example.com"
+            path_prefix = "/v1/"
+            [[routes.rule.upstreams]]
+            name = "backend-1"
+            url = "http://127.0.0.1:8081"
+            [[routes.rule.upstreams]]
+            name = "backend-2"
+            url = "http://127.0.0.1:8082"
+            intent = ["batch"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn test_api_with_align(token: Option<&str>) -> (AdminApi, Arc<UpstreamRegistry>, Arc<Align>) {
+        let config = test_config();
+        let upstreams = Arc::new(UpstreamRegistry::from_config(&config));
+        let align = Arc::new(Align::new(Sense::new(upstreams.clone())));
+        let admin = Admin::new(
+            PathBuf::from("dao.toml"),
+            Arc::new(Memory::new(config)),
+            upstreams.clone(),
+        );
+        let api = AdminApi::new(Arc::new(admin), align.clone(), token.map(String::from));
+        (api, upstreams, align)
+    }
+
+    fn test_api(token: Option<&str>) -> (AdminApi, Arc<UpstreamRegistry>) {
+        let (api, upstreams, _) = test_api_with_align(token);
+        (api, upstreams)
+    }
+
+    const TOKEN: &str = "secret";
+
+    fn get(uri: &str) -> Request<String> {
+        Request::builder()
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", TOKEN))
+            .body(String::new())
+            .unwrap()
+    }
+
+    async fn get_json(api: &AdminApi, uri: &str) -> serde_json::Value {
+        let req = get(uri);
+        let res = api.handle(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn post(path: &str, token: Option<&str>) -> Request<String> {
+        let mut builder = Request::builder().method(Method::POST).uri(path);
+        if let Some(token) = token {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        builder.body(String::new()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_drain_and_undrain() {
+        let (api, upstreams) = test_api(Some(TOKEN));
+
+        let res = api
+            .handle(post("/upstreams/backend-1/drain", Some(TOKEN)))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(upstreams.get("backend-1").unwrap().is_draining());
+
+        let res = api
+            .handle(post("/upstreams/backend-1/undrain", Some(TOKEN)))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!upstreams.get("backend-1").unwrap().is_draining());
+
+        let res = api
+            .handle(post("/upstreams/missing/drain", Some(TOKEN)))
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_histogram_route() {
+        let (api, upstreams) = test_api(Some(TOKEN));
+        let upstream = upstreams.get("backend-1").unwrap();
+        for ms in [5, 10, 20] {
+            upstream.record_request(Duration::from_millis(ms), true);
+        }
+
+        let json = get_json(&api, "/upstreams/backend-1/histogram").await;
+        assert_eq!(json["encoding"], "hdrhistogram-v2-deflate");
+        assert!(json["samples"].as_u64().unwrap() >= 3);
+        let encoded = BASE64_STANDARD
+            .decode(json["histogram"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(encoded, upstream.get_stats().encode_histogram());
+
+        let req = get("/upstreams/missing/histogram");
+        assert_eq!(api.handle(req).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_token_required() {
+        let (api, upstreams) = test_api(Some("secret"));
+
+        let res = api.handle(post("/upstreams/backend-1/drain", None)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = api
+            .handle(post("/upstreams/backend-1/drain", Some("wrong")))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(!upstreams.get("backend-1").unwrap().is_draining());
+
+        let res = api
+            .handle(post("/upstreams/backend-1/drain", Some("secret")))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(upstreams.get("backend-1").unwrap().is_draining());
+    }
+
+    #[tokio::test]
+    async fn test_unconfigured_auth_rejects() {
+        let (api, upstreams) = test_api(None);
+
+        let res = api.handle(post("/upstreams/backend-1/drain", None)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(!upstreams.get("backend-1").unwrap().is_draining());
+        let req = Request::builder()
+            .uri("/debug/explain?host=api.example.com&path=/v1/items")
+            .body(String::new())
+            .unwrap();
+        assert_eq!(api.handle(req).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_hmac_signed_requests() {
+        let auth = AdminAuthConfig {
+            hmac_secret: "shared".to_string(),
+            max_clock_skew_secs: 300,
+        };
+        let (api, upstreams) = test_api(None);
+        let api = api.with_hmac_auth(&auth);
+        let signer = RequestSigner::new(&auth);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let signed = |path: &str, timestamp: u64, signed_body: &str, body: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri(path)
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(
+                    SIGNATURE_HEADER,
+                    signer.sign("POST", path, timestamp, signed_body.as_bytes()),
+                )
+                .body(body.to_string())
+                .unwrap()
+        };
+
+        let res = api.handle(post("/upstreams/backend-1/drain", None)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // Тело изменено после подписи
+        let res = api
+            .handle(signed(
+                "/upstreams/backend-1/drain",
+                now,
+                "{}",
+                r#"{"x":1}"#,
+            ))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // Подпись старше допустимого расхождения часов
+        let res = api
+            .handle(signed("/upstreams/backend-1/drain", now - 600, "{}", "{}"))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(!upstreams.get("backend-1").unwrap().is_draining());
+
+        let res = api
+            .handle(signed("/upstreams/backend-1/drain", now, "{}", "{}"))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(upstreams.get("backend-1").unwrap().is_draining());
+
+        // Повтор перехваченного запроса отклоняется
+        let replayed = signed("/upstreams/backend-1/undrain", now, "{}", "{}");
+        let replay = signed("/upstreams/backend-1/undrain", now, "{}", "{}");
+        assert_eq!(api.handle(replayed).await.status(), StatusCode::OK);
+        assert_eq!(api.handle(replay).await.status(), StatusCode::UNAUTHORIZED);
+
+        // Тело сверх предела не читается
+        let large = "x".repeat(MAX_SIGNED_BODY_BYTES + 1);
+        let res = api
+            .handle(signed(
+                "/upstreams/backend-1/drain",
+                now + 1,
+                &large,
+                &large,
+            ))
+            .await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_explain_matches_live_selection() {
+        let (api, upstreams, align) = test_api_with_align(Some(TOKEN));
+
+        // backend-1 медленный и с ошибками — resonant должен выбрать backend-2
+        let slow = upstreams.get("backend-1").unwrap();
+        for _ in 0..20 {
+            slow.record_request(Duration::from_millis(800), false);
+        }
+        upstreams
+            .get("backend-2")
+            .unwrap()
+            .record_request(Duration::from_millis(5), true);
+
+        let json = get_json(&api, "/debug/explain?host=api.example.com&path=/v1/items").await;
+        assert_eq!(json["route"], "api");
+        assert_eq!(
+            json["explanation"]["candidates"].as_array().unwrap().len(),
+            2
+        );
+
+        // Тот же путь, что у живого запроса: матчинг → upstream'ы маршрута → Align
+        let config = test_config();
+        let sample = Request::builder()
+            .uri("/v1/items")
+            .header(header::HOST, "api.example.com")
+            .body(())
+            .unwrap();
+        let route = config
+            .routes
+            .find_route(&MatchContext::from_request(&sample))
+            .unwrap();
+        let live = align
+            .select_upstream(&route.policy, &upstreams.route_upstreams(route), None)
+            .unwrap();
+        assert_eq!(json["explanation"]["selected"], live.name.as_str());
+        assert_eq!(live.name, "backend-2");
+
+        // Intent из запроса учитывается
+        let json = get_json(
+            &api,
+            "/debug/explain?host=api.example.com&path=/v1/x&intent=batch",
+        )
+        .await;
+        assert_eq!(json["explanation"]["intent"], "batch");
+
+        let json = get_json(&api, "/debug/explain?host=other.example.com&path=/v1/x").await;
+        assert!(json["route"].is_null());
+
+        // Стратегия с состоянием: разбор показывает следующий живой выбор
+        let mut config = test_config();
+        config.routes.rule[0].policy = "swrr".to_string();
+        api.admin.reloader.apply(config.clone()).unwrap();
+        let route = &config.routes.rule[0];
+        for _ in 0..4 {
+            let json = get_json(&api, "/debug/explain?host=api.example.com&path=/v1/items").await;
+            let live = align
+                .select_route_upstream(route, &api.admin.upstreams().route_upstreams(route), None)
+                .unwrap();
+            assert_eq!(json["explanation"]["selected"], live.name.as_str());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_diff_route() {
+        let (api, _) = test_api(Some(TOKEN));
+        let memory = api.admin.memory.clone();
+
+        memory.create_snapshot("initial");
+        let mut config = test_config();
+        config.routes.rule[0].upstreams[1].url = "http://127.0.0.1:9092".to_string();
+        memory.update_config(config).unwrap();
+
+        let json = get_json(&api, "/snapshots").await;
+        assert_eq!(json["count"], 2);
+        assert_eq!(json["retention"]["max_count"], 100);
+        assert!(json["retention"]["max_age_secs"].is_null());
+
+        let json = get_json(&api, "/snapshots/diff/0/1").await;
+        assert_eq!(
+            json["changes"][0]["path"],
+            "routes.rule[api].upstreams[backend-2].url"
+        );
+        assert_eq!(json["changes"][0]["kind"], "changed");
+
+        let req = get("/snapshots/diff/0/9");
+        assert_eq!(api.handle(req).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_routes_reflect_reload() {
+        let (api, _) = test_api(Some(TOKEN));
+        let json = get_json(&api, "/routes").await;
+        assert_eq!(json["routes"].as_array().unwrap().len(), 1);
+        assert_eq!(json["routes"][0]["match"]["host"], "api.example.com");
+        assert_eq!(json["routes"][0]["upstreams"][1]["present"], true);
+
+        let mut config = test_config();
+        let mut web = config.routes.rule[0].clone();
+        web.name = "web".to_string();
+        web.upstreams.truncate(1);
+        web.upstreams[0].name = "web-1".to_string();
+        web.upstreams[0].url = "http://127.0.0.1:9090".to_string();
+        config.routes.rule.push(web);
+        api.admin.reloader.apply(config).unwrap();
+        api.admin.set_upstream_draining("backend-1", true);
+
+        let json = get_json(&api, "/routes").await;
+        let web = &json["routes"][1];
+        assert_eq!(web["name"], "web");
+        assert_eq!(web["upstreams"][0]["url"], "http://127.0.0.1:9090");
+        assert_eq!(web["upstreams"][0]["present"], true);
+        assert_eq!(json["routes"][0]["upstreams"][0]["draining"], true);
+
+        let req = get("/routes?format=text");
+        let res = api.handle(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("ROUTE"), "{}", text);
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.starts_with("web ") && l.contains("http://127.0.0.1:9090")),
+            "{}",
+            text
+        );
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.contains("backend-1") && l.ends_with("draining")),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("host=api.example.com path_prefix=/v1/"),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics_events_stream() {
+        let (api, upstreams) = test_api(Some(TOKEN));
+        let feed = MetricsFeed::new();
+        let api = api.with_metrics_feed(feed.clone());
+        let producer = tokio::spawn(
+            Sense::new(upstreams).run_metrics_feed(Duration::from_millis(20), feed.clone()),
+        );
+
+        let req = get("/events/metrics");
+        let res = api.handle(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/event-stream");
+        assert_eq!(feed.subscribers(), 1);
+
+        let mut body = res.into_body();
+        let frame = tokio::time::timeout(Duration::from_secs(2), body.frame())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let data = frame.into_data().unwrap();
+        let event = std::str::from_utf8(&data).unwrap();
+        let json: serde_json::Value = serde_json::from_str(
+            event
+                .strip_prefix("data: ")
+                .unwrap()
+                .strip_suffix("\n\n")
+                .unwrap(),
+        )
+        .unwrap();
+        let names: Vec<_> = json["upstreams"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["upstream_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["backend-1", "backend-2"]);
+
+        // Отключение клиента освобождает подписку
+        drop(body);
+        assert_eq!(feed.subscribers(), 0);
+        producer.abort();
+    }
+}
@@ -3,38 +3,112 @@
 //! Модуль для:
 //! - Горячей перезагрузки конфигурации
 //! - Мониторинга изменений файла конфигурации
-//! - API управления (будущее)
+//! - Hot-reload TLS сертификатов
+//! - Перечитывания файлов upstream'ов маршрутов
+//! - HTTP API управления
 
-use dao_core::config::DaoConfig;
-use dao_core::memory::Memory;
-use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use dao_core::config::{DaoConfig, SnapshotRetention};
+use dao_core::memory::{ConfigDiff, Memory};
+use dao_core::upstream::UpstreamRegistry;
+use notify::event::ModifyKind;
+use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
 
+pub mod api;
 pub mod reload;
+pub mod signature;
+pub mod tls;
+pub mod upstreams;
 
+pub use api::AdminApi;
 pub use reload::ConfigReloader;
+pub use tls::start_tls_watch;
+pub use upstreams::start_upstreams_watch;
+
+/// Наблюдение за файлами из `include` основного конфига (уже
+/// наблюдаемые пропускаются)
+fn watch_included(
+    watcher: &mut RecommendedWatcher,
+    watched: &mut HashSet<PathBuf>,
+    config: &DaoConfig,
+    config_path: &Path,
+) {
+    match config.included_files(config_path) {
+        Ok(files) => {
+            for file in files {
+                if watched.contains(&file) {
+                    continue;
+                }
+                match watcher.watch(&file, RecursiveMode::NonRecursive) {
+                    Ok(()) => {
+                        watched.insert(file);
+                    }
+                    Err(e) => tracing::warn!("Failed to watch {:?}: {}", file, e),
+                }
+            }
+        }
+        Err(e) => tracing::warn!("Failed to resolve config includes: {}", e),
+    }
+}
+
+/// Проверка, появился ли удаленный файл конфигурации
+const CONFIG_REWATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Перечитывание конфигурации из файла; ошибка не меняет текущую
+fn reload(
+    reloader: &ConfigReloader,
+    watcher: &mut RecommendedWatcher,
+    watched: &mut HashSet<PathBuf>,
+    config_path: &Path,
+) {
+    match DaoConfig::from_file(config_path) {
+        Ok(new_config) => {
+            // Новые файлы из include тоже под наблюдением
+            watch_included(watcher, watched, &new_config, config_path);
+            if let Err(e) = reloader.apply(new_config) {
+                tracing::error!("Failed to update config: {}", e);
+            } else {
+                tracing::info!("Config reloaded successfully");
+            }
+        }
+        Err(e) => {
+            tracing::error!("Failed to load config: {}", e);
+        }
+    }
+}
 
 /// Admin — система управления
 pub struct Admin {
     config_path: PathBuf,
     memory: Arc<Memory>,
-    reloader: ConfigReloader,
+    upstreams: Arc<UpstreamRegistry>,
+    reloader: Arc<ConfigReloader>,
 }
 
 impl Admin {
-    pub fn new(config_path: PathBuf, memory: Arc<Memory>) -> Self {
-        let reloader = ConfigReloader::new(memory.clone());
+    pub fn new(
+        config_path: PathBuf,
+        memory: Arc<Memory>,
+        upstreams: Arc<UpstreamRegistry>,
+    ) -> Self {
+        let reloader = Arc::new(ConfigReloader::new(memory.clone(), upstreams.clone()));
         Self {
             config_path,
             memory,
+            upstreams,
             reloader,
         }
     }
 
-    /// Запуск мониторинга конфигурации
+    /// Запуск мониторинга конфигурации.
+    ///
+    /// Удаление или переименование файла (атомарное сохранение редактором)
+    /// не сбрасывает конфигурацию: действует последняя корректная, а
+    /// наблюдение восстанавливается, когда файл появится снова.
     pub async fn start_config_watch(&self) -> anyhow::Result<()> {
         let (tx, mut rx) = mpsc::channel(100);
         let config_path = self.config_path.clone();
@@ -52,40 +126,67 @@ impl Admin {
         )?;
 
         watcher.watch(&config_path, RecursiveMode::NonRecursive)?;
+        let mut watched = HashSet::new();
+        watch_included(
+            &mut watcher,
+            &mut watched,
+            &self.memory.get_config(),
+            &config_path,
+        );
 
         tracing::info!("Started config watch for: {:?}", config_path);
 
         // Event loop
-        let memory = self.memory.clone();
-        let config_path_clone = config_path.clone();
+        let reloader = self.reloader.clone();
 
         tokio::spawn(async move {
-            while let Some(event) = rx.recv().await {
-                match event.kind {
-                    notify::EventKind::Modify(_) | notify::EventKind::Create(_) => {
-                        tracing::info!("Config file changed, reloading...");
-
-                        match DaoConfig::from_file(&config_path_clone) {
-                            Ok(new_config) => {
-                                if let Err(e) = memory.update_config(new_config) {
-                                    tracing::error!("Failed to update config: {}", e);
-                                } else {
-                                    tracing::info!("Config reloaded successfully");
-                                }
+            // Основной файл удален: ждем его появления
+            let mut missing = false;
+            let mut rewatch = tokio::time::interval(CONFIG_REWATCH_INTERVAL);
+            loop {
+                tokio::select! {
+                    event = rx.recv() => {
+                        let Some(event) = event else { break };
+                        match event.kind {
+                            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+                                if event.paths.contains(&config_path) =>
+                            {
+                                tracing::warn!(
+                                    "Config file {:?} removed or renamed, keeping current config",
+                                    config_path
+                                );
+                                // Наблюдение за прежним inode снимается и
+                                // ставится заново на тот же путь
+                                let _ = watcher.unwatch(&config_path);
+                                missing = true;
                             }
-                            Err(e) => {
-                                tracing::error!("Failed to load config: {}", e);
+                            EventKind::Modify(_) | EventKind::Create(_) => {
+                                tracing::info!("Config file changed, reloading...");
+                                reload(&reloader, &mut watcher, &mut watched, &config_path);
                             }
+                            _ => {}
+                        }
+                    }
+                    _ = rewatch.tick(), if missing => {
+                        if !config_path.exists() {
+                            continue;
+                        }
+                        match watcher.watch(&config_path, RecursiveMode::NonRecursive) {
+                            Ok(()) => {
+                                missing = false;
+                                tracing::info!(
+                                    "Config file {:?} is back, reloading...",
+                                    config_path
+                                );
+                                reload(&reloader, &mut watcher, &mut watched, &config_path);
+                            }
+                            Err(e) => tracing::warn!("Failed to watch {:?}: {}", config_path, e),
                         }
                     }
-                    _ => {}
                 }
             }
         });
 
-        // Держим watcher живым
-        std::mem::forget(watcher);
-
         Ok(())
     }
 
@@ -94,11 +195,77 @@ impl Admin {
         self.reloader.reload_from_file(&self.config_path).await
     }
 
+    /// Перезагрузка конфигурации по SIGHUP (`kill -HUP`), независимо от
+    /// событий файловой системы
+    #[cfg(unix)]
+    pub fn start_sighup_reload(self: &Arc<Self>) -> anyhow::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        // Обработчик ставится до возврата: сигнал после вызова не теряется
+        let mut hangup = signal(SignalKind::hangup())?;
+        let admin = self.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                tracing::info!("SIGHUP received, reloading config");
+                if let Err(e) = admin.reload_config().await {
+                    tracing::error!("Config reload on SIGHUP failed: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Наблюдение за `upstreams_file` маршрутов
+    pub fn start_upstreams_watch(&self) -> anyhow::Result<()> {
+        start_upstreams_watch(
+            self.config_path.clone(),
+            self.memory.clone(),
+            self.reloader.clone(),
+        )
+    }
+
+    /// Реестр upstream'ов
+    pub fn upstreams(&self) -> &Arc<UpstreamRegistry> {
+        &self.upstreams
+    }
+
     /// Получение текущей конфигурации
-    pub fn get_current_config(&self) -> DaoConfig {
+    pub fn get_current_config(&self) -> Arc<DaoConfig> {
         self.memory.get_config()
     }
 
+    /// Включение/выключение drain режима upstream'а.
+    ///
+    /// Возвращает false, если upstream с таким именем не найден.
+    pub fn set_upstream_draining(&self, name: &str, draining: bool) -> bool {
+        let mut found = false;
+        for upstream in self.upstreams.load().iter().filter(|u| u.name == name) {
+            upstream.set_draining(draining);
+            found = true;
+        }
+        if found {
+            tracing::info!(
+                "Upstream {} {}",
+                name,
+                if draining { "draining" } else { "undrained" }
+            );
+        }
+        found
+    }
+
+    /// Количество snapshot'ов и действующие настройки их хранения
+    pub fn snapshot_status(&self) -> (usize, SnapshotRetention) {
+        (
+            self.memory.snapshot_count(),
+            self.memory.get_config().snapshots.clone(),
+        )
+    }
+
+    /// Diff между snapshot'ами по индексам истории
+    pub fn diff_snapshots(&self, from: usize, to: usize) -> Option<ConfigDiff> {
+        self.memory.diff_snapshots(from, to)
+    }
+
     /// Откат к предыдущему snapshot
     pub fn rollback(&self, snapshot_index: usize) -> anyhow::Result<()> {
         self.memory
@@ -106,3 +273,98 @@ impl Admin {
             .map_err(|e| anyhow::anyhow!("Rollback failed: {}", e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(path: &Path, route: &str) {
+        std::fs::write(
+            path,
+            format!(
+                r#"
+                [server]
+                bind = "127.0.0.1:0"
+
+                [[routes.rule]]
+                name = "{}"
+                policy = "resonant"
+                match = {{ path_prefix = "/" }}
+                upstreams = [{{ name = "backend", url = "http://127.0.0.1:8081" }}]
+                "#,
+                route
+            ),
+        )
+        .unwrap();
+    }
+
+    async fn wait_for_route(admin: &Admin, route: &str) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while admin.get_current_config().routes.get(route).is_none() {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "route {} not loaded",
+                route
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_removed_config_kept_until_recreated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dao.toml");
+        write_config(&path, "first");
+        let config = DaoConfig::from_file(&path).unwrap();
+        let admin = Admin::new(
+            path.clone(),
+            Arc::new(Memory::new(config.clone())),
+            Arc::new(UpstreamRegistry::from_config(&config)),
+        );
+        admin.start_config_watch().await.unwrap();
+
+        // Удаление файла не сбрасывает конфигурацию
+        std::fs::remove_file(&path).unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(admin.get_current_config().routes.get("first").is_some());
+
+        write_config(&path, "second");
+        wait_for_route(&admin, "second").await;
+
+        // Наблюдение восстановлено: следующие правки тоже подхватываются
+        write_config(&path, "third");
+        wait_for_route(&admin, "third").await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sighup_reloads_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dao.toml");
+        write_config(&path, "before");
+        let config = DaoConfig::from_file(&path).unwrap();
+        let admin = Arc::new(Admin::new(
+            path.clone(),
+            Arc::new(Memory::new(config.clone())),
+            Arc::new(UpstreamRegistry::from_config(&config)),
+        ));
+        admin.start_sighup_reload().unwrap();
+        let hangup = || {
+            let status = std::process::Command::new("kill")
+                .args(["-HUP", &std::process::id().to_string()])
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+
+        write_config(&path, "after");
+        hangup();
+        wait_for_route(&admin, "after").await;
+        let (snapshots, _) = admin.snapshot_status();
+
+        // Повторный сигнал без изменений файла не создает snapshot
+        hangup();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(admin.snapshot_status().0, snapshots);
+    }
+}
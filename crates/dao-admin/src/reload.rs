@@ -1,18 +1,20 @@
 //! Config reloader
 
-use dao_core::config::DaoConfig;
+use dao_core::config::{DaoConfig, UpstreamConfig};
 use dao_core::memory::Memory;
+use dao_core::upstream::UpstreamRegistry;
 use std::path::Path;
 use std::sync::Arc;
 
 /// Перезагрузчик конфигурации
 pub struct ConfigReloader {
     memory: Arc<Memory>,
+    upstreams: Arc<UpstreamRegistry>,
 }
 
 impl ConfigReloader {
-    pub fn new(memory: Arc<Memory>) -> Self {
-        Self { memory }
+    pub fn new(memory: Arc<Memory>, upstreams: Arc<UpstreamRegistry>) -> Self {
+        Self { memory, upstreams }
     }
 
     /// Перезагрузка конфигурации из файла
@@ -21,17 +23,139 @@ impl ConfigReloader {
 
         let new_config = DaoConfig::from_file(path)?;
         new_config.validate()?;
-
-        self.memory.update_config(new_config)?;
+        self.apply(new_config)?;
 
         tracing::info!("Config reloaded successfully");
         Ok(())
     }
 
-    /// Валидация конфигурации без применения
-    pub fn validate_config(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
-        let config = DaoConfig::from_file(path)?;
-        config.validate()?;
+    /// Применение новой конфигурации: Memory + набор upstream'ов.
+    ///
+    /// Совпадающая с текущей конфигурация пропускается — одно изменение
+    /// файла может прийти и от watcher'а, и по SIGHUP.
+    pub fn apply(&self, new_config: DaoConfig) -> anyhow::Result<()> {
+        let current = self.memory.get_config();
+        if serde_json::to_value(&new_config)? == serde_json::to_value(&*current)? {
+            tracing::info!("Config unchanged, nothing to apply");
+            return Ok(());
+        }
+        // Невалидная конфигурация не должна задеть ни Memory, ни upstream'ы
+        let errors = new_config.validation_errors();
+        if !errors.is_empty() {
+            let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            anyhow::bail!(messages.join("; "));
+        }
+        // Upstream с новым URL — другой backend: выученные запреты
+        // intent'ов прежнего к нему не относятся
+        for name in self.upstreams.reload(&new_config) {
+            self.memory.forget_profile(&name);
+        }
+        self.memory.update_config(new_config)?;
         Ok(())
     }
+
+    /// Новый набор upstream'ов одного маршрута поверх текущей
+    /// конфигурации; статистика оставшихся upstream'ов сохраняется
+    pub fn apply_route_upstreams(
+        &self,
+        route: &str,
+        upstreams: Vec<UpstreamConfig>,
+    ) -> anyhow::Result<()> {
+        let new_config = self
+            .memory
+            .get_config()
+            .with_route_upstreams(route, upstreams)?;
+        self.apply(new_config)
+    }
+
+    /// Валидация конфигурации без применения (все ошибки в одном сообщении)
+    pub fn validate_config(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        DaoConfig::check_file(path).map(|_| ()).map_err(|errors| {
+            let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            anyhow::anyhow!(messages.join("; "))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dao_core::Intent;
+    use std::time::Duration;
+
+    fn config(url: &str) -> DaoConfig {
+        toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+            match = {{ path_prefix = "/" }}
+            upstreams = [{{ name = "backend", url = "{}" }}]
+            "#,
+            url
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_changed_url_resets_errors_and_intent_bans() {
+        let initial = config("http://127.0.0.1:8081");
+        let memory = Arc::new(Memory::new(initial.clone()));
+        let upstreams = Arc::new(UpstreamRegistry::from_config(&initial));
+        let reloader = ConfigReloader::new(memory.clone(), upstreams.clone());
+
+        // Прежний backend отказывал: intent запрещен, ошибки в статистике
+        let intent = Intent::new("batch");
+        let backend = upstreams.get("backend").unwrap();
+        for _ in 0..dao_core::memory::profile::FORBID_AFTER_FAILURES {
+            backend.record_request(Duration::from_millis(10), false);
+            memory.observe("backend", &intent, 1.0, 10.0, false);
+        }
+        assert!(!memory.accepts_intent("backend", &intent));
+
+        // Тот же URL — история сохраняется
+        let mut same_url = initial.clone();
+        same_url.routes.rule[0].priority = Some(1);
+        reloader.apply(same_url).unwrap();
+        assert!(upstreams.get("backend").unwrap().get_stats().error_count > 0);
+        assert!(!memory.accepts_intent("backend", &intent));
+
+        reloader.apply(config("http://127.0.0.1:9081")).unwrap();
+        let backend = upstreams.get("backend").unwrap();
+        assert_eq!(backend.url, "http://127.0.0.1:9081");
+        assert_eq!(backend.get_stats().error_count, 0);
+        assert!(memory.accepts_intent("backend", &intent));
+    }
+
+    #[test]
+    fn test_invalid_config_leaves_upstreams_untouched() {
+        let initial = config("http://127.0.0.1:8081");
+        let memory = Arc::new(Memory::new(initial.clone()));
+        let upstreams = Arc::new(UpstreamRegistry::from_config(&initial));
+        let reloader = ConfigReloader::new(memory.clone(), upstreams.clone());
+
+        let invalid: UpstreamConfig = toml::from_str(
+            r#"
+            name = "backend"
+            url = "ftp://127.0.0.1:9081"
+            "#,
+        )
+        .unwrap();
+        let err = reloader
+            .apply_route_upstreams("api", vec![invalid])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("scheme"), "{}", err);
+        assert_eq!(
+            upstreams.get("backend").unwrap().url,
+            "http://127.0.0.1:8081"
+        );
+        assert_eq!(
+            memory.get_config().routes.rule[0].upstreams[0].url,
+            "http://127.0.0.1:8081"
+        );
+    }
 }
@@ -0,0 +1,189 @@
+//! HMAC подпись запросов к admin API
+//!
+//! Подписывается `METHOD\npath?query\ntimestamp\n` + тело запроса;
+//! подпись — hex HMAC-SHA256 в `X-DAO-Signature`, время (unix, сек) — в
+//! `X-DAO-Timestamp`. Подпись принимается один раз: повтор в пределах
+//! допустимого расхождения часов отклоняется.
+
+use dao_core::config::AdminAuthConfig;
+use http::HeaderName;
+use parking_lot::Mutex;
+use ring::hmac;
+use std::collections::HashMap;
+
+/// Заголовок с подписью
+pub const SIGNATURE_HEADER: HeaderName = HeaderName::from_static("x-dao-signature");
+/// Заголовок со временем подписи
+pub const TIMESTAMP_HEADER: HeaderName = HeaderName::from_static("x-dao-timestamp");
+
+/// Предел принятых подписей, хранимых для отсечения повторов: при
+/// переполнении новые подписи отклоняются до истечения старых
+const MAX_SEEN_SIGNATURES: usize = 10_000;
+
+/// Проверка (и создание) подписей общим секретом
+pub struct RequestSigner {
+    key: hmac::Key,
+    max_clock_skew_secs: u64,
+    /// Принятые подписи → время подписи; хранятся, пока время в окне
+    seen: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+impl RequestSigner {
+    pub fn new(config: &AdminAuthConfig) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, config.hmac_secret.as_bytes()),
+            max_clock_skew_secs: config.max_clock_skew_secs,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Время подписи в пределах `max_clock_skew_secs` от `now` — до чтения
+    /// тела запроса
+    pub fn timestamp_fresh(&self, timestamp: &str, now: u64) -> bool {
+        timestamp
+            .parse::<u64>()
+            .is_ok_and(|timestamp| timestamp.abs_diff(now) <= self.max_clock_skew_secs)
+    }
+
+    /// Подпись запроса (hex) — для клиентов и тестов
+    pub fn sign(&self, method: &str, path: &str, timestamp: u64, body: &[u8]) -> String {
+        let tag = hmac::sign(&self.key, &message(method, path, timestamp, body));
+        tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Подпись верна, время в пределах `max_clock_skew_secs` от `now` и
+    /// подпись еще не предъявлялась.
+    ///
+    /// Сравнение подписи — за постоянное время.
+    pub fn verify(
+        &self,
+        method: &str,
+        path: &str,
+        timestamp: &str,
+        signature: &str,
+        body: &[u8],
+        now: u64,
+    ) -> bool {
+        let Ok(timestamp) = timestamp.parse::<u64>() else {
+            return false;
+        };
+        if timestamp.abs_diff(now) > self.max_clock_skew_secs {
+            return false;
+        }
+        let Some(tag) = decode_hex(signature) else {
+            return false;
+        };
+        if hmac::verify(&self.key, &message(method, path, timestamp, body), &tag).is_err() {
+            return false;
+        }
+
+        // Повтор принятой подписи; записи вне окна уже не пройдут по времени
+        let mut seen = self.seen.lock();
+        let window = self.max_clock_skew_secs;
+        seen.retain(|_, signed_at| signed_at.abs_diff(now) <= window);
+        if seen.contains_key(&tag) {
+            return false;
+        }
+        if seen.len() >= MAX_SEEN_SIGNATURES {
+            tracing::warn!("Admin signature replay cache is full, rejecting request");
+            return false;
+        }
+        seen.insert(tag, timestamp);
+        true
+    }
+}
+
+fn message(method: &str, path: &str, timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let mut message = format!("{}\n{}\n{}\n", method, path, timestamp).into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> RequestSigner {
+        RequestSigner::new(&AdminAuthConfig {
+            hmac_secret: "s3cret".to_string(),
+            max_clock_skew_secs: 60,
+        })
+    }
+
+    #[test]
+    fn test_signature_roundtrip() {
+        let signer = signer();
+        let signature = signer.sign("POST", "/upstreams/a/drain", 1_000, b"{}");
+        assert_eq!(signature.len(), 64);
+        assert!(signer.verify(
+            "POST",
+            "/upstreams/a/drain",
+            "1000",
+            &signature,
+            b"{}",
+            1_030
+        ));
+        // Та же подпись повторно (replay в пределах окна)
+        assert!(!signer.verify(
+            "POST",
+            "/upstreams/a/drain",
+            "1000",
+            &signature,
+            b"{}",
+            1_031
+        ));
+        let fresh = signer.sign("POST", "/upstreams/a/drain", 1_001, b"{}");
+        assert!(signer.verify("POST", "/upstreams/a/drain", "1001", &fresh, b"{}", 1_031));
+
+        // Любая часть запроса входит в подпись
+        assert!(!signer.verify(
+            "POST",
+            "/upstreams/b/drain",
+            "1000",
+            &signature,
+            b"{}",
+            1_030
+        ));
+        assert!(!signer.verify(
+            "POST",
+            "/upstreams/a/drain",
+            "1000",
+            &signature,
+            b"{ }",
+            1_030
+        ));
+        assert!(!signer.verify(
+            "POST",
+            "/upstreams/a/drain",
+            "1001",
+            &signature,
+            b"{}",
+            1_030
+        ));
+        assert!(!signer.verify("POST", "/upstreams/a/drain", "1000", "zz", b"{}", 1_030));
+
+        // Старая подпись (replay) и подпись из будущего
+        assert!(!signer.verify(
+            "POST",
+            "/upstreams/a/drain",
+            "1000",
+            &signature,
+            b"{}",
+            1_061
+        ));
+        assert!(!signer.verify("POST", "/upstreams/a/drain", "1000", &signature, b"{}", 939));
+        assert!(signer.timestamp_fresh("1000", 1_060));
+        assert!(!signer.timestamp_fresh("1000", 1_061));
+        assert!(!signer.timestamp_fresh("soon", 1_000));
+    }
+}
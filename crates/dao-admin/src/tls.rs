@@ -0,0 +1,152 @@
+//! Hot-reload TLS сертификатов listener'ов
+
+use dao_core::gate::Listener;
+use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Каталог файла (для файла без каталога — текущий)
+pub(crate) fn parent_dir(file: &Path) -> PathBuf {
+    match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Наблюдение за сертификатами TLS listener'ов: при изменении файлов
+/// listener перечитывает пару, битая пара не применяется.
+///
+/// Наблюдаются каталоги, а не сами файлы, — так переживается атомарная
+/// замена файла (rename, symlink swap cert-manager'а).
+pub fn start_tls_watch(listeners: Vec<Arc<Listener>>) -> anyhow::Result<()> {
+    let listeners: Vec<_> = listeners
+        .into_iter()
+        .filter_map(|listener| {
+            let (cert, key) = listener.tls_files()?;
+            let dirs: HashSet<_> = [parent_dir(Path::new(cert)), parent_dir(Path::new(key))].into();
+            Some((listener, dirs))
+        })
+        .collect();
+    if listeners.is_empty() {
+        return Ok(());
+    }
+
+    let (tx, mut rx) = mpsc::channel(100);
+    let mut watcher = RecommendedWatcher::new(
+        move |res: Result<Event, notify::Error>| {
+            if let Ok(event) = res {
+                let _ = tx.blocking_send(event);
+            }
+        },
+        Config::default().with_poll_interval(Duration::from_secs(2)),
+    )?;
+    let watched: HashSet<_> = listeners.iter().flat_map(|(_, dirs)| dirs).collect();
+    for dir in watched {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        tracing::info!("Started TLS certificate watch for: {:?}", dir);
+    }
+
+    tokio::spawn(async move {
+        // Watcher живет, пока работает цикл событий
+        let _watcher = watcher;
+        while let Some(event) = rx.recv().await {
+            if event.kind.is_access() {
+                continue;
+            }
+            for (listener, dirs) in &listeners {
+                let touched = event
+                    .paths
+                    .iter()
+                    .any(|path| path.parent().is_some_and(|dir| dirs.contains(dir)));
+                if !touched {
+                    continue;
+                }
+                match listener.reload_tls() {
+                    Ok(()) => tracing::info!(
+                        "TLS certificate reloaded for listener {:?}",
+                        listener.local_addr().ok()
+                    ),
+                    Err(e) => {
+                        tracing::warn!("TLS certificate reload failed, keeping previous: {}", e)
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dao_core::gate::{ListenerConfig, TcpOptions, TlsConfig};
+    use rustls::pki_types::{CertificateDer, ServerName};
+
+    /// Новый self-signed сертификат в `dir`, возвращает его DER
+    fn write_cert(dir: &Path) -> CertificateDer<'static> {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.join("dao.key"), cert.key_pair.serialize_pem()).unwrap();
+        std::fs::write(dir.join("dao.crt"), cert.cert.pem()).unwrap();
+        cert.cert.der().clone()
+    }
+
+    /// Успешен ли handshake с доверием только к `ca`
+    async fn handshake(listener: &Listener, ca: CertificateDer<'static>) -> bool {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca).unwrap();
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let addr = listener.local_addr().unwrap();
+        let client = async {
+            let stream = tokio::net::TcpStream::connect(addr).await?;
+            let name = ServerName::try_from("localhost").unwrap();
+            connector.connect(name, stream).await
+        };
+        let server = async {
+            let (stream, peer_addr) = listener.accept().await?;
+            listener.handshake(stream, peer_addr).await
+        };
+        let (_, client) = tokio::join!(server, client);
+        client.is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_replaced_cert_served_to_new_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_ca = write_cert(dir.path());
+        let listener = Arc::new(
+            Listener::bind(ListenerConfig {
+                bind_addr: "127.0.0.1:0".to_string(),
+                tls: Some(TlsConfig {
+                    cert_path: dir.path().join("dao.crt").to_string_lossy().into_owned(),
+                    key_path: dir.path().join("dao.key").to_string_lossy().into_owned(),
+                    alpn: None,
+                    alpn_strict: false,
+                    client_ca: None,
+                }),
+                tcp: TcpOptions::default(),
+                proxy_protocol: false,
+            })
+            .await
+            .unwrap(),
+        );
+        start_tls_watch(vec![listener.clone()]).unwrap();
+        assert!(handshake(&listener, old_ca).await);
+
+        let new_ca = write_cert(dir.path());
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while !handshake(&listener, new_ca.clone()).await {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "new certificate not served"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Перечитывание `upstreams_file` маршрутов независимо от основной
+//! конфигурации
+
+use crate::reload::ConfigReloader;
+use crate::tls::parent_dir;
+use dao_core::config::load_upstreams_file;
+use dao_core::memory::Memory;
+use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Затронут ли файл событием (по каталогу и имени: `pool.json` и
+/// `./pool.json` — один файл)
+fn touches(event: &Event, file: &Path) -> bool {
+    let dir = parent_dir(file);
+    event
+        .paths
+        .iter()
+        .any(|path| path.file_name() == file.file_name() && parent_dir(path) == dir)
+}
+
+/// Наблюдение за `upstreams_file` маршрутов: при изменении файла
+/// меняется только набор upstream'ов его маршрута, статистика оставшихся
+/// upstream'ов сохраняется. Битый файл не применяется.
+///
+/// Соответствие маршрут → файл берется из текущей конфигурации, каталоги
+/// наблюдаются по состоянию на запуск.
+pub fn start_upstreams_watch(
+    config_path: PathBuf,
+    memory: Arc<Memory>,
+    reloader: Arc<ConfigReloader>,
+) -> anyhow::Result<()> {
+    let dirs: HashSet<_> = memory
+        .get_config()
+        .upstreams_files(&config_path)
+        .iter()
+        .map(|(_, file)| parent_dir(file))
+        .collect();
+    if dirs.is_empty() {
+        return Ok(());
+    }
+
+    let (tx, mut rx) = mpsc::channel(100);
+    let mut watcher = RecommendedWatcher::new(
+        move |res: Result<Event, notify::Error>| {
+            if let Ok(event) = res {
+                let _ = tx.blocking_send(event);
+            }
+        },
+        Config::default().with_poll_interval(Duration::from_secs(2)),
+    )?;
+    for dir in &dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        tracing::info!("Started upstreams file watch for: {:?}", dir);
+    }
+
+    tokio::spawn(async move {
+        // Watcher живет, пока работает цикл событий
+        let _watcher = watcher;
+        while let Some(event) = rx.recv().await {
+            if event.kind.is_access() {
+                continue;
+            }
+            let config = memory.get_config();
+            for (route, file) in config.upstreams_files(&config_path) {
+                if !touches(&event, &file) {
+                    continue;
+                }
+                let upstreams = match load_upstreams_file(&file) {
+                    Ok(upstreams) => upstreams,
+                    Err(e) => {
+                        tracing::warn!("Upstreams file reload failed, keeping previous: {}", e);
+                        continue;
+                    }
+                };
+                // Повторные события на одну запись не порождают snapshot'ы
+                let current = config.routes.get(&route).map(|r| &r.upstreams);
+                if current.map(serde_json::to_value).and_then(Result::ok)
+                    == serde_json::to_value(&upstreams).ok()
+                {
+                    continue;
+                }
+                match reloader.apply_route_upstreams(&route, upstreams) {
+                    Ok(()) => {
+                        tracing::info!("Upstreams of route {} reloaded from {:?}", route, file)
+                    }
+                    Err(e) => tracing::warn!("Upstreams file {:?} rejected: {}", file, e),
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dao_core::config::DaoConfig;
+    use dao_core::upstream::UpstreamRegistry;
+
+    fn write_pool(dir: &Path, names: &[&str]) {
+        let upstreams: Vec<_> = names
+            .iter()
+            .map(|name| serde_json::json!({ "name": name, "url": format!("http://{}", name) }))
+            .collect();
+        std::fs::write(
+            dir.join("upstreams/pool-a.json"),
+            serde_json::to_string(&upstreams).unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn route_upstreams(memory: &Memory, route: &str) -> Vec<String> {
+        let config = memory.get_config();
+        config
+            .routes
+            .get(route)
+            .unwrap()
+            .upstreams
+            .iter()
+            .map(|u| u.name.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_upstreams_file_change_updates_only_its_route() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("upstreams")).unwrap();
+        write_pool(dir.path(), &["a", "b"]);
+        let config_path = dir.path().join("dao.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "pool"
+            policy = "resonant"
+            match = { path_prefix = "/pool/" }
+            upstreams_file = "upstreams/pool-a.json"
+
+            [[routes.rule]]
+            name = "static"
+            policy = "resonant"
+            match = { path_prefix = "/" }
+            upstreams = [{ name = "cdn", url = "http://cdn" }]
+            "#,
+        )
+        .unwrap();
+
+        let config = DaoConfig::from_file(&config_path).unwrap();
+        let memory = Arc::new(Memory::new(config.clone()));
+        let upstreams = Arc::new(UpstreamRegistry::from_config(&config));
+        let reloader = Arc::new(ConfigReloader::new(memory.clone(), upstreams.clone()));
+        upstreams
+            .get("a")
+            .unwrap()
+            .record_request(Duration::from_millis(10), true);
+        let snapshots = memory.snapshot_count();
+
+        start_upstreams_watch(config_path, memory.clone(), reloader).unwrap();
+        write_pool(dir.path(), &["a", "c"]);
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while route_upstreams(&memory, "pool") != ["a", "c"] {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "upstreams file not reloaded"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        assert_eq!(route_upstreams(&memory, "static"), ["cdn"]);
+        assert!(memory.snapshot_count() > snapshots);
+        let names: Vec<_> = upstreams.load().iter().map(|u| u.name.clone()).collect();
+        assert_eq!(names, ["a", "c", "cdn"]);
+        // Оставшийся upstream сохранил статистику
+        assert_eq!(upstreams.get("a").unwrap().get_stats().success_count, 1);
+    }
+}
@@ -0,0 +1,154 @@
+//! A/B эксперименты: детерминированное назначение плеча по ключу запроса
+
+use crate::config::{AbArmConfig, AbTestConfig, AB_BUCKETS};
+use crate::upstream::UpstreamState;
+use http::Request;
+use std::sync::Arc;
+
+/// Назначение запроса в плечо эксперимента маршрута.
+///
+/// Ключ (ID пользователя из заголовка или cookie) хешируется стабильной
+/// FNV-1a — одинаково между рестартами и экземплярами DAO, поэтому
+/// пользователь всегда попадает в одно и то же плечо.
+pub struct AbSplit<'a> {
+    config: &'a AbTestConfig,
+}
+
+impl<'a> AbSplit<'a> {
+    pub fn new(config: &'a AbTestConfig) -> Self {
+        Self { config }
+    }
+
+    /// Плечо запроса; `None` — в запросе нет ключа
+    pub fn assign<B>(&self, req: &Request<B>) -> Option<&'a AbArmConfig> {
+        let bucket = bucket(&self.key(req)?);
+        self.config
+            .arms
+            .iter()
+            .find(|arm| (arm.buckets[0]..=arm.buckets[1]).contains(&bucket))
+    }
+
+    /// Ключ: заголовок, затем cookie
+    fn key<B>(&self, req: &Request<B>) -> Option<String> {
+        let from_header = self.config.key_header.as_ref().and_then(|name| {
+            req.headers()
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        });
+        if let Some(key) = from_header {
+            return Some(key.to_string());
+        }
+
+        let cookie = self.config.key_cookie.as_deref()?;
+        req.headers()
+            .get_all(http::header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, value)| *name == cookie && !value.is_empty())
+            .map(|(_, value)| value.to_string())
+    }
+}
+
+/// Upstream'ы плеча среди upstream'ов маршрута
+pub fn arm_upstreams(
+    arm: &AbArmConfig,
+    upstreams: &[Arc<UpstreamState>],
+) -> Vec<Arc<UpstreamState>> {
+    upstreams
+        .iter()
+        .filter(|u| arm.upstreams.contains(&u.name))
+        .cloned()
+        .collect()
+}
+
+/// Бакет ключа `0..AB_BUCKETS`
+fn bucket(key: &str) -> u32 {
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    (hash % u64::from(AB_BUCKETS)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream::test_upstreams;
+
+    fn config() -> AbTestConfig {
+        AbTestConfig {
+            key_header: Some("x-user-id".to_string()),
+            key_cookie: Some("uid".to_string()),
+            arms: vec![
+                AbArmConfig {
+                    name: "control".to_string(),
+                    buckets: [0, 49],
+                    upstreams: vec!["stable".to_string()],
+                },
+                AbArmConfig {
+                    name: "variant".to_string(),
+                    buckets: [50, 99],
+                    upstreams: vec!["next".to_string()],
+                },
+            ],
+        }
+    }
+
+    fn request(header: Option<&str>, cookie: Option<&str>) -> Request<()> {
+        let mut builder = Request::get("/checkout");
+        if let Some(user) = header {
+            builder = builder.header("x-user-id", user);
+        }
+        if let Some(cookie) = cookie {
+            builder = builder.header(http::header::COOKIE, cookie);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_same_key_same_arm() {
+        let config = config();
+        let split = AbSplit::new(&config);
+
+        let mut arms = std::collections::HashSet::new();
+        for user in 0..200 {
+            let user = format!("user-{}", user);
+            let arm = split.assign(&request(Some(&user), None)).unwrap();
+            for _ in 0..5 {
+                assert_eq!(
+                    split.assign(&request(Some(&user), None)).unwrap().name,
+                    arm.name
+                );
+            }
+            // Тот же ключ из cookie — то же плечо
+            let cookie = format!("theme=dark; uid={}", user);
+            assert_eq!(
+                split.assign(&request(None, Some(&cookie))).unwrap().name,
+                arm.name
+            );
+            arms.insert(arm.name.clone());
+        }
+        assert_eq!(arms.len(), 2);
+        assert!(split.assign(&request(None, Some("theme=dark"))).is_none());
+    }
+
+    #[test]
+    fn test_bucket_is_stable() {
+        // Значения не зависят от процесса: назначения переживают рестарт
+        assert_eq!(bucket(""), (0xcbf2_9ce4_8422_2325u64 % 100) as u32);
+        assert_eq!(bucket("user-42"), bucket("user-42"));
+        assert!((0..10_000).all(|i| bucket(&i.to_string()) < AB_BUCKETS));
+    }
+
+    #[test]
+    fn test_arm_upstreams() {
+        let upstreams = test_upstreams(&["stable", "next"]);
+        let config = config();
+        let selected = arm_upstreams(&config.arms[1], &upstreams);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].name, "next");
+    }
+}
@@ -0,0 +1,106 @@
+//! Заголовки ответа с решением Align (какой upstream и почему)
+
+use super::SelectionExplanation;
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+/// Upstream, обслуживший запрос
+pub const SELECTED_UPSTREAM_HEADER: HeaderName = HeaderName::from_static("x-dao-selected");
+
+/// Политика маршрута
+pub const SELECTION_POLICY_HEADER: HeaderName = HeaderName::from_static("x-dao-policy");
+
+/// Score выбранного upstream'а по политике
+pub const SELECTION_SCORE_HEADER: HeaderName = HeaderName::from_static("x-dao-score");
+
+/// Выдача решения Align клиенту заголовками.
+///
+/// Выключена по умолчанию (`server.expose_selection_headers`): заголовки
+/// раскрывают внутренние имена upstream'ов.
+pub struct SelectionHeaders {
+    enabled: bool,
+}
+
+impl SelectionHeaders {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Добавление заголовков для upstream'а `selected`; score берется из
+    /// `explanation`, если upstream в нем оценен
+    pub fn apply(
+        &self,
+        headers: &mut HeaderMap,
+        explanation: &SelectionExplanation,
+        selected: &str,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        if let Ok(value) = HeaderValue::from_str(selected) {
+            headers.insert(SELECTED_UPSTREAM_HEADER, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&explanation.policy) {
+            headers.insert(SELECTION_POLICY_HEADER, value);
+        }
+        let score = explanation
+            .candidates
+            .iter()
+            .find(|c| c.name == selected)
+            .and_then(|c| c.score);
+        if let Some(score) = score {
+            if let Ok(value) = HeaderValue::from_str(&format!("{:.4}", score)) {
+                headers.insert(SELECTION_SCORE_HEADER, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::Align;
+    use crate::sense::Sense;
+    use crate::upstream::{test_upstreams, UpstreamRegistry};
+    use std::sync::Arc;
+
+    fn explain() -> (SelectionExplanation, String) {
+        let upstreams = test_upstreams(&["backend-a", "backend-b"]);
+        upstreams[0].set_draining(true);
+        let align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        let selected = align.select_upstream("resonant", &upstreams, None).unwrap();
+        (
+            align.explain_selection("resonant", &upstreams, None),
+            selected.name.clone(),
+        )
+    }
+
+    #[test]
+    fn test_selection_headers_enabled() {
+        let (explanation, selected) = explain();
+        assert_eq!(selected, "backend-b");
+
+        let mut headers = HeaderMap::new();
+        SelectionHeaders::new(true).apply(&mut headers, &explanation, &selected);
+        assert_eq!(headers[SELECTED_UPSTREAM_HEADER], "backend-b");
+        assert_eq!(headers[SELECTION_POLICY_HEADER], "resonant");
+        let score: f64 = headers[SELECTION_SCORE_HEADER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(score.is_finite());
+    }
+
+    #[test]
+    fn test_selection_headers_disabled() {
+        let (explanation, selected) = explain();
+
+        let mut headers = HeaderMap::new();
+        SelectionHeaders::new(false).apply(&mut headers, &explanation, &selected);
+        assert!(headers.is_empty());
+    }
+}
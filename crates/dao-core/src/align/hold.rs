@@ -0,0 +1,69 @@
+//! Удержание выбора upstream'а на соединении (`selection_hold_ms`)
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Недавние выборы на одном соединении клиента: маршрут → upstream.
+///
+/// В отличие от sticky-сессий, удержание короткое и не продлевается:
+/// по истечении окна upstream выбирается политикой заново. Живет вместе
+/// с соединением, поэтому не требует очистки. Таблица создается при
+/// первом удержании: соединения маршрутов без `selection_hold_ms` ее
+/// не заводят.
+#[derive(Debug, Default)]
+pub struct SelectionHold {
+    held: OnceLock<Mutex<HashMap<String, (String, Instant)>>>,
+}
+
+impl SelectionHold {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Upstream, выбранный для маршрута, пока не истекло окно
+    pub fn get(&self, route: &str, now: Instant) -> Option<String> {
+        let mut held = self.held.get()?.lock();
+        match held.get(route) {
+            Some((upstream, until)) if now < *until => Some(upstream.clone()),
+            Some(_) => {
+                held.remove(route);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Запоминание выбора на `window` от `now`
+    pub fn hold(&self, route: &str, upstream: &str, window: Duration, now: Instant) {
+        self.held
+            .get_or_init(Default::default)
+            .lock()
+            .insert(route.to_string(), (upstream.to_string(), now + window));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hold_expires_without_extension() {
+        let hold = SelectionHold::new();
+        let now = Instant::now();
+        assert_eq!(hold.get("api", now), None);
+        assert!(hold.held.get().is_none());
+        let window = Duration::from_millis(100);
+        hold.hold("api", "backend-1", window, now);
+
+        assert_eq!(
+            hold.get("api", now + Duration::from_millis(50)).as_deref(),
+            Some("backend-1")
+        );
+        assert_eq!(hold.get("other", now), None);
+        // Повторные чтения окно не продлевают
+        assert_eq!(hold.get("api", now + window), None);
+        assert_eq!(hold.get("api", now), None);
+    }
+}
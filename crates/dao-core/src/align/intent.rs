@@ -0,0 +1,142 @@
+//! Intent classifier — определение намерения по запросу
+
+use crate::config::IntentRulesConfig;
+use crate::Intent;
+use http::Request;
+
+/// Классификатор intent запроса.
+///
+/// Порядок: заголовок (если настроен) → первое подходящее правило.
+/// `None` — запрос не классифицирован, используется intent маршрута.
+pub struct IntentClassifier<'a> {
+    config: &'a IntentRulesConfig,
+}
+
+impl<'a> IntentClassifier<'a> {
+    pub fn new(config: &'a IntentRulesConfig) -> Self {
+        Self { config }
+    }
+
+    /// Intent запроса
+    pub fn classify<B>(&self, req: &Request<B>) -> Option<Intent> {
+        if let Some(header) = &self.config.header {
+            let from_header = req
+                .headers()
+                .get(header.as_str())
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty());
+            if let Some(intent) = from_header {
+                return Some(Intent::new(intent));
+            }
+        }
+
+        self.config
+            .rule
+            .iter()
+            .find(|rule| rule.matches(req))
+            .map(|rule| rule.intent())
+    }
+
+    /// Intent запроса с fallback на intent маршрута
+    pub fn classify_or<B>(&self, req: &Request<B>, route_intent: Option<Intent>) -> Option<Intent> {
+        self.classify(req).or(route_intent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Method;
+
+    fn rules() -> IntentRulesConfig {
+        toml::from_str(
+            r#"
+            header = "X-Intent"
+
+            [[rule]]
+            intent = "realtime"
+            path_prefix = "/v1/chat/completions"
+            methods = ["POST"]
+
+            [[rule]]
+            intent = "batch"
+            path_prefix = "/v1/batch"
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn request(method: Method, path: &str) -> Request<()> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_classify_by_rules() {
+        let config = rules();
+        assert!(config.validate().is_ok());
+        let classifier = IntentClassifier::new(&config);
+
+        assert_eq!(
+            classifier.classify(&request(Method::POST, "/v1/chat/completions")),
+            Some(Intent::new("realtime"))
+        );
+        assert_eq!(
+            classifier.classify(&request(Method::GET, "/v1/batch/42")),
+            Some(Intent::new("batch"))
+        );
+        // Метод не подходит — правило не срабатывает
+        assert_eq!(
+            classifier.classify(&request(Method::GET, "/v1/chat/completions")),
+            None
+        );
+        assert_eq!(classifier.classify(&request(Method::GET, "/health")), None);
+    }
+
+    #[test]
+    fn test_header_overrides_rules() {
+        let config = rules();
+        let classifier = IntentClassifier::new(&config);
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri("/v1/batch/42")
+            .header("x-intent", "realtime")
+            .body(())
+            .unwrap();
+        assert_eq!(classifier.classify(&req), Some(Intent::new("realtime")));
+
+        // Без настроенного header заголовок игнорируется
+        let no_header = IntentRulesConfig {
+            header: None,
+            ..rules()
+        };
+        let classifier = IntentClassifier::new(&no_header);
+        assert_eq!(classifier.classify(&req), Some(Intent::new("batch")));
+    }
+
+    #[test]
+    fn test_route_intent_fallback() {
+        let config = rules();
+        let classifier = IntentClassifier::new(&config);
+
+        assert_eq!(
+            classifier.classify_or(
+                &request(Method::GET, "/health"),
+                Some(Intent::new("default"))
+            ),
+            Some(Intent::new("default"))
+        );
+        assert_eq!(
+            classifier.classify_or(
+                &request(Method::GET, "/v1/batch"),
+                Some(Intent::new("default"))
+            ),
+            Some(Intent::new("batch"))
+        );
+    }
+}
@@ -2,24 +2,54 @@
 //!
 //! Модуль политик маршрутизации:
 //! - Resonant load balancing
+//! - Peak EWMA (least response time)
 //! - Circuit breaker
 //! - Canary routing
 //! - A/B testing
 
-use crate::{Intent, Result, upstream::UpstreamState};
-use crate::sense::Sense;
+use crate::config::RouteRule;
+use crate::memory::Memory;
+use crate::sense::{LoadComponents, ResonanceMetrics, Sense};
+use crate::{
+    upstream::{BreakerState, InFlightGuard, UpstreamRegistry, UpstreamState},
+    Intent,
+};
+use serde::Serialize;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+pub mod ab;
+pub mod expose;
+pub mod hold;
+pub mod intent;
+pub mod pin;
 pub mod policy;
 pub mod selector;
 
-pub use policy::{Policy, PolicyWeights};
-pub use selector::UpstreamSelector;
+pub use ab::{arm_upstreams, AbSplit};
+pub use expose::{
+    SelectionHeaders, SELECTED_UPSTREAM_HEADER, SELECTION_POLICY_HEADER, SELECTION_SCORE_HEADER,
+};
+pub use hold::SelectionHold;
+pub use intent::IntentClassifier;
+pub use pin::{UpstreamOverride, UPSTREAM_OVERRIDDEN_HEADER, UPSTREAM_OVERRIDE_HEADER};
+pub use policy::{
+    Policy, PolicyWeights, DEFAULT_P99_BUDGET_MS, P2C_POLICY, PEAK_EWMA_POLICY, SLO_POLICY,
+    SWRR_POLICY,
+};
+pub use selector::{
+    P2cStrategy, PeakEwmaStrategy, ResonantStrategy, SelectionStrategy, SloStrategy,
+    SmoothWeightedStrategy,
+};
 
 /// Align — система принятия решений
 pub struct Align {
     sense: Sense,
     policies: PolicyRegistry,
+    /// Окно slow start для новых upstream'ов (None — выключен)
+    slow_start: Option<Duration>,
+    /// Профили сервисов: запрещенные intent'ы не направляются
+    memory: Option<Memory>,
 }
 
 impl Align {
@@ -27,70 +57,459 @@ impl Align {
         Self {
             sense,
             policies: PolicyRegistry::new(),
+            slow_start: None,
+            memory: None,
         }
     }
 
+    /// Учет профилей сервисов при выборе
+    pub fn set_memory(&mut self, memory: Memory) {
+        self.memory = Some(memory);
+    }
+
+    /// Включение slow start
+    pub fn set_slow_start(&mut self, window: Option<Duration>) {
+        self.slow_start = window;
+    }
+
+    /// Источник метрик системы
+    pub fn sense(&self) -> &Sense {
+        &self.sense
+    }
+
     /// Регистрация политики
     pub fn register_policy(&mut self, name: String, weights: PolicyWeights) {
         self.policies.register(name, weights);
     }
 
-    /// Выбор upstream для запроса
+    /// Своя стратегия выбора под именем политики (заменяет одноименную)
+    pub fn register_strategy(&mut self, name: String, strategy: Box<dyn SelectionStrategy>) {
+        self.policies.register_strategy(name, strategy);
+    }
+
+    /// Выбор upstream для запроса стратегией политики
     pub fn select_upstream(
         &self,
         policy_name: &str,
         upstreams: &[Arc<UpstreamState>],
         request_intent: Option<&Intent>,
     ) -> Option<Arc<UpstreamState>> {
-        let default_weights = PolicyWeights::default();
-        let weights = self.policies.get(policy_name)
-            .unwrap_or(&default_weights);
+        self.select_in_route(policy_name, "", upstreams, request_intent)
+    }
 
-        let metrics = self.sense.get_resonance_metrics();
+    /// Выбор upstream'а маршрута его политикой; состояние стратегии
+    /// (текущие веса swrr) — свое у каждого маршрута
+    pub fn select_route_upstream(
+        &self,
+        route: &RouteRule,
+        upstreams: &[Arc<UpstreamState>],
+        request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        self.select_in_route(&route.policy, &route.name, upstreams, request_intent)
+    }
 
-        // Вычисление resonant score для каждого upstream
-        let mut scored_upstreams: Vec<_> = upstreams
+    fn select_in_route(
+        &self,
+        policy_name: &str,
+        route: &str,
+        upstreams: &[Arc<UpstreamState>],
+        request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        let candidates: Vec<_> = self
+            .candidates(policy_name, upstreams, request_intent)
+            .into_iter()
+            .cloned()
+            .collect();
+        let metrics: Vec<_> = candidates.iter().map(|u| ResonanceMetrics::of(u)).collect();
+        self.policies.strategy(policy_name).select_in_route(
+            route,
+            &candidates,
+            &metrics,
+            request_intent,
+        )
+    }
+
+    /// Выбор по цепочке маршрутов (основной + fallback'и): первый маршрут,
+    /// в котором есть кого выбрать. `primary` сужает кандидатов основного
+    /// маршрута (плечо A/B); None — все его upstream'ы
+    pub fn select_with_fallback<'r>(
+        &self,
+        chain: &[&'r RouteRule],
+        primary: Option<&[Arc<UpstreamState>]>,
+        registry: &UpstreamRegistry,
+        request_intent: Option<&Intent>,
+    ) -> Option<(Arc<UpstreamState>, &'r RouteRule)> {
+        for (depth, route) in chain.iter().enumerate() {
+            let upstreams = match primary {
+                Some(primary) if depth == 0 => primary.to_vec(),
+                _ => registry.route_upstreams(route),
+            };
+            if let Some(upstream) = self.select_route_upstream(route, &upstreams, request_intent) {
+                if depth > 0 {
+                    metrics::counter!(
+                        "dao_fallback_used_total",
+                        "route" => chain[0].name.clone(),
+                        "fallback" => route.name.clone()
+                    )
+                    .increment(1);
+                }
+                return Some((upstream, route));
+            }
+        }
+        None
+    }
+
+    /// Удержанный upstream, если он все еще может принять запрос: без
+    /// drain, не на пределе, без запрета intent'а (slow start не
+    /// учитывается — upstream уже принимал запросы)
+    pub fn held_upstream(
+        &self,
+        policy_name: &str,
+        upstreams: &[Arc<UpstreamState>],
+        request_intent: Option<&Intent>,
+        name: &str,
+    ) -> Option<Arc<UpstreamState>> {
+        eligible(upstreams)
+            .find(|u| u.name == name)
+            .filter(|u| {
+                !self.rejects_intent(u, request_intent)
+                    && self.matches_intent(policy_name, u, request_intent)
+            })
+            .cloned()
+    }
+
+    /// Допуск circuit breaker'а выбранного upstream'а. Выбор пробу не
+    /// занимает: если последнюю пробу полуоткрытой цепи успел занять другой
+    /// запрос, выбор повторяется среди остальных `candidates` (при
+    /// `reselect`; явно закрепленный upstream не подменяется)
+    pub fn admit(
+        &self,
+        route: &RouteRule,
+        selected: Arc<UpstreamState>,
+        mut candidates: Vec<Arc<UpstreamState>>,
+        request_intent: Option<&Intent>,
+        reselect: bool,
+    ) -> Option<(Arc<UpstreamState>, InFlightGuard)> {
+        let mut upstream = selected;
+        loop {
+            if let Some(in_flight) = upstream.try_begin_request() {
+                return Some((upstream, in_flight));
+            }
+            tracing::debug!("Circuit of upstream {} rejects request", upstream.name);
+            if !reselect {
+                return None;
+            }
+            candidates.retain(|u| u.name != upstream.name);
+            upstream = self.select_route_upstream(route, &candidates, request_intent)?;
+        }
+    }
+
+    /// Разбор решения без проксирования: выбор и score — той же
+    /// стратегией, что и в `select_upstream`, плюс исключенные upstream'ы
+    pub fn explain_selection(
+        &self,
+        policy_name: &str,
+        upstreams: &[Arc<UpstreamState>],
+        request_intent: Option<&Intent>,
+    ) -> SelectionExplanation {
+        self.explain_in_route(policy_name, "", upstreams, request_intent)
+    }
+
+    /// Разбор решения маршрута: как `select_route_upstream`, с состоянием
+    /// стратегии маршрута (выбор его не сдвигает)
+    pub fn explain_route_selection(
+        &self,
+        route: &RouteRule,
+        upstreams: &[Arc<UpstreamState>],
+        request_intent: Option<&Intent>,
+    ) -> SelectionExplanation {
+        self.explain_in_route(&route.policy, &route.name, upstreams, request_intent)
+    }
+
+    fn explain_in_route(
+        &self,
+        policy_name: &str,
+        route: &str,
+        upstreams: &[Arc<UpstreamState>],
+        request_intent: Option<&Intent>,
+    ) -> SelectionExplanation {
+        let participants: Vec<_> = self
+            .candidates(policy_name, upstreams, request_intent)
+            .into_iter()
+            .cloned()
+            .collect();
+        let metrics: Vec<_> = participants
+            .iter()
+            .map(|u| ResonanceMetrics::of(u))
+            .collect();
+        let strategy = self.policies.strategy(policy_name);
+        let selected = strategy
+            .preview_in_route(route, &participants, &metrics, request_intent)
+            .map(|u| u.name.clone());
+        // Составляющие load_resonance — в нормировании латентности политики
+        let latency = self.weights(policy_name).latency;
+
+        let candidates = upstreams
             .iter()
             .map(|upstream| {
-                let resonance = metrics
-                    .iter()
-                    .find(|m| m.upstream_name == upstream.name)
-                    .map(|m| m.load_resonance)
-                    .unwrap_or(0.0);
-
-                let intent_gap = if let Some(req_intent) = request_intent {
-                    upstream.intent_gap(req_intent)
+                let position = participants.iter().position(|u| Arc::ptr_eq(u, upstream));
+                let score =
+                    position.and_then(|i| strategy.score(upstream, &metrics[i], request_intent));
+                let draining = upstream.is_draining();
+                let at_capacity = upstream.at_capacity();
+                let breaker = upstream.breaker_state();
+                let intent_rejected = self.rejects_intent(upstream, request_intent);
+                // Причина исключения — в порядке фильтров `candidates`
+                let excluded = position.is_none().then_some(if draining {
+                    "draining"
+                } else if at_capacity {
+                    "at concurrency cap"
+                } else if let Some((BreakerState::Open, _)) = breaker {
+                    "circuit open"
+                } else if let Some((BreakerState::HalfOpen, Some(0))) = breaker {
+                    "half-open probes in flight"
+                } else if intent_rejected {
+                    "intent rejected by profile"
+                } else if !self.matches_intent(policy_name, upstream, request_intent) {
+                    "intent unmatched (strict)"
                 } else {
-                    0.0
-                };
+                    "slow start"
+                });
+                CandidateScore {
+                    name: upstream.name.clone(),
+                    score,
+                    excluded,
+                    draining,
+                    in_flight: upstream.in_flight(),
+                    at_capacity,
+                    breaker: breaker.map(|(state, _)| state),
+                    probes_remaining: breaker.and_then(|(_, probes)| probes),
+                    intent_rejected,
+                    load: position
+                        .filter(|_| score.is_some() && policy_name != PEAK_EWMA_POLICY)
+                        .map(|i| metrics[i].load_components(&latency)),
+                    slow_start_factor: self
+                        .slow_start
+                        .map(|window| upstream.slow_start_factor(window, Instant::now()))
+                        .unwrap_or(1.0),
+                }
+            })
+            .collect();
 
-                let tempo_spike = metrics
-                    .iter()
-                    .find(|m| m.upstream_name == upstream.name)
-                    .map(|m| m.tempo_spikiness)
-                    .unwrap_or(0.0);
+        SelectionExplanation {
+            policy: policy_name.to_string(),
+            intent: request_intent.map(|i| i.0.clone()),
+            candidates,
+            selected,
+        }
+    }
+
+    /// Веса политики (дефолтные, если политика не зарегистрирована)
+    fn weights(&self, policy_name: &str) -> PolicyWeights {
+        self.policies.get(policy_name).cloned().unwrap_or_default()
+    }
+
+    /// Запрещен ли intent запроса профилем upstream'а
+    fn rejects_intent(&self, upstream: &UpstreamState, request_intent: Option<&Intent>) -> bool {
+        match (&self.memory, request_intent) {
+            (Some(memory), Some(intent)) => !memory.accepts_intent(&upstream.name, intent),
+            _ => false,
+        }
+    }
+
+    /// Подходит ли upstream под intent запроса при `strict_intent` политики
+    /// (без строгого режима, без intent'а запроса или без intent'ов у
+    /// upstream'а — подходит любой)
+    fn matches_intent(
+        &self,
+        policy_name: &str,
+        upstream: &UpstreamState,
+        request_intent: Option<&Intent>,
+    ) -> bool {
+        match request_intent {
+            Some(intent) if self.weights(policy_name).strict_intent => {
+                upstream.intent_gap(intent) < 1.0
+            }
+            _ => true,
+        }
+    }
+
+    /// Upstream'ы, участвующие в выборе: без drain и без запрета intent'а
+    /// в профиле; прогревающийся upstream допускается с вероятностью своей
+    /// доли slow start окна. Если фильтр отсеял всех — участвуют все
+    /// оставшиеся до него; исключение — `strict_intent`, где без
+    /// совпадения intent'а выбирать некого.
+    fn candidates<'a>(
+        &self,
+        policy_name: &str,
+        upstreams: &'a [Arc<UpstreamState>],
+        request_intent: Option<&Intent>,
+    ) -> Vec<&'a Arc<UpstreamState>> {
+        let available: Vec<_> = eligible(upstreams).collect();
+        let accepting: Vec<_> = available
+            .iter()
+            .copied()
+            .filter(|u| !self.rejects_intent(u, request_intent))
+            .collect();
+        let mut available = if accepting.is_empty() {
+            available
+        } else {
+            accepting
+        };
+        available.retain(|u| self.matches_intent(policy_name, u, request_intent));
 
-                let score = weights.w_load * resonance
-                    + weights.w_intent * intent_gap
-                    + weights.w_tempo * tempo_spike;
+        let Some(window) = self.slow_start else {
+            return available;
+        };
 
-                (upstream.clone(), score)
+        let now = Instant::now();
+        let admitted: Vec<_> = available
+            .iter()
+            .copied()
+            .filter(|u| {
+                let factor = u.slow_start_factor(window, now);
+                factor >= 1.0 || fastrand::f64() < factor
             })
             .collect();
 
-        // Сортировка по score (меньше = лучше)
-        scored_upstreams.sort_by(|a, b| {
-            a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal)
-        });
+        if admitted.is_empty() {
+            available
+        } else {
+            admitted
+        }
+    }
+}
+
+/// Объяснение выбора upstream'а
+#[derive(Debug, Clone, Serialize)]
+pub struct SelectionExplanation {
+    pub policy: String,
+    pub intent: Option<String>,
+    pub candidates: Vec<CandidateScore>,
+    pub selected: Option<String>,
+}
+
+/// Кандидат и его score (None — исключен из выбора)
+#[derive(Debug, Clone, Serialize)]
+pub struct CandidateScore {
+    pub name: String,
+    pub score: Option<f64>,
+    /// Почему upstream не участвует в выборе (None — участвует)
+    pub excluded: Option<&'static str>,
+    pub draining: bool,
+    pub in_flight: usize,
+    /// Занят до `max_concurrency`
+    pub at_capacity: bool,
+    /// Состояние circuit breaker'а (None — выключен)
+    pub breaker: Option<BreakerState>,
+    /// Свободные пробы полуоткрытой цепи
+    pub probes_remaining: Option<u32>,
+    /// Intent запроса запрещен профилем upstream'а
+    pub intent_rejected: bool,
+    /// Составляющие load_resonance в score (None — не участвует или
+    /// политика без resonant score)
+    pub load: Option<LoadComponents>,
+    /// Доля трафика в slow start окне (1.0 — полная)
+    pub slow_start_factor: f64,
+}
+
+/// Upstream с минимальным score (первый при равенстве)
+fn best(scored: &[(Arc<UpstreamState>, f64)]) -> Option<&Arc<UpstreamState>> {
+    scored
+        .iter()
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(upstream, _)| upstream)
+}
+
+/// Случайный (пропорционально weight) upstream среди тех, чей score не
+/// дальше `epsilon` от минимального
+fn near_best(scored: &[(Arc<UpstreamState>, f64)], epsilon: f64) -> Option<&Arc<UpstreamState>> {
+    let min = scored
+        .iter()
+        .map(|(_, score)| *score)
+        .fold(f64::INFINITY, f64::min);
+    let near: Vec<_> = scored
+        .iter()
+        .filter(|(_, score)| *score <= min + epsilon)
+        .map(|(upstream, _)| upstream)
+        .collect();
+    if near.is_empty() {
+        return best(scored);
+    }
+    Some(near[weighted_index(&near, None)])
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Число вычисленных resonant score (для сравнения политик в тестах)
+    static SCORED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Resonant score upstream'а (меньше = лучше)
+fn resonant_score(
+    weights: &PolicyWeights,
+    upstream: &UpstreamState,
+    resonance: f64,
+    tempo_spike: f64,
+    request_intent: Option<&Intent>,
+) -> f64 {
+    #[cfg(test)]
+    SCORED.with(|scored| scored.set(scored.get() + 1));
+
+    let intent_gap = if let Some(req_intent) = request_intent {
+        upstream.intent_gap(req_intent)
+    } else {
+        0.0
+    };
+
+    weights.w_load * resonance + weights.w_intent * intent_gap + weights.w_tempo * tempo_spike
+}
 
-        // Возврат лучшего upstream
-        scored_upstreams.first().map(|(u, _)| u.clone())
+/// Случайный индекс кандидата пропорционально weight (`skip` не выбирается)
+fn weighted_index(candidates: &[impl AsRef<UpstreamState>], skip: Option<usize>) -> usize {
+    let weight = |i: usize| {
+        if Some(i) == skip {
+            0
+        } else {
+            u64::from(candidates[i].as_ref().weight.max(1))
+        }
+    };
+    let total: u64 = (0..candidates.len()).map(weight).sum();
+
+    let mut point = fastrand::u64(0..total);
+    for i in 0..candidates.len() {
+        let w = weight(i);
+        if point < w {
+            return i;
+        }
+        point -= w;
     }
+    candidates.len() - 1
+}
+
+/// Upstream'ы, доступные для новых запросов: без drain и не занятые до
+/// `max_concurrency` (проверка при выборе, без резервирования — при гонке
+/// предел может быть ненадолго превышен)
+fn eligible(upstreams: &[Arc<UpstreamState>]) -> impl Iterator<Item = &Arc<UpstreamState>> {
+    upstreams
+        .iter()
+        .filter(|u| !u.is_draining() && !u.at_capacity() && u.breaker_admits())
 }
 
-/// Реестр политик
+/// Peak EWMA: стоимость `ewma_ms * (in_flight + 1)`.
+///
+/// EWMA ограничена снизу 1 мс, чтобы upstream без наблюдений не собирал
+/// все параллельные запросы до первого ответа.
+fn peak_ewma_cost(u: &UpstreamState) -> f64 {
+    u.ewma_latency_ms().max(1.0) * (u.in_flight() + 1) as f64
+}
+
+/// Реестр политик: веса (для score и объяснений) и стратегии выбора
 struct PolicyRegistry {
     policies: std::collections::HashMap<String, PolicyWeights>,
+    strategies: std::collections::HashMap<String, Box<dyn SelectionStrategy>>,
+    /// Стратегия для незарегистрированных имен
+    fallback: ResonantStrategy,
 }
 
 impl PolicyRegistry {
@@ -98,22 +517,86 @@ impl PolicyRegistry {
         let mut policies = std::collections::HashMap::new();
         // Дефолтная политика
         policies.insert("resonant".to_string(), PolicyWeights::default());
-        Self { policies }
+
+        let mut strategies: std::collections::HashMap<String, Box<dyn SelectionStrategy>> =
+            std::collections::HashMap::new();
+        strategies.insert(
+            "resonant".to_string(),
+            Box::new(ResonantStrategy {
+                weights: PolicyWeights::default(),
+            }),
+        );
+        strategies.insert(PEAK_EWMA_POLICY.to_string(), Box::new(PeakEwmaStrategy));
+        strategies.insert(
+            SWRR_POLICY.to_string(),
+            Box::<SmoothWeightedStrategy>::default(),
+        );
+        strategies.insert(
+            P2C_POLICY.to_string(),
+            Box::new(P2cStrategy {
+                weights: PolicyWeights::default(),
+            }),
+        );
+        strategies.insert(
+            SLO_POLICY.to_string(),
+            Box::new(SloStrategy {
+                weights: PolicyWeights::default(),
+            }),
+        );
+
+        Self {
+            policies,
+            strategies,
+            fallback: ResonantStrategy {
+                weights: PolicyWeights::default(),
+            },
+        }
     }
 
+    /// Веса политики; p2c использует их для своих двух кандидатов,
+    /// peak EWMA и swrr весов не имеют. Политика с `p99_budget_ms` — SLO.
     fn register(&mut self, name: String, weights: PolicyWeights) {
+        let weights = weights.normalized();
+        let strategy: Option<Box<dyn SelectionStrategy>> = match name.as_str() {
+            PEAK_EWMA_POLICY | SWRR_POLICY => None,
+            P2C_POLICY => Some(Box::new(P2cStrategy {
+                weights: weights.clone(),
+            })),
+            _ if name == SLO_POLICY || weights.p99_budget_ms.is_some() => {
+                Some(Box::new(SloStrategy {
+                    weights: weights.clone(),
+                }))
+            }
+            _ => Some(Box::new(ResonantStrategy {
+                weights: weights.clone(),
+            })),
+        };
+        if let Some(strategy) = strategy {
+            self.strategies.insert(name.clone(), strategy);
+        }
         self.policies.insert(name, weights);
     }
 
+    fn register_strategy(&mut self, name: String, strategy: Box<dyn SelectionStrategy>) {
+        self.strategies.insert(name, strategy);
+    }
+
     fn get(&self, name: &str) -> Option<&PolicyWeights> {
         self.policies.get(name)
     }
+
+    fn strategy(&self, name: &str) -> &dyn SelectionStrategy {
+        self.strategies
+            .get(name)
+            .map(|strategy| strategy.as_ref())
+            .unwrap_or(&self.fallback)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
+    use crate::upstream::{test_upstreams, UpstreamRegistry};
 
     #[test]
     fn test_align_selection() {
@@ -136,16 +619,884 @@ mod tests {
         upstreams[0].record_request(Duration::from_millis(100), true);
         upstreams[1].record_request(Duration::from_millis(10), true);
 
-        let sense = Sense::new(Arc::new(upstreams.clone()));
+        let sense = Sense::new(Arc::new(UpstreamRegistry::new(upstreams.clone())));
         let align = Align::new(sense);
 
         let upstreams_arc: Vec<_> = upstreams.into_iter().map(Arc::new).collect();
-        let selected = align.select_upstream(
-            "resonant",
-            &upstreams_arc,
-            Some(&Intent::new("realtime")),
-        );
+        let selected =
+            align.select_upstream("resonant", &upstreams_arc, Some(&Intent::new("realtime")));
 
         assert!(selected.is_some());
     }
+
+    #[test]
+    fn test_peak_ewma_reacts_faster_than_p95() {
+        let upstreams: Vec<_> = ["slow-now", "steady"]
+            .iter()
+            .map(|name| UpstreamState::new(name.to_string(), format!("http://{}", name), vec![], 1))
+            .collect();
+
+        // Одинаковая история быстрых ответов
+        for upstream in &upstreams {
+            for _ in 0..100 {
+                upstream.record_request(Duration::from_millis(10), true);
+            }
+        }
+        // Короткая серия медленных ответов у первого upstream'а
+        for _ in 0..5 {
+            upstreams[0].record_request(Duration::from_millis(500), true);
+        }
+
+        let sense = Sense::new(Arc::new(UpstreamRegistry::new(upstreams.clone())));
+        let align = Align::new(sense);
+        let upstreams_arc: Vec<_> = upstreams.into_iter().map(Arc::new).collect();
+
+        // p95 еще не заметил всплеск — resonant оставляет первый upstream
+        let resonant = align
+            .select_upstream("resonant", &upstreams_arc, None)
+            .unwrap();
+        assert_eq!(resonant.name, "slow-now");
+
+        // EWMA реагирует сразу
+        let ewma = align
+            .select_upstream(PEAK_EWMA_POLICY, &upstreams_arc, None)
+            .unwrap();
+        assert_eq!(ewma.name, "steady");
+    }
+
+    #[test]
+    fn test_drained_upstream_not_selected() {
+        let upstreams = test_upstreams(&["drained", "active"]);
+        let align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+
+        upstreams[0].set_draining(true);
+        for policy in ["resonant", PEAK_EWMA_POLICY] {
+            for _ in 0..10 {
+                let selected = align.select_upstream(policy, &upstreams, None).unwrap();
+                assert_eq!(selected.name, "active");
+            }
+        }
+
+        // Все upstream'ы в drain — выбирать некого
+        upstreams[1].set_draining(true);
+        assert!(align
+            .select_upstream("resonant", &upstreams, None)
+            .is_none());
+
+        upstreams[0].set_draining(false);
+        let selected = align.select_upstream("resonant", &upstreams, None).unwrap();
+        assert_eq!(selected.name, "drained");
+    }
+
+    #[test]
+    fn test_peak_ewma_penalizes_in_flight() {
+        let upstreams = test_upstreams(&["busy", "idle"]);
+        for upstream in &upstreams {
+            upstream.record_request(Duration::from_millis(10), true);
+        }
+
+        let align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        let _guards: Vec<_> = (0..3).map(|_| upstreams[0].begin_request()).collect();
+
+        let selected = align
+            .select_upstream(PEAK_EWMA_POLICY, &upstreams, None)
+            .unwrap();
+        assert_eq!(selected.name, "idle");
+    }
+
+    #[test]
+    fn test_slow_start_share_grows() {
+        let upstreams = test_upstreams(&["fresh", "warm"]);
+        let window = Duration::from_secs(60);
+        let mut align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        align.set_slow_start(Some(window));
+
+        let now = Instant::now();
+        upstreams[1].mark_eligible_at(now - window * 2);
+
+        // Без истории стоимость равна — без slow start "fresh" получал бы все
+        let share_at = |elapsed: Duration| {
+            upstreams[0].mark_eligible_at(now - elapsed);
+            let picks = (0..2000)
+                .filter(|_| {
+                    align
+                        .select_upstream(PEAK_EWMA_POLICY, &upstreams, None)
+                        .unwrap()
+                        .name
+                        == "fresh"
+                })
+                .count();
+            picks as f64 / 2000.0
+        };
+
+        let early = share_at(window / 10);
+        let middle = share_at(window / 2);
+        let late = share_at(window * 9 / 10);
+        let done = share_at(window);
+
+        assert!(
+            early < middle && middle < late,
+            "{} {} {}",
+            early,
+            middle,
+            late
+        );
+        assert!((middle - 0.5).abs() < 0.1, "middle share {}", middle);
+        assert_eq!(done, 1.0);
+
+        // Выход из drain перезапускает slow start
+        upstreams[0].set_draining(true);
+        upstreams[0].set_draining(false);
+        assert!(upstreams[0].slow_start_factor(window, Instant::now()) < 0.1);
+    }
+
+    #[test]
+    fn test_profile_forbidden_intent_avoided() {
+        let upstreams = test_upstreams(&["flaky", "stable"]);
+        let memory = Memory::new(
+            toml::from_str("[server]\nbind = \"127.0.0.1:0\"\n[routes]\nrule = []").unwrap(),
+        );
+        let mut align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        align.set_memory(memory.clone());
+
+        let realtime = Intent::new("realtime");
+        let selected = align
+            .select_upstream(PEAK_EWMA_POLICY, &upstreams, Some(&realtime))
+            .unwrap();
+        assert_eq!(selected.name, "flaky");
+
+        for _ in 0..crate::memory::profile::FORBID_AFTER_FAILURES {
+            memory.observe("flaky", &realtime, 10.0, 50.0, false);
+        }
+        let selected = align
+            .select_upstream(PEAK_EWMA_POLICY, &upstreams, Some(&realtime))
+            .unwrap();
+        assert_eq!(selected.name, "stable");
+
+        // Другой intent по-прежнему идет на "flaky"
+        let batch = Intent::new("batch");
+        let selected = align
+            .select_upstream(PEAK_EWMA_POLICY, &upstreams, Some(&batch))
+            .unwrap();
+        assert_eq!(selected.name, "flaky");
+
+        // Если intent запрещен везде — выбор не блокируется
+        upstreams[1].set_draining(true);
+        assert!(align
+            .select_upstream(PEAK_EWMA_POLICY, &upstreams, Some(&realtime))
+            .is_some());
+    }
+
+    #[test]
+    fn test_strict_intent_excludes_unmatched_upstreams() {
+        let upstreams: Vec<_> = [("batch-1", "batch"), ("batch-2", "batch")]
+            .iter()
+            .map(|(name, intent)| {
+                Arc::new(UpstreamState::new(
+                    name.to_string(),
+                    format!("http://{}", name),
+                    vec![Intent::new(*intent)],
+                    1,
+                ))
+            })
+            .collect();
+        let mut align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        let strict = PolicyWeights::default().with_strict_intent(true);
+        align.register_policy("strict".to_string(), strict);
+        let realtime = Intent::new("realtime");
+
+        // По умолчанию неподходящий upstream все равно выбирается
+        assert!(align
+            .select_upstream("resonant", &upstreams, Some(&realtime))
+            .is_some());
+
+        assert!(align
+            .select_upstream("strict", &upstreams, Some(&realtime))
+            .is_none());
+        let explanation = align.explain_selection("strict", &upstreams, Some(&realtime));
+        assert!(explanation.selected.is_none());
+        assert!(explanation
+            .candidates
+            .iter()
+            .all(|c| c.excluded == Some("intent unmatched (strict)")));
+
+        // Совпавший intent и запрос без intent'а выбираются как обычно
+        let batch = Intent::new("batch");
+        assert!(align
+            .select_upstream("strict", &upstreams, Some(&batch))
+            .is_some());
+        assert!(align.select_upstream("strict", &upstreams, None).is_some());
+    }
+
+    #[test]
+    fn test_fallback_route_used_when_primaries_unavailable() {
+        let config: crate::config::DaoConfig = toml::from_str(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "primary"
+            policy = "resonant"
+            fallback_route = "secondary"
+            match = { path_prefix = "/" }
+            upstreams = [{ name = "p1", url = "http://p1" }, { name = "p2", url = "http://p2" }]
+
+            [[routes.rule]]
+            name = "secondary"
+            policy = "resonant"
+            match = { path_prefix = "/backup/" }
+            upstreams = [{ name = "s1", url = "http://s1" }]
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let registry = Arc::new(UpstreamRegistry::from_config(&config));
+        let align = Align::new(Sense::new(registry.clone()));
+        let primary = &config.routes.rule[0];
+        let chain = config.routes.fallback_chain(primary);
+
+        let (upstream, route) = align
+            .select_with_fallback(&chain, None, &registry, None)
+            .unwrap();
+        assert_eq!(
+            (upstream.name.as_str(), route.name.as_str()),
+            ("p1", "primary")
+        );
+
+        // Все основные upstream'ы недоступны — выбор уходит в fallback
+        for name in ["p1", "p2"] {
+            registry.get(name).unwrap().set_draining(true);
+        }
+        let (upstream, route) = align
+            .select_with_fallback(&chain, None, &registry, None)
+            .unwrap();
+        assert_eq!(
+            (upstream.name.as_str(), route.name.as_str()),
+            ("s1", "secondary")
+        );
+
+        // Кандидаты основного маршрута сужены (плечо A/B): p1 доступен
+        registry.get("p1").unwrap().set_draining(false);
+        let arm = registry.route_upstreams(primary);
+        let (upstream, route) = align
+            .select_with_fallback(&chain, Some(&arm[..1]), &registry, None)
+            .unwrap();
+        assert_eq!(
+            (upstream.name.as_str(), route.name.as_str()),
+            ("p1", "primary")
+        );
+        let (upstream, route) = align
+            .select_with_fallback(&chain, Some(&arm[1..]), &registry, None)
+            .unwrap();
+        assert_eq!(
+            (upstream.name.as_str(), route.name.as_str()),
+            ("s1", "secondary")
+        );
+
+        registry.get("p1").unwrap().set_draining(true);
+        registry.get("s1").unwrap().set_draining(true);
+        assert!(align
+            .select_with_fallback(&chain, None, &registry, None)
+            .is_none());
+    }
+
+    #[test]
+    fn test_p2c_scores_two_candidates() {
+        // 100 upstream'ов: первая половина медленная
+        let upstreams: Vec<_> = (0..100)
+            .map(|i| {
+                let u = UpstreamState::new(format!("u{}", i), format!("http://u{}", i), vec![], 1);
+                let latency = if i < 50 { 900 } else { 10 };
+                for _ in 0..20 {
+                    u.record_request(Duration::from_millis(latency), true);
+                }
+                u
+            })
+            .collect();
+        let sense = Sense::new(Arc::new(UpstreamRegistry::new(upstreams.clone())));
+        let align = Align::new(sense);
+        let upstreams: Vec<_> = upstreams.into_iter().map(Arc::new).collect();
+
+        let scored = |policy: &str| {
+            SCORED.with(|scored| scored.set(0));
+            align.select_upstream(policy, &upstreams, None).unwrap();
+            SCORED.with(|scored| scored.get())
+        };
+        assert_eq!(scored("resonant"), 100);
+        assert_eq!(scored(P2C_POLICY), 2);
+
+        // Медленный выбирается, только если оба кандидата медленные (~1/4)
+        let slow = (0..1000)
+            .filter(|_| {
+                let selected = align.select_upstream(P2C_POLICY, &upstreams, None).unwrap();
+                selected.name[1..].parse::<usize>().unwrap() < 50
+            })
+            .count();
+        assert!(slow < 400, "slow upstream selected {} times", slow);
+
+        // Меньше двух кандидатов — полный перебор
+        for upstream in &upstreams[1..] {
+            upstream.set_draining(true);
+        }
+        let selected = align.select_upstream(P2C_POLICY, &upstreams, None).unwrap();
+        assert_eq!(selected.name, "u0");
+    }
+
+    #[test]
+    fn test_epsilon_spreads_equal_scores() {
+        let upstreams: Vec<_> = (0..3)
+            .map(|i| UpstreamState::new(format!("u{}", i), format!("http://u{}", i), vec![], 1))
+            .collect();
+        let sense = Sense::new(Arc::new(UpstreamRegistry::new(upstreams.clone())));
+        let mut align = Align::new(sense);
+        align.register_policy(
+            "spread".to_string(),
+            PolicyWeights::default().with_epsilon(0.05),
+        );
+        let upstreams: Vec<_> = upstreams.into_iter().map(Arc::new).collect();
+
+        let mut counts = std::collections::HashMap::new();
+        for _ in 0..3000 {
+            let selected = align.select_upstream("spread", &upstreams, None).unwrap();
+            *counts.entry(selected.name.clone()).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 3);
+        for (name, count) in &counts {
+            assert!(
+                (800..1200).contains(count),
+                "{} selected {} times",
+                name,
+                count
+            );
+        }
+
+        // Без epsilon — всегда первый из равных
+        for _ in 0..10 {
+            let selected = align.select_upstream("resonant", &upstreams, None).unwrap();
+            assert_eq!(selected.name, "u0");
+        }
+    }
+
+    #[test]
+    fn test_normalized_weights_match_fractional() {
+        let upstreams: Vec<_> = [
+            ("batch", "batch"),
+            ("general", "realtime"),
+            ("chat", "realtime.chat"),
+        ]
+        .iter()
+        .map(|(name, intent)| {
+            Arc::new(UpstreamState::new(
+                name.to_string(),
+                format!("http://{}", name),
+                vec![Intent::new(*intent)],
+                1,
+            ))
+        })
+        .collect();
+        let mut align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        align.register_policy("fractional".to_string(), PolicyWeights::new(0.6, 0.3, 0.1));
+        align.register_policy(
+            "normalized".to_string(),
+            PolicyWeights::new(6.0, 3.0, 1.0).with_normalize(true),
+        );
+        align.register_policy("raw".to_string(), PolicyWeights::new(6.0, 3.0, 1.0));
+
+        for intent in ["batch", "realtime", "realtime.chat", "unknown"] {
+            let intent = Intent::new(intent);
+            let scores = |policy| -> Vec<f64> {
+                align
+                    .explain_selection(policy, &upstreams, Some(&intent))
+                    .candidates
+                    .into_iter()
+                    .map(|c| c.score.unwrap())
+                    .collect()
+            };
+            let fractional = scores("fractional");
+            let normalized = scores("normalized");
+            let raw = scores("raw");
+            for i in 0..upstreams.len() {
+                assert!(
+                    (fractional[i] - normalized[i]).abs() < 1e-12,
+                    "{:?}",
+                    normalized
+                );
+                // Без нормирования score в 10 раз больше
+                assert!((raw[i] - 10.0 * fractional[i]).abs() < 1e-9, "{:?}", raw);
+            }
+
+            let selected = |policy| {
+                let upstream = align.select_upstream(policy, &upstreams, Some(&intent));
+                upstream.unwrap().name.clone()
+            };
+            assert_eq!(selected("normalized"), selected("fractional"));
+        }
+    }
+
+    #[test]
+    fn test_explain_exposes_latency_normalization() {
+        let upstreams: Vec<_> = [("fast", 50), ("slow", 500)]
+            .iter()
+            .map(|(name, latency_ms)| {
+                let upstream =
+                    UpstreamState::new(name.to_string(), format!("http://{}", name), vec![], 1);
+                upstream.record_request(Duration::from_millis(*latency_ms), true);
+                upstream
+            })
+            .collect();
+        let sense = Sense::new(Arc::new(UpstreamRegistry::new(upstreams.clone())));
+        let mut align = Align::new(sense);
+        align.register_policy(
+            "tight".to_string(),
+            PolicyWeights::default().with_latency(crate::sense::LatencyNormalization {
+                reference_ms: 50.0,
+                cap: 100.0,
+                ..Default::default()
+            }),
+        );
+        let upstreams: Vec<_> = upstreams.into_iter().map(Arc::new).collect();
+
+        let load = |policy| -> Vec<LoadComponents> {
+            align
+                .explain_selection(policy, &upstreams, None)
+                .candidates
+                .into_iter()
+                .map(|c| c.load.unwrap())
+                .collect()
+        };
+        let (default, tight) = (load("resonant"), load("tight"));
+        assert!(default[0].latency < default[1].latency, "{:?}", default);
+        // Опорная латентность вдвое меньше — составляющая вдвое больше
+        assert!((tight[0].latency - 2.0 * default[0].latency).abs() < 1e-6);
+        assert!((tight[1].load_resonance - 2.0 * default[1].load_resonance).abs() < 1e-6);
+
+        let explanation = align.explain_selection("tight", &upstreams, None);
+        assert_eq!(explanation.selected.as_deref(), Some("fast"));
+    }
+
+    #[test]
+    fn test_hierarchical_intent_graded_score() {
+        let upstreams: Vec<_> = [
+            ("batch", "batch"),
+            ("general", "realtime"),
+            ("chat", "realtime.chat"),
+        ]
+        .iter()
+        .map(|(name, intent)| {
+            Arc::new(UpstreamState::new(
+                name.to_string(),
+                format!("http://{}", name),
+                vec![Intent::new(*intent)],
+                1,
+            ))
+        })
+        .collect();
+        let align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        let request = Intent::new("realtime.chat");
+
+        let scores: std::collections::HashMap<_, _> = align
+            .explain_selection("resonant", &upstreams, Some(&request))
+            .candidates
+            .into_iter()
+            .map(|c| (c.name, c.score.unwrap()))
+            .collect();
+        assert!(scores["chat"] < scores["general"], "{:?}", scores);
+        assert!(scores["general"] < scores["batch"], "{:?}", scores);
+
+        let selected = align
+            .select_upstream("resonant", &upstreams, Some(&request))
+            .unwrap();
+        assert_eq!(selected.name, "chat");
+
+        // Без точного — родительский intent лучше чужого
+        let selected = align
+            .select_upstream("resonant", &upstreams[..2], Some(&request))
+            .unwrap();
+        assert_eq!(selected.name, "general");
+    }
+
+    #[test]
+    fn test_seeded_selection_is_reproducible() {
+        let upstreams: Vec<_> = (0..10)
+            .map(|i| {
+                Arc::new(UpstreamState::new(
+                    format!("u{}", i),
+                    format!("http://u{}", i),
+                    vec![],
+                    1,
+                ))
+            })
+            .collect();
+        let mut align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        align.register_policy(
+            "spread".to_string(),
+            PolicyWeights::default().with_epsilon(0.05),
+        );
+
+        // Случайность выбора — потоковый генератор fastrand; с одним seed
+        // последовательность решений повторяется
+        let picks = |seed: u64| {
+            fastrand::seed(seed);
+            (0..20)
+                .flat_map(|_| {
+                    [
+                        align
+                            .select_upstream("spread", &upstreams, None)
+                            .unwrap()
+                            .name
+                            .clone(),
+                        align
+                            .select_upstream(P2C_POLICY, &upstreams, None)
+                            .unwrap()
+                            .name
+                            .clone(),
+                    ]
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = picks(42);
+        assert_eq!(first, picks(42));
+        assert_ne!(first, picks(43));
+    }
+
+    #[test]
+    fn test_smooth_weighted_interleaves() {
+        let upstreams: Vec<_> = [("a", 5), ("b", 1), ("c", 1)]
+            .iter()
+            .map(|(name, weight)| {
+                Arc::new(UpstreamState::new(
+                    name.to_string(),
+                    format!("http://{}", name),
+                    vec![],
+                    *weight,
+                ))
+            })
+            .collect();
+        let align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+
+        let sequence: Vec<_> = (0..7)
+            .map(|_| {
+                align
+                    .select_upstream(SWRR_POLICY, &upstreams, None)
+                    .unwrap()
+                    .name
+                    .clone()
+            })
+            .collect();
+        assert_eq!(sequence, ["a", "a", "b", "a", "c", "a", "a"]);
+
+        // Следующий цикл повторяет тот же порядок
+        let sequence: Vec<_> = (0..7)
+            .map(|_| {
+                align
+                    .select_upstream(SWRR_POLICY, &upstreams, None)
+                    .unwrap()
+                    .name
+                    .clone()
+            })
+            .collect();
+        assert_eq!(sequence, ["a", "a", "b", "a", "c", "a", "a"]);
+
+        // Маршруты с общими upstream'ами чередуют их независимо
+        let route = |name: &str| -> RouteRule {
+            toml::from_str(&format!(
+                r#"
+                name = "{}"
+                policy = "swrr"
+                match = {{ path_prefix = "/" }}
+                upstreams = []
+                "#,
+                name
+            ))
+            .unwrap()
+        };
+        let (api, batch) = (route("api"), route("batch"));
+        let mut sequence = Vec::new();
+        for _ in 0..7 {
+            for route in [&api, &batch] {
+                let selected = align
+                    .select_route_upstream(route, &upstreams, None)
+                    .unwrap();
+                sequence.push(selected.name.clone());
+            }
+        }
+        let api: Vec<_> = sequence.iter().step_by(2).collect();
+        let batch: Vec<_> = sequence.iter().skip(1).step_by(2).collect();
+        assert_eq!(api, ["a", "a", "b", "a", "c", "a", "a"]);
+        assert_eq!(batch, api);
+    }
+
+    #[test]
+    fn test_open_circuit_excluded_and_explained() {
+        let breaker = crate::config::CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_secs: 60,
+            half_open_probes: 2,
+        };
+        let upstreams: Vec<_> = (0..2)
+            .map(|i| {
+                let u = UpstreamState::new(format!("u{}", i), format!("http://u{}", i), vec![], 1);
+                Arc::new(u.with_circuit_breaker(&breaker))
+            })
+            .collect();
+        let align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+
+        upstreams[0].record_breaker(false);
+        for _ in 0..10 {
+            let selected = align.select_upstream("resonant", &upstreams, None).unwrap();
+            assert_eq!(selected.name, "u1");
+        }
+        assert!(upstreams[0].try_begin_request().is_none());
+
+        let explanation = align.explain_selection("resonant", &upstreams, None);
+        let breakers: Vec<_> = explanation
+            .candidates
+            .iter()
+            .map(|c| (c.excluded, c.breaker, c.probes_remaining))
+            .collect();
+        assert_eq!(
+            breakers,
+            [
+                (Some("circuit open"), Some(BreakerState::Open), None),
+                (None, Some(BreakerState::Closed), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_probe_race_reselects() {
+        let breaker = crate::config::CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_secs: 0,
+            half_open_probes: 1,
+        };
+        let upstreams: Vec<_> = (0..2)
+            .map(|i| {
+                let u = UpstreamState::new(format!("u{}", i), format!("http://u{}", i), vec![], 1);
+                Arc::new(u.with_circuit_breaker(&breaker))
+            })
+            .collect();
+        let align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        let route: RouteRule = toml::from_str(
+            r#"
+            name = "api"
+            policy = "resonant"
+            match = { path_prefix = "/" }
+            upstreams = []
+            "#,
+        )
+        .unwrap();
+
+        // u0 полуоткрыт, его единственную пробу занял другой запрос уже
+        // после того, как этот выбрал u0
+        upstreams[0].record_breaker(false);
+        let probe = upstreams[0].try_begin_request().unwrap();
+        let (admitted, _in_flight) = align
+            .admit(&route, upstreams[0].clone(), upstreams.clone(), None, true)
+            .unwrap();
+        assert_eq!(admitted.name, "u1");
+
+        // Закрепленный upstream не подменяется
+        assert!(align
+            .admit(&route, upstreams[0].clone(), upstreams.clone(), None, false)
+            .is_none());
+        drop(probe);
+    }
+
+    #[test]
+    fn test_saturated_upstream_excluded() {
+        let upstreams: Vec<_> = (0..2)
+            .map(|i| {
+                let mut u =
+                    UpstreamState::new(format!("u{}", i), format!("http://u{}", i), vec![], 1);
+                u.max_concurrency = Some(1);
+                Arc::new(u)
+            })
+            .collect();
+        let align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+
+        let first = upstreams[0].begin_request();
+        for _ in 0..10 {
+            let selected = align.select_upstream("resonant", &upstreams, None).unwrap();
+            assert_eq!(selected.name, "u1");
+        }
+        let second = upstreams[1].begin_request();
+        assert!(align
+            .select_upstream("resonant", &upstreams, None)
+            .is_none());
+
+        // В разборе занятый upstream не пропадает, а исключен с причиной
+        upstreams[0].set_draining(true);
+        let explanation = align.explain_selection("resonant", &upstreams, None);
+        let excluded: Vec<_> = explanation.candidates.iter().map(|c| c.excluded).collect();
+        assert_eq!(excluded, [Some("draining"), Some("at concurrency cap")]);
+        upstreams[0].set_draining(false);
+
+        drop(first);
+        let selected = align.select_upstream("resonant", &upstreams, None).unwrap();
+        assert_eq!(selected.name, "u0");
+        let explanation = align.explain_selection("resonant", &upstreams, None);
+        assert_eq!(explanation.candidates[0].excluded, None);
+        assert!(explanation.candidates[0].score.is_some());
+        drop(second);
+    }
+
+    #[test]
+    fn test_slo_prefers_upstream_within_budget() {
+        let upstreams = test_upstreams(&["spiky", "steady"]);
+        // spiky: быстрый p95, но хвост p99 за бюджетом; steady: ровные 150 мс
+        for i in 0..100 {
+            let tail = if i < 3 { 1000 } else { 10 };
+            upstreams[0].record_request(Duration::from_millis(tail), true);
+            upstreams[1].record_request(Duration::from_millis(150), true);
+        }
+        let mut align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        let recorder = metrics_util::debugging::DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let violations = || {
+            snapshotter
+                .snapshot()
+                .into_vec()
+                .into_iter()
+                .filter(|(key, ..)| key.key().name() == "dao_slo_violations_total")
+                .map(|(key, .., value)| {
+                    let upstream = key.key().labels().next().map(|l| l.value().to_string());
+                    (upstream, value)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Resonant смотрит на p95 и выбирает spiky
+        let resonant = align.select_upstream("resonant", &upstreams, None).unwrap();
+        assert_eq!(resonant.name, "spiky");
+        metrics::with_local_recorder(&recorder, || {
+            for _ in 0..10 {
+                let selected = align.select_upstream(SLO_POLICY, &upstreams, None).unwrap();
+                assert_eq!(selected.name, "steady");
+            }
+        });
+        // Выбор в бюджете — не нарушение, даже если другие за ним
+        assert!(violations().is_empty());
+
+        // В бюджет не укладывается никто — наименее нарушающий
+        align.register_policy(
+            "strict".to_string(),
+            PolicyWeights::default().with_p99_budget(Some(100.0)),
+        );
+        let selected = metrics::with_local_recorder(&recorder, || {
+            align.select_upstream("strict", &upstreams, None).unwrap()
+        });
+        assert_eq!(selected.name, "steady");
+        assert_eq!(
+            violations(),
+            vec![(
+                Some("steady".to_string()),
+                metrics_util::debugging::DebugValue::Counter(1)
+            )]
+        );
+    }
+
+    struct AlwaysFirst;
+
+    impl SelectionStrategy for AlwaysFirst {
+        fn select(
+            &self,
+            candidates: &[Arc<UpstreamState>],
+            metrics: &[ResonanceMetrics],
+            _request_intent: Option<&Intent>,
+        ) -> Option<Arc<UpstreamState>> {
+            assert_eq!(candidates.len(), metrics.len());
+            candidates.first().cloned()
+        }
+    }
+
+    #[test]
+    fn test_custom_strategy_selects() {
+        let upstreams = test_upstreams(&["first", "second"]);
+        // "first" перегружен — resonant выбрал бы "second"
+        let _guards: Vec<_> = (0..5).map(|_| upstreams[0].begin_request()).collect();
+        upstreams[0].record_request(Duration::from_millis(500), true);
+        upstreams[1].record_request(Duration::from_millis(5), true);
+
+        let mut align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        align.register_strategy("first".to_string(), Box::new(AlwaysFirst));
+
+        for _ in 0..10 {
+            let selected = align.select_upstream("first", &upstreams, None).unwrap();
+            assert_eq!(selected.name, "first");
+        }
+        let selected = align
+            .select_upstream(PEAK_EWMA_POLICY, &upstreams, None)
+            .unwrap();
+        assert_eq!(selected.name, "second");
+
+        // Стратегия видит только допустимых кандидатов
+        upstreams[0].set_draining(true);
+        let selected = align.select_upstream("first", &upstreams, None).unwrap();
+        assert_eq!(selected.name, "second");
+    }
+
+    #[test]
+    fn test_explain_reports_strategy_choice() {
+        let upstreams = test_upstreams(&["first", "second"]);
+        // "first" перегружен — resonant score за "second"
+        let _guards: Vec<_> = (0..5).map(|_| upstreams[0].begin_request()).collect();
+        upstreams[0].record_request(Duration::from_millis(500), true);
+        upstreams[1].record_request(Duration::from_millis(5), true);
+
+        let mut align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+        align.register_strategy("first".to_string(), Box::new(AlwaysFirst));
+
+        let explanation = align.explain_selection("first", &upstreams, None);
+        let selected = align.select_upstream("first", &upstreams, None).unwrap();
+        assert_eq!(
+            explanation.selected.as_deref(),
+            Some(selected.name.as_str())
+        );
+        // Стратегия без score: оба участвуют, score не известен
+        assert!(explanation
+            .candidates
+            .iter()
+            .all(|c| c.excluded.is_none() && c.score.is_none()));
+    }
+
+    #[test]
+    fn test_explain_previews_swrr_without_advancing() {
+        let route: RouteRule = toml::from_str(
+            r#"
+            name = "api"
+            match = { path_prefix = "/" }
+            upstreams = []
+            policy = "swrr"
+            "#,
+        )
+        .unwrap();
+        let upstreams: Vec<_> = [("a", 2), ("b", 1)]
+            .iter()
+            .map(|(name, weight)| {
+                Arc::new(UpstreamState::new(
+                    name.to_string(),
+                    format!("http://{}", name),
+                    vec![],
+                    *weight,
+                ))
+            })
+            .collect();
+        let align = Align::new(Sense::new(Arc::new(UpstreamRegistry::new(vec![]))));
+
+        for _ in 0..6 {
+            let explanation = align.explain_route_selection(&route, &upstreams, None);
+            let again = align.explain_route_selection(&route, &upstreams, None);
+            assert_eq!(explanation.selected, again.selected);
+            let selected = align
+                .select_route_upstream(&route, &upstreams, None)
+                .unwrap();
+            assert_eq!(
+                explanation.selected.as_deref(),
+                Some(selected.name.as_str())
+            );
+        }
+    }
 }
@@ -0,0 +1,111 @@
+//! Явный выбор upstream'а заголовком (тестирование, отладка)
+
+use crate::upstream::UpstreamState;
+use crate::{DaoError, Result};
+use http::{HeaderName, Request};
+use std::sync::Arc;
+
+/// Заголовок запроса с именем upstream'а
+pub const UPSTREAM_OVERRIDE_HEADER: HeaderName = HeaderName::from_static("x-dao-upstream");
+
+/// Заголовок ответа: upstream выбран заголовком, а не политикой
+pub const UPSTREAM_OVERRIDDEN_HEADER: HeaderName =
+    HeaderName::from_static("x-dao-upstream-override");
+
+/// Выбор upstream'а по `X-DAO-Upstream` в обход Align.
+///
+/// Выключен по умолчанию (`server.allow_upstream_override`): заголовок
+/// позволяет клиенту обойти политику и drain.
+pub struct UpstreamOverride {
+    enabled: bool,
+}
+
+impl UpstreamOverride {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    /// Upstream из заголовка среди upstream'ов маршрута.
+    ///
+    /// `None` — заголовка нет или функция выключена; `InvalidRequest` —
+    /// у маршрута нет такого upstream'а.
+    pub fn resolve<B>(
+        &self,
+        req: &Request<B>,
+        upstreams: &[Arc<UpstreamState>],
+    ) -> Result<Option<Arc<UpstreamState>>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let Some(value) = req.headers().get(UPSTREAM_OVERRIDE_HEADER) else {
+            return Ok(None);
+        };
+
+        let name = value
+            .to_str()
+            .map(str::trim)
+            .map_err(|_| DaoError::InvalidRequest("invalid upstream override".to_string()))?;
+        upstreams
+            .iter()
+            .find(|u| u.name == name)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| DaoError::InvalidRequest(format!("unknown upstream: {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream::test_upstreams;
+
+    fn upstreams() -> Vec<Arc<UpstreamState>> {
+        test_upstreams(&["stable", "canary-v2"])
+    }
+
+    fn request(upstream: Option<&str>) -> Request<()> {
+        let mut builder = Request::get("/v1/items");
+        if let Some(upstream) = upstream {
+            builder = builder.header(UPSTREAM_OVERRIDE_HEADER, upstream);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_override_valid() {
+        let upstreams = upstreams();
+        let pinned = UpstreamOverride::new(true)
+            .resolve(&request(Some("canary-v2")), &upstreams)
+            .unwrap()
+            .unwrap();
+        assert_eq!(pinned.name, "canary-v2");
+
+        // Без заголовка — выбор остается за политикой
+        assert!(UpstreamOverride::new(true)
+            .resolve(&request(None), &upstreams)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_override_invalid_name() {
+        let err = UpstreamOverride::new(true)
+            .resolve(&request(Some("other-route-backend")), &upstreams())
+            .unwrap_err();
+        assert!(matches!(err, DaoError::InvalidRequest(_)));
+    }
+
+    #[test]
+    fn test_override_disabled() {
+        let upstreams = upstreams();
+        let disabled = UpstreamOverride::new(false);
+        assert!(disabled
+            .resolve(&request(Some("canary-v2")), &upstreams)
+            .unwrap()
+            .is_none());
+        assert!(disabled
+            .resolve(&request(Some("unknown")), &upstreams)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,5 +1,7 @@
 //! Policy definitions
 
+use crate::sense::LatencyNormalization;
+
 /// Политика маршрутизации
 #[derive(Debug, Clone)]
 pub enum Policy {
@@ -13,6 +15,21 @@ pub enum Policy {
     LeastConnections,
 }
 
+/// Имя встроенной peak EWMA политики
+pub const PEAK_EWMA_POLICY: &str = "peak_ewma";
+
+/// Имя встроенной power-of-two-choices политики
+pub const P2C_POLICY: &str = "p2c";
+
+/// Имя встроенной smooth weighted round-robin политики
+pub const SWRR_POLICY: &str = "swrr";
+
+/// Имя встроенной SLO политики
+pub const SLO_POLICY: &str = "slo";
+
+/// Бюджет p99 встроенной SLO политики (мс)
+pub const DEFAULT_P99_BUDGET_MS: f64 = 200.0;
+
 /// Веса для resonant политики
 #[derive(Debug, Clone)]
 pub struct PolicyWeights {
@@ -22,6 +39,20 @@ pub struct PolicyWeights {
     pub w_intent: f64,
     /// Вес tempo spikiness (вариативность RPS)
     pub w_tempo: f64,
+    /// Upstream'ы со score не дальше epsilon от лучшего равноправны:
+    /// выбор среди них случаен пропорционально weight (0 — всегда лучший)
+    pub epsilon: f64,
+    /// Бюджет p99 латентности (мс): политика предпочитает upstream'ы,
+    /// укладывающиеся в него (см. [`SLO_POLICY`])
+    pub p99_budget_ms: Option<f64>,
+    /// Upstream'ы без совпадения с intent'ом запроса не выбираются: если
+    /// подходящих нет — выбора нет (fallback маршрут или 503)
+    pub strict_intent: bool,
+    /// Масштабирование `w_load`/`w_intent`/`w_tempo` к сумме 1.0 при
+    /// регистрации политики (важны пропорции, а не абсолютные значения)
+    pub normalize: bool,
+    /// Нормирование p95 латентности в load_resonance
+    pub latency: LatencyNormalization,
 }
 
 impl Default for PolicyWeights {
@@ -30,6 +61,11 @@ impl Default for PolicyWeights {
             w_load: 0.6,
             w_intent: 0.3,
             w_tempo: 0.1,
+            epsilon: 0.0,
+            p99_budget_ms: None,
+            strict_intent: false,
+            normalize: false,
+            latency: LatencyNormalization::default(),
         }
     }
 }
@@ -40,11 +76,65 @@ impl PolicyWeights {
             w_load,
             w_intent,
             w_tempo,
+            epsilon: 0.0,
+            p99_budget_ms: None,
+            strict_intent: false,
+            normalize: false,
+            latency: LatencyNormalization::default(),
+        }
+    }
+
+    /// Порог равенства score для случайного выбора
+    pub fn with_epsilon(mut self, epsilon: f64) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// Бюджет p99 для SLO политики
+    pub fn with_p99_budget(mut self, p99_budget_ms: Option<f64>) -> Self {
+        self.p99_budget_ms = p99_budget_ms;
+        self
+    }
+
+    /// Строгое совпадение intent'а
+    pub fn with_strict_intent(mut self, strict_intent: bool) -> Self {
+        self.strict_intent = strict_intent;
+        self
+    }
+
+    /// Нормирование весов к сумме 1.0
+    pub fn with_normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Нормирование латентности
+    pub fn with_latency(mut self, latency: LatencyNormalization) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Веса для расчета score: при `normalize` — доли суммы (нулевая
+    /// сумма оставляет веса как есть)
+    pub fn normalized(&self) -> Self {
+        let sum = self.w_load + self.w_intent + self.w_tempo;
+        if !(self.normalize && sum > 0.0 && sum.is_finite()) {
+            return self.clone();
+        }
+        Self {
+            w_load: self.w_load / sum,
+            w_intent: self.w_intent / sum,
+            w_tempo: self.w_tempo / sum,
+            ..self.clone()
         }
     }
 
     /// Валидация весов (должны быть положительными)
     pub fn validate(&self) -> bool {
-        self.w_load >= 0.0 && self.w_intent >= 0.0 && self.w_tempo >= 0.0
+        self.w_load >= 0.0
+            && self.w_intent >= 0.0
+            && self.w_tempo >= 0.0
+            && self.epsilon >= 0.0
+            && self.p99_budget_ms.is_none_or(|budget| budget > 0.0)
     }
 }
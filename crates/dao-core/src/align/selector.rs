@@ -1,14 +1,372 @@
-//! Upstream selector trait
+//! Стратегии выбора upstream'а
 
-use crate::{Intent, upstream::UpstreamState};
+use super::{
+    best, near_best, peak_ewma_cost, resonant_score, weighted_index, PolicyWeights,
+    DEFAULT_P99_BUDGET_MS,
+};
+use crate::sense::ResonanceMetrics;
+use crate::{upstream::UpstreamState, Intent};
+use parking_lot::Mutex;
+use std::collections::HashMap;
 use std::sync::Arc;
 
-/// Trait для выбора upstream
-pub trait UpstreamSelector: Send + Sync {
+/// Стратегия выбора upstream'а — реализация политики маршрута.
+///
+/// `candidates` уже отфильтрованы Align (drain, профиль сервиса, slow
+/// start), `metrics[i]` — метрики `candidates[i]`.
+pub trait SelectionStrategy: Send + Sync {
     /// Выбор upstream для запроса
     fn select(
         &self,
-        upstreams: &[Arc<UpstreamState>],
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
         request_intent: Option<&Intent>,
     ) -> Option<Arc<UpstreamState>>;
+
+    /// Выбор для маршрута `route`: стратегия с состоянием между выборами
+    /// держит его по маршруту. По умолчанию — [`select`](Self::select)
+    fn select_in_route(
+        &self,
+        _route: &str,
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
+        request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        self.select(candidates, metrics, request_intent)
+    }
+
+    /// Выбор для разбора решения: тот же, что сделал бы
+    /// [`select_in_route`](Self::select_in_route), но без следа в
+    /// состоянии стратегии и метриках. По умолчанию — `select_in_route`
+    fn preview_in_route(
+        &self,
+        route: &str,
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
+        request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        self.select_in_route(route, candidates, metrics, request_intent)
+    }
+
+    /// Score кандидата для разбора решения (меньше = лучше); None —
+    /// стратегия score не считает
+    fn score(
+        &self,
+        _upstream: &UpstreamState,
+        _metrics: &ResonanceMetrics,
+        _request_intent: Option<&Intent>,
+    ) -> Option<f64> {
+        None
+    }
+}
+
+/// Resonant score кандидата по весам политики
+fn weighted_score(
+    weights: &PolicyWeights,
+    upstream: &UpstreamState,
+    metrics: &ResonanceMetrics,
+    request_intent: Option<&Intent>,
+) -> f64 {
+    resonant_score(
+        weights,
+        upstream,
+        metrics.load_components(&weights.latency).load_resonance,
+        metrics.tempo_spikiness,
+        request_intent,
+    )
+}
+
+/// Resonant: минимальный взвешенный score; при `epsilon > 0` — случайный
+/// среди близких к лучшему
+pub struct ResonantStrategy {
+    pub weights: PolicyWeights,
+}
+
+impl ResonantStrategy {
+    fn scored(
+        &self,
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
+        request_intent: Option<&Intent>,
+    ) -> Vec<(Arc<UpstreamState>, f64)> {
+        candidates
+            .iter()
+            .zip(metrics)
+            .map(|(upstream, m)| {
+                (
+                    upstream.clone(),
+                    weighted_score(&self.weights, upstream, m, request_intent),
+                )
+            })
+            .collect()
+    }
+}
+
+impl SelectionStrategy for ResonantStrategy {
+    fn select(
+        &self,
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
+        request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        let scored = self.scored(candidates, metrics, request_intent);
+        if self.weights.epsilon > 0.0 {
+            near_best(&scored, self.weights.epsilon).cloned()
+        } else {
+            best(&scored).cloned()
+        }
+    }
+
+    fn score(
+        &self,
+        upstream: &UpstreamState,
+        metrics: &ResonanceMetrics,
+        request_intent: Option<&Intent>,
+    ) -> Option<f64> {
+        Some(weighted_score(
+            &self.weights,
+            upstream,
+            metrics,
+            request_intent,
+        ))
+    }
+}
+
+/// Peak EWMA: минимальная `ewma_ms * (in_flight + 1)`
+pub struct PeakEwmaStrategy;
+
+impl SelectionStrategy for PeakEwmaStrategy {
+    fn select(
+        &self,
+        candidates: &[Arc<UpstreamState>],
+        _metrics: &[ResonanceMetrics],
+        _request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        let scored: Vec<_> = candidates
+            .iter()
+            .map(|u| (u.clone(), peak_ewma_cost(u)))
+            .collect();
+        best(&scored).cloned()
+    }
+
+    fn score(
+        &self,
+        upstream: &UpstreamState,
+        _metrics: &ResonanceMetrics,
+        _request_intent: Option<&Intent>,
+    ) -> Option<f64> {
+        Some(peak_ewma_cost(upstream))
+    }
+}
+
+/// Power of two choices: два кандидата, выбранных пропорционально weight,
+/// и лучший из них по resonant score. При меньше чем двух кандидатах —
+/// полный перебор.
+pub struct P2cStrategy {
+    pub weights: PolicyWeights,
+}
+
+impl SelectionStrategy for P2cStrategy {
+    fn select(
+        &self,
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
+        request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        if candidates.len() < 2 {
+            let resonant = ResonantStrategy {
+                weights: self.weights.clone(),
+            };
+            return resonant.select(candidates, metrics, request_intent);
+        }
+
+        let first = weighted_index(candidates, None);
+        let second = weighted_index(candidates, Some(first));
+        let score =
+            |i: usize| weighted_score(&self.weights, &candidates[i], &metrics[i], request_intent);
+
+        let chosen = if score(second) < score(first) {
+            second
+        } else {
+            first
+        };
+        Some(candidates[chosen].clone())
+    }
+
+    fn score(
+        &self,
+        upstream: &UpstreamState,
+        metrics: &ResonanceMetrics,
+        request_intent: Option<&Intent>,
+    ) -> Option<f64> {
+        Some(weighted_score(
+            &self.weights,
+            upstream,
+            metrics,
+            request_intent,
+        ))
+    }
+}
+
+/// SLO: upstream'ы с p99 в пределах бюджета (запас `budget - p99 >= 0`)
+/// выбираются resonant score'ом; если в бюджет не укладывается никто —
+/// наименее нарушающий (минимальный p99). Upstream без наблюдений
+/// считается укладывающимся.
+///
+/// Выбор upstream'а вне бюджета учитывается в `dao_slo_violations_total`.
+pub struct SloStrategy {
+    pub weights: PolicyWeights,
+}
+
+impl SloStrategy {
+    fn budget_ms(&self) -> f64 {
+        self.weights.p99_budget_ms.unwrap_or(DEFAULT_P99_BUDGET_MS)
+    }
+
+    /// Выбор и признак нарушения бюджета
+    fn choose(
+        &self,
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
+        request_intent: Option<&Intent>,
+    ) -> Option<(Arc<UpstreamState>, bool)> {
+        let budget = self.budget_ms();
+        let p99: Vec<f64> = candidates.iter().map(|u| u.p99_latency_ms()).collect();
+
+        let (mut compliant, mut compliant_metrics) = (Vec::new(), Vec::new());
+        for ((upstream, m), p99) in candidates.iter().zip(metrics).zip(&p99) {
+            if budget - p99 >= 0.0 {
+                compliant.push(upstream.clone());
+                compliant_metrics.push(m.clone());
+            }
+        }
+
+        if !compliant.is_empty() {
+            let resonant = ResonantStrategy {
+                weights: self.weights.clone(),
+            };
+            return resonant
+                .select(&compliant, &compliant_metrics, request_intent)
+                .map(|upstream| (upstream, false));
+        }
+        let scored: Vec<_> = candidates.iter().cloned().zip(p99).collect();
+        best(&scored).cloned().map(|upstream| (upstream, true))
+    }
+}
+
+impl SelectionStrategy for SloStrategy {
+    fn select(
+        &self,
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
+        request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        let (chosen, violation) = self.choose(candidates, metrics, request_intent)?;
+        if violation {
+            metrics::counter!(
+                "dao_slo_violations_total",
+                "upstream" => chosen.name.clone()
+            )
+            .increment(1);
+        }
+        Some(chosen)
+    }
+
+    fn preview_in_route(
+        &self,
+        _route: &str,
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
+        request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        self.choose(candidates, metrics, request_intent)
+            .map(|(chosen, _)| chosen)
+    }
+
+    fn score(
+        &self,
+        upstream: &UpstreamState,
+        metrics: &ResonanceMetrics,
+        request_intent: Option<&Intent>,
+    ) -> Option<f64> {
+        Some(weighted_score(
+            &self.weights,
+            upstream,
+            metrics,
+            request_intent,
+        ))
+    }
+}
+
+/// Smooth weighted round-robin (как в nginx): каждый выбор прибавляет
+/// кандидатам их weight к текущему весу, выбранный теряет сумму весов.
+/// Выборы чередуются: {5,1,1} дают `a a b a c a a`, а не пять `a` подряд.
+/// Текущие веса — свои у каждого маршрута.
+#[derive(Default)]
+pub struct SmoothWeightedStrategy {
+    /// Текущий вес по маршруту и имени upstream'а
+    current: Mutex<HashMap<String, HashMap<String, i64>>>,
+}
+
+impl SmoothWeightedStrategy {
+    /// Шаг swrr по текущим весам маршрута
+    fn advance(
+        current: &mut HashMap<String, i64>,
+        candidates: &[Arc<UpstreamState>],
+    ) -> Option<Arc<UpstreamState>> {
+        let mut total = 0;
+        let mut chosen: Option<(&Arc<UpstreamState>, i64)> = None;
+        for upstream in candidates {
+            let weight = i64::from(upstream.weight);
+            let value = current.entry(upstream.name.clone()).or_insert(0);
+            *value += weight;
+            total += weight;
+            if chosen.is_none_or(|(_, best)| *value > best) {
+                chosen = Some((upstream, *value));
+            }
+        }
+
+        let (upstream, _) = chosen?;
+        if let Some(value) = current.get_mut(&upstream.name) {
+            *value -= total;
+        }
+        Some(upstream.clone())
+    }
+}
+
+impl SelectionStrategy for SmoothWeightedStrategy {
+    fn select(
+        &self,
+        candidates: &[Arc<UpstreamState>],
+        metrics: &[ResonanceMetrics],
+        request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        self.select_in_route("", candidates, metrics, request_intent)
+    }
+
+    fn select_in_route(
+        &self,
+        route: &str,
+        candidates: &[Arc<UpstreamState>],
+        _metrics: &[ResonanceMetrics],
+        _request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        let mut routes = self.current.lock();
+        if !routes.contains_key(route) {
+            routes.insert(route.to_string(), HashMap::new());
+        }
+        Self::advance(routes.get_mut(route)?, candidates)
+    }
+
+    /// Следующий выбор маршрута на копии текущих весов
+    fn preview_in_route(
+        &self,
+        route: &str,
+        candidates: &[Arc<UpstreamState>],
+        _metrics: &[ResonanceMetrics],
+        _request_intent: Option<&Intent>,
+    ) -> Option<Arc<UpstreamState>> {
+        let mut current = self.current.lock().get(route).cloned().unwrap_or_default();
+        Self::advance(&mut current, candidates)
+    }
 }
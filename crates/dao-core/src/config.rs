@@ -1,202 +1,2095 @@
 //! Конфигурация DAO
 
-use crate::{Intent, Result};
+use crate::{Intent, Result, WeightedIntent};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Корневая конфигурация DAO
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct DaoConfig {
+    /// Файлы с маршрутами и политиками (glob относительно основного файла)
+    #[serde(default)]
+    pub include: Vec<String>,
     pub server: ServerConfig,
     pub telemetry: Option<TelemetryConfig>,
+    #[serde(default)]
     pub routes: RoutesConfig,
     pub policies: Option<HashMap<String, PolicyConfig>>,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    pub admin: Option<AdminConfig>,
+    #[serde(default)]
+    pub intent_rules: IntentRulesConfig,
+    #[serde(default)]
+    pub error_pages: ErrorPagesConfig,
+    #[serde(default)]
+    pub snapshots: SnapshotRetention,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
 impl DaoConfig {
-    /// Загрузка из TOML файла
+    /// Загрузка из TOML файла (с подстановкой `${VAR}` из окружения и
+    /// файлами из `include`)
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
-        let content = std::fs::read_to_string(path.as_ref())?;
-        let config: DaoConfig = toml::from_str(&content)
-            .map_err(|e| crate::DaoError::config(format!("Failed to parse config: {}", e)))?;
+        let path = path.as_ref();
+        let mut config: DaoConfig = parse_file(path)?;
+        for included in config.included_files(path)? {
+            let fragment: ConfigFragment = parse_file(&included)?;
+            config
+                .merge(fragment)
+                .map_err(|e| crate::DaoError::config(format!("{}: {}", included.display(), e)))?;
+        }
+        config.load_upstreams_files(path)?;
+        Ok(config)
+    }
+
+    /// Маршруты с `upstreams_file`: имя маршрута и путь к файлу
+    /// (относительно основного файла конфигурации)
+    pub fn upstreams_files(&self, config_path: &Path) -> Vec<(String, PathBuf)> {
+        let base = config_path.parent().unwrap_or(Path::new(""));
+        self.routes
+            .rule
+            .iter()
+            .filter_map(|route| {
+                Some((
+                    route.name.clone(),
+                    base.join(route.upstreams_file.as_ref()?),
+                ))
+            })
+            .collect()
+    }
+
+    /// Подстановка upstream'ов из `upstreams_file` в маршруты
+    fn load_upstreams_files(&mut self, config_path: &Path) -> Result<()> {
+        let base = config_path.parent().unwrap_or(Path::new(""));
+        for route in &mut self.routes.rule {
+            let Some(file) = &route.upstreams_file else {
+                continue;
+            };
+            if !route.upstreams.is_empty() {
+                return Err(crate::DaoError::config(format!(
+                    "Route '{}': upstreams and upstreams_file are mutually exclusive",
+                    route.name
+                )));
+            }
+            route.upstreams = load_upstreams_file(&base.join(file))?;
+        }
+        Ok(())
+    }
+
+    /// Копия конфигурации с новым набором upstream'ов маршрута
+    /// (перечитанный `upstreams_file`); остальные маршруты не меняются
+    pub fn with_route_upstreams(
+        &self,
+        route: &str,
+        upstreams: Vec<UpstreamConfig>,
+    ) -> Result<Self> {
+        let mut config = self.clone();
+        let rule = config
+            .routes
+            .rule
+            .iter_mut()
+            .find(|r| r.name == route)
+            .ok_or_else(|| crate::DaoError::config(format!("Unknown route '{}'", route)))?;
+        rule.upstreams = upstreams;
         Ok(config)
     }
 
+    /// Файлы из `include` по порядку: шаблоны — в порядке объявления,
+    /// совпадения одного шаблона — по имени
+    pub fn included_files(&self, config_path: &Path) -> Result<Vec<PathBuf>> {
+        let base = config_path.parent().unwrap_or(Path::new(""));
+        let mut files = Vec::new();
+        for pattern in &self.include {
+            let full = base.join(pattern);
+            let matches = glob::glob(&full.to_string_lossy()).map_err(|e| {
+                crate::DaoError::config(format!("Invalid include pattern {}: {}", pattern, e))
+            })?;
+            let mut matched: Vec<PathBuf> = matches.filter_map(|m| m.ok()).collect();
+            matched.sort();
+            files.extend(matched);
+        }
+        Ok(files)
+    }
+
+    /// Слияние включенного файла: маршруты добавляются в конец, повтор
+    /// имени маршрута или политики — ошибка
+    fn merge(&mut self, fragment: ConfigFragment) -> std::result::Result<(), String> {
+        for route in fragment.routes.rule {
+            if self.routes.get(&route.name).is_some() {
+                return Err(format!("duplicate route '{}'", route.name));
+            }
+            self.routes.rule.push(route);
+        }
+        let policies = self.policies.get_or_insert_with(HashMap::new);
+        for (name, policy) in fragment.policies {
+            if policies.contains_key(&name) {
+                return Err(format!("duplicate policy '{}'", name));
+            }
+            policies.insert(name, policy);
+        }
+        Ok(())
+    }
+
+    /// Загрузка и полная проверка файла: все ошибки, а не только первая
+    pub fn check_file(path: impl AsRef<Path>) -> std::result::Result<Self, Vec<crate::DaoError>> {
+        let config = Self::from_file(path).map_err(|e| vec![e])?;
+        let errors = config.validation_errors();
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
     /// Валидация конфигурации
     pub fn validate(&self) -> Result<()> {
+        match self.validation_errors().into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Все ошибки валидации (по одной на секцию, listener и маршрут)
+    pub fn validation_errors(&self) -> Vec<crate::DaoError> {
+        let mut errors = Vec::new();
+
         // Проверка bind-адресов
-        if self.server.bind.is_empty() {
-            return Err(crate::DaoError::config("server.bind is empty"));
+        let listeners = self.server.listeners();
+        if listeners.is_empty() {
+            errors.push(crate::DaoError::config(
+                "No listeners defined (server.bind or [[server.listen]])",
+            ));
+        }
+        for listener in &listeners {
+            errors.extend(listener.validate().err());
+        }
+        if self.server.max_connections == Some(0) {
+            errors.push(crate::DaoError::config(
+                "server.max_connections must be > 0",
+            ));
+        }
+        if self.server.max_headers == Some(0) || self.server.max_header_bytes == Some(0) {
+            errors.push(crate::DaoError::config(
+                "server.max_headers and server.max_header_bytes must be > 0",
+            ));
+        }
+        errors.extend(self.server.http1.validate().err());
+        errors.extend(self.server.http2.validate().err());
+        if let Some(keepalive) = &self.server.tcp_keepalive {
+            if keepalive.idle_secs == 0 || keepalive.interval_secs == Some(0) {
+                errors.push(crate::DaoError::config(
+                    "server.tcp_keepalive: idle_secs and interval_secs must be > 0",
+                ));
+            }
+        }
+
+        errors.extend(self.stats.validate().err());
+        errors.extend(self.snapshots.validate().err());
+        errors.extend(self.logging.validate().err());
+        errors.extend(self.admin.iter().filter_map(|admin| admin.validate().err()));
+        errors.extend(self.intent_rules.validate().err());
+        errors.extend(self.error_pages.validate().err());
+        for (name, policy) in self.policies.iter().flatten() {
+            if !(policy.epsilon >= 0.0 && policy.epsilon.is_finite()) {
+                errors.push(crate::DaoError::config(format!(
+                    "Policy '{}': epsilon must be a finite number >= 0",
+                    name
+                )));
+            }
+            if policy
+                .p99_budget_ms
+                .is_some_and(|budget| !(budget > 0.0 && budget.is_finite()))
+            {
+                errors.push(crate::DaoError::config(format!(
+                    "Policy '{}': p99_budget_ms must be a finite number > 0",
+                    name
+                )));
+            }
+            let sum = policy.w_load + policy.w_intent + policy.w_tempo;
+            if policy.normalize && !(sum > 0.0 && sum.is_finite()) {
+                errors.push(crate::DaoError::config(format!(
+                    "Policy '{}': normalize requires weights with a positive sum",
+                    name
+                )));
+            }
+            if !(policy.latency_reference_ms > 0.0
+                && policy.latency_reference_ms.is_finite()
+                && policy.latency_cap > 0.0)
+            {
+                errors.push(crate::DaoError::config(format!(
+                    "Policy '{}': latency_reference_ms must be a finite number > 0 \
+                     and latency_cap > 0",
+                    name
+                )));
+            }
         }
 
         // Проверка наличия маршрутов
         if self.routes.rule.is_empty() {
-            return Err(crate::DaoError::config("No routes defined"));
+            errors.push(crate::DaoError::config("No routes defined"));
+        }
+
+        errors.extend(self.routes.validate_default().err());
+
+        // Passthrough upstream observe-режима — из upstream'ов маршрутов
+        if let Some(passthrough) = &self.server.passthrough_upstream {
+            let known = self
+                .routes
+                .rule
+                .iter()
+                .flat_map(|route| &route.upstreams)
+                .any(|upstream| &upstream.name == passthrough);
+            if !known {
+                errors.push(crate::DaoError::config(format!(
+                    "server.passthrough_upstream '{}' is not an upstream of any route",
+                    passthrough
+                )));
+            }
+        }
+
+        // Upstream'ы общие по имени (статистика, клиент): одноименные
+        // определения в разных маршрутах должны совпадать
+        let mut upstreams: HashMap<&str, &UpstreamConfig> = HashMap::new();
+        let mut conflicting = std::collections::BTreeSet::new();
+        for upstream in self.routes.rule.iter().flat_map(|route| &route.upstreams) {
+            let first = *upstreams.entry(&upstream.name).or_insert(upstream);
+            if first != upstream {
+                conflicting.insert(&upstream.name);
+            }
+        }
+        for name in conflicting {
+            errors.push(crate::DaoError::config(format!(
+                "Upstream '{}' is defined differently in several routes",
+                name
+            )));
+        }
+
+        // Валидация каждого маршрута
+        for route in &self.routes.rule {
+            errors.extend(route.validate().err());
+            if let Some(fallback) = &route.fallback_route {
+                if self.routes.get(fallback).is_none() {
+                    errors.push(crate::DaoError::config(format!(
+                        "Route '{}': unknown fallback_route '{}'",
+                        route.name, fallback
+                    )));
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// JSON Schema конфигурации (для автодополнения в редакторах)
+    pub fn json_schema() -> serde_json::Value {
+        serde_json::to_value(schemars::schema_for!(DaoConfig)).unwrap_or_default()
+    }
+}
+
+/// Подстановка переменных окружения: `${VAR}`, `${VAR:-default}`;
+/// `$${` — литерал `${`. Незаданная переменная без default — ошибка.
+pub fn expand_env(content: &str) -> Result<String> {
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = escaped;
+        } else if let Some(var) = rest.strip_prefix("${") {
+            let end = var
+                .find('}')
+                .ok_or_else(|| crate::DaoError::config("Unterminated ${...} in config"))?;
+            let (name, default) = match var[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&var[..end], None),
+            };
+            match (std::env::var(name), default) {
+                (Ok(value), _) => out.push_str(&value),
+                (Err(_), Some(default)) => out.push_str(default),
+                (Err(_), None) => {
+                    return Err(crate::DaoError::config(format!(
+                        "Environment variable {} is not set",
+                        name
+                    )))
+                }
+            }
+            rest = &var[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Конфигурация сервера
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ServerConfig {
+    /// Основной listener (сокращенная форма для одного адреса)
+    pub bind: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// ALPN протоколы основного listener'а (по умолчанию h2, http/1.1)
+    pub alpn: Option<Vec<String>>,
+    #[serde(default)]
+    pub alpn_strict: bool,
+    /// CA клиентских сертификатов (mTLS) основного listener'а
+    pub tls_client_ca: Option<String>,
+    /// Дополнительные listener'ы (`[[server.listen]]`)
+    #[serde(default)]
+    pub listen: Vec<ListenConfig>,
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    /// Закрытие соединений без запросов в работе и входящих данных (сек)
+    pub idle_timeout_secs: Option<u64>,
+    /// Таймаут чтения заголовков запроса HTTP/1 (сек)
+    pub read_timeout_secs: Option<u64>,
+    /// Таймаут записи ответа клиенту (сек)
+    pub write_timeout_secs: Option<u64>,
+    /// Встроенный health endpoint (до таблицы маршрутов)
+    #[serde(default = "default_health_path")]
+    pub health_path: String,
+    /// Окно slow start (сек): доля трафика нового upstream'а растет
+    /// линейно до полной. Читается при запуске — reload не меняет
+    pub slow_start_secs: Option<u64>,
+    /// Лимит параллельных запросов (503 при превышении)
+    pub max_concurrent_requests: Option<usize>,
+    /// Лимит одновременных соединений
+    pub max_connections: Option<usize>,
+    /// Соединения сверх `max_connections`: закрыть или ждать слота
+    #[serde(default)]
+    pub connection_overflow: ConnectionOverflow,
+    /// Разрешить выбор upstream'а заголовком `X-DAO-Upstream` (отладка)
+    #[serde(default)]
+    pub allow_upstream_override: bool,
+    /// Заголовки `X-DAO-Selected`/`X-DAO-Policy`/`X-DAO-Score` в ответах
+    #[serde(default)]
+    pub expose_selection_headers: bool,
+    /// TCP_NODELAY на входящих и upstream соединениях
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    /// TCP keepalive на входящих и upstream соединениях
+    pub tcp_keepalive: Option<TcpKeepaliveConfig>,
+    /// Максимум bucket'ов rate limit в памяти (LRU)
+    pub rate_limit_max_keys: Option<usize>,
+    /// Клиенты upstream'ов: общие по URL или свои у каждого upstream'а.
+    /// Читается при запуске — reload не меняет
+    #[serde(default)]
+    pub upstream_pool: UpstreamPool,
+    /// Максимум заголовков запроса (431 при превышении)
+    pub max_headers: Option<usize>,
+    /// Максимальный суммарный размер заголовков запроса, байт (431)
+    pub max_header_bytes: Option<usize>,
+    /// Параметры HTTP/1.1 соединений клиентов
+    #[serde(default)]
+    pub http1: Http1Config,
+    /// Параметры HTTP/2 соединений клиентов
+    #[serde(default)]
+    pub http2: Http2Config,
+    /// Режим работы: `observe` — выбор Align только в метрики
+    /// (`dao_would_select`), запросы — на passthrough upstream маршрута
+    #[serde(default)]
+    pub mode: ServerMode,
+    /// Passthrough upstream для `observe` (по имени); у маршрута без
+    /// такого upstream'а — первый в списке
+    pub passthrough_upstream: Option<String>,
+}
+
+/// Режим работы сервера (`server.mode`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerMode {
+    /// Запрос уходит к upstream'у, выбранному политикой
+    #[default]
+    Enforce,
+    /// Выбор вычисляется, но запрос уходит к passthrough upstream'у;
+    /// circuit breaker трафик не отклоняет
+    Observe,
+}
+
+/// Разделение соединений к upstream'ам (`server.upstream_pool`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamPool {
+    /// Upstream'ы с одинаковым URL и настройками делят клиента
+    #[default]
+    Shared,
+    /// Свой клиент у каждого upstream'а (изоляция соединений)
+    PerUpstream,
+}
+
+/// Поведение при исчерпании `server.max_connections`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionOverflow {
+    /// Новое соединение принимается и сразу закрывается
+    #[default]
+    Reject,
+    /// Принятое соединение ждет освобождения слота, accept приостановлен
+    /// (очередь — backlog ядра)
+    Wait,
+}
+
+/// Минимальный буфер чтения HTTP/1, допустимый в hyper
+pub const HTTP1_MIN_BUF_SIZE: usize = 8192;
+
+/// Максимальный размер окна HTTP/2 (RFC 9113)
+const HTTP2_MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+/// HTTP/1.1 соединения клиентов (`[server.http1]`)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Http1Config {
+    /// Keep-alive: выключен — соединение закрывается после ответа
+    /// (`Connection: close`)
+    #[serde(default = "default_http1_keepalive")]
+    pub keepalive: bool,
+    /// Буфер чтения соединения, байт (не меньше 8192)
+    pub max_buf_size: Option<usize>,
+    /// Полузакрытое клиентом соединение дообслуживается, а не закрывается
+    #[serde(default)]
+    pub half_close: bool,
+    /// Ответы на конвейерные (pipelined) запросы отправляются пачкой
+    #[serde(default)]
+    pub pipeline_flush: bool,
+}
+
+fn default_http1_keepalive() -> bool {
+    true
+}
+
+impl Default for Http1Config {
+    fn default() -> Self {
+        Self {
+            keepalive: default_http1_keepalive(),
+            max_buf_size: None,
+            half_close: false,
+            pipeline_flush: false,
+        }
+    }
+}
+
+impl Http1Config {
+    pub fn validate(&self) -> Result<()> {
+        if self
+            .max_buf_size
+            .is_some_and(|size| size < HTTP1_MIN_BUF_SIZE)
+        {
+            return Err(crate::DaoError::config(format!(
+                "server.http1.max_buf_size must be >= {}",
+                HTTP1_MIN_BUF_SIZE
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// HTTP/2 соединения клиентов (`[server.http2]`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Http2Config {
+    /// Максимум одновременных потоков на соединение
+    pub max_concurrent_streams: Option<u32>,
+    /// Начальное окно потока, байт
+    pub initial_stream_window_size: Option<u32>,
+    /// Начальное окно соединения, байт
+    pub initial_connection_window_size: Option<u32>,
+    /// Окна подстраиваются под задержку (BDP); размеры окон выше
+    /// игнорируются
+    #[serde(default)]
+    pub adaptive_window: bool,
+}
+
+impl Http2Config {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_concurrent_streams == Some(0) {
+            return Err(crate::DaoError::config(
+                "server.http2.max_concurrent_streams must be > 0",
+            ));
+        }
+        let windows = [
+            self.initial_stream_window_size,
+            self.initial_connection_window_size,
+        ];
+        if windows
+            .iter()
+            .flatten()
+            .any(|&size| size == 0 || size > HTTP2_MAX_WINDOW_SIZE)
+        {
+            return Err(crate::DaoError::config(format!(
+                "server.http2 window sizes must be in 1..={}",
+                HTTP2_MAX_WINDOW_SIZE
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Параметры TCP keepalive (`[server.tcp_keepalive]`)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TcpKeepaliveConfig {
+    /// Простой соединения до первой пробы (сек)
+    pub idle_secs: u64,
+    /// Интервал между пробами (сек)
+    pub interval_secs: Option<u64>,
+    /// Число неотвеченных проб до разрыва
+    pub probes: Option<u32>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: None,
+            tls_cert: None,
+            tls_key: None,
+            alpn: None,
+            alpn_strict: false,
+            tls_client_ca: None,
+            listen: Vec::new(),
+            workers: default_workers(),
+            idle_timeout_secs: None,
+            read_timeout_secs: None,
+            write_timeout_secs: None,
+            health_path: default_health_path(),
+            slow_start_secs: None,
+            max_concurrent_requests: None,
+            max_connections: None,
+            connection_overflow: ConnectionOverflow::default(),
+            allow_upstream_override: false,
+            expose_selection_headers: false,
+            tcp_nodelay: default_tcp_nodelay(),
+            tcp_keepalive: None,
+            rate_limit_max_keys: None,
+            upstream_pool: UpstreamPool::default(),
+            max_headers: None,
+            max_header_bytes: None,
+            http1: Http1Config::default(),
+            http2: Http2Config::default(),
+            mode: ServerMode::default(),
+            passthrough_upstream: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Все listener'ы: `server.bind` (если задан) + блоки `[[server.listen]]`
+    pub fn listeners(&self) -> Vec<ListenConfig> {
+        let mut listeners = Vec::with_capacity(self.listen.len() + 1);
+        if let Some(bind) = &self.bind {
+            listeners.push(ListenConfig {
+                bind: bind.clone(),
+                tls_cert: self.tls_cert.clone(),
+                tls_key: self.tls_key.clone(),
+                alpn: self.alpn.clone(),
+                alpn_strict: self.alpn_strict,
+                tls_client_ca: self.tls_client_ca.clone(),
+                proxy_protocol: false,
+            });
+        }
+        listeners.extend(self.listen.iter().cloned());
+        listeners
+    }
+}
+
+/// Конфигурация отдельного listener'а
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ListenConfig {
+    pub bind: String,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// ALPN протоколы в порядке предпочтения (по умолчанию h2, http/1.1)
+    pub alpn: Option<Vec<String>>,
+    /// Отклонять TLS соединения, в которых ALPN не согласован
+    #[serde(default)]
+    pub alpn_strict: bool,
+    /// PEM с CA клиентских сертификатов (mTLS): предъявленный сертификат
+    /// проверяется, его CN/SAN доступны маршрутам; клиенты без сертификата
+    /// допускаются (требовать identity — фильтром маршрута)
+    pub tls_client_ca: Option<String>,
+    /// Заголовок PROXY protocol v1/v2 перед TLS/HTTP: адрес клиента
+    /// берется из него (соединения без заголовка отклоняются)
+    #[serde(default)]
+    pub proxy_protocol: bool,
+}
+
+/// Поддерживаемые ALPN протоколы
+pub const KNOWN_ALPN_PROTOCOLS: &[&str] = &["h2", "http/1.1"];
+
+impl ListenConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.bind.is_empty() {
+            return Err(crate::DaoError::config("Listener bind address is empty"));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(crate::DaoError::config(format!(
+                "Listener '{}' must set both tls_cert and tls_key",
+                self.bind
+            )));
+        }
+        if self.tls_client_ca.is_some() && self.tls_cert.is_none() {
+            return Err(crate::DaoError::config(format!(
+                "Listener '{}': tls_client_ca requires TLS",
+                self.bind
+            )));
+        }
+        if let Some(alpn) = &self.alpn {
+            if self.tls_cert.is_none() {
+                return Err(crate::DaoError::config(format!(
+                    "Listener '{}': alpn requires TLS",
+                    self.bind
+                )));
+            }
+            if alpn.is_empty() {
+                return Err(crate::DaoError::config(format!(
+                    "Listener '{}': alpn list is empty",
+                    self.bind
+                )));
+            }
+            if let Some(unknown) = alpn
+                .iter()
+                .find(|p| !KNOWN_ALPN_PROTOCOLS.contains(&p.as_str()))
+            {
+                return Err(crate::DaoError::config(format!(
+                    "Listener '{}': unknown ALPN protocol '{}' (expected one of {:?})",
+                    self.bind, unknown, KNOWN_ALPN_PROTOCOLS
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_tcp_nodelay() -> bool {
+    true
+}
+
+fn default_health_path() -> String {
+    "/dao-health".to_string()
+}
+
+fn default_workers() -> usize {
+    num_cpus::get()
+}
+
+/// Конфигурация статистики upstream'ов
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StatsConfig {
+    /// Коэффициент сглаживания EWMA латентности (0.0 - 1.0]
+    #[serde(default = "default_ewma_alpha")]
+    pub ewma_alpha: f64,
+    /// Нижняя граница гистограммы латентности (мкс)
+    #[serde(default = "default_histogram_min_us")]
+    pub histogram_min_us: u64,
+    /// Верхняя граница гистограммы (мкс); большие значения насыщаются
+    #[serde(default = "default_histogram_max_us")]
+    pub histogram_max_us: u64,
+    /// Значащие цифры гистограммы (0 - 5)
+    #[serde(default = "default_histogram_sigfigs")]
+    pub histogram_sigfigs: u8,
+    /// Статистика без запросов дольше этого времени (сек) считается
+    /// устаревшей и постепенно забывается
+    #[serde(default = "default_stale_after_secs")]
+    pub stale_after_secs: u64,
+}
+
+fn default_ewma_alpha() -> f64 {
+    0.3
+}
+
+fn default_histogram_min_us() -> u64 {
+    1
+}
+
+fn default_histogram_max_us() -> u64 {
+    60_000_000
+}
+
+fn default_histogram_sigfigs() -> u8 {
+    3
+}
+
+fn default_stale_after_secs() -> u64 {
+    60
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            ewma_alpha: default_ewma_alpha(),
+            histogram_min_us: default_histogram_min_us(),
+            histogram_max_us: default_histogram_max_us(),
+            histogram_sigfigs: default_histogram_sigfigs(),
+            stale_after_secs: default_stale_after_secs(),
+        }
+    }
+}
+
+impl StatsConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.ewma_alpha > 0.0 && self.ewma_alpha <= 1.0) {
+            return Err(crate::DaoError::config(format!(
+                "stats.ewma_alpha must be in (0, 1], got {}",
+                self.ewma_alpha
+            )));
+        }
+        if self.histogram_min_us < 1 {
+            return Err(crate::DaoError::config(
+                "stats.histogram_min_us must be >= 1",
+            ));
+        }
+        if self.histogram_max_us < self.histogram_min_us.saturating_mul(2) {
+            return Err(crate::DaoError::config(
+                "stats.histogram_max_us must be at least 2 * histogram_min_us",
+            ));
+        }
+        if self.histogram_sigfigs > 5 {
+            return Err(crate::DaoError::config(
+                "stats.histogram_sigfigs must be 0 - 5",
+            ));
+        }
+        if self.stale_after_secs == 0 {
+            return Err(crate::DaoError::config(
+                "stats.stale_after_secs must be > 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Хранение snapshot'ов конфигурации: сначала отсекаются старше
+/// `max_age_secs`, затем самые старые сверх `max_count`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SnapshotRetention {
+    #[serde(default = "default_snapshot_max_count")]
+    pub max_count: usize,
+    /// Максимальный возраст snapshot'а (сек), None — без ограничения
+    pub max_age_secs: Option<u64>,
+}
+
+fn default_snapshot_max_count() -> usize {
+    100
+}
+
+impl Default for SnapshotRetention {
+    fn default() -> Self {
+        Self {
+            max_count: default_snapshot_max_count(),
+            max_age_secs: None,
+        }
+    }
+}
+
+impl SnapshotRetention {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_count == 0 {
+            return Err(crate::DaoError::config("snapshots.max_count must be > 0"));
+        }
+        if self.max_age_secs == Some(0) {
+            return Err(crate::DaoError::config(
+                "snapshots.max_age_secs must be > 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Логирование (`[logging]`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LoggingConfig {
+    /// Заголовки, значения которых в логах заменяются на `***`
+    /// (upstream получает их без изменений)
+    #[serde(default = "default_redact_headers")]
+    pub redact_headers: Vec<String>,
+    /// Заголовки, значения которых попадают в логи; у остальных в логе
+    /// только имя и `***` (`redact_headers` маскируются всегда)
+    #[serde(default = "default_log_headers")]
+    pub log_headers: Vec<String>,
+}
+
+fn default_redact_headers() -> Vec<String> {
+    ["authorization", "cookie", "set-cookie", "x-api-key"]
+        .iter()
+        .map(|h| h.to_string())
+        .collect()
+}
+
+fn default_log_headers() -> Vec<String> {
+    [
+        "host",
+        "user-agent",
+        "accept",
+        "content-type",
+        "content-length",
+        "x-request-id",
+    ]
+    .iter()
+    .map(|h| h.to_string())
+    .collect()
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            redact_headers: default_redact_headers(),
+            log_headers: default_log_headers(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (field, names) in [
+            ("redact_headers", &self.redact_headers),
+            ("log_headers", &self.log_headers),
+        ] {
+            if let Some(name) = names
+                .iter()
+                .find(|name| http::HeaderName::from_bytes(name.as_bytes()).is_err())
+            {
+                return Err(crate::DaoError::config(format!(
+                    "logging.{}: invalid header name {}",
+                    field, name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Конфигурация admin API
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AdminConfig {
+    pub bind: String,
+    /// Bearer токен для доступа к API; нужен он или `auth` — без
+    /// аутентификации admin API не запускается
+    pub token: Option<String>,
+    /// HMAC подпись запросов — альтернатива bearer токену
+    pub auth: Option<AdminAuthConfig>,
+}
+
+/// Подпись запросов к admin API: HMAC-SHA256 от метода, пути, времени и
+/// тела в `X-DAO-Signature`, время — в `X-DAO-Timestamp`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AdminAuthConfig {
+    /// Общий секрет клиента и DAO
+    pub hmac_secret: String,
+    /// Допустимое расхождение `X-DAO-Timestamp` с часами DAO (сек);
+    /// более старые подписи отклоняются
+    #[serde(default = "default_admin_clock_skew_secs")]
+    pub max_clock_skew_secs: u64,
+}
+
+fn default_admin_clock_skew_secs() -> u64 {
+    300
+}
+
+impl AdminConfig {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(auth) = &self.auth {
+            if auth.hmac_secret.is_empty() {
+                return Err(crate::DaoError::config("admin.auth.hmac_secret is empty"));
+            }
+        }
+        if self.token.as_deref().is_none_or(str::is_empty) && self.auth.is_none() {
+            return Err(crate::DaoError::config(
+                "admin requires token or auth.hmac_secret",
+            ));
         }
+        Ok(())
+    }
+}
+
+/// Конфигурация телеметрии
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TelemetryConfig {
+    pub prometheus_bind: String,
+    /// Не удалось занять `prometheus_bind` после повторов — остановка
+    /// запуска (по умолчанию DAO работает без exporter'а)
+    #[serde(default)]
+    pub fail_on_bind_error: bool,
+}
+
+/// Содержимое файла из `include`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFragment {
+    #[serde(default)]
+    routes: RoutesConfig,
+    #[serde(default)]
+    policies: HashMap<String, PolicyConfig>,
+}
+
+/// Чтение TOML файла с подстановкой переменных окружения
+fn parse_file<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        crate::DaoError::config(format!("Failed to read config {}: {}", path.display(), e))
+    })?;
+    let content = expand_env(&content)?;
+    toml::from_str(&content).map_err(|e| {
+        crate::DaoError::config(format!("Failed to parse config {}: {}", path.display(), e))
+    })
+}
+
+/// Upstream'ы маршрута из `upstreams_file`: JSON массив в формате
+/// `[[routes.rule.upstreams]]`
+pub fn load_upstreams_file(path: &Path) -> Result<Vec<UpstreamConfig>> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        crate::DaoError::config(format!(
+            "Failed to read upstreams file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    serde_json::from_str(&content).map_err(|e| {
+        crate::DaoError::config(format!(
+            "Failed to parse upstreams file {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+/// Конфигурация маршрутов
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct RoutesConfig {
+    pub rule: Vec<RouteRule>,
+    /// Маршрут (по имени) для запросов, не совпавших ни с одним правилом
+    #[serde(default)]
+    pub default: Option<String>,
+    /// Redirect несовпавших запросов на этот URL (вместо `default`)
+    #[serde(default)]
+    pub default_redirect: Option<String>,
+    /// Код ответа для несовпавших: 301/302/303/307/308 при `default_redirect`,
+    /// иначе 4xx/5xx (по умолчанию 404 и 302 соответственно)
+    #[serde(default)]
+    pub default_status: Option<u16>,
+}
+
+/// Маршрут с наибольшим рангом; при равенстве — первый
+fn best_ranked<'a>(routes: impl Iterator<Item = &'a RouteRule>) -> Option<&'a RouteRule> {
+    routes.fold(None, |best: Option<&RouteRule>, route| match best {
+        Some(best) if best.rank() >= route.rank() => Some(best),
+        _ => Some(route),
+    })
+}
+
+/// Ответ на запрос, не совпавший ни с одним маршрутом
+#[derive(Debug, Clone, Copy)]
+pub enum Unmatched<'a> {
+    /// Обработка маршрутом `routes.default`
+    Route(&'a RouteRule),
+    /// Redirect с кодом и `Location`
+    Redirect(u16, &'a str),
+    /// Ответ с кодом ошибки
+    Status(u16),
+}
+
+/// Коды, допустимые для `default_redirect`
+const REDIRECT_STATUSES: [u16; 5] = [301, 302, 303, 307, 308];
+
+/// Максимальная длина цепочки fallback-маршрутов (без основного)
+pub const MAX_FALLBACK_DEPTH: usize = 4;
+
+impl RoutesConfig {
+    /// Маршрут для запроса. Среди подходящих побеждает больший `priority`,
+    /// затем более специфичный match (`path_exact` > `path_prefix`, длинный
+    /// prefix > короткий, с `host` > без), при равенстве — первый в файле.
+    pub fn find_route(&self, ctx: &MatchContext<'_>) -> Option<&RouteRule> {
+        best_ranked(self.rule.iter().filter(|r| r.match_rule.matches(ctx)))
+    }
+
+    /// Маршрут, совпавший с запросом по всем условиям, кроме метода: его
+    /// фильтры доступа и CORS проверяются до ответа 405
+    pub fn find_route_any_method(&self, ctx: &MatchContext<'_>) -> Option<&RouteRule> {
+        best_ranked(
+            self.rule
+                .iter()
+                .filter(|r| r.match_rule.matches_target(ctx)),
+        )
+    }
+
+    /// Методы маршрутов, совпавших с запросом по всем условиям, кроме
+    /// метода (для 405 и `Allow`); пусто — таких маршрутов нет
+    pub fn allowed_methods(&self, ctx: &MatchContext<'_>) -> Vec<String> {
+        let mut allowed: Vec<String> = Vec::new();
+        let methods = self
+            .rule
+            .iter()
+            .filter(|r| r.match_rule.matches_target(ctx))
+            .flat_map(|r| r.match_rule.methods.iter().flatten());
+        for method in methods {
+            let method = method.to_ascii_uppercase();
+            if !allowed.contains(&method) {
+                allowed.push(method);
+            }
+        }
+        allowed
+    }
+
+    /// Маршрут для запроса с учетом `default`: совпавший или маршрут по умолчанию
+    pub fn resolve(&self, ctx: &MatchContext<'_>) -> Option<&RouteRule> {
+        self.find_route(ctx)
+            .or_else(|| self.default.as_deref().and_then(|name| self.get(name)))
+    }
+
+    /// Что делать с запросом без маршрута
+    pub fn unmatched(&self) -> Unmatched<'_> {
+        if let Some(route) = self.default.as_deref().and_then(|name| self.get(name)) {
+            return Unmatched::Route(route);
+        }
+        match &self.default_redirect {
+            Some(location) => Unmatched::Redirect(self.default_status.unwrap_or(302), location),
+            None => Unmatched::Status(self.default_status.unwrap_or(404)),
+        }
+    }
+
+    /// Проверка настроек маршрута по умолчанию
+    pub fn validate_default(&self) -> Result<()> {
+        if let Some(name) = &self.default {
+            if self.default_redirect.is_some() || self.default_status.is_some() {
+                return Err(crate::DaoError::config(
+                    "routes.default cannot be combined with default_redirect/default_status",
+                ));
+            }
+            if self.get(name).is_none() {
+                return Err(crate::DaoError::config(format!(
+                    "routes.default: unknown route '{}'",
+                    name
+                )));
+            }
+        }
+        match (&self.default_redirect, self.default_status) {
+            (Some(location), status) => {
+                if http::HeaderValue::from_str(location).is_err() {
+                    return Err(crate::DaoError::config(
+                        "routes.default_redirect: invalid URL",
+                    ));
+                }
+                if status.is_some_and(|status| !REDIRECT_STATUSES.contains(&status)) {
+                    return Err(crate::DaoError::config(
+                        "routes.default_status must be 301, 302, 303, 307 or 308 with default_redirect",
+                    ));
+                }
+            }
+            (None, Some(status)) if !(400..=599).contains(&status) => {
+                return Err(crate::DaoError::config(
+                    "routes.default_status must be 4xx or 5xx",
+                ));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Маршрут по имени
+    pub fn get(&self, name: &str) -> Option<&RouteRule> {
+        self.rule.iter().find(|r| r.name == name)
+    }
+
+    /// Маршрут и его fallback'и по `fallback_route`: обход останавливается
+    /// на цикле, неизвестном имени или после `MAX_FALLBACK_DEPTH` переходов
+    pub fn fallback_chain<'a>(&'a self, route: &'a RouteRule) -> Vec<&'a RouteRule> {
+        let mut chain = vec![route];
+        let mut current = route;
+        while let Some(next) = current.fallback_route.as_deref() {
+            if chain.len() > MAX_FALLBACK_DEPTH || chain.iter().any(|r| r.name == next) {
+                break;
+            }
+            let Some(next) = self.get(next) else {
+                break;
+            };
+            chain.push(next);
+            current = next;
+        }
+        chain
+    }
+}
+
+/// Правило маршрутизации
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RouteRule {
+    pub name: String,
+    #[serde(rename = "match")]
+    pub match_rule: MatchRule,
+    pub policy: String,
+    pub intent: Option<String>,
+    #[serde(default)]
+    pub upstreams: Vec<UpstreamConfig>,
+    /// JSON файл с upstream'ами маршрута (вместо `upstreams`), путь
+    /// относительно основного файла; перечитывается отдельно от конфигурации
+    pub upstreams_file: Option<String>,
+    pub filters: Option<FilterConfig>,
+    /// Маршрут, upstream'ы которого используются, если здесь выбрать некого
+    pub fallback_route: Option<String>,
+    /// Общий бюджет времени запроса до заголовков ответа (мс), по
+    /// истечении — 504; таймауты upstream'ов урезаются до остатка
+    pub deadline_ms: Option<u64>,
+    /// Общий таймаут запроса (мс): от приема запроса до отправки тела
+    /// ответа целиком; до заголовков — 504, при передаче тела — обрыв
+    pub request_timeout_ms: Option<u64>,
+    /// Окно удержания выбора (мс): запросы того же соединения идут к
+    /// выбранному upstream'у, пока он может их принять
+    pub selection_hold_ms: Option<u64>,
+    /// Протокол маршрута: для `grpc` успех upstream'а — `grpc-status: 0`
+    #[serde(default)]
+    pub protocol: RouteProtocol,
+    /// Приоритет при выборе маршрута (больше — раньше, по умолчанию 0)
+    pub priority: Option<i32>,
+    /// A/B эксперимент: плечо по ключу пользователя, политика маршрута
+    /// выбирает среди upstream'ов плеча
+    pub ab_test: Option<AbTestConfig>,
+}
+
+/// Протокол маршрута
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteProtocol {
+    /// Успех — 2xx статус ответа
+    #[default]
+    Http,
+    /// Успех — `grpc-status: 0` в trailers (или в заголовках)
+    Grpc,
+}
+
+/// Число бакетов A/B эксперимента (бакеты `0..=99`)
+pub const AB_BUCKETS: u32 = 100;
+
+/// A/B эксперимент маршрута: стабильный ключ хешируется в бакет, бакет
+/// определяет плечо. Без ключа в запросе — выбор среди всех upstream'ов.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AbTestConfig {
+    /// Заголовок с ключом (ID пользователя)
+    pub key_header: Option<String>,
+    /// Cookie с ключом — если заголовка нет
+    pub key_cookie: Option<String>,
+    pub arms: Vec<AbArmConfig>,
+}
+
+/// Плечо эксперимента
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AbArmConfig {
+    pub name: String,
+    /// Диапазон бакетов включительно, например `[0, 49]`
+    pub buckets: [u32; 2],
+    /// Имена upstream'ов маршрута
+    pub upstreams: Vec<String>,
+}
+
+impl AbTestConfig {
+    /// Плечи покрывают все бакеты без пересечений и ссылаются на
+    /// upstream'ы маршрута
+    fn validate(&self, route: &RouteRule) -> Result<()> {
+        if self.key_header.is_none() && self.key_cookie.is_none() {
+            return Err(crate::DaoError::config(
+                "ab_test: key_header or key_cookie is required",
+            ));
+        }
+        if let Some(header) = &self.key_header {
+            http::HeaderName::from_bytes(header.as_bytes()).map_err(|_| {
+                crate::DaoError::config(format!("ab_test: invalid key_header '{}'", header))
+            })?;
+        }
+        if self.arms.is_empty() {
+            return Err(crate::DaoError::config("ab_test: no arms"));
+        }
+
+        let mut owner: Vec<Option<&str>> = vec![None; AB_BUCKETS as usize];
+        for arm in &self.arms {
+            if self.arms.iter().filter(|a| a.name == arm.name).count() > 1 {
+                return Err(crate::DaoError::config(format!(
+                    "ab_test: duplicate arm '{}'",
+                    arm.name
+                )));
+            }
+            let [from, to] = arm.buckets;
+            if from > to || to >= AB_BUCKETS {
+                return Err(crate::DaoError::config(format!(
+                    "ab_test: arm '{}' buckets must be within 0..={} and ordered",
+                    arm.name,
+                    AB_BUCKETS - 1
+                )));
+            }
+            for bucket in from..=to {
+                if let Some(other) = owner[bucket as usize].replace(&arm.name) {
+                    return Err(crate::DaoError::config(format!(
+                        "ab_test: bucket {} is in both '{}' and '{}'",
+                        bucket, other, arm.name
+                    )));
+                }
+            }
+            if arm.upstreams.is_empty() {
+                return Err(crate::DaoError::config(format!(
+                    "ab_test: arm '{}' has no upstreams",
+                    arm.name
+                )));
+            }
+            if let Some(unknown) = arm
+                .upstreams
+                .iter()
+                .find(|name| !route.upstreams.iter().any(|u| &u.name == *name))
+            {
+                return Err(crate::DaoError::config(format!(
+                    "ab_test: arm '{}' references unknown upstream '{}'",
+                    arm.name, unknown
+                )));
+            }
+        }
+        if let Some(bucket) = owner.iter().position(Option::is_none) {
+            return Err(crate::DaoError::config(format!(
+                "ab_test: bucket {} is not assigned to any arm",
+                bucket
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl RouteRule {
+    pub fn validate(&self) -> Result<()> {
+        if self.upstreams.is_empty() {
+            return Err(crate::DaoError::config(format!(
+                "Route '{}' has no upstreams",
+                self.name
+            )));
+        }
+        for upstream in &self.upstreams {
+            upstream
+                .validate()
+                .map_err(|e| crate::DaoError::config(format!("Route '{}': {}", self.name, e)))?;
+        }
+        if let Some(filters) = &self.filters {
+            filters
+                .validate()
+                .map_err(|e| crate::DaoError::config(format!("Route '{}': {}", self.name, e)))?;
+        }
+        if self.deadline_ms == Some(0) {
+            return Err(crate::DaoError::config(format!(
+                "Route '{}': deadline_ms must be > 0",
+                self.name
+            )));
+        }
+        if self.request_timeout_ms == Some(0) {
+            return Err(crate::DaoError::config(format!(
+                "Route '{}': request_timeout_ms must be > 0",
+                self.name
+            )));
+        }
+        if self.selection_hold_ms == Some(0) {
+            return Err(crate::DaoError::config(format!(
+                "Route '{}': selection_hold_ms must be > 0",
+                self.name
+            )));
+        }
+        if let Some(methods) = &self.match_rule.methods {
+            if methods.is_empty() {
+                return Err(crate::DaoError::config(format!(
+                    "Route '{}': match.methods is empty",
+                    self.name
+                )));
+            }
+            if let Some(method) = methods
+                .iter()
+                .find(|m| http::Method::from_bytes(m.as_bytes()).is_err())
+            {
+                return Err(crate::DaoError::config(format!(
+                    "Route '{}': invalid method {}",
+                    self.name, method
+                )));
+            }
+        }
+        if let Some(ab_test) = &self.ab_test {
+            ab_test
+                .validate(self)
+                .map_err(|e| crate::DaoError::config(format!("Route '{}': {}", self.name, e)))?;
+        }
+        Ok(())
+    }
+
+    /// Порядок при выборе маршрута: приоритет, затем специфичность match
+    fn rank(&self) -> (i32, (u8, usize, bool)) {
+        (self.priority.unwrap_or(0), self.match_rule.specificity())
+    }
+
+    /// Бюджет времени запроса
+    pub fn deadline(&self) -> Option<std::time::Duration> {
+        self.deadline_ms.map(std::time::Duration::from_millis)
+    }
+
+    pub fn request_timeout(&self) -> Option<std::time::Duration> {
+        self.request_timeout_ms
+            .map(std::time::Duration::from_millis)
+    }
+
+    pub fn selection_hold(&self) -> Option<std::time::Duration> {
+        self.selection_hold_ms.map(std::time::Duration::from_millis)
+    }
+
+    pub fn intent(&self) -> Option<Intent> {
+        self.intent.as_ref().map(|s| Intent::new(s.clone()))
+    }
+}
+
+/// `upgrade` правила, совпадающий с любым запросом на смену протокола
+pub const UPGRADE_ANY: &str = "*";
+
+/// Правило матчинга запроса
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct MatchRule {
+    pub host: Option<String>,
+    pub path_prefix: Option<String>,
+    pub path_exact: Option<String>,
+    /// Токен `Upgrade` (без учета регистра): `websocket`, `h2c`; `*` —
+    /// любой запрос на смену протокола
+    pub upgrade: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+    /// HTTP методы (без учета регистра); None — любые
+    pub methods: Option<Vec<String>>,
+    /// CN сертификата клиента (mTLS, `tls_client_ca` listener'а)
+    pub client_cert_cn: Option<String>,
+    /// Один из SAN сертификата клиента (DNS, URI, email, IP)
+    pub client_cert_san: Option<String>,
+}
+
+impl MatchRule {
+    /// Специфичность: вид условия на путь (exact > prefix > нет), длина
+    /// prefix'а, наличие условия на host
+    fn specificity(&self) -> (u8, usize, bool) {
+        let path = if self.path_exact.is_some() {
+            2
+        } else if self.path_prefix.is_some() {
+            1
+        } else {
+            0
+        };
+        let prefix_len = self.path_prefix.as_ref().map_or(0, String::len);
+        (path, prefix_len, self.host.is_some())
+    }
+
+    /// Проверка соответствия запроса правилу
+    pub fn matches(&self, ctx: &MatchContext<'_>) -> bool {
+        self.matches_target(ctx) && self.allows_method(ctx.method)
+    }
+
+    /// Разрешен ли метод (`methods` не задан — любой)
+    pub fn allows_method(&self, method: &http::Method) -> bool {
+        self.methods.as_ref().is_none_or(|methods| {
+            methods
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(method.as_str()))
+        })
+    }
+
+    /// Совпадение по всем условиям, кроме метода
+    fn matches_target(&self, ctx: &MatchContext<'_>) -> bool {
+        if self
+            .host
+            .as_ref()
+            .is_some_and(|host| ctx.host != Some(host.as_str()))
+        {
+            return false;
+        }
+        if self
+            .path_prefix
+            .as_ref()
+            .is_some_and(|prefix| !ctx.path.starts_with(prefix.as_str()))
+        {
+            return false;
+        }
+        if self
+            .path_exact
+            .as_ref()
+            .is_some_and(|exact| ctx.path != exact)
+        {
+            return false;
+        }
+        // Upgrade (WebSocket и любые другие протоколы)
+        if let Some(upgrade) = &self.upgrade {
+            let matched = ctx.upgrade.is_some_and(|requested| {
+                upgrade == UPGRADE_ANY || requested.eq_ignore_ascii_case(upgrade)
+            });
+            if !matched {
+                return false;
+            }
+        }
+        // Identity клиента из сертификата mTLS
+        if let Some(cn) = &self.client_cert_cn {
+            if ctx.client_identity.and_then(|id| id.common_name.as_deref()) != Some(cn.as_str()) {
+                return false;
+            }
+        }
+        if let Some(san) = &self.client_cert_san {
+            if !ctx.client_identity.is_some_and(|id| id.has_san(san)) {
+                return false;
+            }
+        }
+        self.headers.iter().flatten().all(|(name, expected)| {
+            ctx.headers.get(name).and_then(|v| v.to_str().ok()) == Some(expected.as_str())
+        })
+    }
+}
+
+/// Части запроса, по которым выбирается маршрут: извлекаются один раз
+/// и не требуют тела (тесты, `/debug/explain`)
+#[derive(Debug, Clone, Copy)]
+pub struct MatchContext<'a> {
+    /// Заголовок `Host`
+    pub host: Option<&'a str>,
+    pub path: &'a str,
+    pub method: &'a http::Method,
+    pub headers: &'a http::HeaderMap,
+    pub query: Option<&'a str>,
+    /// Заголовок `Upgrade`
+    pub upgrade: Option<&'a str>,
+    /// Identity из сертификата клиента (extensions запроса)
+    pub client_identity: Option<&'a crate::gate::ClientIdentity>,
+}
+
+impl<'a> MatchContext<'a> {
+    pub fn from_request<B>(req: &'a http::Request<B>) -> Self {
+        let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+        Self {
+            host: header(http::header::HOST),
+            path: req.uri().path(),
+            method: req.method(),
+            headers: req.headers(),
+            query: req.uri().query(),
+            upgrade: header(http::header::UPGRADE),
+            client_identity: req.extensions().get(),
+        }
+    }
+}
+
+/// Тела ответов с ошибками: `[error_pages.502]`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorPagesConfig {
+    /// Переопределения по HTTP статусу
+    #[serde(flatten)]
+    pub pages: std::collections::BTreeMap<String, ErrorPage>,
+}
+
+impl ErrorPagesConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (status, page) in &self.pages {
+            if !status
+                .parse::<u16>()
+                .is_ok_and(|s| (400..=599).contains(&s))
+            {
+                return Err(crate::DaoError::config(format!(
+                    "Invalid error page status: {}",
+                    status
+                )));
+            }
+            http::HeaderValue::from_str(&page.content_type).map_err(|_| {
+                crate::DaoError::config(format!(
+                    "Invalid error page content type for {}: {}",
+                    status, page.content_type
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Переопределение для статуса
+    pub fn page(&self, status: u16) -> Option<&ErrorPage> {
+        self.pages.get(&status.to_string())
+    }
+}
+
+/// Тело ошибки; `{request_id}` в `body` заменяется на ID запроса
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorPage {
+    pub body: String,
+    #[serde(default = "default_error_content_type")]
+    pub content_type: String,
+}
+
+fn default_error_content_type() -> String {
+    "application/json".to_string()
+}
+
+/// Правила определения intent по запросу
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct IntentRulesConfig {
+    /// Заголовок, которым клиент явно задает intent (например, `X-Intent`)
+    pub header: Option<String>,
+    #[serde(default)]
+    pub rule: Vec<IntentRule>,
+}
+
+impl IntentRulesConfig {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(header) = &self.header {
+            http::HeaderName::from_bytes(header.as_bytes()).map_err(|_| {
+                crate::DaoError::config(format!("Invalid intent header: {}", header))
+            })?;
+        }
+        for rule in &self.rule {
+            rule.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Правило: запрос с подходящим путем/методом получает intent
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IntentRule {
+    pub intent: String,
+    pub path_prefix: Option<String>,
+    pub methods: Option<Vec<String>>,
+}
+
+impl IntentRule {
+    pub fn validate(&self) -> Result<()> {
+        if self.path_prefix.is_none() && self.methods.is_none() {
+            return Err(crate::DaoError::config(format!(
+                "Intent rule '{}' has no path_prefix or methods",
+                self.intent
+            )));
+        }
+        for method in self.methods.iter().flatten() {
+            http::Method::from_bytes(method.as_bytes()).map_err(|_| {
+                crate::DaoError::config(format!(
+                    "Intent rule '{}': invalid method {}",
+                    self.intent, method
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Проверка соответствия запроса правилу
+    pub fn matches<B>(&self, req: &http::Request<B>) -> bool {
+        if let Some(prefix) = &self.path_prefix {
+            if !req.uri().path().starts_with(prefix) {
+                return false;
+            }
+        }
+        if let Some(methods) = &self.methods {
+            if !methods
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(req.method().as_str()))
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn intent(&self) -> Intent {
+        Intent::new(self.intent.clone())
+    }
+}
+
+/// Конфигурация upstream'а
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UpstreamConfig {
+    pub name: String,
+    pub url: String,
+    /// Intent'ы upstream'а, опционально с весом: `"realtime:0.9"`
+    pub intent: Option<Vec<String>>,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Не проверять TLS сертификат `https://` upstream'а (только для dev)
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    /// PEM файл с CA для проверки сертификата upstream'а
+    pub ca_cert: Option<String>,
+    /// HTTP/2 к upstream'у: h2c для `http://`, ALPN h2 для `https://` (gRPC)
+    #[serde(default)]
+    pub http2: bool,
+    /// Ожидание ответа upstream'а (сек), по истечении — 504
+    pub timeout_secs: Option<u64>,
+    /// Установка соединения: TCP connect и TLS handshake (мс)
+    pub connect_timeout_ms: Option<u64>,
+    /// Предел запросов в полете: занятый upstream не выбирается
+    pub max_concurrency: Option<usize>,
+    /// Host (для HTTP/2 — `:authority`) запроса к upstream'у;
+    /// по умолчанию в HTTP/1.1 передается Host клиента, в HTTP/2
+    /// `:authority` — адрес из `url`
+    pub override_host: Option<String>,
+    /// Circuit breaker: размыкание после ошибок подряд, пробы при восстановлении
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// Circuit breaker upstream'а (`[routes.rule.upstreams.circuit_breaker]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CircuitBreakerConfig {
+    /// Ошибок подряд (соединение, таймаут, 5xx) до размыкания
+    #[serde(default = "default_breaker_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Время в разомкнутом состоянии до пробных запросов (сек)
+    #[serde(default = "default_breaker_open_secs")]
+    pub open_secs: u64,
+    /// Пробных запросов одновременно в полуоткрытом состоянии; столько же
+    /// успешных замыкают цепь
+    #[serde(default = "default_breaker_half_open_probes")]
+    pub half_open_probes: u32,
+}
+
+fn default_breaker_failure_threshold() -> u32 {
+    5
+}
+
+fn default_breaker_open_secs() -> u64 {
+    30
+}
+
+fn default_breaker_half_open_probes() -> u32 {
+    1
+}
+
+impl UpstreamConfig {
+    pub fn validate(&self) -> Result<()> {
+        let uri: http::Uri = self.url.parse().map_err(|e| {
+            crate::DaoError::config(format!("Upstream {}: invalid url: {}", self.name, e))
+        })?;
+        if !matches!(uri.scheme_str(), Some("http" | "https" | "ws" | "wss")) {
+            return Err(crate::DaoError::config(format!(
+                "Upstream {}: url scheme must be http, https, ws or wss",
+                self.name
+            )));
+        }
+
+        if self.max_concurrency == Some(0) {
+            return Err(crate::DaoError::config(format!(
+                "Upstream {}: max_concurrency must be > 0",
+                self.name
+            )));
+        }
+        if let Some(breaker) = &self.circuit_breaker {
+            if breaker.failure_threshold == 0
+                || breaker.open_secs == 0
+                || breaker.half_open_probes == 0
+            {
+                return Err(crate::DaoError::config(format!(
+                    "Upstream {}: circuit_breaker failure_threshold, open_secs and half_open_probes must be > 0",
+                    self.name
+                )));
+            }
+        }
+        if let Some(host) = &self.override_host {
+            host.parse::<http::uri::Authority>().map_err(|e| {
+                crate::DaoError::config(format!(
+                    "Upstream {}: invalid override_host {:?}: {}",
+                    self.name, host, e
+                ))
+            })?;
+        }
+
+        for intent in self.intent.iter().flatten() {
+            WeightedIntent::parse(intent)?;
+        }
+        Ok(())
+    }
+
+    /// Intent'ы с весами (некорректные записи отсекаются при валидации)
+    pub fn intents(&self) -> Vec<WeightedIntent> {
+        self.intent
+            .iter()
+            .flatten()
+            .filter_map(|s| WeightedIntent::parse(s).ok())
+            .collect()
+    }
+}
+
+/// Конфигурация фильтров
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FilterConfig {
+    /// Заголовки запроса к upstream'у; в значениях подставляются
+    /// `{route}`, `{client_ip}`, `{request_id}` и `{upstream}`
+    pub request_headers_add: Option<HashMap<String, String>>,
+    /// Удаляются до добавления: заголовок из обоих списков остается со
+    /// значением из `*_headers_add` (раньше — снимался)
+    pub request_headers_remove: Option<Vec<String>>,
+    /// Неизвестная `{переменная}` в `request_headers_add`: остается как
+    /// есть или ошибка конфигурации
+    #[serde(default)]
+    pub unknown_header_variables: UnknownHeaderVariables,
+    pub response_headers_add: Option<HashMap<String, String>>,
+    /// Заголовки, удаляемые из ответа upstream'а (`Server`, `X-Powered-By`)
+    pub response_headers_remove: Option<Vec<String>>,
+    /// Статичное значение `Server` в ответах вместо заголовка upstream'а
+    pub server_header: Option<String>,
+    /// Лимит запросов в секунду на ключ (429 сверх лимита)
+    pub rate_limit_rps: Option<u32>,
+    /// Емкость bucket'а (по умолчанию = `rate_limit_rps`)
+    pub rate_limit_burst: Option<u32>,
+    /// Ключ лимита: маршрут целиком, IP клиента или заголовок
+    #[serde(default)]
+    pub rate_limit_key: RateLimitKey,
+    pub cors: Option<CorsConfig>,
+    pub jwt: Option<JwtConfig>,
+    pub basic_auth: Option<BasicAuthConfig>,
+    /// Кэш ответов upstream'а (GET, по `Cache-Control`)
+    pub cache: Option<CacheConfig>,
+    /// Повтор запроса с тем же `Idempotency-Key` получает сохраненный
+    /// ответ вместо повторного проксирования
+    pub idempotency: Option<IdempotencyConfig>,
+    /// Замена тел ошибок upstream'а (HTML 5xx) на JSON конверт
+    pub error_shaping: Option<ErrorShapingConfig>,
+    /// Разрешенные сети клиента (пусто — все)
+    #[serde(default)]
+    #[schemars(with = "Vec<String>")]
+    pub allow_cidrs: Vec<ipnet::IpNet>,
+    /// Запрещенные сети клиента (приоритетнее allow)
+    #[serde(default)]
+    #[schemars(with = "Vec<String>")]
+    pub deny_cidrs: Vec<ipnet::IpNet>,
+    /// Разрешенные CN сертификата клиента (mTLS); с `client_cert_san` —
+    /// достаточно любого совпадения, иначе 403 (пусто — все)
+    #[serde(default)]
+    pub client_cert_cn: Vec<String>,
+    /// Разрешенные SAN сертификата клиента
+    #[serde(default)]
+    pub client_cert_san: Vec<String>,
+}
+
+/// Нормализация ошибок upstream'а: тело ответа с одним из `statuses`
+/// заменяется шаблоном, статус сохраняется.
+///
+/// В `body` подставляются `{status}`, `{request_id}` и `{upstream}`;
+/// тело по умолчанию имени upstream'а не раскрывает.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorShapingConfig {
+    pub statuses: Vec<u16>,
+    #[serde(default = "default_error_shaping_body")]
+    pub body: String,
+    #[serde(default = "default_error_content_type")]
+    pub content_type: String,
+}
+
+fn default_error_shaping_body() -> String {
+    r#"{"error":"upstream_error","status":{status},"request_id":"{request_id}"}"#.to_string()
+}
+
+impl ErrorShapingConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.statuses.is_empty() {
+            return Err(crate::DaoError::config("error_shaping.statuses is empty"));
+        }
+        if let Some(status) = self.statuses.iter().find(|s| !(400..=599).contains(*s)) {
+            return Err(crate::DaoError::config(format!(
+                "Invalid error_shaping status: {}",
+                status
+            )));
+        }
+        http::HeaderValue::from_str(&self.content_type).map_err(|_| {
+            crate::DaoError::config(format!(
+                "Invalid error_shaping content type: {}",
+                self.content_type
+            ))
+        })?;
+        Ok(())
+    }
+}
+
+/// Кэш ответов маршрута
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CacheConfig {
+    /// Общий объем кэша маршрута (байт)
+    #[serde(default = "default_cache_max_bytes")]
+    pub max_bytes: usize,
+    /// Максимальный размер одного ответа (байт)
+    #[serde(default = "default_cache_max_entry_bytes")]
+    pub max_entry_bytes: usize,
+}
+
+fn default_cache_max_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_cache_max_entry_bytes() -> usize {
+    1024 * 1024
+}
+
+/// Ответы по `Idempotency-Key`: ключ — метод, путь, значение заголовка,
+/// учетные данные клиента (`Authorization`, без них — адрес) и тело запроса
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct IdempotencyConfig {
+    /// Сколько хранится ответ (с)
+    #[serde(default = "default_idempotency_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Общий объем сохраненных ответов маршрута (байт)
+    #[serde(default = "default_cache_max_bytes")]
+    pub max_bytes: usize,
+    /// Ответ больше лимита (байт) не сохраняется
+    #[serde(default = "default_cache_max_entry_bytes")]
+    pub max_entry_bytes: usize,
+    /// Тело запроса с ключом читается целиком для отпечатка; больше
+    /// лимита (байт) — 413
+    #[serde(default = "default_cache_max_entry_bytes")]
+    pub max_request_bytes: usize,
+    /// Сколько повтор ждет первый запрос с тем же ключом (мс), затем 409
+    #[serde(default = "default_idempotency_wait_ms")]
+    pub wait_ms: u64,
+}
+
+fn default_idempotency_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_idempotency_wait_ms() -> u64 {
+    10_000
+}
 
-        // Валидация каждого маршрута
-        for route in &self.routes.rule {
-            route.validate()?;
-        }
+impl IdempotencyConfig {
+    pub fn ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ttl_secs)
+    }
 
-        Ok(())
+    pub fn wait(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.wait_ms)
     }
 }
 
-/// Конфигурация сервера
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ServerConfig {
-    pub bind: String,
-    pub tls_cert: Option<String>,
-    pub tls_key: Option<String>,
-    #[serde(default = "default_workers")]
-    pub workers: usize,
+/// Обработка неизвестных переменных в шаблонах заголовков
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownHeaderVariables {
+    /// Подставляется как написано: `{tenant}`
+    #[default]
+    Literal,
+    /// Ошибка при проверке конфигурации
+    Reject,
 }
 
-fn default_workers() -> usize {
-    num_cpus::get()
+/// Ключ rate limit: `"route"`, `"client_ip"` или `{ header = "X-Api-Key" }`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    #[default]
+    Route,
+    ClientIp,
+    Header(String),
 }
 
-/// Конфигурация телеметрии
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TelemetryConfig {
-    pub prometheus_bind: String,
+impl FilterConfig {
+    pub fn validate(&self) -> Result<()> {
+        let added = [&self.request_headers_add, &self.response_headers_add]
+            .into_iter()
+            .flatten()
+            .flatten();
+        for (name, value) in added {
+            http::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| crate::DaoError::config(format!("Invalid header name: {}", name)))?;
+            http::HeaderValue::from_str(value).map_err(|_| {
+                crate::DaoError::config(format!("Invalid value for header {}", name))
+            })?;
+        }
+        if self.unknown_header_variables == UnknownHeaderVariables::Reject {
+            for (name, value) in self.request_headers_add.iter().flatten() {
+                if let Some(unknown) = crate::flow::template::unknown_variables(value).first() {
+                    return Err(crate::DaoError::config(format!(
+                        "Unknown variable {{{}}} in header {}",
+                        unknown, name
+                    )));
+                }
+            }
+        }
+        let removed = [&self.request_headers_remove, &self.response_headers_remove]
+            .into_iter()
+            .flatten()
+            .flatten();
+        for name in removed {
+            http::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| crate::DaoError::config(format!("Invalid header name: {}", name)))?;
+        }
+        if let Some(server) = &self.server_header {
+            http::HeaderValue::from_str(server)
+                .map_err(|_| crate::DaoError::config("Invalid server_header value"))?;
+        }
+        if self.rate_limit_rps == Some(0) || self.rate_limit_burst == Some(0) {
+            return Err(crate::DaoError::config(
+                "rate_limit_rps and rate_limit_burst must be > 0",
+            ));
+        }
+        if let RateLimitKey::Header(name) = &self.rate_limit_key {
+            http::HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                crate::DaoError::config(format!("Invalid rate_limit_key header: {}", name))
+            })?;
+        }
+        if let Some(basic_auth) = &self.basic_auth {
+            basic_auth.validate()?;
+        }
+        if let Some(cors) = &self.cors {
+            cors.validate()?;
+        }
+        if let Some(jwt) = &self.jwt {
+            jwt.validate()?;
+        }
+        if let Some(error_shaping) = &self.error_shaping {
+            error_shaping.validate()?;
+        }
+        if let Some(idempotency) = &self.idempotency {
+            if idempotency.ttl_secs == 0
+                || idempotency.max_entry_bytes == 0
+                || idempotency.wait_ms == 0
+            {
+                return Err(crate::DaoError::config(
+                    "idempotency: ttl_secs, max_entry_bytes and wait_ms must be > 0",
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
-/// Конфигурация маршрутов
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RoutesConfig {
-    pub rule: Vec<RouteRule>,
+/// Конфигурация CORS
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CorsConfig {
+    /// Разрешенные origin'ы (точные значения или `*`)
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// Access-Control-Max-Age в секундах
+    pub max_age: Option<u64>,
 }
 
-/// Правило маршрутизации
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RouteRule {
-    pub name: String,
-    #[serde(rename = "match")]
-    pub match_rule: MatchRule,
-    pub policy: String,
-    pub intent: Option<String>,
-    pub upstreams: Vec<UpstreamConfig>,
-    pub filters: Option<FilterConfig>,
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "HEAD", "POST"]
+        .iter()
+        .map(|m| m.to_string())
+        .collect()
 }
 
-impl RouteRule {
+impl CorsConfig {
     pub fn validate(&self) -> Result<()> {
-        if self.upstreams.is_empty() {
-            return Err(crate::DaoError::config(format!(
-                "Route '{}' has no upstreams",
-                self.name
-            )));
+        if self.allowed_origins.is_empty() {
+            return Err(crate::DaoError::config("cors.allowed_origins is empty"));
+        }
+        // Спецификация запрещает `*` вместе с credentials
+        if self.allow_credentials && self.allowed_origins.iter().any(|o| o == "*") {
+            return Err(crate::DaoError::config(
+                "cors: allowed_origins = \"*\" cannot be combined with allow_credentials",
+            ));
         }
         Ok(())
     }
-
-    pub fn intent(&self) -> Option<Intent> {
-        self.intent.as_ref().map(|s| Intent::new(s.clone()))
-    }
 }
 
-/// Правило матчинга запроса
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct MatchRule {
-    pub host: Option<String>,
-    pub path_prefix: Option<String>,
-    pub path_exact: Option<String>,
-    pub upgrade: Option<String>,
-    pub headers: Option<HashMap<String, String>>,
+/// Конфигурация JWT аутентификации
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JwtConfig {
+    /// Общий секрет для HS* алгоритмов
+    pub secret: Option<String>,
+    /// Публичный ключ в PEM (RS*/PS*/ES*/EdDSA)
+    pub public_key_pem: Option<String>,
+    /// URL JWKS, `https://` или `http://` (ключи кэшируются на `jwks_ttl_secs`)
+    pub jwks_url: Option<String>,
+    #[serde(default = "default_jwks_ttl_secs")]
+    pub jwks_ttl_secs: u64,
+    /// Разрешенные алгоритмы (по умолчанию HS256 для secret, иначе RS256)
+    pub algorithms: Option<Vec<String>>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// Допуск на расхождение часов при проверке exp/nbf
+    #[serde(default)]
+    pub leeway_secs: u64,
+    /// Проброс проверенных claim'ов к upstream: claim -> заголовок
+    pub claims_to_headers: Option<HashMap<String, String>>,
 }
 
-impl MatchRule {
-    /// Проверка соответствия запроса правилу
-    pub fn matches(&self, req: &http::Request<hyper::body::Incoming>) -> bool {
-        // Host matching
-        if let Some(expected_host) = &self.host {
-            let host = req
-                .headers()
-                .get(http::header::HOST)
-                .and_then(|v| v.to_str().ok());
-            if host != Some(expected_host.as_str()) {
-                return false;
-            }
-        }
+fn default_jwks_ttl_secs() -> u64 {
+    300
+}
 
-        // Path matching
-        let path = req.uri().path();
-        if let Some(prefix) = &self.path_prefix {
-            if !path.starts_with(prefix) {
-                return false;
-            }
+impl JwtConfig {
+    pub fn validate(&self) -> Result<()> {
+        let key_sources = [
+            self.secret.is_some(),
+            self.public_key_pem.is_some(),
+            self.jwks_url.is_some(),
+        ];
+        if key_sources.iter().filter(|set| **set).count() != 1 {
+            return Err(crate::DaoError::config(
+                "jwt: exactly one of secret, public_key_pem, jwks_url must be set",
+            ));
         }
-        if let Some(exact) = &self.path_exact {
-            if path != exact {
-                return false;
-            }
+        for alg in self.algorithms() {
+            alg.parse::<jsonwebtoken::Algorithm>().map_err(|_| {
+                crate::DaoError::config(format!("jwt: unknown algorithm '{}'", alg))
+            })?;
         }
-
-        // Upgrade matching (WebSocket)
-        if let Some(expected_upgrade) = &self.upgrade {
-            let upgrade = req
-                .headers()
-                .get(http::header::UPGRADE)
-                .and_then(|v| v.to_str().ok());
-            if upgrade != Some(expected_upgrade.as_str()) {
-                return false;
+        if let Some(claims) = &self.claims_to_headers {
+            for header in claims.values() {
+                http::HeaderName::from_bytes(header.as_bytes()).map_err(|e| {
+                    crate::DaoError::config(format!("jwt: invalid header name '{}': {}", header, e))
+                })?;
             }
         }
+        Ok(())
+    }
 
-        // Header matching
-        if let Some(expected_headers) = &self.headers {
-            for (key, expected_value) in expected_headers {
-                let actual_value = req
-                    .headers()
-                    .get(key)
-                    .and_then(|v| v.to_str().ok());
-                if actual_value != Some(expected_value.as_str()) {
-                    return false;
-                }
-            }
+    /// Эффективный список разрешенных алгоритмов
+    pub fn algorithms(&self) -> Vec<String> {
+        match &self.algorithms {
+            Some(algs) => algs.clone(),
+            None if self.secret.is_some() => vec!["HS256".to_string()],
+            None => vec!["RS256".to_string()],
         }
-
-        true
     }
 }
 
-/// Конфигурация upstream'а
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UpstreamConfig {
-    pub name: String,
-    pub url: String,
-    pub intent: Option<Vec<String>>,
-    #[serde(default = "default_weight")]
-    pub weight: u32,
+/// Конфигурация HTTP Basic аутентификации
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BasicAuthConfig {
+    #[serde(default = "default_basic_auth_realm")]
+    pub realm: String,
+    /// Пользователи в формате `user:bcrypt-hash`
+    pub users: Vec<String>,
 }
 
-fn default_weight() -> u32 {
-    1
+fn default_basic_auth_realm() -> String {
+    "dao".to_string()
 }
 
-impl UpstreamConfig {
-    pub fn intents(&self) -> Vec<Intent> {
-        self.intent
-            .as_ref()
-            .map(|v| v.iter().map(|s| Intent::new(s.clone())).collect())
-            .unwrap_or_default()
+impl BasicAuthConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.users.is_empty() {
+            return Err(crate::DaoError::config("basic_auth: no users defined"));
+        }
+        if self.realm.contains('"') {
+            return Err(crate::DaoError::config(
+                "basic_auth: realm must not contain quotes",
+            ));
+        }
+        for entry in &self.users {
+            let Some((user, hash)) = entry.split_once(':') else {
+                return Err(crate::DaoError::config(
+                    "basic_auth: users must be in 'user:bcrypt-hash' format",
+                ));
+            };
+            hash.parse::<bcrypt::HashParts>().map_err(|_| {
+                crate::DaoError::config(format!("basic_auth: invalid bcrypt hash for '{}'", user))
+            })?;
+        }
+        Ok(())
     }
-}
 
-/// Конфигурация фильтров
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FilterConfig {
-    pub request_headers_add: Option<HashMap<String, String>>,
-    pub request_headers_remove: Option<Vec<String>>,
-    pub response_headers_add: Option<HashMap<String, String>>,
-    pub rate_limit_rps: Option<u32>,
+    /// Пары (пользователь, хэш)
+    pub fn credentials(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.users.iter().filter_map(|entry| entry.split_once(':'))
+    }
 }
 
 /// Конфигурация политики
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PolicyConfig {
     #[serde(default = "default_w_load")]
     pub w_load: f64,
@@ -204,11 +2097,60 @@ pub struct PolicyConfig {
     pub w_intent: f64,
     #[serde(default = "default_w_tempo")]
     pub w_tempo: f64,
+    /// Разброс score, в пределах которого upstream'ы выбираются случайно
+    #[serde(default)]
+    pub epsilon: f64,
+    /// Бюджет p99 латентности (мс): политика предпочитает upstream'ы,
+    /// укладывающиеся в него (для `slo` по умолчанию 200)
+    pub p99_budget_ms: Option<f64>,
+    /// Без upstream'а с intent'ом запроса — 503 (или fallback маршрут)
+    /// вместо выбора неподходящего
+    #[serde(default)]
+    pub strict_intent: bool,
+    /// Веса масштабируются к сумме 1.0 перед расчетом score: `6/3/1`
+    /// равносильно `0.6/0.3/0.1` (`epsilon` — в масштабе нормированного
+    /// score). Выключено — веса используются как есть
+    #[serde(default)]
+    pub normalize: bool,
+    /// Шкала латентности в load_resonance: `linear` — p95 /
+    /// `latency_reference_ms`, `log` — log2(1 + p95 / `latency_reference_ms`)
+    #[serde(default)]
+    pub latency_scale: LatencyScale,
+    /// p95 (мс), дающая компоненту латентности 1.0
+    #[serde(default = "default_latency_reference_ms")]
+    pub latency_reference_ms: f64,
+    /// Верхняя граница компоненты латентности: выше нее различия в p95
+    /// не влияют на выбор
+    #[serde(default = "default_latency_cap")]
+    pub latency_cap: f64,
+}
+
+/// Шкала нормирования латентности
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyScale {
+    /// Пропорционально p95
+    #[default]
+    Linear,
+    /// Логарифм p95: различия в сотни мс не заглушают различия в единицы
+    Log,
 }
 
-fn default_w_load() -> f64 { 0.6 }
-fn default_w_intent() -> f64 { 0.3 }
-fn default_w_tempo() -> f64 { 0.1 }
+fn default_w_load() -> f64 {
+    0.6
+}
+fn default_w_intent() -> f64 {
+    0.3
+}
+fn default_w_tempo() -> f64 {
+    0.1
+}
+fn default_latency_reference_ms() -> f64 {
+    100.0
+}
+fn default_latency_cap() -> f64 {
+    10.0
+}
 
 impl Default for PolicyConfig {
     fn default() -> Self {
@@ -216,6 +2158,13 @@ impl Default for PolicyConfig {
             w_load: default_w_load(),
             w_intent: default_w_intent(),
             w_tempo: default_w_tempo(),
+            epsilon: 0.0,
+            p99_budget_ms: None,
+            strict_intent: false,
+            normalize: false,
+            latency_scale: LatencyScale::default(),
+            latency_reference_ms: default_latency_reference_ms(),
+            latency_cap: default_latency_cap(),
         }
     }
 }
@@ -232,15 +2181,771 @@ mod tests {
             path_exact: None,
             upgrade: None,
             headers: None,
+            methods: None,
+            client_cert_cn: None,
+            client_cert_san: None,
         };
 
         let req = http::Request::builder()
             .uri("http://api.example.com/test")
             .header(http::header::HOST, "api.example.com")
-            .body(hyper::body::Incoming::default())
+            .body(())
+            .unwrap();
+        assert!(rule.matches(&MatchContext::from_request(&req)));
+
+        let other = http::Request::builder()
+            .uri("http://other.example.com/test")
+            .header(http::header::HOST, "other.example.com")
+            .body(())
+            .unwrap();
+        assert!(!rule.matches(&MatchContext::from_request(&other)));
+    }
+
+    #[test]
+    fn test_match_context_from_request() {
+        let req = http::Request::post("/ws/chat?room=1")
+            .header(http::header::HOST, "chat.example.com")
+            .header(http::header::UPGRADE, "websocket")
+            .header("x-tenant", "acme")
+            .body(())
+            .unwrap();
+        let ctx = MatchContext::from_request(&req);
+        assert_eq!(ctx.host, Some("chat.example.com"));
+        assert_eq!(ctx.path, "/ws/chat");
+        assert_eq!(ctx.method, http::Method::POST);
+        assert_eq!(ctx.query, Some("room=1"));
+        assert_eq!(ctx.upgrade, Some("websocket"));
+        assert_eq!(ctx.headers["x-tenant"], "acme");
+
+        let bare = http::Request::get("/").body(()).unwrap();
+        let ctx = MatchContext::from_request(&bare);
+        assert_eq!((ctx.host, ctx.query, ctx.upgrade), (None, None, None));
+    }
+
+    #[test]
+    fn test_match_rule_conditions() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("x-tenant", "acme".parse().unwrap());
+        headers.insert("x-env", "prod".parse().unwrap());
+        let ctx = MatchContext {
+            host: Some("api.example.com"),
+            path: "/api/v1/users",
+            method: &http::Method::GET,
+            headers: &headers,
+            query: None,
+            upgrade: None,
+            client_identity: None,
+        };
+        let rule = |toml_str: &str| -> MatchRule { toml::from_str(toml_str).unwrap() };
+
+        // Без условий — любой запрос
+        assert!(rule("").matches(&ctx));
+
+        assert!(rule(r#"host = "api.example.com""#).matches(&ctx));
+        assert!(!rule(r#"host = "example.com""#).matches(&ctx));
+        assert!(!rule(r#"host = "api.example.com""#).matches(&MatchContext { host: None, ..ctx }));
+
+        assert!(rule(r#"path_prefix = "/api/""#).matches(&ctx));
+        assert!(!rule(r#"path_prefix = "/static/""#).matches(&ctx));
+        assert!(rule(r#"path_exact = "/api/v1/users""#).matches(&ctx));
+        assert!(!rule(r#"path_exact = "/api/v1""#).matches(&ctx));
+
+        let websocket = MatchContext {
+            upgrade: Some("websocket"),
+            ..ctx
+        };
+        assert!(rule(r#"upgrade = "websocket""#).matches(&websocket));
+        assert!(!rule(r#"upgrade = "websocket""#).matches(&ctx));
+        let custom = MatchContext {
+            upgrade: Some("custom-proto/1"),
+            ..ctx
+        };
+        assert!(rule(r#"upgrade = "*""#).matches(&custom));
+        assert!(rule(r#"upgrade = "*""#).matches(&websocket));
+        assert!(!rule(r#"upgrade = "*""#).matches(&ctx));
+        assert!(rule(r#"upgrade = "WebSocket""#).matches(&websocket));
+        assert!(!rule(r#"upgrade = "websocket""#).matches(&custom));
+
+        // Заголовки: нужны все, значение — точное совпадение
+        assert!(rule(r#"headers = { x-tenant = "acme", x-env = "prod" }"#).matches(&ctx));
+        assert!(!rule(r#"headers = { x-tenant = "other" }"#).matches(&ctx));
+        assert!(!rule(r#"headers = { x-tenant = "acme", x-region = "eu" }"#).matches(&ctx));
+
+        // Методы без учета регистра
+        assert!(rule(r#"methods = ["get", "POST"]"#).matches(&ctx));
+        assert!(!rule(r#"methods = ["POST"]"#).matches(&ctx));
+        let post = MatchContext {
+            method: &http::Method::POST,
+            ..ctx
+        };
+        assert!(rule(r#"methods = ["POST"]"#).matches(&post));
+
+        // Условия комбинируются через И
+        let combined = rule(
+            r#"
+            host = "api.example.com"
+            path_prefix = "/api/"
+            methods = ["GET"]
+            headers = { x-tenant = "acme" }
+            "#,
+        );
+        assert!(combined.matches(&ctx));
+        assert!(!combined.matches(&MatchContext {
+            path: "/health",
+            ..ctx
+        }));
+
+        // Identity из сертификата клиента; без сертификата — не совпадает
+        let identity = crate::gate::ClientIdentity {
+            common_name: Some("svc-a".to_string()),
+            sans: vec!["spiffe://mesh/svc-a".to_string()],
+        };
+        let mtls = MatchContext {
+            client_identity: Some(&identity),
+            ..ctx
+        };
+        assert!(rule(r#"client_cert_cn = "svc-a""#).matches(&mtls));
+        assert!(!rule(r#"client_cert_cn = "svc-b""#).matches(&mtls));
+        assert!(!rule(r#"client_cert_cn = "svc-a""#).matches(&ctx));
+        assert!(rule(r#"client_cert_san = "spiffe://mesh/svc-a""#).matches(&mtls));
+        assert!(!rule(r#"client_cert_san = "svc-a""#).matches(&mtls));
+    }
+
+    #[test]
+    fn test_ab_test_validation() {
+        let route = |arms: &str| -> RouteRule {
+            toml::from_str(&format!(
+                r#"
+                name = "checkout"
+                policy = "resonant"
+                match = {{ path_prefix = "/" }}
+                upstreams = [
+                    {{ name = "stable", url = "http://127.0.0.1:8081" }},
+                    {{ name = "next", url = "http://127.0.0.1:8082" }},
+                ]
+                [ab_test]
+                key_header = "x-user-id"
+                {}
+                "#,
+                arms
+            ))
+            .unwrap()
+        };
+
+        let valid = r#"
+            [[ab_test.arms]]
+            name = "control"
+            buckets = [0, 49]
+            upstreams = ["stable"]
+            [[ab_test.arms]]
+            name = "variant"
+            buckets = [50, 99]
+            upstreams = ["next"]
+        "#;
+        route(valid).validate().unwrap();
+
+        for (arms, error) in [
+            (
+                valid.replace("[50, 99]", "[40, 99]"),
+                "bucket 40 is in both",
+            ),
+            (
+                valid.replace("[50, 99]", "[60, 99]"),
+                "bucket 50 is not assigned",
+            ),
+            (
+                valid.replace("[50, 99]", "[50, 100]"),
+                "buckets must be within",
+            ),
+            (
+                valid.replace("[\"next\"]", "[\"missing\"]"),
+                "unknown upstream 'missing'",
+            ),
+        ] {
+            let err = route(&arms).validate().unwrap_err().to_string();
+            assert!(err.contains(error), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_allowed_methods_aggregated() {
+        let routes: RoutesConfig = toml::from_str(
+            r#"
+            [[rule]]
+            name = "read"
+            policy = "resonant"
+            match = { path_prefix = "/items", methods = ["get", "HEAD"] }
+            upstreams = [{ name = "reader", url = "http://127.0.0.1:8081" }]
+
+            [[rule]]
+            name = "write"
+            policy = "resonant"
+            match = { path_prefix = "/items", methods = ["POST", "GET"] }
+            upstreams = [{ name = "writer", url = "http://127.0.0.1:8082" }]
+            "#,
+        )
+        .unwrap();
+        let request = |method: &str, path: &str| {
+            http::Request::builder()
+                .method(method)
+                .uri(path)
+                .body(())
+                .unwrap()
+        };
+
+        let delete = request("DELETE", "/items/1");
+        let delete = MatchContext::from_request(&delete);
+        assert!(routes.find_route(&delete).is_none());
+        assert_eq!(routes.allowed_methods(&delete), ["GET", "HEAD", "POST"]);
+
+        let post = request("POST", "/items/1");
+        assert_eq!(
+            routes
+                .find_route(&MatchContext::from_request(&post))
+                .unwrap()
+                .name,
+            "write"
+        );
+        let other = request("DELETE", "/other");
+        assert!(routes
+            .allowed_methods(&MatchContext::from_request(&other))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_server_listeners() {
+        let server: ServerConfig = toml::from_str(
+            r#"
+            bind = "0.0.0.0:8080"
+
+            [[listen]]
+            bind = "0.0.0.0:8443"
+            tls_cert = "certs/dao.crt"
+            tls_key = "certs/dao.key"
+            "#,
+        )
+        .unwrap();
+
+        let listeners = server.listeners();
+        assert_eq!(listeners.len(), 2);
+        assert_eq!(listeners[0].bind, "0.0.0.0:8080");
+        assert!(listeners[0].tls_cert.is_none());
+        assert_eq!(listeners[1].bind, "0.0.0.0:8443");
+        assert!(listeners[1].validate().is_ok());
+
+        let half_tls = ListenConfig {
+            bind: "0.0.0.0:8443".to_string(),
+            tls_cert: Some("certs/dao.crt".to_string()),
+            tls_key: None,
+            alpn: None,
+            alpn_strict: false,
+            tls_client_ca: None,
+            proxy_protocol: false,
+        };
+        assert!(half_tls.validate().is_err());
+
+        let mut bad_alpn = listeners[1].clone();
+        bad_alpn.alpn = Some(vec!["h3".to_string()]);
+        assert!(bad_alpn.validate().is_err());
+        bad_alpn.alpn = Some(vec!["h2".to_string()]);
+        assert!(bad_alpn.validate().is_ok());
+    }
+
+    #[test]
+    fn test_check_file_reports_all_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [stats]
+            ewma_alpha = 2.0
+
+            [error_pages.200]
+            body = "ok"
+
+            [[routes.rule]]
+            name = "no-upstreams"
+            policy = "resonant"
+            upstreams = []
+            [routes.rule.match]
+            path_prefix = "/"
+
+            [[routes.rule]]
+            name = "bad-url"
+            policy = "resonant"
+            [routes.rule.match]
+            path_prefix = "/v2/"
+            [[routes.rule.upstreams]]
+            name = "backend"
+            url = "ftp://127.0.0.1:21"
+            "#,
+        )
+        .unwrap();
+
+        let errors: Vec<String> = DaoConfig::check_file(&path)
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(errors.len(), 4, "{:#?}", errors);
+        assert!(errors[0].contains("ewma_alpha"));
+        assert!(errors[1].contains("error page status: 200"));
+        assert!(errors[2].contains("'no-upstreams' has no upstreams"));
+        assert!(errors[3].contains("Route 'bad-url'") && errors[3].contains("scheme"));
+
+        // Синтаксическая ошибка — одна ошибка разбора
+        std::fs::write(&path, "[server\nbind =").unwrap();
+        let errors = DaoConfig::check_file(&path).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().contains("Failed to parse config"));
+
+        // Пример конфигурации из репозитория валиден
+        let example = concat!(env!("CARGO_MANIFEST_DIR"), "/../../configs/dao.toml");
+        if let Err(errors) = DaoConfig::check_file(example) {
+            panic!("example config is invalid: {:?}", errors);
+        }
+    }
+
+    #[test]
+    fn test_expand_env() {
+        std::env::set_var("DAO_TEST_BACKEND", "10.0.0.5:8081");
+        let expanded = expand_env(
+            "url = \"http://${DAO_TEST_BACKEND}\"\ntoken = \"${DAO_TEST_UNSET:-dev}\"\nhash = \"$2y$10$$${x}\"",
+        )
+        .unwrap();
+        assert_eq!(
+            expanded,
+            "url = \"http://10.0.0.5:8081\"\ntoken = \"dev\"\nhash = \"$2y$10$${x}\""
+        );
+
+        assert!(expand_env("token = \"${DAO_TEST_UNSET}\"").is_err());
+        assert!(expand_env("token = \"${DAO_TEST_BACKEND\"").is_err());
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = DaoConfig::json_schema();
+        let properties = &schema["properties"];
+        assert!(properties["server"].is_object());
+        assert!(properties["routes"].is_object());
+        assert!(schema.to_string().contains("allow_upstream_override"));
+    }
+
+    #[test]
+    fn test_fallback_chain() {
+        let routes: RoutesConfig = toml::from_str(
+            r#"
+            [[rule]]
+            name = "primary"
+            policy = "resonant"
+            fallback_route = "secondary"
+            match = { path_prefix = "/" }
+            upstreams = [{ name = "a", url = "http://a" }]
+
+            [[rule]]
+            name = "secondary"
+            policy = "resonant"
+            fallback_route = "tertiary"
+            match = { path_prefix = "/" }
+            upstreams = [{ name = "b", url = "http://b" }]
+
+            [[rule]]
+            name = "tertiary"
+            policy = "resonant"
+            fallback_route = "primary"
+            match = { path_prefix = "/" }
+            upstreams = [{ name = "c", url = "http://c" }]
+            "#,
+        )
+        .unwrap();
+
+        // Цикл primary → secondary → tertiary → primary обрывается
+        let names: Vec<_> = routes
+            .fallback_chain(&routes.rule[0])
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect();
+        assert_eq!(names, ["primary", "secondary", "tertiary"]);
+
+        // Длинная цепочка ограничена глубиной
+        let mut long = RoutesConfig::default();
+        for i in 0..10 {
+            let mut route = routes.rule[0].clone();
+            route.name = format!("r{}", i);
+            route.fallback_route = Some(format!("r{}", i + 1));
+            long.rule.push(route);
+        }
+        assert_eq!(
+            long.fallback_chain(&long.rule[0]).len(),
+            MAX_FALLBACK_DEPTH + 1
+        );
+    }
+
+    #[test]
+    fn test_find_route_prefers_specific_match() {
+        let routes: RoutesConfig = toml::from_str(
+            r#"
+            [[rule]]
+            name = "catch-all"
+            policy = "resonant"
+            match = { path_prefix = "/" }
+            upstreams = [{ name = "a", url = "http://a" }]
+
+            [[rule]]
+            name = "api"
+            policy = "resonant"
+            match = { path_prefix = "/api/" }
+            upstreams = [{ name = "b", url = "http://b" }]
+
+            [[rule]]
+            name = "users"
+            policy = "resonant"
+            match = { path_exact = "/api/v1/users" }
+            upstreams = [{ name = "c", url = "http://c" }]
+            "#,
+        )
+        .unwrap();
+        let route = |path: &str| {
+            let req = http::Request::get(path).body(()).unwrap();
+            routes
+                .find_route(&MatchContext::from_request(&req))
+                .unwrap()
+                .name
+                .clone()
+        };
+
+        assert_eq!(route("/api/v1/users"), "users");
+        assert_eq!(route("/api/v1/orders"), "api");
+        assert_eq!(route("/index.html"), "catch-all");
+
+        // Явный приоритет сильнее специфичности
+        let mut routes = routes;
+        routes.rule[0].priority = Some(10);
+        let req = http::Request::get("/api/v1/users").body(()).unwrap();
+        assert_eq!(
+            routes
+                .find_route(&MatchContext::from_request(&req))
+                .unwrap()
+                .name,
+            "catch-all"
+        );
+
+        // При равном ранге — первый в файле
+        routes.rule[0].priority = None;
+        routes.rule[2] = routes.rule[1].clone();
+        routes.rule[2].name = "api-copy".to_string();
+        let req = http::Request::get("/api/v1/users").body(()).unwrap();
+        assert_eq!(
+            routes
+                .find_route(&MatchContext::from_request(&req))
+                .unwrap()
+                .name,
+            "api"
+        );
+    }
+
+    #[test]
+    fn test_invalid_header_names_rejected() {
+        let filters: FilterConfig =
+            toml::from_str(r#"response_headers_remove = ["Server", "X-Powered-By"]"#).unwrap();
+        assert!(filters.validate().is_ok());
+
+        for bad in [
+            r#"response_headers_remove = ["bad header"]"#,
+            r#"response_headers_add = { "x-ok" = "line\nbreak" }"#,
+            r#"request_headers_add = { "bad:name" = "v" }"#,
+            r#"server_header = "dao\r\n""#,
+        ] {
+            let filters: FilterConfig = toml::from_str(bad).unwrap();
+            assert!(filters.validate().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_default_route_validation() {
+        let mut routes: RoutesConfig = toml::from_str(
+            r#"
+            default = "api"
+
+            [[rule]]
+            name = "api"
+            policy = "resonant"
+            match = { path_prefix = "/api/" }
+            upstreams = [{ name = "a", url = "http://a" }]
+            "#,
+        )
+        .unwrap();
+        assert!(routes.validate_default().is_ok());
+        let req = http::Request::get("/other").body(()).unwrap();
+        let ctx = MatchContext::from_request(&req);
+        assert!(routes.find_route(&ctx).is_none());
+        assert_eq!(routes.resolve(&ctx).unwrap().name, "api");
+
+        routes.default = Some("missing".to_string());
+        assert!(routes.validate_default().is_err());
+
+        routes.default = None;
+        routes.default_redirect = Some("https://example.com/".to_string());
+        assert!(matches!(
+            routes.unmatched(),
+            Unmatched::Redirect(302, "https://example.com/")
+        ));
+        routes.default_status = Some(404);
+        assert!(routes.validate_default().is_err());
+
+        routes.default_redirect = None;
+        assert!(matches!(routes.unmatched(), Unmatched::Status(404)));
+        routes.default_status = Some(302);
+        assert!(routes.validate_default().is_err());
+    }
+
+    /// Основной файл с `include` и каталог routes/ с фрагментами
+    fn write_split_config(dir: &Path, fragments: &[(&str, &str)]) -> PathBuf {
+        let main = dir.join("dao.toml");
+        std::fs::write(
+            &main,
+            r#"
+            include = ["routes/*.toml"]
+
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "main"
+            policy = "resonant"
+            match = { path_prefix = "/" }
+            upstreams = [{ name = "a", url = "http://a" }]
+            "#,
+        )
+        .unwrap();
+        std::fs::create_dir(dir.join("routes")).unwrap();
+        for (name, content) in fragments {
+            std::fs::write(dir.join("routes").join(name), content).unwrap();
+        }
+        main
+    }
+
+    #[test]
+    fn test_include_merges_routes_and_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write_split_config(
+            dir.path(),
+            &[
+                (
+                    "20-batch.toml",
+                    r#"
+                    [[routes.rule]]
+                    name = "batch"
+                    policy = "bulk"
+                    match = { path_prefix = "/batch/" }
+                    upstreams = [{ name = "b", url = "http://b" }]
+
+                    [policies.bulk]
+                    w_load = 0.9
+                    "#,
+                ),
+                (
+                    "10-api.toml",
+                    r#"
+                    [[routes.rule]]
+                    name = "api"
+                    policy = "resonant"
+                    match = { path_prefix = "/api/" }
+                    upstreams = [{ name = "c", url = "http://c" }]
+                    "#,
+                ),
+            ],
+        );
+
+        let config = DaoConfig::from_file(&main).unwrap();
+        assert!(config.validate().is_ok());
+        let names: Vec<_> = config.routes.rule.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["main", "api", "batch"]);
+        assert!(config.policies.as_ref().unwrap().contains_key("bulk"));
+        assert_eq!(config.included_files(&main).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_include_duplicate_route_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write_split_config(
+            dir.path(),
+            &[(
+                "api.toml",
+                r#"
+                [[routes.rule]]
+                name = "main"
+                policy = "resonant"
+                match = { path_prefix = "/api/" }
+                upstreams = [{ name = "c", url = "http://c" }]
+                "#,
+            )],
+        );
+
+        let err = DaoConfig::from_file(&main).unwrap_err().to_string();
+        assert!(err.contains("duplicate route 'main'"), "{}", err);
+        assert!(err.contains("api.toml"), "{}", err);
+    }
+
+    #[test]
+    fn test_upstreams_file_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("upstreams")).unwrap();
+        std::fs::write(
+            dir.path().join("upstreams/pool-a.json"),
+            r#"[{ "name": "a1", "url": "http://a1" }, { "name": "a2", "url": "http://a2", "weight": 3 }]"#,
+        )
+        .unwrap();
+        let main = dir.path().join("dao.toml");
+        let write_main = |upstreams: &str| {
+            std::fs::write(
+                &main,
+                format!(
+                    r#"
+                    [server]
+                    bind = "127.0.0.1:0"
+
+                    [[routes.rule]]
+                    name = "pool"
+                    policy = "resonant"
+                    match = {{ path_prefix = "/" }}
+                    upstreams_file = "upstreams/pool-a.json"
+                    {}
+                    "#,
+                    upstreams
+                ),
+            )
+            .unwrap();
+        };
+
+        write_main("");
+        let config = DaoConfig::from_file(&main).unwrap();
+        config.validate().unwrap();
+        let route = config.routes.get("pool").unwrap();
+        let names: Vec<_> = route.upstreams.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["a1", "a2"]);
+        assert_eq!(route.upstreams[1].weight, 3);
+        assert_eq!(
+            config.upstreams_files(&main),
+            [("pool".to_string(), dir.path().join("upstreams/pool-a.json"))]
+        );
+
+        let updated = config
+            .with_route_upstreams("pool", route.upstreams[..1].to_vec())
             .unwrap();
+        assert_eq!(updated.routes.get("pool").unwrap().upstreams.len(), 1);
+        assert!(config.with_route_upstreams("missing", vec![]).is_err());
+
+        write_main(r#"upstreams = [{ name = "b", url = "http://b" }]"#);
+        let err = DaoConfig::from_file(&main).unwrap_err().to_string();
+        assert!(err.contains("mutually exclusive"), "{}", err);
+    }
+
+    #[test]
+    fn test_http_connection_options_validation() {
+        let server = |extra: &str| -> ServerConfig {
+            toml::from_str(&format!("bind = \"127.0.0.1:0\"\n{}", extra)).unwrap()
+        };
+        let defaults = server("");
+        assert!(defaults.http1.keepalive);
+        assert!(defaults.http2.max_concurrent_streams.is_none());
+
+        let tuned = server(
+            "[http1]\nkeepalive = false\nmax_buf_size = 65536\n[http2]\nmax_concurrent_streams = 100",
+        );
+        assert!(!tuned.http1.keepalive);
+        assert!(tuned.http1.validate().is_ok() && tuned.http2.validate().is_ok());
 
-        // Note: This test won't compile without proper body type
-        // Just demonstrating the structure
+        assert!(server("[http1]\nmax_buf_size = 4096")
+            .http1
+            .validate()
+            .is_err());
+        assert!(server("[http2]\nmax_concurrent_streams = 0")
+            .http2
+            .validate()
+            .is_err());
+        assert!(server("[http2]\ninitial_stream_window_size = 2147483648")
+            .http2
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_passthrough_upstream_validated() {
+        let config = |passthrough: &str| -> DaoConfig {
+            toml::from_str(&format!(
+                r#"
+                [server]
+                bind = "127.0.0.1:0"
+                mode = "observe"
+                passthrough_upstream = "{}"
+
+                [[routes.rule]]
+                name = "api"
+                policy = "resonant"
+                match = {{ path_prefix = "/" }}
+                upstreams = [{{ name = "legacy", url = "http://127.0.0.1:8081" }}]
+                "#,
+                passthrough
+            ))
+            .unwrap()
+        };
+        assert!(config("legacy").validate().is_ok());
+        let err = config("legasy").validate().unwrap_err().to_string();
+        assert!(err.contains("passthrough_upstream 'legasy'"), "{}", err);
+    }
+
+    #[test]
+    fn test_conflicting_upstream_definitions_rejected() {
+        let config = |second_url: &str| -> DaoConfig {
+            toml::from_str(&format!(
+                r#"
+                [server]
+                bind = "127.0.0.1:0"
+
+                [[routes.rule]]
+                name = "api"
+                policy = "resonant"
+                match = {{ path_prefix = "/api" }}
+                upstreams = [{{ name = "shared", url = "http://127.0.0.1:8081" }}]
+
+                [[routes.rule]]
+                name = "web"
+                policy = "resonant"
+                match = {{ path_prefix = "/" }}
+                upstreams = [{{ name = "shared", url = "{}" }}]
+                "#,
+                second_url
+            ))
+            .unwrap()
+        };
+        // Один upstream в двух маршрутах
+        assert!(config("http://127.0.0.1:8081").validate().is_ok());
+        let err = config("http://127.0.0.1:9091")
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("Upstream 'shared' is defined differently"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_admin_requires_auth() {
+        let admin = |auth: &str| -> AdminConfig {
+            toml::from_str(&format!("bind = \"127.0.0.1:9103\"\n{}", auth)).unwrap()
+        };
+        assert!(admin("token = \"secret\"").validate().is_ok());
+        assert!(admin("[auth]\nhmac_secret = \"shared\"").validate().is_ok());
+        for open in ["", "token = \"\""] {
+            let err = admin(open).validate().unwrap_err().to_string();
+            assert!(err.contains("admin requires token"), "{}", err);
+        }
     }
 }
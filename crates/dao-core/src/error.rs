@@ -21,6 +21,10 @@ pub enum DaoError {
     #[error("Upstream error: {0}")]
     Upstream(String),
 
+    /// Сбой запроса к upstream'у с классификацией (статус ответа, метрики)
+    #[error("Upstream {0} error: {1}")]
+    UpstreamRequest(crate::upstream::UpstreamErrorKind, String),
+
     #[error("Policy error: {0}")]
     Policy(String),
 
@@ -33,6 +37,13 @@ pub enum DaoError {
     #[error("Invalid request: {0}")]
     InvalidRequest(String),
 
+    /// Тело больше допустимого для буферизации — ответ 413
+    #[error("Payload too large: body exceeds {0} bytes")]
+    PayloadTooLarge(usize),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
     #[error("Service unavailable: {0}")]
     ServiceUnavailable(String),
 
@@ -0,0 +1,178 @@
+//! HTTP Basic authentication filter
+
+use crate::config::BasicAuthConfig;
+use crate::{constant_time_eq, DaoError, Result};
+use base64::Engine;
+use http::{header, HeaderMap, HeaderName, HeaderValue};
+
+/// Заголовок с именем аутентифицированного пользователя для upstream'а
+pub const AUTHENTICATED_USER_HEADER: HeaderName = HeaderName::from_static("x-authenticated-user");
+
+/// Basic auth фильтр маршрута
+pub struct BasicAuthFilter<'a> {
+    config: &'a BasicAuthConfig,
+}
+
+impl<'a> BasicAuthFilter<'a> {
+    pub fn new(config: &'a BasicAuthConfig) -> Self {
+        Self { config }
+    }
+
+    /// Проверка `Authorization: Basic`; возвращает имя пользователя.
+    ///
+    /// bcrypt выполняется всегда — и для неизвестного пользователя
+    /// (против хэша первого пользователя), чтобы время ответа не выдавало
+    /// существование логина. Сравнение хэшей в bcrypt — constant-time по
+    /// выходу фиксированной длины, длина пароля на него не влияет.
+    /// Учетные данные не попадают ни в ошибки, ни в логи.
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<String> {
+        let (user, password) = basic_credentials(headers)
+            .ok_or_else(|| DaoError::Unauthorized("missing basic credentials".to_string()))?;
+
+        let mut matched = None;
+        for (name, hash) in self.config.credentials() {
+            if constant_time_eq(name.as_bytes(), user.as_bytes()) && matched.is_none() {
+                matched = Some(hash);
+            }
+        }
+
+        let known = matched.is_some();
+        let hash = matched
+            .or_else(|| self.config.credentials().next().map(|(_, hash)| hash))
+            .ok_or_else(|| DaoError::Unauthorized("invalid credentials".to_string()))?
+            .to_string();
+
+        // bcrypt намеренно медленный — не блокируем runtime
+        let verified =
+            tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash).unwrap_or(false))
+                .await
+                .map_err(|e| DaoError::internal(format!("basic auth task failed: {}", e)))?;
+
+        if verified && known {
+            Ok(user)
+        } else {
+            Err(DaoError::Unauthorized("invalid credentials".to_string()))
+        }
+    }
+
+    /// Проброс пользователя к upstream; присланный клиентом заголовок удаляется
+    pub fn inject_user(user: &str, headers: &mut HeaderMap) {
+        headers.remove(AUTHENTICATED_USER_HEADER);
+        if let Ok(value) = HeaderValue::from_str(user) {
+            headers.insert(AUTHENTICATED_USER_HEADER, value);
+        }
+    }
+
+    /// Значение `WWW-Authenticate` для 401 ответа (RFC 7617)
+    pub fn challenge(&self) -> HeaderValue {
+        HeaderValue::from_str(&format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            self.config.realm
+        ))
+        .unwrap_or_else(|_| HeaderValue::from_static("Basic realm=\"dao\""))
+    }
+}
+
+/// Разбор `Authorization: Basic base64(user:password)`
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic_config() -> BasicAuthConfig {
+        // Минимальная стоимость bcrypt, чтобы тесты были быстрыми
+        let hash = bcrypt::hash("s3cret", 4).unwrap();
+        BasicAuthConfig {
+            realm: "internal".to_string(),
+            users: vec![format!("alice:{}", hash)],
+        }
+    }
+
+    fn auth_headers(user: &str, password: &str) -> HeaderMap {
+        let encoded =
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Basic {}", encoded)).unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_valid() {
+        let config = basic_config();
+        assert!(config.validate().is_ok());
+        let filter = BasicAuthFilter::new(&config);
+
+        let user = filter
+            .authenticate(&auth_headers("alice", "s3cret"))
+            .await
+            .unwrap();
+        assert_eq!(user, "alice");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHENTICATED_USER_HEADER,
+            HeaderValue::from_static("mallory"),
+        );
+        BasicAuthFilter::inject_user(&user, &mut headers);
+        assert_eq!(headers[AUTHENTICATED_USER_HEADER], "alice");
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_wrong_password() {
+        let config = basic_config();
+        let filter = BasicAuthFilter::new(&config);
+
+        // Префикс, продолжение и пароль той же длины — все отклоняются
+        for password in ["s3cre", "s3cret!", "s3creT", ""] {
+            let err = filter
+                .authenticate(&auth_headers("alice", password))
+                .await
+                .unwrap_err();
+            // Ошибка не содержит учетных данных
+            assert_eq!(err.to_string(), "Unauthorized: invalid credentials");
+        }
+
+        // Неизвестный пользователь с чужим паролем
+        assert!(filter
+            .authenticate(&auth_headers("bob", "s3cret"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_missing_header() {
+        let config = basic_config();
+        let filter = BasicAuthFilter::new(&config);
+
+        assert!(filter.authenticate(&HeaderMap::new()).await.is_err());
+
+        let mut bearer = HeaderMap::new();
+        bearer.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer abc"),
+        );
+        assert!(filter.authenticate(&bearer).await.is_err());
+
+        assert_eq!(
+            filter.challenge(),
+            "Basic realm=\"internal\", charset=\"UTF-8\""
+        );
+    }
+}
@@ -0,0 +1,538 @@
+//! Тело проксируемого ответа
+//!
+//! По умолчанию тело upstream'а передается клиенту потоком, кадр за кадром,
+//! без копирования и без накопления в памяти. Фильтры, которым нужно тело
+//! целиком (компрессия, трансформации), должны явно перейти на
+//! [`buffer_limited`] — с лимитом размера; тем, кому нужно повторить тело
+//! запроса, — общий [`BodyBuffer`]. Размер тела считается на лету
+//! оберткой [`counted`], трафик идет в счетчик по мере передачи через
+//! [`metered`], trailers наблюдаются оберткой [`with_trailers`], срок
+//! передачи ограничивает [`with_deadline`], состояние запроса (слот
+//! upstream'а) живет до конца передачи в [`holding`].
+
+use crate::Result;
+use bytes::Bytes;
+use http::HeaderMap;
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full, LengthLimitError, Limited};
+use hyper::body::{Body, Frame, Incoming, SizeHint};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::time::Sleep;
+
+/// Тело ответа DAO
+pub type ProxyBody = BoxBody<Bytes, hyper::Error>;
+
+/// Потоковая передача тела upstream'а без буферизации
+pub fn passthrough(body: Incoming) -> ProxyBody {
+    body.boxed()
+}
+
+/// Пустое тело
+pub fn empty() -> ProxyBody {
+    Empty::<Bytes>::new()
+        .map_err(|never: Infallible| match never {})
+        .boxed()
+}
+
+/// Тело из готовых данных (ответы самого DAO)
+pub fn full(data: impl Into<Bytes>) -> ProxyBody {
+    Full::new(data.into())
+        .map_err(|never: Infallible| match never {})
+        .boxed()
+}
+
+/// Подсчет байт тела по мере передачи: `on_complete` вызывается один раз —
+/// в конце потока или при обрыве (drop) с числом переданных байт
+pub fn counted<B, F>(body: B, on_complete: F) -> CountingBody<B, F>
+where
+    F: FnOnce(u64),
+{
+    CountingBody {
+        inner: body,
+        report: BytesReport {
+            bytes: 0,
+            on_complete: Some(on_complete),
+        },
+    }
+}
+
+/// Тело, считающее переданные байты (см. [`counted`])
+#[pin_project::pin_project]
+pub struct CountingBody<B, F: FnOnce(u64)> {
+    #[pin]
+    inner: B,
+    report: BytesReport<F>,
+}
+
+struct BytesReport<F: FnOnce(u64)> {
+    bytes: u64,
+    on_complete: Option<F>,
+}
+
+impl<F: FnOnce(u64)> BytesReport<F> {
+    fn finish(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(self.bytes);
+        }
+    }
+}
+
+impl<F: FnOnce(u64)> Drop for BytesReport<F> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl<B, F> Body for CountingBody<B, F>
+where
+    B: Body<Data = Bytes>,
+    F: FnOnce(u64),
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Self::Error>>> {
+        let this = self.project();
+        let frame = std::task::ready!(this.inner.poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.report.bytes += data.len() as u64;
+                }
+            }
+            Some(Err(_)) | None => this.report.finish(),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Счетчик байт, увеличиваемый на каждом кадре данных — в отличие от
+/// [`counted`], длинные потоки видны в метриках до своего конца
+pub fn metered<B>(body: B, counter: metrics::Counter) -> MeteredBody<B> {
+    MeteredBody {
+        inner: body,
+        counter,
+    }
+}
+
+/// Тело, увеличивающее счетчик по мере передачи (см. [`metered`])
+#[pin_project::pin_project]
+pub struct MeteredBody<B> {
+    #[pin]
+    inner: B,
+    counter: metrics::Counter,
+}
+
+impl<B> Body for MeteredBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Self::Error>>> {
+        let this = self.project();
+        let frame = std::task::ready!(this.inner.poll_frame(cx));
+        if let Some(Ok(frame)) = &frame {
+            if let Some(data) = frame.data_ref() {
+                this.counter.increment(data.len() as u64);
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Наблюдение за концом тела: `on_end` вызывается один раз — с trailers
+/// или с `None`, если поток закончился (или оборвался ошибкой) без них.
+/// Тело, брошенное до конца (клиент ушел), `on_end` не вызывает.
+pub fn with_trailers<B, F>(body: B, on_end: F) -> TrailersBody<B, F>
+where
+    F: FnOnce(Option<&HeaderMap>),
+{
+    TrailersBody {
+        inner: body,
+        on_end: Some(on_end),
+    }
+}
+
+/// Тело, сообщающее о своих trailers (см. [`with_trailers`])
+#[pin_project::pin_project]
+pub struct TrailersBody<B, F> {
+    #[pin]
+    inner: B,
+    on_end: Option<F>,
+}
+
+impl<B, F> Body for TrailersBody<B, F>
+where
+    B: Body<Data = Bytes>,
+    F: FnOnce(Option<&HeaderMap>),
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Self::Error>>> {
+        let this = self.project();
+        let frame = std::task::ready!(this.inner.poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    if let Some(on_end) = this.on_end.take() {
+                        on_end(Some(trailers));
+                    }
+                }
+            }
+            Some(Err(_)) | None => {
+                if let Some(on_end) = this.on_end.take() {
+                    on_end(None);
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Тело, удерживающее `guard` до конца потока или до drop (запрос к
+/// upstream'у в полете, пока клиент читает ответ)
+pub fn holding<B, G>(body: B, guard: G) -> HoldingBody<B, G> {
+    HoldingBody {
+        inner: body,
+        guard: Some(guard),
+    }
+}
+
+/// Тело с guard'ом (см. [`holding`])
+#[pin_project::pin_project]
+pub struct HoldingBody<B, G> {
+    #[pin]
+    inner: B,
+    guard: Option<G>,
+}
+
+impl<B, G> Body for HoldingBody<B, G>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Self::Error>>> {
+        let mut this = self.project();
+        let frame = std::task::ready!(this.inner.as_mut().poll_frame(cx));
+        if frame.is_none() || this.inner.is_end_stream() {
+            this.guard.take();
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Ошибка тела, отдаваемого клиенту: hyper обрывает на ней соединение
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Тело не передано до срока
+#[derive(Debug, thiserror::Error)]
+#[error("response body deadline exceeded")]
+pub struct DeadlineExceeded;
+
+/// Передача тела не дольше `deadline`: по истечении поток завершается
+/// ошибкой [`DeadlineExceeded`], и клиент видит обрыв, а не усеченный, но
+/// корректный ответ. `None` — без ограничения.
+pub fn with_deadline<B>(body: B, deadline: Option<Instant>) -> DeadlineBody<B> {
+    DeadlineBody {
+        inner: body,
+        sleep: deadline.map(|deadline| Box::pin(tokio::time::sleep_until(deadline.into()))),
+    }
+}
+
+/// Тело со сроком передачи (см. [`with_deadline`])
+#[pin_project::pin_project]
+pub struct DeadlineBody<B> {
+    #[pin]
+    inner: B,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<B> Body for DeadlineBody<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Self::Error>>> {
+        let this = self.project();
+        if let Some(sleep) = this.sleep {
+            if sleep.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Some(Err(DeadlineExceeded.into())));
+            }
+        }
+        this.inner
+            .poll_frame(cx)
+            .map(|frame| frame.map(|frame| frame.map_err(Into::into)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Явная буферизация тела для фильтров, которым нужен весь payload.
+///
+/// Ошибка, если тело больше `limit` байт.
+pub async fn buffer_limited<B>(body: B, limit: usize) -> Result<Bytes>
+where
+    B: Body,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    BodyBuffer::new(limit).collect(body).await
+}
+
+/// Буферизация тела целиком с общим лимитом — одна реализация для всех,
+/// кому нужно отправить тело запроса повторно.
+///
+/// Тело больше лимита — [`DaoError::PayloadTooLarge`](crate::DaoError::PayloadTooLarge)
+/// (ответ 413); чтение прекращается, как только лимит превышен.
+#[derive(Debug, Clone, Copy)]
+pub struct BodyBuffer {
+    max_bytes: usize,
+}
+
+impl BodyBuffer {
+    pub const fn new(max_bytes: usize) -> Self {
+        Self { max_bytes }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Сбор тела в `Bytes`
+    pub async fn collect<B>(&self, body: B) -> Result<Bytes>
+    where
+        B: Body,
+        B::Error: std::error::Error + Send + Sync + 'static,
+    {
+        // Объявленная длина уже больше лимита — отказ без чтения
+        if body.size_hint().lower() > self.max_bytes as u64 {
+            return Err(crate::DaoError::PayloadTooLarge(self.max_bytes));
+        }
+        let collected = Limited::new(body, self.max_bytes)
+            .collect()
+            .await
+            .map_err(|e| {
+                if e.is::<LengthLimitError>() {
+                    crate::DaoError::PayloadTooLarge(self.max_bytes)
+                } else {
+                    crate::DaoError::Filter(format!("Failed to buffer body: {}", e))
+                }
+            })?;
+        Ok(collected.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use http_body_util::StreamBody;
+    use hyper::body::Frame;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_counted_chunked_body() {
+        let total = Arc::new(AtomicUsize::new(0));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let chunks = futures::stream::iter([3usize, 5, 7])
+            .map(|len| Ok::<_, Infallible>(Frame::data(Bytes::from(vec![1u8; len]))));
+        let body = counted(StreamBody::new(chunks), {
+            let (total, calls) = (total.clone(), calls.clone());
+            move |bytes| {
+                total.store(bytes as usize, Ordering::SeqCst);
+                calls.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected.len(), 15);
+        assert_eq!(total.load(Ordering::SeqCst), 15);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Оборванный поток отчитывается переданным до обрыва
+        let mut body = counted(Full::new(Bytes::from_static(b"hello")), {
+            let total = total.clone();
+            move |bytes| total.store(bytes as usize, Ordering::SeqCst)
+        });
+        total.store(usize::MAX, Ordering::SeqCst);
+        body.frame().await.unwrap().unwrap();
+        drop(body);
+        assert_eq!(total.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_metered_counts_while_streaming() {
+        let counter = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let chunks = futures::stream::iter([4usize, 6])
+            .map(|len| Ok::<_, Infallible>(Frame::data(Bytes::from(vec![1u8; len]))));
+        let mut body = metered(
+            StreamBody::new(chunks),
+            metrics::Counter::from_arc(counter.clone()),
+        );
+
+        // Счетчик растет с каждым кадром, не дожидаясь конца потока
+        body.frame().await.unwrap().unwrap();
+        assert_eq!(counter.load(Ordering::SeqCst), 4);
+        body.frame().await.unwrap().unwrap();
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[tokio::test]
+    async fn test_with_trailers() {
+        let status = Arc::new(parking_lot::Mutex::new(None));
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "14".parse().unwrap());
+        let frames = futures::stream::iter([
+            Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"reply"))),
+            Ok(Frame::trailers(trailers)),
+        ]);
+        let body = with_trailers(StreamBody::new(frames), {
+            let status = status.clone();
+            move |trailers| {
+                *status.lock() = Some(trailers.and_then(|t| t.get("grpc-status").cloned()))
+            }
+        });
+        body.collect().await.unwrap();
+        assert_eq!(*status.lock(), Some(Some("14".parse().unwrap())));
+
+        // Конец без trailers
+        let body = with_trailers(Full::new(Bytes::from_static(b"hello")), {
+            let status = status.clone();
+            move |trailers| *status.lock() = Some(trailers.cloned().map(|_| "x".parse().unwrap()))
+        });
+        body.collect().await.unwrap();
+        assert_eq!(*status.lock(), Some(None));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_aborts_slow_body() {
+        let frames =
+            futures::stream::iter([Bytes::from_static(b"first"), Bytes::from_static(b"late")])
+                .then(|data| async move {
+                    if data == "late" {
+                        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                    }
+                    Ok::<_, Infallible>(Frame::data(data))
+                });
+        let deadline = Instant::now() + std::time::Duration::from_millis(50);
+        let mut body = with_deadline(StreamBody::new(Box::pin(frames)), Some(deadline));
+
+        let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!(first, "first");
+        let err = body.frame().await.unwrap().unwrap_err();
+        assert!(err.is::<DeadlineExceeded>(), "{}", err);
+        assert!(Instant::now() < deadline + std::time::Duration::from_secs(1));
+
+        // Без срока тело передается как есть
+        let body = with_deadline(Full::new(Bytes::from_static(b"hello")), None);
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "hello");
+    }
+
+    #[tokio::test]
+    async fn test_buffer_limited() {
+        let body = Full::new(Bytes::from_static(b"hello"));
+        assert_eq!(buffer_limited(body, 16).await.unwrap(), "hello");
+
+        let body = Full::new(Bytes::from(vec![0u8; 32]));
+        assert!(buffer_limited(body, 16).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_body_buffer_cap() {
+        let buffer = BodyBuffer::new(8);
+        let chunks = futures::stream::iter([3usize, 5])
+            .map(|len| Ok::<_, Infallible>(Frame::data(Bytes::from(vec![1u8; len]))));
+        assert_eq!(
+            buffer.collect(StreamBody::new(chunks)).await.unwrap().len(),
+            8
+        );
+
+        // Длина неизвестна заранее — лимит превышается посреди потока
+        let produced = Arc::new(AtomicUsize::new(0));
+        let chunks = futures::stream::iter(0..4).map({
+            let produced = produced.clone();
+            move |_| {
+                produced.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, Infallible>(Frame::data(Bytes::from(vec![1u8; 5])))
+            }
+        });
+        let err = buffer.collect(StreamBody::new(chunks)).await.unwrap_err();
+        assert!(
+            matches!(err, crate::DaoError::PayloadTooLarge(8)),
+            "{}",
+            err
+        );
+        assert_eq!(produced.load(Ordering::SeqCst), 2);
+
+        // Известная длина больше лимита
+        let err = buffer
+            .collect(Full::new(Bytes::from(vec![0u8; 9])))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::DaoError::PayloadTooLarge(8)));
+    }
+}
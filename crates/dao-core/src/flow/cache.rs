@@ -0,0 +1,494 @@
+//! Кэш ответов upstream'ов (GET, `Cache-Control`)
+//!
+//! Кэшируются только ответы 200 с `max-age`/`s-maxage`, без `no-store`,
+//! `private` и `Set-Cookie`, с известным `Content-Length` в пределах
+//! лимита записи — остальные ответы идут клиенту потоком, как обычно.
+//! Размер кэша ограничен суммой байт, вытесняются давно не читанные.
+
+use crate::config::CacheConfig;
+use bytes::Bytes;
+use dashmap::DashMap;
+use http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode};
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Заголовок ответа: `HIT` или `MISS`
+pub const CACHE_STATUS_HEADER: HeaderName = HeaderName::from_static("x-cache");
+
+/// Ключ запроса: метод, host, путь и копия заголовков (для `Vary`)
+#[derive(Debug, Clone)]
+pub struct RequestKey {
+    primary: String,
+    headers: HeaderMap,
+}
+
+impl RequestKey {
+    /// Ключ кэшируемого запроса: GET без `Authorization` и без
+    /// `Cache-Control: no-store`
+    pub fn from_request<B>(req: &Request<B>) -> Option<Self> {
+        if req.method() != Method::GET || req.headers().contains_key(header::AUTHORIZATION) {
+            return None;
+        }
+        if directives(req.headers()).contains_key("no-store") {
+            return None;
+        }
+
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .or_else(|| req.uri().host())
+            .unwrap_or_default();
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+        Some(Self {
+            primary: format!("GET {}{}", host.to_ascii_lowercase(), path),
+            headers: req.headers().clone(),
+        })
+    }
+
+    /// Ключ записи с учетом значений заголовков из `Vary`
+    fn variant(&self, vary: &[HeaderName]) -> String {
+        let mut key = self.primary.clone();
+        for name in vary {
+            key.push('\n');
+            key.push_str(name.as_str());
+            key.push(':');
+            for value in self.headers.get_all(name) {
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+                key.push(',');
+            }
+        }
+        key
+    }
+}
+
+/// Сохраненный ответ
+struct CachedResponse {
+    primary: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    ttl: Duration,
+}
+
+impl CachedResponse {
+    fn size(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        self.body.len() + headers
+    }
+}
+
+/// `Vary` URL'а и число его сохраненных вариантов
+struct VaryEntry {
+    names: Vec<HeaderName>,
+    variants: usize,
+}
+
+struct Store {
+    entries: LruCache<String, CachedResponse>,
+    /// Заголовки `Vary` по первичному ключу
+    vary: HashMap<String, VaryEntry>,
+    bytes: usize,
+}
+
+impl Store {
+    /// Учет удаленной записи; `Vary` ее URL забывается вместе с последним
+    /// вариантом
+    fn forget(&mut self, removed: CachedResponse) {
+        self.bytes -= removed.size();
+        if let Some(vary) = self.vary.get_mut(&removed.primary) {
+            vary.variants = vary.variants.saturating_sub(1);
+            if vary.variants == 0 {
+                self.vary.remove(&removed.primary);
+            }
+        }
+    }
+}
+
+/// Кэш ответов одного маршрута
+pub struct ResponseCache {
+    store: Mutex<Store>,
+    max_bytes: usize,
+    max_entry_bytes: usize,
+}
+
+impl ResponseCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            store: Mutex::new(Store {
+                entries: LruCache::unbounded(),
+                vary: HashMap::new(),
+                bytes: 0,
+            }),
+            max_bytes: config.max_bytes,
+            max_entry_bytes: config.max_entry_bytes,
+        }
+    }
+
+    /// Свежий ответ из кэша (с `Age` и `X-Cache: HIT`)
+    pub fn lookup(&self, key: &RequestKey, now: Instant) -> Option<Response<Bytes>> {
+        let mut store = self.store.lock();
+        let vary = store.vary.get(&key.primary)?.names.clone();
+        let variant = key.variant(&vary);
+
+        let entry = store.entries.get(&variant)?;
+        let age = now.saturating_duration_since(entry.stored_at);
+        if age >= entry.ttl {
+            if let Some(expired) = store.entries.pop(&variant) {
+                store.forget(expired);
+            }
+            return None;
+        }
+
+        let mut response = Response::new(entry.body.clone());
+        *response.status_mut() = entry.status;
+        *response.headers_mut() = entry.headers.clone();
+        response
+            .headers_mut()
+            .insert(header::AGE, HeaderValue::from(age.as_secs()));
+        response
+            .headers_mut()
+            .insert(CACHE_STATUS_HEADER, HeaderValue::from_static("HIT"));
+        Some(response)
+    }
+
+    /// Время жизни ответа в кэше, если его можно сохранить
+    pub fn storable_ttl(&self, status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+        if status != StatusCode::OK || headers.contains_key(header::SET_COOKIE) {
+            return None;
+        }
+        if headers
+            .get_all(header::VARY)
+            .iter()
+            .any(|v| v.as_bytes() == b"*")
+        {
+            return None;
+        }
+        let length: usize = headers
+            .get(header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()?;
+        if length > self.max_entry_bytes {
+            return None;
+        }
+
+        let directives = directives(headers);
+        if directives.contains_key("no-store") || directives.contains_key("private") {
+            return None;
+        }
+        let max_age = directives
+            .get("s-maxage")
+            .or_else(|| directives.get("max-age"))?
+            .as_deref()?
+            .parse()
+            .ok()?;
+        (max_age > 0).then(|| Duration::from_secs(max_age))
+    }
+
+    /// Сохранение ответа; вытесняет давно не читанные записи сверх лимита
+    pub fn store(
+        &self,
+        key: &RequestKey,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: Bytes,
+        ttl: Duration,
+        now: Instant,
+    ) {
+        let vary: Vec<HeaderName> = headers
+            .get_all(header::VARY)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+            .collect();
+        let entry = CachedResponse {
+            primary: key.primary.clone(),
+            status,
+            headers: headers.clone(),
+            body,
+            stored_at: now,
+            ttl,
+        };
+        if entry.size() > self.max_bytes {
+            return;
+        }
+
+        let mut store = self.store.lock();
+        let variant = key.variant(&vary);
+        store.bytes += entry.size();
+        let replaced = store.entries.put(variant, entry);
+        let known = store.vary.entry(key.primary.clone()).or_insert(VaryEntry {
+            names: Vec::new(),
+            variants: 0,
+        });
+        known.names = vary;
+        match replaced {
+            Some(replaced) => store.bytes -= replaced.size(),
+            None => known.variants += 1,
+        }
+        while store.bytes > self.max_bytes {
+            match store.entries.pop_lru() {
+                Some((_, evicted)) => store.forget(evicted),
+                None => break,
+            }
+        }
+    }
+
+    /// Максимальный размер одного ответа (байт)
+    pub fn max_entry_bytes(&self) -> usize {
+        self.max_entry_bytes
+    }
+
+    /// Занятый объем (байт)
+    pub fn size_bytes(&self) -> usize {
+        self.store.lock().bytes
+    }
+}
+
+/// Кэши маршрутов; пересоздается при изменении лимитов в конфиге
+#[derive(Default)]
+pub struct CacheRegistry {
+    caches: DashMap<String, (CacheConfig, Arc<ResponseCache>)>,
+}
+
+impl CacheRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Кэш маршрута
+    pub fn for_route(&self, route: &str, config: &CacheConfig) -> Arc<ResponseCache> {
+        if let Some(entry) = self.caches.get(route) {
+            if entry.0 == *config {
+                return entry.1.clone();
+            }
+        }
+        let cache = Arc::new(ResponseCache::new(config));
+        self.caches
+            .insert(route.to_string(), (config.clone(), cache.clone()));
+        cache
+    }
+}
+
+/// Директивы `Cache-Control` (имя в нижнем регистре → значение)
+fn directives(headers: &HeaderMap) -> HashMap<String, Option<String>> {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| match d.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_ascii_lowercase(),
+                Some(value.trim().trim_matches('"').to_string()),
+            ),
+            None => (d.trim().to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> ResponseCache {
+        ResponseCache::new(&CacheConfig {
+            max_bytes: 1024,
+            max_entry_bytes: 256,
+        })
+    }
+
+    fn key(path: &str) -> RequestKey {
+        let req = Request::get(path)
+            .header(header::HOST, "api.example.com")
+            .body(())
+            .unwrap();
+        RequestKey::from_request(&req).unwrap()
+    }
+
+    fn response_headers(cache_control: &str, length: usize) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CACHE_CONTROL, cache_control.parse().unwrap());
+        headers.insert(header::CONTENT_LENGTH, length.into());
+        headers
+    }
+
+    #[test]
+    fn test_cache_hit_and_miss() {
+        let cache = cache();
+        let now = Instant::now();
+        let key = key("/items?page=1");
+        assert!(cache.lookup(&key, now).is_none());
+
+        let headers = response_headers("public, max-age=60", 5);
+        let ttl = cache.storable_ttl(StatusCode::OK, &headers).unwrap();
+        assert_eq!(ttl, Duration::from_secs(60));
+        cache.store(
+            &key,
+            StatusCode::OK,
+            &headers,
+            Bytes::from_static(b"items"),
+            ttl,
+            now,
+        );
+
+        let hit = cache.lookup(&key, now + Duration::from_secs(5)).unwrap();
+        assert_eq!(hit.body(), "items");
+        assert_eq!(hit.headers()[CACHE_STATUS_HEADER], "HIT");
+        assert_eq!(hit.headers()[header::AGE], "5");
+
+        // Другой запрос — промах
+        assert!(cache.lookup(&self::key("/items?page=2"), now).is_none());
+        // Запрос с Authorization не кэшируется
+        let private = Request::get("/items?page=1")
+            .header(header::AUTHORIZATION, "Bearer t")
+            .body(())
+            .unwrap();
+        assert!(RequestKey::from_request(&private).is_none());
+    }
+
+    #[test]
+    fn test_cache_expiry() {
+        let cache = cache();
+        let now = Instant::now();
+        let key = key("/items");
+        let headers = response_headers("max-age=10, s-maxage=2", 5);
+        let ttl = cache.storable_ttl(StatusCode::OK, &headers).unwrap();
+        assert_eq!(ttl, Duration::from_secs(2));
+        cache.store(
+            &key,
+            StatusCode::OK,
+            &headers,
+            Bytes::from_static(b"items"),
+            ttl,
+            now,
+        );
+
+        assert!(cache.lookup(&key, now + Duration::from_secs(1)).is_some());
+        assert!(cache.lookup(&key, now + Duration::from_secs(2)).is_none());
+        assert_eq!(cache.size_bytes(), 0);
+    }
+
+    #[test]
+    fn test_cache_not_storable() {
+        let cache = cache();
+        for cache_control in ["no-store, max-age=60", "private, max-age=60", "no-cache"] {
+            let headers = response_headers(cache_control, 5);
+            assert!(
+                cache.storable_ttl(StatusCode::OK, &headers).is_none(),
+                "{}",
+                cache_control
+            );
+        }
+
+        let headers = response_headers("max-age=60", 5);
+        assert!(cache
+            .storable_ttl(StatusCode::NOT_FOUND, &headers)
+            .is_none());
+
+        let mut with_cookie = headers.clone();
+        with_cookie.insert(header::SET_COOKIE, "session=1".parse().unwrap());
+        assert!(cache.storable_ttl(StatusCode::OK, &with_cookie).is_none());
+
+        // Тело больше лимита записи
+        let large = response_headers("max-age=60", 1024);
+        assert!(cache.storable_ttl(StatusCode::OK, &large).is_none());
+    }
+
+    #[test]
+    fn test_cache_vary_and_eviction() {
+        let cache = cache();
+        let now = Instant::now();
+        let mut headers = response_headers("max-age=60", 200);
+        headers.insert(header::VARY, "Accept-Encoding".parse().unwrap());
+
+        let request = |encoding: &str| {
+            let req = Request::get("/asset")
+                .header(header::ACCEPT_ENCODING, encoding)
+                .body(())
+                .unwrap();
+            RequestKey::from_request(&req).unwrap()
+        };
+        let body = Bytes::from(vec![0u8; 200]);
+        cache.store(
+            &request("gzip"),
+            StatusCode::OK,
+            &headers,
+            body.clone(),
+            Duration::from_secs(60),
+            now,
+        );
+        assert!(cache.lookup(&request("gzip"), now).is_some());
+        assert!(cache.lookup(&request("br"), now).is_none());
+
+        // Вытеснение одного варианта не теряет остальные
+        let small = Bytes::from(vec![0u8; 10]);
+        let mut small_headers = response_headers("max-age=60", 10);
+        small_headers.insert(header::VARY, "Accept-Encoding".parse().unwrap());
+        cache.store(
+            &request("br"),
+            StatusCode::OK,
+            &small_headers,
+            small.clone(),
+            Duration::from_secs(60),
+            now,
+        );
+        cache.store(
+            &key("/filler/1"),
+            StatusCode::OK,
+            &headers,
+            body.clone(),
+            Duration::from_secs(60),
+            now,
+        );
+        cache.store(
+            &key("/filler/2"),
+            StatusCode::OK,
+            &headers,
+            body.clone(),
+            Duration::from_secs(60),
+            now,
+        );
+        cache.store(
+            &key("/filler/3"),
+            StatusCode::OK,
+            &headers,
+            body.clone(),
+            Duration::from_secs(60),
+            now,
+        );
+        assert!(cache.lookup(&request("gzip"), now).is_none());
+        assert!(cache.lookup(&request("br"), now).is_some());
+
+        // Объем ограничен: старые записи вытесняются
+        for i in 0..10 {
+            cache.store(
+                &key(&format!("/asset/{}", i)),
+                StatusCode::OK,
+                &headers,
+                body.clone(),
+                Duration::from_secs(60),
+                now,
+            );
+        }
+        assert!(cache.size_bytes() <= 1024);
+        assert!(cache.lookup(&key("/asset/9"), now).is_some());
+        assert!(cache.lookup(&key("/asset/0"), now).is_none());
+    }
+}
@@ -0,0 +1,61 @@
+//! Доступ к маршруту по identity сертификата клиента (mTLS)
+
+use crate::config::FilterConfig;
+use crate::gate::ClientIdentity;
+
+/// Allow list CN/SAN сертификата клиента
+pub struct ClientCertFilter<'a> {
+    common_names: &'a [String],
+    sans: &'a [String],
+}
+
+impl<'a> ClientCertFilter<'a> {
+    pub fn new(config: &'a FilterConfig) -> Self {
+        Self {
+            common_names: &config.client_cert_cn,
+            sans: &config.client_cert_san,
+        }
+    }
+
+    /// Разрешен ли клиент: списки пусты — любой, иначе нужен сертификат
+    /// с CN или SAN из списков
+    pub fn is_allowed(&self, identity: Option<&ClientIdentity>) -> bool {
+        if self.common_names.is_empty() && self.sans.is_empty() {
+            return true;
+        }
+        identity.is_some_and(|identity| {
+            identity
+                .common_name
+                .as_ref()
+                .is_some_and(|cn| self.common_names.contains(cn))
+                || self.sans.iter().any(|san| identity.has_san(san))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_cert_allow_list() {
+        let config: FilterConfig = toml::from_str(
+            "client_cert_cn = [\"svc-a\"]\nclient_cert_san = [\"spiffe://mesh/svc-c\"]",
+        )
+        .unwrap();
+        let filter = ClientCertFilter::new(&config);
+        let identity = |cn: &str, san: &str| ClientIdentity {
+            common_name: Some(cn.to_string()),
+            sans: vec![san.to_string()],
+        };
+
+        assert!(filter.is_allowed(Some(&identity("svc-a", "spiffe://mesh/svc-a"))));
+        assert!(filter.is_allowed(Some(&identity("svc-c", "spiffe://mesh/svc-c"))));
+        assert!(!filter.is_allowed(Some(&identity("svc-b", "spiffe://mesh/svc-b"))));
+        assert!(!filter.is_allowed(None));
+
+        // Без списков — без требований к сертификату
+        let open: FilterConfig = toml::from_str("").unwrap();
+        assert!(ClientCertFilter::new(&open).is_allowed(None));
+    }
+}
@@ -0,0 +1,206 @@
+//! CORS filter
+
+use crate::config::CorsConfig;
+use http::{header, HeaderMap, HeaderValue, Method, Request};
+
+/// CORS фильтр маршрута
+pub struct CorsFilter<'a> {
+    config: &'a CorsConfig,
+}
+
+impl<'a> CorsFilter<'a> {
+    pub fn new(config: &'a CorsConfig) -> Self {
+        Self { config }
+    }
+
+    /// Является ли запрос CORS preflight (OPTIONS + Origin + Access-Control-Request-Method)
+    pub fn is_preflight<B>(req: &Request<B>) -> bool {
+        req.method() == Method::OPTIONS
+            && req.headers().contains_key(header::ORIGIN)
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Заголовки ответа на preflight.
+    ///
+    /// Пустой набор, если origin или метод не разрешены — браузер
+    /// тогда сам отклонит запрос.
+    pub fn preflight_headers(&self, req_headers: &HeaderMap) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        let Some(allow_origin) = req_headers
+            .get(header::ORIGIN)
+            .and_then(|o| self.allowed_origin(o))
+        else {
+            return headers;
+        };
+
+        let method_allowed = req_headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| m.to_str().ok())
+            .is_some_and(|m| {
+                self.config
+                    .allowed_methods
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(m))
+            });
+        if !method_allowed {
+            return headers;
+        }
+
+        self.insert_origin_headers(allow_origin, &mut headers);
+
+        if let Ok(methods) = HeaderValue::from_str(&self.config.allowed_methods.join(", ")) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        if !self.config.allowed_headers.is_empty() {
+            if let Ok(allowed) = HeaderValue::from_str(&self.config.allowed_headers.join(", ")) {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+            }
+        }
+        if let Some(max_age) = self.config.max_age {
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+        }
+
+        headers
+    }
+
+    /// Добавление CORS заголовков к обычному (не preflight) ответу
+    pub fn apply_response_headers(
+        &self,
+        origin: Option<&HeaderValue>,
+        res_headers: &mut HeaderMap,
+    ) {
+        if let Some(allow_origin) = origin.and_then(|o| self.allowed_origin(o)) {
+            self.insert_origin_headers(allow_origin, res_headers);
+        }
+    }
+
+    fn insert_origin_headers(&self, allow_origin: HeaderValue, headers: &mut HeaderMap) {
+        if allow_origin != "*" {
+            // Ответ зависит от Origin — кэши должны это учитывать
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.config.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    /// Значение Access-Control-Allow-Origin для origin'а запроса
+    fn allowed_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        let origin_str = origin.to_str().ok()?;
+
+        if self.config.allowed_origins.iter().any(|o| o == origin_str) {
+            return Some(origin.clone());
+        }
+
+        // `*` с credentials отклоняется при валидации конфига
+        if self.config.allowed_origins.iter().any(|o| o == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors_config() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["Content-Type".to_string()],
+            allow_credentials: true,
+            max_age: Some(600),
+        }
+    }
+
+    #[test]
+    fn test_cors_preflight() {
+        let config = cors_config();
+        let cors = CorsFilter::new(&config);
+
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/v1/items")
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(())
+            .unwrap();
+        assert!(CorsFilter::is_preflight(&req));
+
+        let headers = cors.preflight_headers(req.headers());
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "Content-Type"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(headers[header::VARY], "Origin");
+
+        // Неразрешенный метод — без CORS заголовков
+        let mut put_headers = req.headers().clone();
+        put_headers.insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("PUT"),
+        );
+        assert!(cors.preflight_headers(&put_headers).is_empty());
+    }
+
+    #[test]
+    fn test_cors_simple_request() {
+        let config = cors_config();
+        let cors = CorsFilter::new(&config);
+
+        let origin = HeaderValue::from_static("https://app.example.com");
+        let mut res_headers = HeaderMap::new();
+        cors.apply_response_headers(Some(&origin), &mut res_headers);
+        assert_eq!(
+            res_headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+
+        // Неразрешенный origin — ACAO не выставляется
+        let evil = HeaderValue::from_static("https://evil.example.com");
+        let mut res_headers = HeaderMap::new();
+        cors.apply_response_headers(Some(&evil), &mut res_headers);
+        assert!(!res_headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let preflight = Request::builder()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "https://evil.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(())
+            .unwrap();
+        assert!(cors.preflight_headers(preflight.headers()).is_empty());
+    }
+
+    #[test]
+    fn test_cors_wildcard() {
+        let mut config = cors_config();
+        config.allowed_origins = vec!["*".to_string()];
+        assert!(config.validate().is_err()); // `*` + credentials
+
+        config.allow_credentials = false;
+        assert!(config.validate().is_ok());
+
+        let cors = CorsFilter::new(&config);
+        let origin = HeaderValue::from_static("https://any.example.com");
+        let mut res_headers = HeaderMap::new();
+        cors.apply_response_headers(Some(&origin), &mut res_headers);
+        assert_eq!(res_headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!res_headers.contains_key(header::VARY));
+    }
+}
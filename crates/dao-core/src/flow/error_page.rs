@@ -0,0 +1,199 @@
+//! Тела ответов с ошибками и ID запроса
+
+use crate::config::{ErrorPagesConfig, ErrorShapingConfig};
+use bytes::Bytes;
+use http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+
+/// Заголовок с ID запроса
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Максимальная длина принятого от клиента ID
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// ID запроса: присланный клиентом `X-Request-Id` (если он безопасен для
+/// подстановки в тело и логи) или новый случайный
+pub fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:032x}", fastrand::u128(..)))
+}
+
+/// Формирование тел ответов с ошибками
+pub struct ErrorPages<'a> {
+    config: &'a ErrorPagesConfig,
+}
+
+impl<'a> ErrorPages<'a> {
+    pub fn new(config: &'a ErrorPagesConfig) -> Self {
+        Self { config }
+    }
+
+    /// Content-Type и тело ответа для статуса.
+    ///
+    /// По умолчанию — `{"error":"bad_gateway","request_id":"..."}`: только
+    /// код статуса, без деталей маршрутизации (404 без маршрута и 404
+    /// upstream'а неотличимы).
+    pub fn render(&self, status: StatusCode, request_id: &str) -> (HeaderValue, Bytes) {
+        if let Some(page) = self.config.page(status.as_u16()) {
+            if let Ok(content_type) = HeaderValue::from_str(&page.content_type) {
+                let body = page.body.replace("{request_id}", request_id);
+                return (content_type, Bytes::from(body));
+            }
+        }
+
+        let body = serde_json::json!({
+            "error": error_code(status),
+            "request_id": request_id,
+        });
+        (
+            HeaderValue::from_static("application/json"),
+            Bytes::from(body.to_string()),
+        )
+    }
+
+    /// Заголовки ответа с ошибкой
+    pub fn headers(content_type: HeaderValue, request_id: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, content_type);
+        if let Ok(value) = HeaderValue::from_str(request_id) {
+            headers.insert(REQUEST_ID_HEADER, value);
+        }
+        headers
+    }
+}
+
+/// Тело ответа upstream'а по `error_shaping` маршрута: Content-Type и
+/// тело из шаблона, None — статус не из списка (ответ идет как есть)
+pub fn shape_upstream_error(
+    config: &ErrorShapingConfig,
+    status: StatusCode,
+    request_id: &str,
+    upstream: &str,
+) -> Option<(HeaderValue, Bytes)> {
+    if !config.statuses.contains(&status.as_u16()) {
+        return None;
+    }
+    let content_type = HeaderValue::from_str(&config.content_type).ok()?;
+    let body = config
+        .body
+        .replace("{status}", status.as_str())
+        .replace("{request_id}", request_id)
+        .replace("{upstream}", upstream);
+    Some((content_type, Bytes::from(body)))
+}
+
+/// `Bad Gateway` → `bad_gateway`
+fn error_code(status: StatusCode) -> String {
+    status
+        .canonical_reason()
+        .map(|reason| reason.to_ascii_lowercase().replace([' ', '-'], "_"))
+        .unwrap_or_else(|| status.as_u16().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_json_body() {
+        let config = ErrorPagesConfig::default();
+        let (content_type, body) =
+            ErrorPages::new(&config).render(StatusCode::BAD_GATEWAY, "req-1");
+
+        assert_eq!(content_type, "application/json");
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"error": "bad_gateway", "request_id": "req-1"})
+        );
+
+        let (_, body) = ErrorPages::new(&config).render(StatusCode::NOT_FOUND, "req-2");
+        assert_eq!(&body[..], br#"{"error":"not_found","request_id":"req-2"}"#);
+    }
+
+    #[test]
+    fn test_configured_error_page() {
+        let config: ErrorPagesConfig = toml::from_str(
+            r#"
+            [502]
+            body = '{"error":"upstream unavailable","request_id":"{request_id}"}'
+
+            [503]
+            body = "<h1>Maintenance</h1>"
+            content_type = "text/html"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let pages = ErrorPages::new(&config);
+
+        let (content_type, body) = pages.render(StatusCode::BAD_GATEWAY, "abc");
+        assert_eq!(content_type, "application/json");
+        assert_eq!(
+            &body[..],
+            br#"{"error":"upstream unavailable","request_id":"abc"}"#
+        );
+
+        let (content_type, body) = pages.render(StatusCode::SERVICE_UNAVAILABLE, "abc");
+        assert_eq!(content_type, "text/html");
+        assert_eq!(&body[..], b"<h1>Maintenance</h1>");
+
+        let invalid: ErrorPagesConfig = toml::from_str("[200]\nbody = \"ok\"").unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_shape_upstream_error() {
+        let config: ErrorShapingConfig = toml::from_str("statuses = [502, 503]").unwrap();
+        assert!(config.validate().is_ok());
+
+        let (content_type, body) = shape_upstream_error(
+            &config,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "req-1",
+            "backend-1",
+        )
+        .unwrap();
+        assert_eq!(content_type, "application/json");
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "error": "upstream_error",
+                "status": 503,
+                "request_id": "req-1",
+            })
+        );
+        assert!(
+            shape_upstream_error(&config, StatusCode::INTERNAL_SERVER_ERROR, "req-1", "b")
+                .is_none()
+        );
+
+        let invalid: ErrorShapingConfig = toml::from_str("statuses = [200]").unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_request_id() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("trace-42.a_b"));
+        assert_eq!(request_id(&headers), "trace-42.a_b");
+
+        // Небезопасный ID заменяется сгенерированным
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("\"},{\"x\":1"));
+        let generated = request_id(&headers);
+        assert_eq!(generated.len(), 32);
+        assert!(generated.bytes().all(|b| b.is_ascii_hexdigit()));
+
+        assert_ne!(request_id(&HeaderMap::new()), request_id(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,605 @@
+//! Повторы запросов по `Idempotency-Key`
+//!
+//! Первый запрос с ключом проксируется, его ответ сохраняется на
+//! `ttl_secs`; повтор с тем же методом, путем, ключом, учетными данными
+//! клиента и телом получает сохраненный ответ без обращения к upstream'у.
+//! Повторы, пришедшие, пока первый запрос в работе, ждут его ответа не
+//! дольше `wait_ms`. Ответы 4xx/5xx, ответы с `Set-Cookie` и ответы больше
+//! лимита записи не сохраняются: ожидающие повторы тогда проксируются
+//! сами, по одному.
+
+use crate::config::IdempotencyConfig;
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use http::{HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode};
+use hyper::body::{Body, Frame, SizeHint};
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Заголовок запроса с ключом идемпотентности
+pub const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Заголовок ответа, отданного из сохраненных: `true`
+pub const IDEMPOTENT_REPLAYED_HEADER: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Непустой `Idempotency-Key` запроса; без него запрос проксируется как
+/// обычно
+pub fn has_idempotency_key<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .is_some_and(|key| !key.is_empty())
+}
+
+/// Ключ записи: метод, путь с query, `Idempotency-Key` и отпечаток
+/// учетных данных клиента (`Authorization`, без него — адрес) и тела.
+/// Чужой клиент с тем же ключом или повтор с другим телом сохраненный
+/// ответ не получают
+pub fn idempotency_key<B>(req: &Request<B>, client_ip: IpAddr, body: &[u8]) -> Option<String> {
+    let key = req.headers().get(IDEMPOTENCY_KEY_HEADER)?.to_str().ok()?;
+    if key.is_empty() {
+        return None;
+    }
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+
+    let mut fingerprint = ring::digest::Context::new(&ring::digest::SHA256);
+    match req.headers().get(http::header::AUTHORIZATION) {
+        Some(credentials) => fingerprint.update(credentials.as_bytes()),
+        None => fingerprint.update(client_ip.to_string().as_bytes()),
+    }
+    fingerprint.update(b"\n");
+    fingerprint.update(body);
+    let fingerprint: String = fingerprint
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Some(format!(
+        "{} {}\n{}\n{}",
+        req.method(),
+        path,
+        key,
+        fingerprint
+    ))
+}
+
+/// Сохраненный ответ
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+}
+
+impl StoredResponse {
+    /// Объем записи вместе с ключом
+    fn size(&self, key: &str) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        key.len() + self.body.len() + headers
+    }
+}
+
+struct Store {
+    entries: LruCache<String, StoredResponse>,
+    bytes: usize,
+    /// Ключи запросов в работе; ожидающие видят закрытие канала
+    in_flight: HashMap<String, watch::Receiver<()>>,
+}
+
+/// Ответы по ключам идемпотентности одного маршрута
+pub struct IdempotencyStore {
+    store: Mutex<Store>,
+    ttl: Duration,
+    wait: Duration,
+    max_bytes: usize,
+    max_entry_bytes: usize,
+}
+
+/// Исход обращения по ключу
+pub enum Claim {
+    /// Сохраненный ответ (с `Idempotent-Replayed: true`)
+    Replay(Response<Bytes>),
+    /// Первый запрос с ключом: его ответ сохраняет [`IdempotencyGuard`]
+    Proceed(IdempotencyGuard),
+    /// Запрос с тем же ключом не завершился за `wait_ms` — 409
+    InProgress,
+}
+
+impl IdempotencyStore {
+    pub fn new(config: &IdempotencyConfig) -> Self {
+        Self {
+            store: Mutex::new(Store {
+                entries: LruCache::unbounded(),
+                bytes: 0,
+                in_flight: HashMap::new(),
+            }),
+            ttl: config.ttl(),
+            wait: config.wait(),
+            max_bytes: config.max_bytes,
+            max_entry_bytes: config.max_entry_bytes,
+        }
+    }
+
+    /// Сохраненный ответ или право проксировать запрос; пока запрос с тем
+    /// же ключом в работе — ожидание его завершения, не дольше `wait_ms`
+    pub async fn claim(self: &Arc<Self>, key: String) -> Claim {
+        let deadline = tokio::time::Instant::now() + self.wait;
+        loop {
+            let mut waiting = {
+                let mut store = self.store.lock();
+                if let Some(response) = self.lookup(&mut store, &key, Instant::now()) {
+                    return Claim::Replay(response);
+                }
+                match store.in_flight.get(&key) {
+                    Some(waiting) => waiting.clone(),
+                    None => {
+                        let (done, waiting) = watch::channel(());
+                        store.in_flight.insert(key.clone(), waiting);
+                        return Claim::Proceed(IdempotencyGuard {
+                            store: self.clone(),
+                            key,
+                            _done: done,
+                        });
+                    }
+                }
+            };
+            // Канал закрывается, когда первый запрос завершен или брошен
+            if tokio::time::timeout_at(deadline, waiting.changed())
+                .await
+                .is_err()
+            {
+                return Claim::InProgress;
+            }
+        }
+    }
+
+    fn lookup(&self, store: &mut Store, key: &str, now: Instant) -> Option<Response<Bytes>> {
+        let entry = store.entries.get(key)?;
+        if now.saturating_duration_since(entry.stored_at) >= self.ttl {
+            if let Some(expired) = store.entries.pop(key) {
+                store.bytes -= expired.size(key);
+            }
+            return None;
+        }
+
+        let mut response = Response::new(entry.body.clone());
+        *response.status_mut() = entry.status;
+        *response.headers_mut() = entry.headers.clone();
+        response
+            .headers_mut()
+            .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+        Some(response)
+    }
+
+    /// Сохранение ответа; вытесняет давно не читанные записи сверх лимита
+    fn store(&self, key: &str, entry: StoredResponse) {
+        let size = entry.size(key);
+        if size > self.max_bytes {
+            return;
+        }
+        let mut store = self.store.lock();
+        store.bytes += size;
+        if let Some(replaced) = store.entries.put(key.to_string(), entry) {
+            store.bytes -= replaced.size(key);
+        }
+        while store.bytes > self.max_bytes {
+            match store.entries.pop_lru() {
+                Some((evicted_key, evicted)) => store.bytes -= evicted.size(&evicted_key),
+                None => break,
+            }
+        }
+    }
+
+    /// Занятый объем (байт)
+    pub fn size_bytes(&self) -> usize {
+        self.store.lock().bytes
+    }
+}
+
+/// Право первого запроса с ключом; сброс без сохраненного ответа
+/// отпускает ожидающих проксировать самим
+pub struct IdempotencyGuard {
+    store: Arc<IdempotencyStore>,
+    key: String,
+    _done: watch::Sender<()>,
+}
+
+impl IdempotencyGuard {
+    /// Тело ответа, сохраняемое по мере передачи клиенту. Ошибки (4xx —
+    /// часто временные: 409, 429) и ответы с `Set-Cookie` проходят без
+    /// сохранения
+    pub fn record<B>(self, status: StatusCode, headers: HeaderMap, body: B) -> RecordedBody<B>
+    where
+        B: Body<Data = Bytes>,
+    {
+        let storable = status.as_u16() < 400 && !headers.contains_key(http::header::SET_COOKIE);
+        let recording = storable.then(|| Recording {
+            guard: self,
+            status,
+            headers,
+            buffer: BytesMut::new(),
+        });
+        let mut recorded = RecordedBody {
+            inner: body,
+            recording,
+        };
+        if recorded.inner.is_end_stream() {
+            finish(&mut recorded.recording);
+        }
+        recorded
+    }
+
+    fn complete(self, status: StatusCode, headers: HeaderMap, body: Bytes) {
+        let entry = StoredResponse {
+            status,
+            headers,
+            body,
+            stored_at: Instant::now(),
+        };
+        self.store.store(&self.key, entry);
+    }
+}
+
+impl Drop for IdempotencyGuard {
+    fn drop(&mut self) {
+        self.store.store.lock().in_flight.remove(&self.key);
+    }
+}
+
+/// Ответ, копируемый в хранилище по мере передачи (см.
+/// [`IdempotencyGuard::record`])
+#[pin_project::pin_project]
+pub struct RecordedBody<B> {
+    #[pin]
+    inner: B,
+    recording: Option<Recording>,
+}
+
+struct Recording {
+    guard: IdempotencyGuard,
+    status: StatusCode,
+    headers: HeaderMap,
+    buffer: BytesMut,
+}
+
+/// Сохранение записанного ответа (один раз)
+fn finish(recording: &mut Option<Recording>) {
+    if let Some(recording) = recording.take() {
+        let body = recording.buffer.freeze();
+        recording
+            .guard
+            .complete(recording.status, recording.headers, body);
+    }
+}
+
+impl<B> Body for RecordedBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Self::Error>>> {
+        let mut this = self.project();
+        let frame = std::task::ready!(this.inner.as_mut().poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let (Some(data), Some(recording)) = (frame.data_ref(), this.recording.as_mut()) {
+                    let limit = recording.guard.store.max_entry_bytes;
+                    if recording.buffer.len() + data.len() > limit {
+                        *this.recording = None;
+                    } else {
+                        recording.buffer.extend_from_slice(data);
+                    }
+                }
+                if this.inner.is_end_stream() {
+                    finish(this.recording);
+                }
+            }
+            // Оборванный ответ не сохраняется
+            Some(Err(_)) => *this.recording = None,
+            None => finish(this.recording),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Хранилища маршрутов; пересоздаются при изменении лимитов в конфиге
+#[derive(Default)]
+pub struct IdempotencyRegistry {
+    stores: DashMap<String, (IdempotencyConfig, Arc<IdempotencyStore>)>,
+}
+
+impl IdempotencyRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Хранилище маршрута; заменяется только при смене конфигурации —
+    /// под блокировкой записи, чтобы одновременные запросы получили одно
+    pub fn for_route(&self, route: &str, config: &IdempotencyConfig) -> Arc<IdempotencyStore> {
+        if let Some(entry) = self.stores.get(route) {
+            if entry.0 == *config {
+                return entry.1.clone();
+            }
+        }
+        let mut entry = self
+            .stores
+            .entry(route.to_string())
+            .or_insert_with(|| (config.clone(), Arc::new(IdempotencyStore::new(config))));
+        if entry.0 != *config {
+            *entry = (config.clone(), Arc::new(IdempotencyStore::new(config)));
+        }
+        entry.1.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Full};
+
+    fn store() -> Arc<IdempotencyStore> {
+        Arc::new(IdempotencyStore::new(&IdempotencyConfig {
+            ttl_secs: 60,
+            max_bytes: 1024,
+            max_entry_bytes: 256,
+            max_request_bytes: 256,
+            wait_ms: 200,
+        }))
+    }
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    fn key(value: &str) -> String {
+        let req = Request::post("/payments?currency=eur")
+            .header(IDEMPOTENCY_KEY_HEADER, value)
+            .body(())
+            .unwrap();
+        idempotency_key(&req, CLIENT, b"{}").unwrap()
+    }
+
+    async fn respond(guard: IdempotencyGuard, status: StatusCode, body: &'static str) -> Bytes {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain"),
+        );
+        let body = guard.record(
+            status,
+            headers,
+            Full::new(Bytes::from_static(body.as_bytes())),
+        );
+        body.collect().await.unwrap().to_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_retry_replays_stored_response() {
+        let store = store();
+        let Claim::Proceed(guard) = store.claim(key("k1")).await else {
+            panic!("first request must proceed");
+        };
+        assert_eq!(respond(guard, StatusCode::CREATED, "paid").await, "paid");
+
+        let Claim::Replay(replay) = store.claim(key("k1")).await else {
+            panic!("retry must be replayed");
+        };
+        assert_eq!(replay.status(), StatusCode::CREATED);
+        assert_eq!(replay.body(), "paid");
+        assert_eq!(replay.headers()[http::header::CONTENT_TYPE], "text/plain");
+        assert_eq!(replay.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+
+        // Другой ключ или метод — новый запрос
+        assert!(matches!(store.claim(key("k2")).await, Claim::Proceed(_)));
+        let get = Request::get("/payments?currency=eur")
+            .header(IDEMPOTENCY_KEY_HEADER, "k1")
+            .body(())
+            .unwrap();
+        assert!(matches!(
+            store
+                .claim(idempotency_key(&get, CLIENT, b"{}").unwrap())
+                .await,
+            Claim::Proceed(_)
+        ));
+        let without_key = Request::post("/payments").body(()).unwrap();
+        assert!(!has_idempotency_key(&without_key));
+        assert!(idempotency_key(&without_key, CLIENT, b"{}").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_key_scoped_by_client_and_body() {
+        let request = |authorization: Option<&str>| {
+            let mut req = Request::post("/payments")
+                .header(IDEMPOTENCY_KEY_HEADER, "k1")
+                .body(())
+                .unwrap();
+            if let Some(authorization) = authorization {
+                req.headers_mut().insert(
+                    http::header::AUTHORIZATION,
+                    HeaderValue::from_str(authorization).unwrap(),
+                );
+            }
+            req
+        };
+        let other_ip = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 7));
+        let alice = request(Some("Bearer alice"));
+        let key = idempotency_key(&alice, CLIENT, b"{}").unwrap();
+
+        // Те же учетные данные с другого адреса — тот же клиент
+        assert_eq!(idempotency_key(&alice, other_ip, b"{}").unwrap(), key);
+        // Другой клиент, другое тело, другой адрес без учетных данных
+        assert_ne!(
+            idempotency_key(&request(Some("Bearer bob")), CLIENT, b"{}").unwrap(),
+            key
+        );
+        assert_ne!(
+            idempotency_key(&alice, CLIENT, b"{\"amount\":1}").unwrap(),
+            key
+        );
+        assert_ne!(
+            idempotency_key(&request(None), CLIENT, b"{}").unwrap(),
+            idempotency_key(&request(None), other_ip, b"{}").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_bounded_and_errors_not_stored() {
+        let store = store();
+        let Claim::Proceed(guard) = store.claim(key("k1")).await else {
+            panic!("first request must proceed");
+        };
+        // Первый запрос не завершился за wait_ms — повтор получает 409
+        assert!(matches!(store.claim(key("k1")).await, Claim::InProgress));
+
+        // 429 и ответы с Set-Cookie не сохраняются
+        respond(guard, StatusCode::TOO_MANY_REQUESTS, "slow down").await;
+        let Claim::Proceed(guard) = store.claim(key("k1")).await else {
+            panic!("retry after 429 must proceed");
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::SET_COOKIE,
+            HeaderValue::from_static("session=abc"),
+        );
+        guard
+            .record(
+                StatusCode::OK,
+                headers,
+                Full::new(Bytes::from_static(b"private")),
+            )
+            .collect()
+            .await
+            .unwrap();
+        assert!(matches!(store.claim(key("k1")).await, Claim::Proceed(_)));
+        assert_eq!(store.size_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_same_key_waits_for_first() {
+        let store = store();
+        let Claim::Proceed(guard) = store.claim(key("k1")).await else {
+            panic!("first request must proceed");
+        };
+
+        let retry = tokio::spawn({
+            let store = store.clone();
+            async move { store.claim(key("k1")).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!retry.is_finished());
+
+        respond(guard, StatusCode::OK, "done").await;
+        let Claim::Replay(replay) = retry.await.unwrap() else {
+            panic!("concurrent retry must get the first response");
+        };
+        assert_eq!(replay.body(), "done");
+    }
+
+    #[tokio::test]
+    async fn test_server_error_not_stored() {
+        let store = store();
+        let Claim::Proceed(guard) = store.claim(key("k1")).await else {
+            panic!("first request must proceed");
+        };
+        let retry = tokio::spawn({
+            let store = store.clone();
+            async move { store.claim(key("k1")).await }
+        });
+        respond(guard, StatusCode::BAD_GATEWAY, "oops").await;
+
+        // Ожидавший повтор проксируется сам
+        assert!(matches!(retry.await.unwrap(), Claim::Proceed(_)));
+        assert_eq!(store.size_bytes(), 0);
+
+        // Объем записи учитывает ключ
+        let Claim::Proceed(guard) = store.claim(key("k3")).await else {
+            panic!("first request must proceed");
+        };
+        guard
+            .record(
+                StatusCode::OK,
+                HeaderMap::new(),
+                Full::new(Bytes::from_static(b"ok")),
+            )
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(store.size_bytes(), key("k3").len() + 2);
+
+        // Ответ больше лимита записи тоже не сохраняется
+        let Claim::Proceed(guard) = store.claim(key("k2")).await else {
+            panic!("first request must proceed");
+        };
+        let large = guard.record(
+            StatusCode::OK,
+            HeaderMap::new(),
+            Full::new(Bytes::from(vec![0u8; 512])),
+        );
+        large.collect().await.unwrap();
+        assert!(matches!(store.claim(key("k2")).await, Claim::Proceed(_)));
+    }
+
+    #[test]
+    fn test_registry_shares_store_per_route() {
+        let registry = IdempotencyRegistry::new();
+        let config = IdempotencyConfig {
+            ttl_secs: 60,
+            max_bytes: 1024,
+            max_entry_bytes: 256,
+            max_request_bytes: 256,
+            wait_ms: 200,
+        };
+        // Первые запросы маршрута одновременно — хранилище одно
+        let barrier = std::sync::Barrier::new(8);
+        let stores: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        registry.for_route("payments", &config)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert!(stores.iter().all(|store| Arc::ptr_eq(store, &stores[0])));
+
+        // Смена конфигурации заменяет хранилище
+        let changed = IdempotencyConfig {
+            ttl_secs: 30,
+            ..config.clone()
+        };
+        let replaced = registry.for_route("payments", &changed);
+        assert!(!Arc::ptr_eq(&replaced, &stores[0]));
+        assert!(Arc::ptr_eq(
+            &registry.for_route("payments", &changed),
+            &replaced
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! Контроль доступа по IP адресу клиента
+
+use crate::config::FilterConfig;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// Allow/deny фильтр маршрута по CIDR
+pub struct IpAccessFilter<'a> {
+    allow: &'a [IpNet],
+    deny: &'a [IpNet],
+}
+
+impl<'a> IpAccessFilter<'a> {
+    pub fn new(config: &'a FilterConfig) -> Self {
+        Self {
+            allow: &config.allow_cidrs,
+            deny: &config.deny_cidrs,
+        }
+    }
+
+    /// Разрешен ли клиент: deny приоритетнее allow, пустой allow — все
+    pub fn is_allowed(&self, peer: IpAddr) -> bool {
+        // IPv4-mapped IPv6 (dual-stack сокет) сравнивается как IPv4
+        let peer = peer.to_canonical();
+
+        if self.deny.iter().any(|net| net.contains(&peer)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&peer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_config(allow: &[&str], deny: &[&str]) -> FilterConfig {
+        toml::from_str(&format!(
+            "allow_cidrs = {:?}\ndeny_cidrs = {:?}",
+            allow, deny
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ip_access_allowed() {
+        let config = filter_config(&["10.0.0.0/8", "fd00::/8"], &[]);
+        let filter = IpAccessFilter::new(&config);
+
+        assert!(filter.is_allowed("10.1.2.3".parse().unwrap()));
+        assert!(filter.is_allowed("fd00::1".parse().unwrap()));
+        assert!(filter.is_allowed("::ffff:10.1.2.3".parse().unwrap()));
+
+        // Пустой allow list — разрешены все
+        let open = filter_config(&[], &[]);
+        assert!(IpAccessFilter::new(&open).is_allowed("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ip_access_denied() {
+        let config = filter_config(&["10.0.0.0/8"], &["10.0.13.0/24", "2001:db8::/32"]);
+        let filter = IpAccessFilter::new(&config);
+
+        // deny приоритетнее allow
+        assert!(!filter.is_allowed("10.0.13.37".parse().unwrap()));
+        assert!(filter.is_allowed("10.0.14.1".parse().unwrap()));
+
+        let deny_only = filter_config(&[], &["2001:db8::/32"]);
+        let filter = IpAccessFilter::new(&deny_only);
+        assert!(!filter.is_allowed("2001:db8::5".parse().unwrap()));
+        assert!(filter.is_allowed("2001:db9::5".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ip_access_outside_allow_ranges() {
+        let config = filter_config(&["10.0.0.0/8", "192.168.0.0/16"], &[]);
+        let filter = IpAccessFilter::new(&config);
+
+        assert!(!filter.is_allowed("203.0.113.7".parse().unwrap()));
+        assert!(!filter.is_allowed("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ip_access_invalid_cidr() {
+        assert!(toml::from_str::<FilterConfig>(r#"allow_cidrs = ["10.0.0.0/33"]"#).is_err());
+        assert!(toml::from_str::<FilterConfig>(r#"deny_cidrs = ["10.0.0."]"#).is_err());
+    }
+}
@@ -0,0 +1,478 @@
+//! JWT authentication filter
+
+use crate::config::JwtConfig;
+use crate::{DaoError, Result};
+use bytes::Bytes;
+use dashmap::DashMap;
+use http::{header, HeaderMap, HeaderName, HeaderValue};
+use http_body_util::{BodyExt, Empty};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use std::time::{Duration, Instant};
+
+/// Проверенные claim'ы токена
+pub type JwtClaims = serde_json::Map<String, serde_json::Value>;
+
+/// Таймаут загрузки JWKS
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// JWT фильтр маршрута
+pub struct JwtFilter<'a> {
+    config: &'a JwtConfig,
+    jwks: &'a JwksCache,
+}
+
+impl<'a> JwtFilter<'a> {
+    pub fn new(config: &'a JwtConfig, jwks: &'a JwksCache) -> Self {
+        Self { config, jwks }
+    }
+
+    /// Проверка `Authorization: Bearer` токена: подпись, exp, iss/aud
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<JwtClaims> {
+        let token = bearer_token(headers)
+            .ok_or_else(|| DaoError::Unauthorized("missing bearer token".to_string()))?;
+
+        let token_header = jsonwebtoken::decode_header(token)
+            .map_err(|e| DaoError::Unauthorized(format!("malformed token: {}", e)))?;
+
+        let allowed: Vec<Algorithm> = self
+            .config
+            .algorithms()
+            .iter()
+            .filter_map(|a| a.parse().ok())
+            .collect();
+        if !allowed.contains(&token_header.alg) {
+            return Err(DaoError::Unauthorized(format!(
+                "algorithm {:?} not allowed",
+                token_header.alg
+            )));
+        }
+
+        let key = self
+            .decoding_key(token_header.alg, token_header.kid.as_deref())
+            .await?;
+
+        let mut validation = Validation::new(token_header.alg);
+        validation.algorithms = allowed;
+        validation.leeway = self.config.leeway_secs;
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let data = jsonwebtoken::decode::<JwtClaims>(token, &key, &validation)
+            .map_err(|e| DaoError::Unauthorized(format!("invalid token: {}", e)))?;
+
+        Ok(data.claims)
+    }
+
+    /// Проброс claim'ов в заголовки запроса к upstream.
+    ///
+    /// Заголовки из `claims_to_headers`, присланные клиентом, удаляются
+    /// всегда — иначе их можно подделать.
+    pub fn inject_claims(&self, claims: &JwtClaims, headers: &mut HeaderMap) {
+        let Some(mapping) = &self.config.claims_to_headers else {
+            return;
+        };
+
+        for (claim, header_name) in mapping {
+            let Ok(name) = HeaderName::from_bytes(header_name.as_bytes()) else {
+                continue;
+            };
+            headers.remove(&name);
+
+            let value = match claims.get(claim) {
+                Some(serde_json::Value::String(s)) => s.clone(),
+                Some(serde_json::Value::Null) | None => continue,
+                Some(other) => other.to_string(),
+            };
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(name, value);
+            }
+        }
+    }
+
+    /// Значение `WWW-Authenticate` для 401 ответа (RFC 6750)
+    pub fn challenge(err: &DaoError) -> HeaderValue {
+        let challenge = match err {
+            DaoError::Unauthorized(msg) if msg != "missing bearer token" => {
+                format!(
+                    "Bearer realm=\"dao\", error=\"invalid_token\", error_description=\"{}\"",
+                    msg.replace('"', "'")
+                )
+            }
+            _ => "Bearer realm=\"dao\"".to_string(),
+        };
+        HeaderValue::from_str(&challenge)
+            .unwrap_or_else(|_| HeaderValue::from_static("Bearer realm=\"dao\""))
+    }
+
+    async fn decoding_key(&self, alg: Algorithm, kid: Option<&str>) -> Result<DecodingKey> {
+        if let Some(secret) = &self.config.secret {
+            return Ok(DecodingKey::from_secret(secret.as_bytes()));
+        }
+
+        if let Some(pem) = &self.config.public_key_pem {
+            let key = match alg {
+                Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512 => DecodingKey::from_rsa_pem(pem.as_bytes()),
+                Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(pem.as_bytes()),
+                Algorithm::EdDSA => DecodingKey::from_ed_pem(pem.as_bytes()),
+                _ => {
+                    return Err(DaoError::Unauthorized(format!(
+                        "algorithm {:?} requires a shared secret",
+                        alg
+                    )))
+                }
+            };
+            return key.map_err(|e| DaoError::Filter(format!("Invalid jwt public key: {}", e)));
+        }
+
+        if let Some(url) = &self.config.jwks_url {
+            let ttl = Duration::from_secs(self.config.jwks_ttl_secs);
+            return self.jwks.decoding_key(url, kid, ttl).await;
+        }
+
+        Err(DaoError::Filter(
+            "jwt: no key source configured".to_string(),
+        ))
+    }
+}
+
+/// Извлечение токена из `Authorization: Bearer <token>`
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let token = token.trim();
+    (!token.is_empty()).then_some(token)
+}
+
+/// Кэш JWKS с TTL (ключ — URL), общий для всех маршрутов
+pub struct JwksCache {
+    entries: DashMap<String, CachedJwks>,
+    /// `https://` (системные корневые сертификаты) и `http://`
+    client: Client<HttpsConnector<HttpConnector>, Empty<Bytes>>,
+    /// Минимальный интервал между принудительными обновлениями
+    /// (неизвестный `kid` не должен превращаться в поток запросов к IdP)
+    refresh_interval: Duration,
+}
+
+#[derive(Clone)]
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+impl JwksCache {
+    pub fn new() -> Self {
+        Self::with_refresh_interval(Duration::from_secs(30))
+    }
+
+    pub fn with_refresh_interval(refresh_interval: Duration) -> Self {
+        Self {
+            entries: DashMap::new(),
+            client: Client::builder(TokioExecutor::new()).build(
+                HttpsConnectorBuilder::new()
+                    .with_tls_config(
+                        rustls::ClientConfig::builder()
+                            .with_root_certificates(crate::upstream::client::native_roots())
+                            .with_no_client_auth(),
+                    )
+                    .https_or_http()
+                    .enable_http1()
+                    .build(),
+            ),
+            refresh_interval,
+        }
+    }
+
+    /// Ключ для проверки подписи по `kid`.
+    ///
+    /// Неизвестный `kid` вызывает внеочередное обновление JWKS (ротация ключей).
+    pub async fn decoding_key(
+        &self,
+        url: &str,
+        kid: Option<&str>,
+        ttl: Duration,
+    ) -> Result<DecodingKey> {
+        let cached = self.entries.get(url).map(|e| e.clone());
+
+        if let Some(cached) = &cached {
+            let fresh = cached.fetched_at.elapsed() < ttl;
+            if fresh {
+                if let Some(key) = find_key(&cached.keys, kid) {
+                    return key;
+                }
+                // Ключ не найден — возможно, ротация; но не чаще refresh_interval
+                if cached.fetched_at.elapsed() < self.refresh_interval {
+                    return Err(DaoError::Unauthorized("unknown signing key".to_string()));
+                }
+            }
+        }
+
+        let keys = self.fetch(url).await?;
+        let key = find_key(&keys, kid);
+        self.entries.insert(
+            url.to_string(),
+            CachedJwks {
+                keys,
+                fetched_at: Instant::now(),
+            },
+        );
+
+        key.unwrap_or_else(|| Err(DaoError::Unauthorized("unknown signing key".to_string())))
+    }
+
+    async fn fetch(&self, url: &str) -> Result<JwkSet> {
+        tracing::debug!("Fetching JWKS from {}", url);
+
+        let uri: http::Uri = url
+            .parse()
+            .map_err(|e| DaoError::Filter(format!("Invalid JWKS URL: {}", e)))?;
+
+        let response = tokio::time::timeout(JWKS_FETCH_TIMEOUT, self.client.get(uri))
+            .await
+            .map_err(|_| DaoError::Filter("JWKS fetch timed out".to_string()))?
+            .map_err(|e| DaoError::Filter(format!("JWKS fetch failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(DaoError::Filter(format!(
+                "JWKS fetch returned {}",
+                response.status()
+            )));
+        }
+
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| DaoError::Filter(format!("JWKS read failed: {}", e)))?
+            .to_bytes();
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+impl Default for JwksCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Поиск ключа по `kid`; без `kid` допускается единственный ключ в наборе
+fn find_key(keys: &JwkSet, kid: Option<&str>) -> Option<Result<DecodingKey>> {
+    let jwk = match kid {
+        Some(kid) => keys.find(kid),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }?;
+
+    Some(DecodingKey::from_jwk(jwk).map_err(|e| DaoError::Filter(format!("Invalid JWK: {}", e))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    const SECRET: &str = "test-secret";
+
+    fn jwt_config() -> JwtConfig {
+        JwtConfig {
+            secret: Some(SECRET.to_string()),
+            public_key_pem: None,
+            jwks_url: None,
+            jwks_ttl_secs: 300,
+            algorithms: None,
+            issuer: Some("https://issuer.example.com".to_string()),
+            audience: None,
+            leeway_secs: 0,
+            claims_to_headers: Some(HashMap::from([(
+                "sub".to_string(),
+                "X-User-Id".to_string(),
+            )])),
+        }
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn token(header: &Header, secret: &str, exp: u64) -> String {
+        let claims = serde_json::json!({
+            "sub": "user-42",
+            "iss": "https://issuer.example.com",
+            "exp": exp,
+        });
+        encode(
+            header,
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn auth_headers(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_jwt_valid_token() {
+        let config = jwt_config();
+        let jwks = JwksCache::new();
+        let filter = JwtFilter::new(&config, &jwks);
+
+        let headers = auth_headers(&token(&Header::default(), SECRET, now() + 600));
+        let claims = filter.authenticate(&headers).await.unwrap();
+        assert_eq!(claims["sub"], "user-42");
+
+        // Клиентский X-User-Id перезаписывается проверенным значением
+        let mut upstream_headers = HeaderMap::new();
+        upstream_headers.insert("x-user-id", HeaderValue::from_static("spoofed"));
+        filter.inject_claims(&claims, &mut upstream_headers);
+        assert_eq!(upstream_headers["x-user-id"], "user-42");
+    }
+
+    #[tokio::test]
+    async fn test_jwt_expired_token() {
+        let config = jwt_config();
+        let jwks = JwksCache::new();
+        let filter = JwtFilter::new(&config, &jwks);
+
+        let headers = auth_headers(&token(&Header::default(), SECRET, now() - 600));
+        let err = filter.authenticate(&headers).await.unwrap_err();
+        assert!(matches!(err, DaoError::Unauthorized(_)));
+        assert!(JwtFilter::challenge(&err)
+            .to_str()
+            .unwrap()
+            .contains("invalid_token"));
+    }
+
+    #[tokio::test]
+    async fn test_jwt_bad_signature() {
+        let config = jwt_config();
+        let jwks = JwksCache::new();
+        let filter = JwtFilter::new(&config, &jwks);
+
+        let headers = auth_headers(&token(&Header::default(), "other-secret", now() + 600));
+        assert!(filter.authenticate(&headers).await.is_err());
+
+        // Алгоритм вне списка разрешенных
+        let headers = auth_headers(&token(&Header::new(Algorithm::HS512), SECRET, now() + 600));
+        assert!(filter.authenticate(&headers).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_jwt_missing_header() {
+        let config = jwt_config();
+        let jwks = JwksCache::new();
+        let filter = JwtFilter::new(&config, &jwks);
+
+        let err = filter.authenticate(&HeaderMap::new()).await.unwrap_err();
+        assert_eq!(JwtFilter::challenge(&err), "Bearer realm=\"dao\"");
+    }
+
+    #[tokio::test]
+    async fn test_jwks_fetched_over_https() {
+        // IdP по https: клиент начинает TLS handshake (ClientHello — 0x16)
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}/jwks", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            use tokio::io::AsyncReadExt;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.read_u8().await.unwrap()
+        });
+
+        let jwks = JwksCache::new();
+        assert!(jwks
+            .decoding_key(&url, None, Duration::from_secs(60))
+            .await
+            .is_err());
+        assert_eq!(server.await.unwrap(), 0x16);
+    }
+
+    #[tokio::test]
+    async fn test_jwks_rotation() {
+        use hyper::server::conn::http1;
+        use hyper::service::service_fn;
+        use hyper_util::rt::TokioIo;
+        use parking_lot::RwLock;
+
+        fn jwks_json(kid: &str, secret: &str) -> String {
+            use base64::Engine;
+            let k = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(secret);
+            serde_json::json!({
+                "keys": [{ "kty": "oct", "kid": kid, "alg": "HS256", "k": k }]
+            })
+            .to_string()
+        }
+
+        // Локальный JWKS endpoint с подменяемым содержимым
+        let jwks_body = Arc::new(RwLock::new(jwks_json("k1", "secret-one")));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let body = jwks_body.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let body = body.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |_req| {
+                        let json = body.read().clone();
+                        async move {
+                            Ok::<_, std::convert::Infallible>(hyper::Response::new(
+                                http_body_util::Full::new(Bytes::from(json)),
+                            ))
+                        }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        let mut config = jwt_config();
+        config.secret = None;
+        config.jwks_url = Some(format!("http://{}/jwks.json", addr));
+        config.algorithms = Some(vec!["HS256".to_string()]);
+        let jwks = JwksCache::with_refresh_interval(Duration::ZERO);
+        let filter = JwtFilter::new(&config, &jwks);
+
+        let mut header = Header {
+            kid: Some("k1".to_string()),
+            ..Default::default()
+        };
+        let headers = auth_headers(&token(&header, "secret-one", now() + 600));
+        assert!(filter.authenticate(&headers).await.is_ok());
+
+        // Ротация: IdP публикует новый ключ k2
+        *jwks_body.write() = jwks_json("k2", "secret-two");
+        header.kid = Some("k2".to_string());
+        let headers = auth_headers(&token(&header, "secret-two", now() + 600));
+        assert!(filter.authenticate(&headers).await.is_ok());
+    }
+}
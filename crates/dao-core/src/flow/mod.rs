@@ -4,15 +4,46 @@
 //! - Header manipulation
 //! - Rate limiting
 //! - Authentication
+//! - CORS
 //! - Compression
 //! - WASM filters (будущее)
 
+use crate::config::FilterConfig;
 use crate::Result;
-use http::{Request, Response, HeaderMap, HeaderName, HeaderValue};
+use http::{HeaderMap, HeaderName, HeaderValue};
 use std::collections::HashMap;
 
+pub mod basic_auth;
+pub mod body;
+pub mod cache;
+pub mod client_cert;
+pub mod cors;
+pub mod error_page;
 pub mod filters;
+pub mod idempotency;
+pub mod ip_access;
+pub mod jwt;
+pub mod rate_limit;
+pub mod redact;
+pub mod template;
+pub mod tunnel;
+pub use basic_auth::BasicAuthFilter;
+pub use body::{BodyBuffer, ProxyBody};
+pub use cache::{CacheRegistry, RequestKey, ResponseCache, CACHE_STATUS_HEADER};
+pub use client_cert::ClientCertFilter;
+pub use cors::CorsFilter;
+pub use error_page::{request_id, shape_upstream_error, ErrorPages, REQUEST_ID_HEADER};
 pub use filters::{Filter, FilterChain};
+pub use idempotency::{
+    has_idempotency_key, idempotency_key, Claim, IdempotencyRegistry, IdempotencyStore,
+    IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER,
+};
+pub use ip_access::IpAccessFilter;
+pub use jwt::{JwksCache, JwtClaims, JwtFilter};
+pub use rate_limit::{rate_limit_key, RateDecision, RateLimiter, DEFAULT_RATE_LIMIT_MAX_KEYS};
+pub use redact::RedactedHeaders;
+pub use template::TemplateVars;
+pub use tunnel::{copy_metered, forward_upgrade, is_upgrade_request, MeteredIo};
 
 /// Flow — система обработки потока
 pub struct Flow {
@@ -53,7 +84,43 @@ impl HeaderManipulator {
         self.remove_headers.push(key);
     }
 
+    /// Правка запроса к upstream'у: `request_headers_remove` и
+    /// `request_headers_add` с подстановкой переменных запроса
+    pub fn for_request(filters: &FilterConfig, vars: &TemplateVars<'_>) -> Self {
+        let mut manipulator = Self::new();
+        for name in filters.request_headers_remove.iter().flatten() {
+            manipulator.remove_header(name.clone());
+        }
+        for (name, value) in filters.request_headers_add.iter().flatten() {
+            manipulator.add_header(name.clone(), template::interpolate(value, vars));
+        }
+        manipulator
+    }
+
+    /// Правка ответа маршрута: `response_headers_remove`,
+    /// `response_headers_add` и `server_header`
+    pub fn for_response(filters: &FilterConfig) -> Self {
+        let mut manipulator = Self::new();
+        for name in filters.response_headers_remove.iter().flatten() {
+            manipulator.remove_header(name.clone());
+        }
+        for (name, value) in filters.response_headers_add.iter().flatten() {
+            manipulator.add_header(name.clone(), value.clone());
+        }
+        if let Some(server) = &filters.server_header {
+            manipulator.add_header(http::header::SERVER.to_string(), server.clone());
+        }
+        manipulator
+    }
+
     pub fn apply_to_headers(&self, headers: &mut HeaderMap) -> Result<()> {
+        // Сначала удаление — добавленные заголовки списком remove не снимаются
+        for key in &self.remove_headers {
+            let header_name = HeaderName::from_bytes(key.as_bytes())
+                .map_err(|e| crate::DaoError::Filter(format!("Invalid header name: {}", e)))?;
+            headers.remove(header_name);
+        }
+
         // Добавление заголовков
         for (key, value) in &self.add_headers {
             let header_name = HeaderName::from_bytes(key.as_bytes())
@@ -63,13 +130,6 @@ impl HeaderManipulator {
             headers.insert(header_name, header_value);
         }
 
-        // Удаление заголовков
-        for key in &self.remove_headers {
-            let header_name = HeaderName::from_bytes(key.as_bytes())
-                .map_err(|e| crate::DaoError::Filter(format!("Invalid header name: {}", e)))?;
-            headers.remove(header_name);
-        }
-
         Ok(())
     }
 }
@@ -97,5 +157,52 @@ mod tests {
 
         assert!(headers.contains_key("x-dao"));
         assert!(!headers.contains_key("x-unwanted"));
+
+        // Заголовок из обоих списков заменяется, а не снимается
+        manipulator.remove_header("server".to_string());
+        manipulator.add_header("server".to_string(), "dao".to_string());
+        headers.insert("server", "nginx".parse().unwrap());
+        manipulator.apply_to_headers(&mut headers).unwrap();
+        assert_eq!(headers["server"], "dao");
+    }
+
+    #[test]
+    fn test_request_headers_interpolated() {
+        let filters: FilterConfig = toml::from_str(
+            r#"
+            request_headers_remove = ["x-internal"]
+            request_headers_add = { "x-route-name" = "{route}", "x-client-ip" = "{client_ip}", "x-trace" = "{request_id}@{upstream}", "x-tenant" = "{tenant}" }
+            "#,
+        )
+        .unwrap();
+        let vars = TemplateVars {
+            route: "api-v1",
+            client_ip: "203.0.113.7".parse().unwrap(),
+            request_id: "req-42",
+            upstream: "backend-1",
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-internal", "1".parse().unwrap());
+        HeaderManipulator::for_request(&filters, &vars)
+            .apply_to_headers(&mut headers)
+            .unwrap();
+
+        assert_eq!(headers["x-route-name"], "api-v1");
+        assert_eq!(headers["x-client-ip"], "203.0.113.7");
+        assert_eq!(headers["x-trace"], "req-42@backend-1");
+        assert_eq!(headers["x-tenant"], "{tenant}");
+        assert!(!headers.contains_key("x-internal"));
+
+        // В режиме reject неизвестная переменная — ошибка конфигурации
+        let mut strict = filters.clone();
+        strict.unknown_header_variables = crate::config::UnknownHeaderVariables::Reject;
+        let err = strict.validate().unwrap_err().to_string();
+        assert!(err.contains("{tenant}"), "{}", err);
+        strict
+            .request_headers_add
+            .as_mut()
+            .unwrap()
+            .remove("x-tenant");
+        strict.validate().unwrap();
     }
 }
@@ -0,0 +1,234 @@
+//! Rate limiting: token bucket на ключ клиента
+//!
+//! Ключ — IP клиента, значение заголовка (например, `X-Api-Key`) или весь
+//! маршрут. Число bucket'ов ограничено LRU: при потоке уникальных ключей
+//! вытесняются давно не встречавшиеся.
+
+use crate::config::{FilterConfig, RateLimitKey};
+use http::{HeaderMap, HeaderName, HeaderValue, Request};
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Лимит числа bucket'ов по умолчанию
+pub const DEFAULT_RATE_LIMIT_MAX_KEYS: usize = 10_000;
+
+/// Остаток бюджета ключа
+pub const RATE_LIMIT_REMAINING_HEADER: HeaderName =
+    HeaderName::from_static("x-ratelimit-remaining");
+
+/// Ключ bucket'а для запроса.
+///
+/// Для ключа по заголовку запрос без заголовка лимитируется по IP клиента.
+pub fn rate_limit_key<B>(config: &FilterConfig, req: &Request<B>, peer: IpAddr) -> String {
+    match &config.rate_limit_key {
+        RateLimitKey::Route => String::new(),
+        RateLimitKey::ClientIp => format!("ip:{}", peer.to_canonical()),
+        RateLimitKey::Header(name) => match req.headers().get(name.as_str()) {
+            Some(value) => format!("header:{}", String::from_utf8_lossy(value.as_bytes())),
+            None => format!("ip:{}", peer.to_canonical()),
+        },
+    }
+}
+
+/// Token bucket'ы всех маршрутов
+pub struct RateLimiter {
+    buckets: Mutex<LruCache<(String, String), Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// После этого момента bucket полон и неотличим от нового
+    full_at: Instant,
+}
+
+/// Решение по запросу
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateDecision {
+    pub allowed: bool,
+    /// Целых токенов после запроса
+    pub remaining: u32,
+    /// Через сколько появится токен (только для отклоненных)
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimiter {
+    pub fn new(max_keys: usize) -> Self {
+        let capacity = NonZeroUsize::new(max_keys).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            buckets: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Списание токена: `rps` — скорость пополнения, `burst` — емкость
+    pub fn check(
+        &self,
+        route: &str,
+        key: &str,
+        rps: u32,
+        burst: u32,
+        now: Instant,
+    ) -> RateDecision {
+        let rate = f64::from(rps.max(1));
+        let capacity = f64::from(burst.max(1));
+
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.get_or_insert_mut((route.to_string(), key.to_string()), || Bucket {
+            tokens: capacity,
+            updated: now,
+            full_at: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        let decision = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            RateDecision {
+                allowed: true,
+                remaining: bucket.tokens as u32,
+                retry_after: None,
+            }
+        } else {
+            RateDecision {
+                allowed: false,
+                remaining: 0,
+                retry_after: Some(Duration::from_secs_f64((1.0 - bucket.tokens) / rate)),
+            }
+        };
+        bucket.full_at = now + Duration::from_secs_f64((capacity - bucket.tokens) / rate);
+        decision
+    }
+
+    /// Удаление полностью пополненных bucket'ов (клиент давно не приходил)
+    pub fn evict_idle(&self, now: Instant) -> usize {
+        let mut buckets = self.buckets.lock();
+        let idle: Vec<_> = buckets
+            .iter()
+            .filter(|(_, bucket)| bucket.full_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &idle {
+            buckets.pop(key);
+        }
+        idle.len()
+    }
+
+    /// Периодическая очистка (фоновая задача)
+    pub async fn run_eviction(self: Arc<Self>, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let evicted = self.evict_idle(Instant::now());
+            if evicted > 0 {
+                tracing::debug!("Evicted {} idle rate limit buckets", evicted);
+            }
+        }
+    }
+
+    /// Число bucket'ов
+    pub fn len(&self) -> usize {
+        self.buckets.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_RATE_LIMIT_MAX_KEYS)
+    }
+}
+
+impl RateDecision {
+    /// Заголовки ответа 429: `Retry-After` (целые секунды) и остаток
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            RATE_LIMIT_REMAINING_HEADER,
+            HeaderValue::from(self.remaining),
+        );
+        if let Some(retry_after) = self.retry_after {
+            let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            headers.insert(http::header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_independent_key_budgets() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        // Бюджет первого ключа исчерпан
+        for remaining in (0..3).rev() {
+            let decision = limiter.check("api", "key-a", 1, 3, now);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+        let rejected = limiter.check("api", "key-a", 1, 3, now);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.headers()[http::header::RETRY_AFTER], "1");
+        assert_eq!(rejected.headers()[RATE_LIMIT_REMAINING_HEADER], "0");
+
+        // Второй ключ и тот же ключ другого маршрута не затронуты
+        assert!(limiter.check("api", "key-b", 1, 3, now).allowed);
+        assert!(limiter.check("admin", "key-a", 1, 3, now).allowed);
+
+        // Пополнение со временем
+        assert!(
+            limiter
+                .check("api", "key-a", 1, 3, now + Duration::from_secs(1))
+                .allowed
+        );
+    }
+
+    #[test]
+    fn test_bucket_map_bounded() {
+        let limiter = RateLimiter::new(100);
+        let now = Instant::now();
+
+        for i in 0..10_000 {
+            limiter.check("api", &format!("client-{}", i), 10, 10, now);
+        }
+        assert_eq!(limiter.len(), 100);
+
+        // Через секунду все bucket'ы полны — очистка удаляет их
+        assert_eq!(limiter.evict_idle(now), 0);
+        assert_eq!(limiter.evict_idle(now + Duration::from_secs(1)), 100);
+        assert!(limiter.is_empty());
+    }
+
+    #[test]
+    fn test_rate_limit_key() {
+        let peer: IpAddr = "192.0.2.7".parse().unwrap();
+        let req = Request::get("/")
+            .header("x-api-key", "secret-1")
+            .body(())
+            .unwrap();
+        let anonymous = Request::get("/").body(()).unwrap();
+
+        let config: FilterConfig =
+            toml::from_str("rate_limit_key = { header = \"X-Api-Key\" }").unwrap();
+        assert_eq!(rate_limit_key(&config, &req, peer), "header:secret-1");
+        assert_eq!(rate_limit_key(&config, &anonymous, peer), "ip:192.0.2.7");
+
+        let config: FilterConfig = toml::from_str("rate_limit_key = \"client_ip\"").unwrap();
+        assert_eq!(rate_limit_key(&config, &req, peer), "ip:192.0.2.7");
+
+        let config: FilterConfig = toml::from_str("").unwrap();
+        assert_eq!(rate_limit_key(&config, &req, peer), "");
+    }
+}
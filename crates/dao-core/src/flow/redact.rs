@@ -0,0 +1,88 @@
+//! Заголовки для логов: выводятся значения только разрешенных заголовков
+
+use crate::config::LoggingConfig;
+use http::HeaderMap;
+use std::fmt;
+
+/// Маска вместо значения скрытого заголовка
+pub const REDACTED: &str = "***";
+
+/// Заголовки в виде `name: value, ...` для логов и отладочного вывода.
+/// Значения выводятся только у `log_headers`, кроме `redact_headers`
+/// (без учета регистра); остальные заменяются на `***`. Сами заголовки
+/// не меняются — upstream получает исходные значения.
+pub struct RedactedHeaders<'a> {
+    headers: &'a HeaderMap,
+    logging: &'a LoggingConfig,
+}
+
+impl<'a> RedactedHeaders<'a> {
+    pub fn new(headers: &'a HeaderMap, logging: &'a LoggingConfig) -> Self {
+        Self { headers, logging }
+    }
+
+    fn is_logged(&self, name: &str) -> bool {
+        let listed = |list: &[String]| list.iter().any(|h| h.eq_ignore_ascii_case(name));
+        listed(&self.logging.log_headers) && !listed(&self.logging.redact_headers)
+    }
+}
+
+impl fmt::Display for RedactedHeaders<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.headers.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            let value = if self.is_logged(name.as_str()) {
+                value.to_str().unwrap_or("<binary>")
+            } else {
+                REDACTED
+            };
+            write!(f, "{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_sensitive_headers_redacted() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer secret-token".parse().unwrap());
+        headers.insert("cookie", "session=abc".parse().unwrap());
+        headers.insert("x-api-key", "key-1".parse().unwrap());
+        headers.insert("accept", "application/json".parse().unwrap());
+        headers.insert("x-auth-token", "tok-2".parse().unwrap());
+
+        let logging = LoggingConfig::default();
+        let line = RedactedHeaders::new(&headers, &logging).to_string();
+        assert!(line.contains("authorization: ***"), "{}", line);
+        assert!(line.contains("cookie: ***"), "{}", line);
+        assert!(line.contains("x-api-key: ***"), "{}", line);
+        assert!(line.contains("accept: application/json"), "{}", line);
+        // Заголовок вне allowlist скрыт, даже если его нет в redact_headers
+        assert!(line.contains("x-auth-token: ***"), "{}", line);
+        assert!(
+            !line.contains("secret-token") && !line.contains("abc"),
+            "{}",
+            line
+        );
+        assert!(!line.contains("tok-2"), "{}", line);
+
+        // Свои списки, регистр не важен; redact_headers сильнее log_headers
+        let custom = LoggingConfig {
+            redact_headers: vec!["Accept".to_string()],
+            log_headers: vec!["ACCEPT".to_string(), "Authorization".to_string()],
+        };
+        let line = RedactedHeaders::new(&headers, &custom).to_string();
+        assert!(
+            line.contains("accept: ***") && line.contains("secret-token"),
+            "{}",
+            line
+        );
+        assert!(line.contains("x-auth-token: ***"), "{}", line);
+    }
+}
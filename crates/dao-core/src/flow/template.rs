@@ -0,0 +1,110 @@
+//! Шаблоны значений заголовков: `{route}`, `{client_ip}`, `{request_id}`,
+//! `{upstream}` подставляются на каждый запрос
+
+use std::net::IpAddr;
+
+/// Переменные, доступные в шаблонах заголовков запроса
+pub const TEMPLATE_VARIABLES: &[&str] = &["route", "client_ip", "request_id", "upstream"];
+
+/// Значения переменных для одного запроса
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateVars<'a> {
+    pub route: &'a str,
+    pub client_ip: IpAddr,
+    pub request_id: &'a str,
+    pub upstream: &'a str,
+}
+
+impl TemplateVars<'_> {
+    fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "route" => Some(self.route.to_string()),
+            "client_ip" => Some(self.client_ip.to_string()),
+            "request_id" => Some(self.request_id.to_string()),
+            "upstream" => Some(self.upstream.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Подстановка переменных; неизвестные `{name}` остаются как есть
+pub fn interpolate(template: &str, vars: &TemplateVars<'_>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some((start, name, end)) = next_variable(rest) {
+        out.push_str(&rest[..start]);
+        match vars.get(name) {
+            Some(value) => out.push_str(&value),
+            None => out.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Неизвестные переменные шаблона (для проверки конфигурации)
+pub fn unknown_variables(template: &str) -> Vec<&str> {
+    let mut unknown = Vec::new();
+    let mut rest = template;
+    while let Some((_, name, end)) = next_variable(rest) {
+        if !TEMPLATE_VARIABLES.contains(&name) {
+            unknown.push(name);
+        }
+        rest = &rest[end..];
+    }
+    unknown
+}
+
+/// Ближайшая `{name}`: начало, имя и конец (после `}`); имя — буквы,
+/// цифры и `_`, иначе скобки — обычный текст
+fn next_variable(s: &str) -> Option<(usize, &str, usize)> {
+    let mut from = 0;
+    while let Some(open) = s[from..].find('{').map(|i| from + i) {
+        let name_len = s[open + 1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(s.len() - open - 1);
+        let close = open + 1 + name_len;
+        if name_len > 0 && s[close..].starts_with('}') {
+            return Some((open, &s[open + 1..close], close + 1));
+        }
+        from = open + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> TemplateVars<'static> {
+        TemplateVars {
+            route: "api-v1",
+            client_ip: "203.0.113.7".parse().unwrap(),
+            request_id: "req-42",
+            upstream: "backend-1",
+        }
+    }
+
+    #[test]
+    fn test_interpolate_variables() {
+        let vars = vars();
+        assert_eq!(interpolate("{route}", &vars), "api-v1");
+        assert_eq!(interpolate("{client_ip}", &vars), "203.0.113.7");
+        assert_eq!(interpolate("id={request_id}", &vars), "id=req-42");
+        assert_eq!(interpolate("{upstream}/{route}", &vars), "backend-1/api-v1");
+        assert_eq!(interpolate("static", &vars), "static");
+    }
+
+    #[test]
+    fn test_unknown_variables_left_literal() {
+        let vars = vars();
+        assert_eq!(interpolate("{tenant}-{route}", &vars), "{tenant}-api-v1");
+        assert_eq!(interpolate("{ {} {route", &vars), "{ {} {route");
+        assert_eq!(
+            unknown_variables("{tenant}-{route}-{region}"),
+            ["tenant", "region"]
+        );
+        assert!(unknown_variables("{route} {} {client_ip}").is_empty());
+    }
+}
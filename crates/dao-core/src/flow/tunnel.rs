@@ -0,0 +1,208 @@
+//! Туннели (WebSocket, upgrade): двунаправленное копирование с подсчетом
+//! байт в обе стороны
+
+use http::{header, HeaderMap, HeaderValue, Request};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Поток, считающий прочитанные и записанные байты
+#[pin_project::pin_project]
+pub struct MeteredIo<S> {
+    #[pin]
+    inner: S,
+    read: metrics::Counter,
+    written: metrics::Counter,
+}
+
+impl<S> MeteredIo<S> {
+    pub fn new(inner: S, read: metrics::Counter, written: metrics::Counter) -> Self {
+        Self {
+            inner,
+            read,
+            written,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for MeteredIo<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let before = buf.filled().len();
+        std::task::ready!(this.inner.poll_read(cx, buf))?;
+        this.read.increment((buf.filled().len() - before) as u64);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for MeteredIo<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let written = std::task::ready!(this.inner.poll_write(cx, buf))?;
+        this.written.increment(written as u64);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+/// Копирование между клиентом и upstream'ом до закрытия обеих сторон.
+///
+/// `bytes_in` — от клиента, `bytes_out` — клиенту; возвращает те же числа.
+pub async fn copy_metered<C, U>(
+    client: C,
+    upstream: &mut U,
+    bytes_in: metrics::Counter,
+    bytes_out: metrics::Counter,
+) -> io::Result<(u64, u64)>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let mut client = MeteredIo::new(client, bytes_in, bytes_out);
+    tokio::io::copy_bidirectional(&mut client, upstream).await
+}
+
+/// Запрос на смену протокола (HTTP/1.1 `Connection: upgrade` +
+/// `Upgrade`): WebSocket, h2c или любой другой токен
+pub fn is_upgrade_request<B>(req: &Request<B>) -> bool {
+    req.version() == http::Version::HTTP_11 && upgrade_token(req.headers()).is_some()
+}
+
+/// Значение `Upgrade`, если `Connection` содержит `upgrade`
+fn upgrade_token(headers: &HeaderMap) -> Option<&HeaderValue> {
+    let connection_upgrade = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    connection_upgrade
+        .then(|| headers.get(header::UPGRADE))
+        .flatten()
+}
+
+/// Возврат `Connection` и `Upgrade` после снятия hop-by-hop заголовков:
+/// upstream'у передаются `upgrade` и токены заголовков, дошедших до него
+/// (`HTTP2-Settings` для h2c), остальные токены (`keep-alive`, `close`) — нет
+pub fn forward_upgrade(original: &HeaderMap, forwarded: &mut HeaderMap) {
+    let Some(upgrade) = upgrade_token(original) else {
+        return;
+    };
+    let tokens: Vec<&str> = original
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|token| token.eq_ignore_ascii_case("upgrade") || forwarded.contains_key(*token))
+        .collect();
+    let connection = HeaderValue::from_str(&tokens.join(", "))
+        .unwrap_or_else(|_| HeaderValue::from_static("upgrade"));
+    forwarded.insert(header::UPGRADE, upgrade.clone());
+    forwarded.insert(header::CONNECTION, connection);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_copy_metered_counts_both_directions() {
+        let (bytes_in, bytes_out) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+        let (client, mut client_peer) = tokio::io::duplex(64);
+        let (mut upstream, mut upstream_peer) = tokio::io::duplex(64);
+
+        let tunnel = tokio::spawn({
+            let counters = (
+                metrics::Counter::from_arc(bytes_in.clone()),
+                metrics::Counter::from_arc(bytes_out.clone()),
+            );
+            async move { copy_metered(client, &mut upstream, counters.0, counters.1).await }
+        });
+
+        // Upstream отвечает эхом с префиксом
+        let echo = tokio::spawn(async move {
+            let mut request = vec![0u8; 1000];
+            upstream_peer.read_exact(&mut request).await.unwrap();
+            upstream_peer.write_all(b"echo:").await.unwrap();
+            upstream_peer.write_all(&request).await.unwrap();
+            upstream_peer.shutdown().await.unwrap();
+        });
+
+        client_peer.write_all(&[7u8; 1000]).await.unwrap();
+        let mut response = Vec::new();
+        client_peer.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response.len(), 1005);
+        client_peer.shutdown().await.unwrap();
+
+        echo.await.unwrap();
+        let (sent, received) = tunnel.await.unwrap().unwrap();
+        assert_eq!((sent, received), (1000, 1005));
+        assert_eq!(bytes_in.load(Ordering::Relaxed), 1000);
+        assert_eq!(bytes_out.load(Ordering::Relaxed), 1005);
+    }
+
+    #[test]
+    fn test_upgrade_request_any_token() {
+        let request = |connection: &str, upgrade: &str| {
+            Request::get("/")
+                .header(header::CONNECTION, connection)
+                .header(header::UPGRADE, upgrade)
+                .body(())
+                .unwrap()
+        };
+        assert!(is_upgrade_request(&request("Upgrade", "websocket")));
+        assert!(is_upgrade_request(&request(
+            "keep-alive, upgrade",
+            "custom-proto/1"
+        )));
+        assert!(!is_upgrade_request(&request("keep-alive", "websocket")));
+
+        let mut http2 = request("upgrade", "h2c");
+        *http2.version_mut() = http::Version::HTTP_2;
+        assert!(!is_upgrade_request(&http2));
+
+        let mut forwarded = HeaderMap::new();
+        forward_upgrade(
+            request("keep-alive, Upgrade", "custom-proto/1").headers(),
+            &mut forwarded,
+        );
+        assert_eq!(forwarded[header::UPGRADE], "custom-proto/1");
+        assert_eq!(forwarded[header::CONNECTION], "Upgrade");
+
+        // h2c: токен HTTP2-Settings сохраняется вместе с заголовком
+        let mut h2c = request("Upgrade, HTTP2-Settings, close", "h2c");
+        h2c.headers_mut().insert(
+            "http2-settings",
+            HeaderValue::from_static("AAMAAABkAARAAAAAAAIAAAAA"),
+        );
+        let mut forwarded = HeaderMap::new();
+        forwarded.insert("http2-settings", h2c.headers()["http2-settings"].clone());
+        forward_upgrade(h2c.headers(), &mut forwarded);
+        assert_eq!(forwarded[header::UPGRADE], "h2c");
+        assert_eq!(forwarded[header::CONNECTION], "Upgrade, HTTP2-Settings");
+    }
+}
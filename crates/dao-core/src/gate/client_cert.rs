@@ -0,0 +1,94 @@
+//! Identity клиента из сертификата mTLS
+
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Identity из проверенного сертификата клиента; доступна маршрутам
+/// (`match.client_cert_cn`/`client_cert_san`) и фильтрам через
+/// extensions запроса
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// CN субъекта
+    pub common_name: Option<String>,
+    /// Subject Alternative Names: DNS, URI (SPIFFE), email, IP
+    pub sans: Vec<String>,
+}
+
+impl ClientIdentity {
+    /// Разбор DER сертификата клиента (None — не X.509)
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+        let sans = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|ext| {
+                ext.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::DNSName(v)
+                        | GeneralName::URI(v)
+                        | GeneralName::RFC822Name(v) => Some(v.to_string()),
+                        GeneralName::IPAddress(bytes) => {
+                            ip_from_bytes(bytes).map(|ip| ip.to_string())
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self { common_name, sans })
+    }
+
+    /// Identity из первого сертификата цепочки, предъявленной при handshake
+    pub fn from_session(session: &rustls::ServerConnection) -> Option<Self> {
+        session
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .and_then(|cert| Self::from_der(cert))
+    }
+
+    pub fn has_san(&self, san: &str) -> bool {
+        self.sans.iter().any(|s| s == san)
+    }
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<std::net::IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(std::net::IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(std::net::IpAddr::from),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_from_cert() {
+        let mut params = rcgen::CertificateParams::new(vec!["svc-a.internal".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "svc-a");
+        params.subject_alt_names.push(rcgen::SanType::URI(
+            "spiffe://mesh/svc-a".try_into().unwrap(),
+        ));
+        let cert = params
+            .self_signed(&rcgen::KeyPair::generate().unwrap())
+            .unwrap();
+
+        let identity = ClientIdentity::from_der(cert.der()).unwrap();
+        assert_eq!(identity.common_name.as_deref(), Some("svc-a"));
+        assert_eq!(identity.sans, ["svc-a.internal", "spiffe://mesh/svc-a"]);
+        assert!(identity.has_san("spiffe://mesh/svc-a"));
+        assert!(ClientIdentity::from_der(b"not a certificate").is_none());
+    }
+}
@@ -0,0 +1,196 @@
+//! Лимиты параллельных запросов и соединений, in-flight gauge
+
+use crate::config::ConnectionOverflow;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Gauge запросов в полете: глобальный и по маршрутам
+pub const IN_FLIGHT_GAUGE: &str = "dao_in_flight_requests";
+pub const ROUTE_IN_FLIGHT_GAUGE: &str = "dao_route_in_flight_requests";
+/// Соединения, закрытые сверх `server.max_connections`
+pub const CONNECTIONS_REJECTED_COUNTER: &str = "dao_connections_rejected_total";
+
+/// Ограничение параллельных запросов (`server.max_concurrent_requests`)
+#[derive(Clone)]
+pub struct ConcurrencyLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+    in_flight: Arc<AtomicUsize>,
+    routes: Arc<DashMap<String, Arc<AtomicUsize>>>,
+}
+
+impl ConcurrencyLimiter {
+    /// `None` — без лимита, только учет
+    pub fn new(max_concurrent: Option<usize>) -> Self {
+        Self {
+            semaphore: max_concurrent.map(|max| Arc::new(Semaphore::new(max))),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            routes: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Разрешение на запрос; `None` — лимит исчерпан
+    pub fn try_acquire(&self) -> Option<RequestPermit> {
+        let permit = match &self.semaphore {
+            Some(semaphore) => Some(semaphore.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+
+        let count = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics::gauge!(IN_FLIGHT_GAUGE).set(count as f64);
+
+        Some(RequestPermit {
+            _permit: permit,
+            in_flight: self.in_flight.clone(),
+            route: None,
+            routes: self.routes.clone(),
+        })
+    }
+
+    /// Запросов в полете
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Запросов в полете по маршруту
+    pub fn route_in_flight(&self, route: &str) -> usize {
+        self.routes
+            .get(route)
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+}
+
+/// Ограничение одновременных соединений (`server.max_connections`)
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+    overflow: ConnectionOverflow,
+}
+
+impl ConnectionLimiter {
+    /// `None` — без лимита
+    pub fn new(max_connections: Option<usize>, overflow: ConnectionOverflow) -> Self {
+        Self {
+            semaphore: max_connections.map(|max| Arc::new(Semaphore::new(max))),
+            overflow,
+        }
+    }
+
+    /// Слот для принятого соединения: в режиме `wait` — ожидание
+    /// освобождения (accept приостановлен), иначе `None` — лимит исчерпан,
+    /// соединение нужно закрыть
+    pub async fn admit(&self) -> Option<ConnectionPermit> {
+        let Some(semaphore) = &self.semaphore else {
+            return Some(ConnectionPermit { _permit: None });
+        };
+        let permit = match self.overflow {
+            ConnectionOverflow::Wait => semaphore.clone().acquire_owned().await.ok(),
+            ConnectionOverflow::Reject => semaphore.clone().try_acquire_owned().ok(),
+        };
+        if permit.is_none() {
+            metrics::counter!(CONNECTIONS_REJECTED_COUNTER).increment(1);
+        }
+        Some(ConnectionPermit {
+            _permit: Some(permit?),
+        })
+    }
+}
+
+/// Слот соединения; освобождается при drop
+pub struct ConnectionPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// RAII разрешение: gauge уменьшается при drop на любом пути выхода
+pub struct RequestPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+    in_flight: Arc<AtomicUsize>,
+    route: Option<(String, Arc<AtomicUsize>)>,
+    routes: Arc<DashMap<String, Arc<AtomicUsize>>>,
+}
+
+impl RequestPermit {
+    /// Учет запроса в маршруте (после сопоставления)
+    pub fn set_route(&mut self, route: &str) {
+        if self.route.is_some() {
+            return;
+        }
+        let counter = self.routes.entry(route.to_string()).or_default().clone();
+        let count = counter.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics::gauge!(ROUTE_IN_FLIGHT_GAUGE, "route" => route.to_string()).set(count as f64);
+        self.route = Some((route.to_string(), counter));
+    }
+}
+
+impl Drop for RequestPermit {
+    fn drop(&mut self) {
+        let count = self.in_flight.fetch_sub(1, Ordering::Relaxed) - 1;
+        metrics::gauge!(IN_FLIGHT_GAUGE).set(count as f64);
+
+        if let Some((route, counter)) = self.route.take() {
+            let count = counter.fetch_sub(1, Ordering::Relaxed) - 1;
+            metrics::gauge!(ROUTE_IN_FLIGHT_GAUGE, "route" => route).set(count as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_rejects_over_capacity() {
+        let limiter = ConcurrencyLimiter::new(Some(3));
+
+        let mut held: Vec<_> = (0..3)
+            .map(|_| {
+                let mut permit = limiter.try_acquire().unwrap();
+                permit.set_route("api");
+                permit
+            })
+            .collect();
+        assert_eq!(limiter.in_flight(), 3);
+        assert_eq!(limiter.route_in_flight("api"), 3);
+
+        // N+1-й запрос отклоняется и не учитывается
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.in_flight(), 3);
+
+        // Освободившийся слот доступен снова
+        held.pop();
+        assert!(limiter.try_acquire().is_some());
+
+        held.clear();
+        assert_eq!(limiter.in_flight(), 0);
+        assert_eq!(limiter.route_in_flight("api"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_gauge_released_on_task_end() {
+        let limiter = ConcurrencyLimiter::new(None);
+
+        let tasks: Vec<_> = (0..10)
+            .map(|i| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    let mut permit = limiter.try_acquire().unwrap();
+                    permit.set_route("batch");
+                    tokio::task::yield_now().await;
+                    // Ошибочный путь тоже освобождает gauge
+                    if i % 2 == 0 {
+                        return Err("upstream failed");
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for task in tasks {
+            let _ = task.await.unwrap();
+        }
+
+        assert_eq!(limiter.in_flight(), 0);
+        assert_eq!(limiter.route_in_flight("batch"), 0);
+    }
+}
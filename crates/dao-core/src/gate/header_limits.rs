@@ -0,0 +1,88 @@
+//! Лимиты заголовков запроса: число и суммарный размер
+
+use crate::config::{ServerConfig, HTTP1_MIN_BUF_SIZE};
+use http::HeaderMap;
+use hyper::server::conn::{http1, http2};
+
+/// Лимиты заголовков (`server.max_headers`, `server.max_header_bytes`).
+///
+/// Передаются в hyper: HTTP/1 отвечает 431 на превышение числа заголовков
+/// или буфера чтения, HTTP/2 — на превышение `SETTINGS_MAX_HEADER_LIST_SIZE`.
+/// Буфер hyper не меньше 8 KiB, поэтому меньшие лимиты размера проверяет
+/// `exceeded` уже по разобранным заголовкам.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeaderLimits {
+    pub max_headers: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl HeaderLimits {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            max_headers: config.max_headers,
+            max_bytes: config.max_header_bytes,
+        }
+    }
+
+    pub fn apply_http1(&self, builder: &mut http1::Builder) {
+        if let Some(max_headers) = self.max_headers {
+            builder.max_headers(max_headers);
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            builder.max_buf_size(max_bytes.max(HTTP1_MIN_BUF_SIZE));
+        }
+    }
+
+    pub fn apply_http2<E>(&self, builder: &mut http2::Builder<E>) {
+        if let Some(max_bytes) = self.max_bytes {
+            builder.max_header_list_size(u32::try_from(max_bytes).unwrap_or(u32::MAX));
+        }
+    }
+
+    /// Превышен ли лимит разобранными заголовками (размер — имена и
+    /// значения)
+    pub fn exceeded(&self, headers: &HeaderMap) -> bool {
+        if self.max_headers.is_some_and(|max| headers.len() > max) {
+            return true;
+        }
+        self.max_bytes.is_some_and(|max| {
+            let size: usize = headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum();
+            size > max
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exceeded() {
+        let mut headers = HeaderMap::new();
+        for i in 0..10 {
+            headers.insert(
+                http::HeaderName::try_from(format!("x-h{}", i)).unwrap(),
+                "v".repeat(100).parse().unwrap(),
+            );
+        }
+
+        assert!(!HeaderLimits::default().exceeded(&headers));
+        let count = |max| HeaderLimits {
+            max_headers: Some(max),
+            max_bytes: None,
+        };
+        assert!(!count(10).exceeded(&headers));
+        assert!(count(9).exceeded(&headers));
+
+        // 10 заголовков по 4 + 100 байт
+        let size = |max| HeaderLimits {
+            max_headers: None,
+            max_bytes: Some(max),
+        };
+        assert!(!size(1040).exceeded(&headers));
+        assert!(size(1039).exceeded(&headers));
+    }
+}
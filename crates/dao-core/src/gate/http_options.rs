@@ -0,0 +1,47 @@
+//! Параметры HTTP/1.1 и HTTP/2 соединений клиентов
+
+use crate::config::{Http1Config, Http2Config, ServerConfig};
+use hyper::server::conn::{http1, http2};
+
+/// `[server.http1]` и `[server.http2]` для builder'ов hyper
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    pub http1: Http1Config,
+    pub http2: Http2Config,
+}
+
+impl HttpOptions {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            http1: config.http1.clone(),
+            http2: config.http2.clone(),
+        }
+    }
+
+    /// Явный `max_buf_size` заменяет буфер из `max_header_bytes` — поэтому
+    /// применяется после `HeaderLimits`
+    pub fn apply_http1(&self, builder: &mut http1::Builder) {
+        builder
+            .keep_alive(self.http1.keepalive)
+            .half_close(self.http1.half_close)
+            .pipeline_flush(self.http1.pipeline_flush);
+        if let Some(size) = self.http1.max_buf_size {
+            builder.max_buf_size(size);
+        }
+    }
+
+    pub fn apply_http2<E>(&self, builder: &mut http2::Builder<E>) {
+        if let Some(streams) = self.http2.max_concurrent_streams {
+            builder.max_concurrent_streams(streams);
+        }
+        if let Some(size) = self.http2.initial_stream_window_size {
+            builder.initial_stream_window_size(size);
+        }
+        if let Some(size) = self.http2.initial_connection_window_size {
+            builder.initial_connection_window_size(size);
+        }
+        if self.http2.adaptive_window {
+            builder.adaptive_window(true);
+        }
+    }
+}
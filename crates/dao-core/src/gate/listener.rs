@@ -1,14 +1,14 @@
 //! Listener types and connection handling
 
+use super::ClientIdentity;
 use std::net::SocketAddr;
 use tokio::net::TcpStream;
 
-/// Протокол соединения
+/// Протокол соединения; WebSocket и другие upgrade — поверх HTTP/1.1
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Protocol {
     Http1,
     Http2,
-    WebSocket,
 }
 
 /// Входящее соединение
@@ -24,6 +24,8 @@ pub enum Connection {
         stream: Box<tokio_rustls::server::TlsStream<TcpStream>>,
         peer_addr: SocketAddr,
         protocol: Protocol,
+        /// Identity из сертификата клиента (mTLS)
+        client_identity: Option<ClientIdentity>,
     },
 }
 
@@ -36,6 +38,16 @@ impl Connection {
         }
     }
 
+    /// Identity клиента из сертификата (только TLS с `tls_client_ca`)
+    pub fn client_identity(&self) -> Option<&ClientIdentity> {
+        match self {
+            Connection::Plain { .. } => None,
+            Connection::Tls {
+                client_identity, ..
+            } => client_identity.as_ref(),
+        }
+    }
+
     /// Получение протокола
     pub fn protocol(&self) -> Protocol {
         match self {
@@ -3,71 +3,215 @@
 //! Модуль приема входящих соединений:
 //! - TCP/TLS listeners
 //! - ALPN negotiation (h1/h2)
+//! - mTLS: identity клиента из сертификата
 //! - SNI routing (будущее)
 
 use crate::Result;
+use arc_swap::ArcSwap;
 use rustls::ServerConfig;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
 use tokio_rustls::TlsAcceptor;
 
+pub mod client_cert;
+pub mod concurrency;
+pub mod header_limits;
+pub mod http_options;
 pub mod listener;
+pub mod proxy_protocol;
+pub mod socket;
+pub mod timeout;
 
-pub use listener::{GateListener, Connection, Protocol};
+pub use client_cert::ClientIdentity;
+pub use concurrency::{ConcurrencyLimiter, ConnectionLimiter, ConnectionPermit, RequestPermit};
+pub use header_limits::HeaderLimits;
+pub use http_options::HttpOptions;
+pub use listener::{Connection, GateListener, Protocol};
+pub use socket::{TcpKeepalive, TcpOptions};
+pub use timeout::{ActiveRequest, ConnectionTimeouts, TimedStream, TimeoutSwitch};
 
 /// Конфигурация Gate
 #[derive(Debug, Clone)]
 pub struct GateConfig {
+    pub listeners: Vec<ListenerConfig>,
+}
+
+/// Конфигурация одного listener'а
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
     pub bind_addr: String,
     pub tls: Option<TlsConfig>,
+    /// Параметры принятых TCP соединений
+    pub tcp: TcpOptions,
+    /// Заголовок PROXY protocol в начале каждого соединения
+    pub proxy_protocol: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct TlsConfig {
     pub cert_path: String,
     pub key_path: String,
+    /// ALPN протоколы в порядке предпочтения (None — h2, http/1.1)
+    pub alpn: Option<Vec<String>>,
+    /// Отклонять соединения без согласованного ALPN
+    pub alpn_strict: bool,
+    /// PEM с CA клиентских сертификатов (mTLS, сертификат необязателен)
+    pub client_ca: Option<String>,
+}
+
+impl TlsConfig {
+    /// Эффективный список ALPN протоколов
+    pub fn alpn_protocols(&self) -> Vec<String> {
+        self.alpn
+            .clone()
+            .unwrap_or_else(|| vec!["h2".to_string(), "http/1.1".to_string()])
+    }
+
+    /// Протокол для клиентов, не приславших ALPN (None — отклонить)
+    fn alpn_fallback(&self) -> Option<Protocol> {
+        if self.alpn_strict {
+            return None;
+        }
+        let protocols = self.alpn_protocols();
+        if protocols.iter().any(|p| p == "http/1.1") {
+            Some(Protocol::Http1)
+        } else {
+            protocols
+                .first()
+                .and_then(|p| alpn_to_protocol(p.as_bytes()))
+        }
+    }
 }
 
-/// Gate — точка входа в систему
+/// Gate — точка входа в систему (набор listener'ов)
 pub struct Gate {
-    listener: TcpListener,
-    tls_acceptor: Option<TlsAcceptor>,
+    listeners: Vec<Arc<Listener>>,
 }
 
 impl Gate {
-    /// Создание нового Gate
+    /// Создание нового Gate: bind всех listener'ов
     pub async fn new(config: GateConfig) -> Result<Self> {
+        let mut listeners = Vec::with_capacity(config.listeners.len());
+        for listener_cfg in config.listeners {
+            listeners.push(Arc::new(Listener::bind(listener_cfg).await?));
+        }
+
+        Ok(Self { listeners })
+    }
+
+    /// Все listener'ы Gate
+    pub fn listeners(&self) -> &[Arc<Listener>] {
+        &self.listeners
+    }
+
+    /// Получение всех локальных адресов
+    pub fn local_addrs(&self) -> std::io::Result<Vec<std::net::SocketAddr>> {
+        self.listeners.iter().map(|l| l.local_addr()).collect()
+    }
+}
+
+/// Listener — один bind-адрес с опциональным TLS
+pub struct Listener {
+    listener: TcpListener,
+    tls: Option<ListenerTls>,
+    alpn_fallback: Option<Protocol>,
+    tcp: TcpOptions,
+    proxy_protocol: bool,
+}
+
+/// TLS listener'а: конфигурация заменяется при обновлении сертификата,
+/// начатые соединения продолжают работать со старой
+struct ListenerTls {
+    config: TlsConfig,
+    server_config: ArcSwap<ServerConfig>,
+}
+
+impl Listener {
+    /// Bind listener'а по конфигурации
+    pub async fn bind(config: ListenerConfig) -> Result<Self> {
         let listener = TcpListener::bind(&config.bind_addr).await?;
 
-        let tls_acceptor = if let Some(tls_cfg) = config.tls {
-            let tls_acceptor = create_tls_acceptor(&tls_cfg).await?;
-            Some(tls_acceptor)
+        let (tls, alpn_fallback) = if let Some(tls_cfg) = config.tls {
+            let server_config = ArcSwap::from_pointee(create_tls_config(&tls_cfg)?);
+            let alpn_fallback = tls_cfg.alpn_fallback();
+            (
+                Some(ListenerTls {
+                    config: tls_cfg,
+                    server_config,
+                }),
+                alpn_fallback,
+            )
         } else {
-            None
+            (None, Some(Protocol::Http1))
         };
 
         Ok(Self {
             listener,
-            tls_acceptor,
+            tls,
+            alpn_fallback,
+            tcp: config.tcp,
+            proxy_protocol: config.proxy_protocol,
         })
     }
 
-    /// Получение следующего соединения
-    pub async fn accept(&self) -> Result<Connection> {
+    /// Прием следующего TCP соединения. PROXY protocol и TLS — в
+    /// [`Listener::handshake`], в задаче соединения: медленный клиент не
+    /// задерживает прием остальных
+    pub async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
         let (stream, peer_addr) = self.listener.accept().await?;
+        self.tcp.apply(&stream)?;
+        Ok((stream, peer_addr))
+    }
+
+    /// Заголовок PROXY protocol и TLS handshake принятого соединения
+    pub async fn handshake(
+        &self,
+        mut stream: TcpStream,
+        mut peer_addr: SocketAddr,
+    ) -> Result<Connection> {
+        // За L4 балансировщиком адрес клиента — из заголовка PROXY protocol
+        if self.proxy_protocol {
+            let header = tokio::time::timeout(
+                proxy_protocol::PROXY_HEADER_TIMEOUT,
+                proxy_protocol::read_header(&mut stream),
+            )
+            .await
+            .map_err(|_| {
+                crate::DaoError::InvalidRequest(format!(
+                    "PROXY protocol: no header from {}",
+                    peer_addr
+                ))
+            })??;
+            if let Some(client_addr) = header {
+                peer_addr = client_addr;
+            }
+        }
 
-        let connection = if let Some(acceptor) = &self.tls_acceptor {
-            // TLS handshake
-            let tls_stream = acceptor.accept(stream).await
+        let connection = if let Some(tls) = &self.tls {
+            // TLS handshake с актуальным сертификатом
+            let acceptor = TlsAcceptor::from(tls.server_config.load_full());
+            let tls_stream = acceptor
+                .accept(stream)
+                .await
                 .map_err(|e| crate::DaoError::Tls(e.to_string()))?;
 
             // Определение протокола через ALPN
-            let protocol = detect_alpn_protocol(&tls_stream);
+            let protocol = detect_alpn_protocol(&tls_stream)
+                .or(self.alpn_fallback)
+                .ok_or_else(|| {
+                    crate::DaoError::Tls(format!(
+                        "No ALPN protocol negotiated with {} (strict mode)",
+                        peer_addr
+                    ))
+                })?;
 
+            let client_identity = ClientIdentity::from_session(tls_stream.get_ref().1);
             Connection::Tls {
                 stream: Box::new(tls_stream),
                 peer_addr,
                 protocol,
+                client_identity,
             }
         } else {
             Connection::Plain {
@@ -84,10 +228,27 @@ impl Gate {
     pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
         self.listener.local_addr()
     }
+
+    /// Пути сертификата и ключа (None — listener без TLS)
+    pub fn tls_files(&self) -> Option<(&str, &str)> {
+        self.tls
+            .as_ref()
+            .map(|tls| (tls.config.cert_path.as_str(), tls.config.key_path.as_str()))
+    }
+
+    /// Перечитывание сертификата и ключа: новые handshake'и используют
+    /// новую пару. При ошибке остается прежняя.
+    pub fn reload_tls(&self) -> Result<()> {
+        if let Some(tls) = &self.tls {
+            let server_config = create_tls_config(&tls.config)?;
+            tls.server_config.store(Arc::new(server_config));
+        }
+        Ok(())
+    }
 }
 
-/// Создание TLS acceptor из конфигурации
-async fn create_tls_acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
+/// rustls конфигурация сервера из файлов сертификата и ключа
+fn create_tls_config(config: &TlsConfig) -> Result<ServerConfig> {
     use rustls_pemfile::{certs, private_key};
 
     let cert_file = std::fs::File::open(&config.cert_path)?;
@@ -104,28 +265,255 @@ async fn create_tls_acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
         .map_err(|e| crate::DaoError::Tls(format!("Failed to read key: {}", e)))?
         .ok_or_else(|| crate::DaoError::Tls("No private key found".to_string()))?;
 
-    let mut tls_config = ServerConfig::builder()
-        .with_no_client_auth()
+    let builder = ServerConfig::builder();
+    let builder = match &config.client_ca {
+        Some(client_ca) => builder.with_client_cert_verifier(client_cert_verifier(client_ca)?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut tls_config = builder
         .with_single_cert(cert_chain, key)
         .map_err(|e| crate::DaoError::Tls(e.to_string()))?;
 
-    // ALPN protocols: h2, http/1.1
-    tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    // ALPN protocols: из конфига, по умолчанию h2, http/1.1.
+    // Клиент без общих протоколов получает no_application_protocol alert.
+    tls_config.alpn_protocols = config
+        .alpn_protocols()
+        .into_iter()
+        .map(String::into_bytes)
+        .collect();
 
-    Ok(TlsAcceptor::from(Arc::new(tls_config)))
+    Ok(tls_config)
+}
+
+/// Проверка сертификатов клиентов по CA; клиенты без сертификата
+/// допускаются
+fn client_cert_verifier(
+    ca_path: &str,
+) -> Result<Arc<dyn rustls::server::danger::ClientCertVerifier>> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(ca_path)?);
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut reader) {
+        let cert =
+            cert.map_err(|e| crate::DaoError::Tls(format!("Failed to read client CA: {}", e)))?;
+        roots
+            .add(cert)
+            .map_err(|e| crate::DaoError::Tls(format!("Invalid client CA: {}", e)))?;
+    }
+    if roots.is_empty() {
+        return Err(crate::DaoError::Tls(format!(
+            "No client CA certificates in {}",
+            ca_path
+        )));
+    }
+    rustls::server::WebPkiClientVerifier::builder(Arc::new(roots))
+        .allow_unauthenticated()
+        .build()
+        .map_err(|e| crate::DaoError::Tls(e.to_string()))
 }
 
-/// Определение протокола из ALPN
-fn detect_alpn_protocol<S>(stream: &tokio_rustls::server::TlsStream<S>) -> Protocol {
+/// Определение протокола из согласованного ALPN
+fn detect_alpn_protocol<S>(stream: &tokio_rustls::server::TlsStream<S>) -> Option<Protocol> {
     let (_, session) = stream.get_ref();
+    session.alpn_protocol().and_then(alpn_to_protocol)
+}
+
+fn alpn_to_protocol(alpn: &[u8]) -> Option<Protocol> {
+    match alpn {
+        b"h2" => Some(Protocol::Http2),
+        b"http/1.1" => Some(Protocol::Http1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Прием соединения вместе с PROXY/TLS, как в задаче соединения
+    async fn accept(listener: &Listener) -> Result<Connection> {
+        let (stream, peer_addr) = listener.accept().await?;
+        listener.handshake(stream, peer_addr).await
+    }
+
+    #[tokio::test]
+    async fn test_gate_multiple_listeners() {
+        let gate = Gate::new(GateConfig {
+            listeners: vec![
+                ListenerConfig {
+                    bind_addr: "127.0.0.1:0".to_string(),
+                    tls: None,
+                    tcp: TcpOptions::default(),
+                    proxy_protocol: false,
+                },
+                ListenerConfig {
+                    bind_addr: "127.0.0.1:0".to_string(),
+                    tls: None,
+                    tcp: TcpOptions::default(),
+                    proxy_protocol: false,
+                },
+            ],
+        })
+        .await
+        .unwrap();
+
+        let addrs = gate.local_addrs().unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert_ne!(addrs[0].port(), addrs[1].port());
 
-    if let Some(alpn) = session.alpn_protocol() {
-        match alpn {
-            b"h2" => Protocol::Http2,
-            b"http/1.1" => Protocol::Http1,
-            _ => Protocol::Http1,
+        // Каждый listener принимает свои соединения
+        for (listener, addr) in gate.listeners().iter().zip(&addrs) {
+            let client = tokio::net::TcpStream::connect(addr);
+            let (conn, client) = tokio::join!(accept(listener), client);
+            let conn = conn.unwrap();
+            assert_eq!(conn.peer_addr(), client.unwrap().local_addr().unwrap());
+            assert_eq!(conn.protocol(), Protocol::Http1);
         }
-    } else {
-        Protocol::Http1
+    }
+
+    #[tokio::test]
+    async fn test_accepted_stream_nodelay() {
+        let listener = Listener::bind(ListenerConfig {
+            bind_addr: "127.0.0.1:0".to_string(),
+            tls: None,
+            tcp: TcpOptions::default(),
+            proxy_protocol: false,
+        })
+        .await
+        .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (conn, _client) = tokio::join!(accept(&listener), tokio::net::TcpStream::connect(addr));
+        match conn.unwrap() {
+            Connection::Plain { stream, .. } => assert!(stream.nodelay().unwrap()),
+            Connection::Tls { .. } => panic!("expected plain connection"),
+        }
+    }
+
+    /// Self-signed сертификат во временной директории
+    fn test_tls_config(
+        dir: &tempfile::TempDir,
+        alpn: Option<Vec<&str>>,
+        alpn_strict: bool,
+    ) -> (TlsConfig, rustls::pki_types::CertificateDer<'static>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.path().join("dao.crt");
+        let key_path = dir.path().join("dao.key");
+        std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+
+        let config = TlsConfig {
+            cert_path: cert_path.to_string_lossy().into_owned(),
+            key_path: key_path.to_string_lossy().into_owned(),
+            alpn: alpn.map(|v| v.into_iter().map(String::from).collect()),
+            alpn_strict,
+            client_ca: None,
+        };
+        (config, cert.cert.der().clone())
+    }
+
+    async fn tls_connect(
+        addr: std::net::SocketAddr,
+        ca: rustls::pki_types::CertificateDer<'static>,
+        alpn: &[&str],
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca).unwrap();
+        let mut client_config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.alpn_protocols = alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        let server_name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+        let tls = connector.connect(server_name, stream).await?;
+        Ok(tls.get_ref().1.alpn_protocol().map(|p| p.to_vec()))
+    }
+
+    #[tokio::test]
+    async fn test_alpn_h2_only_rejects_http11_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tls, ca) = test_tls_config(&dir, Some(vec!["h2"]), true);
+        let listener = Listener::bind(ListenerConfig {
+            bind_addr: "127.0.0.1:0".to_string(),
+            tls: Some(tls),
+            tcp: TcpOptions::default(),
+            proxy_protocol: false,
+        })
+        .await
+        .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // http/1.1-only клиент — handshake отклонен
+        let (server, client) = tokio::join!(
+            accept(&listener),
+            tls_connect(addr, ca.clone(), &["http/1.1"])
+        );
+        assert!(server.is_err());
+        assert!(client.is_err());
+
+        // Клиент без ALPN — отклонен в strict режиме
+        let (server, _client) = tokio::join!(accept(&listener), tls_connect(addr, ca.clone(), &[]));
+        assert!(server.is_err());
+
+        // h2 клиент принимается как HTTP/2
+        let (server, client) = tokio::join!(
+            accept(&listener),
+            tls_connect(addr, ca, &["h2", "http/1.1"])
+        );
+        assert_eq!(server.unwrap().protocol(), Protocol::Http2);
+        assert_eq!(client.unwrap().as_deref(), Some(&b"h2"[..]));
+    }
+
+    #[tokio::test]
+    async fn test_alpn_default_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tls, ca) = test_tls_config(&dir, None, false);
+        let listener = Listener::bind(ListenerConfig {
+            bind_addr: "127.0.0.1:0".to_string(),
+            tls: Some(tls),
+            tcp: TcpOptions::default(),
+            proxy_protocol: false,
+        })
+        .await
+        .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Без ALPN — HTTP/1.1 по умолчанию
+        let (server, _client) = tokio::join!(accept(&listener), tls_connect(addr, ca, &[]));
+        assert_eq!(server.unwrap().protocol(), Protocol::Http1);
+    }
+
+    #[tokio::test]
+    async fn test_reload_tls_presents_new_cert() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tls, old_ca) = test_tls_config(&dir, None, false);
+        let listener = Listener::bind(ListenerConfig {
+            bind_addr: "127.0.0.1:0".to_string(),
+            tls: Some(tls),
+            tcp: TcpOptions::default(),
+            proxy_protocol: false,
+        })
+        .await
+        .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Сертификат заменен на диске — до reload отдается прежний
+        let (_, new_ca) = test_tls_config(&dir, None, false);
+        let (_, client) = tokio::join!(accept(&listener), tls_connect(addr, new_ca.clone(), &[]));
+        assert!(client.is_err());
+
+        listener.reload_tls().unwrap();
+        let (_, client) = tokio::join!(accept(&listener), tls_connect(addr, new_ca.clone(), &[]));
+        assert!(client.is_ok());
+        let (_, client) = tokio::join!(accept(&listener), tls_connect(addr, old_ca, &[]));
+        assert!(client.is_err());
+
+        // Битый файл — ошибка, в работе остается последний корректный
+        let (cert_path, _) = listener.tls_files().unwrap();
+        std::fs::write(cert_path, "not a certificate").unwrap();
+        assert!(listener.reload_tls().is_err());
+        let (_, client) = tokio::join!(accept(&listener), tls_connect(addr, new_ca, &[]));
+        assert!(client.is_ok());
     }
 }
@@ -0,0 +1,226 @@
+//! PROXY protocol v1/v2: адрес клиента за L4 балансировщиком
+//!
+//! Заголовок читается с начала TCP потока до TLS и HTTP — ровно его байты,
+//! без упреждающего чтения, чтобы остаток потока остался нетронутым.
+
+use crate::{DaoError, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Ожидание заголовка от балансировщика
+pub const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Сигнатура v2
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Максимальная длина строки v1 вместе с CRLF
+const V1_MAX_LEN: usize = 107;
+
+/// Чтение заголовка PROXY protocol.
+///
+/// `Some(addr)` — адрес клиента из заголовка; `None` — заголовок без
+/// адреса (`UNKNOWN`, `LOCAL` — проверки здоровья балансировщика), адрес
+/// соединения остается прежним. Отсутствующий или поврежденный заголовок —
+/// ошибка.
+pub async fn read_header<S>(stream: &mut S) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut prefix = [0u8; 5];
+    stream.read_exact(&mut prefix).await?;
+    if &prefix == b"PROXY" {
+        read_v1(stream, prefix).await
+    } else if prefix == V2_SIGNATURE[..5] {
+        read_v2(stream, prefix).await
+    } else {
+        Err(malformed("missing header"))
+    }
+}
+
+/// Текстовый v1: строка до CRLF читается по байту
+async fn read_v1<S>(stream: &mut S, prefix: [u8; 5]) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(malformed("v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| malformed("v1 header is not ASCII"))?;
+    parse_v1(line)
+}
+
+/// `PROXY TCP4 <src> <dst> <sport> <dport>` (без CRLF)
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>> {
+    let mut parts = line.split(' ');
+    if parts.next() != Some("PROXY") {
+        return Err(malformed("v1 header must start with PROXY"));
+    }
+    let family = parts.next().ok_or_else(|| malformed("v1 family missing"))?;
+    if family == "UNKNOWN" {
+        return Ok(None);
+    }
+
+    let fields: Vec<_> = parts.collect();
+    let [src, dst, src_port, dst_port] = fields[..] else {
+        return Err(malformed("v1 header must have 4 address fields"));
+    };
+    let src: IpAddr = match family {
+        "TCP4" => src.parse::<Ipv4Addr>().map(IpAddr::V4),
+        "TCP6" => src.parse::<Ipv6Addr>().map(IpAddr::V6),
+        _ => return Err(malformed(format!("v1 unknown family {}", family))),
+    }
+    .map_err(|_| malformed(format!("v1 invalid source address {}", src)))?;
+    dst.parse::<IpAddr>()
+        .map_err(|_| malformed(format!("v1 invalid destination address {}", dst)))?;
+    let port = |value: &str| {
+        value
+            .parse::<u16>()
+            .map_err(|_| malformed(format!("v1 invalid port {}", value)))
+    };
+    let src_port = port(src_port)?;
+    port(dst_port)?;
+
+    Ok(Some(SocketAddr::new(src, src_port)))
+}
+
+/// Бинарный v2: 16 байт заголовка и блок адресов указанной длины
+async fn read_v2<S>(stream: &mut S, prefix: [u8; 5]) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 16];
+    header[..5].copy_from_slice(&prefix);
+    stream.read_exact(&mut header[5..]).await?;
+    let mut addresses = vec![0u8; u16::from_be_bytes([header[14], header[15]]) as usize];
+    stream.read_exact(&mut addresses).await?;
+    parse_v2(&header, &addresses)
+}
+
+fn parse_v2(header: &[u8; 16], addresses: &[u8]) -> Result<Option<SocketAddr>> {
+    if header[..12] != V2_SIGNATURE {
+        return Err(malformed("v2 invalid signature"));
+    }
+    if header[12] >> 4 != 2 {
+        return Err(malformed("v2 unsupported version"));
+    }
+    match header[12] & 0x0f {
+        // LOCAL — соединение самого балансировщика
+        0 => return Ok(None),
+        1 => {}
+        command => return Err(malformed(format!("v2 unknown command {}", command))),
+    }
+
+    // Старшие 4 бита — семейство адресов, младшие — транспорт
+    match header[13] >> 4 {
+        0 => Ok(None),
+        1 => {
+            let Some(block) = addresses.get(..12) else {
+                return Err(malformed("v2 IPv4 address block too short"));
+            };
+            let ip = Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            let port = u16::from_be_bytes([block[8], block[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        2 => {
+            let Some(block) = addresses.get(..36) else {
+                return Err(malformed("v2 IPv6 address block too short"));
+            };
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&block[..16]);
+            let port = u16::from_be_bytes([block[32], block[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        // UNIX сокеты — адреса, не представимые как SocketAddr
+        3 => Ok(None),
+        family => Err(malformed(format!("v2 unknown address family {}", family))),
+    }
+}
+
+fn malformed(reason: impl std::fmt::Display) -> DaoError {
+    DaoError::InvalidRequest(format!("PROXY protocol: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(input: &[u8]) -> (Result<Option<SocketAddr>>, Vec<u8>) {
+        let mut stream = input;
+        let result = read_header(&mut stream).await;
+        (result, stream.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_v1_header() {
+        let (addr, rest) =
+            read(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 443\r\nGET / HTTP/1.1\r\n").await;
+        assert_eq!(addr.unwrap(), Some("203.0.113.7:51234".parse().unwrap()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        let (addr, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n").await;
+        assert_eq!(addr.unwrap(), Some("[2001:db8::1]:4000".parse().unwrap()));
+
+        let (addr, _) = read(b"PROXY UNKNOWN\r\n").await;
+        assert_eq!(addr.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_v2_header() {
+        let mut input = V2_SIGNATURE.to_vec();
+        // PROXY, TCP over IPv4, 12 байт адресов + 3 байта TLV
+        input.extend([0x21, 0x11, 0x00, 15]);
+        input.extend([198, 51, 100, 9, 10, 0, 0, 1]);
+        input.extend(40000u16.to_be_bytes());
+        input.extend(443u16.to_be_bytes());
+        input.extend([0x04, 0x00, 0x00]);
+        input.extend(b"\x16\x03\x01");
+
+        let (addr, rest) = read(&input).await;
+        assert_eq!(addr.unwrap(), Some("198.51.100.9:40000".parse().unwrap()));
+        // Данные после заголовка (начало TLS ClientHello) не тронуты
+        assert_eq!(rest, b"\x16\x03\x01");
+
+        // LOCAL: адрес соединения не заменяется
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend([0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(read(&local).await.0.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_headers_rejected() {
+        for input in [
+            &b"GET / HTTP/1.1\r\n\r\n"[..],
+            b"PROXY TCP4 203.0.113.7 10.0.0.1 51234\r\n",
+            b"PROXY TCP4 not-an-ip 10.0.0.1 51234 443\r\n",
+            b"PROXY TCP4 203.0.113.7 10.0.0.1 99999 443\r\n",
+            b"PROXY TCP5 203.0.113.7 10.0.0.1 51234 443\r\n",
+            b"PROXY TCP4",
+        ] {
+            assert!(
+                read(input).await.0.is_err(),
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+        let mut too_long = b"PROXY TCP4 ".to_vec();
+        too_long.extend([b'1'; 120]);
+        assert!(read(&too_long).await.0.is_err());
+
+        // v2 с короткой адресной частью и неизвестной версией
+        let mut short = V2_SIGNATURE.to_vec();
+        short.extend([0x21, 0x11, 0x00, 4, 1, 2, 3, 4]);
+        assert!(read(&short).await.0.is_err());
+        let mut version = V2_SIGNATURE.to_vec();
+        version.extend([0x31, 0x11, 0x00, 0x00]);
+        assert!(read(&version).await.0.is_err());
+    }
+}
@@ -0,0 +1,107 @@
+//! Параметры TCP сокетов (входящие соединения и соединения к upstream'ам)
+
+use crate::config::ServerConfig;
+use socket2::SockRef;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// Параметры TCP соединения
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpOptions {
+    /// TCP_NODELAY: без алгоритма Нейгла (меньше задержка мелких ответов)
+    pub nodelay: bool,
+    /// SO_KEEPALIVE (None — системные настройки)
+    pub keepalive: Option<TcpKeepalive>,
+}
+
+/// Параметры TCP keepalive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    /// Простой соединения до первой пробы
+    pub idle: Duration,
+    /// Интервал между пробами
+    pub interval: Option<Duration>,
+    /// Число неотвеченных проб до разрыва
+    pub probes: Option<u32>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: None,
+        }
+    }
+}
+
+impl TcpOptions {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            nodelay: config.tcp_nodelay,
+            keepalive: config.tcp_keepalive.as_ref().map(|keepalive| TcpKeepalive {
+                idle: Duration::from_secs(keepalive.idle_secs),
+                interval: keepalive.interval_secs.map(Duration::from_secs),
+                probes: keepalive.probes,
+            }),
+        }
+    }
+
+    /// Применение к принятому/установленному соединению
+    pub fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(keepalive) = &self.keepalive {
+            SockRef::from(stream).set_tcp_keepalive(&keepalive.to_socket2())?;
+        }
+        Ok(())
+    }
+}
+
+impl TcpKeepalive {
+    fn to_socket2(self) -> socket2::TcpKeepalive {
+        let mut keepalive = socket2::TcpKeepalive::new().with_time(self.idle);
+        if let Some(interval) = self.interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        if let Some(probes) = self.probes {
+            keepalive = keepalive.with_retries(probes);
+        }
+        keepalive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_apply_nodelay_and_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (accepted, _client) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        let (stream, _) = accepted.unwrap();
+
+        let options = TcpOptions {
+            nodelay: true,
+            keepalive: Some(TcpKeepalive {
+                idle: Duration::from_secs(30),
+                interval: Some(Duration::from_secs(5)),
+                probes: Some(3),
+            }),
+        };
+        options.apply(&stream).unwrap();
+
+        assert!(stream.nodelay().unwrap());
+        let socket = SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(
+            socket.tcp_keepalive_time().unwrap(),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            socket.tcp_keepalive_interval().unwrap(),
+            Duration::from_secs(5)
+        );
+        assert_eq!(socket.tcp_keepalive_retries().unwrap(), 3);
+    }
+}
@@ -0,0 +1,336 @@
+//! Таймауты входящих соединений
+
+use crate::config::ServerConfig;
+use futures::task::AtomicWaker;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioTimer;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_io_timeout::TimeoutStream;
+
+/// Соединение с таймаутами чтения/записи на уровне IO.
+///
+/// Таймаут чтения — idle: действует, только пока на соединении нет запросов
+/// в работе (см. [`TimeoutSwitch::request`]). Все таймауты снимаются через
+/// [`TimeoutSwitch::disable`] — после upgrade соединение становится
+/// туннелем, где долгое молчание сторон — норма
+pub struct TimedStream<S> {
+    inner: Pin<Box<TimeoutStream<S>>>,
+    switch: TimeoutSwitch,
+}
+
+/// Управление таймаутами [`TimedStream`]
+#[derive(Debug, Clone, Default)]
+pub struct TimeoutSwitch {
+    state: Arc<SwitchState>,
+}
+
+#[derive(Debug, Default)]
+struct SwitchState {
+    disabled: AtomicBool,
+    /// Запросы в работе: от приема до конца тела ответа
+    active: AtomicUsize,
+    /// Чтение, ждущее данных без таймаута, — перезапускается с idle-таймаутом,
+    /// когда запросов не остается
+    reader: AtomicWaker,
+}
+
+impl TimeoutSwitch {
+    /// Снятие таймаутов чтения и записи до конца соединения
+    pub fn disable(&self) {
+        self.state.disabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Запрос в работе: пока guard жив, idle-таймаут не действует
+    pub fn request(&self) -> ActiveRequest {
+        self.state.active.fetch_add(1, Ordering::AcqRel);
+        ActiveRequest {
+            state: self.state.clone(),
+        }
+    }
+
+    fn is_disabled(&self) -> bool {
+        self.state.disabled.load(Ordering::Relaxed)
+    }
+}
+
+/// Guard запроса в работе (см. [`TimeoutSwitch::request`])
+#[derive(Debug)]
+pub struct ActiveRequest {
+    state: Arc<SwitchState>,
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        if self.state.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.state.reader.wake();
+        }
+    }
+}
+
+impl<S> TimedStream<S> {
+    /// Выключатель таймаутов этого соединения
+    pub fn switch(&self) -> TimeoutSwitch {
+        self.switch.clone()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for TimedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.switch.is_disabled() {
+            return this.inner.as_mut().get_pin_mut().poll_read(cx, buf);
+        }
+        // Регистрация до проверки: завершение последнего запроса разбудит
+        // чтение, и оно перейдет на idle-таймаут
+        let state = &this.switch.state;
+        state.reader.register(cx.waker());
+        if state.active.load(Ordering::Acquire) > 0 {
+            this.inner.as_mut().get_pin_mut().poll_read(cx, buf)
+        } else {
+            this.inner.as_mut().poll_read(cx, buf)
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for TimedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.switch.is_disabled() {
+            this.inner.as_mut().get_pin_mut().poll_write(cx, buf)
+        } else {
+            this.inner.as_mut().poll_write(cx, buf)
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.switch.is_disabled() {
+            this.inner
+                .as_mut()
+                .get_pin_mut()
+                .poll_write_vectored(cx, bufs)
+        } else {
+            this.inner.as_mut().poll_write_vectored(cx, bufs)
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.switch.is_disabled() {
+            this.inner.as_mut().get_pin_mut().poll_flush(cx)
+        } else {
+            this.inner.as_mut().poll_flush(cx)
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.switch.is_disabled() {
+            this.inner.as_mut().get_pin_mut().poll_shutdown(cx)
+        } else {
+            this.inner.as_mut().poll_shutdown(cx)
+        }
+    }
+}
+
+/// Таймауты соединения
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionTimeouts {
+    /// Закрытие соединения без запросов в работе и без входящих данных
+    /// дольше этого времени
+    pub idle: Option<Duration>,
+    /// Максимальное время чтения заголовков запроса (HTTP/1)
+    pub header_read: Option<Duration>,
+    /// Максимальное время ожидания записи клиенту
+    pub write: Option<Duration>,
+}
+
+impl ConnectionTimeouts {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            idle: config.idle_timeout_secs.map(Duration::from_secs),
+            header_read: config.read_timeout_secs.map(Duration::from_secs),
+            write: config.write_timeout_secs.map(Duration::from_secs),
+        }
+    }
+
+    /// Обертка IO: ожидание чтения дольше `idle` между запросами или записи
+    /// дольше `write` завершается ошибкой `TimedOut`, и hyper закрывает
+    /// соединение
+    pub fn wrap<S>(&self, stream: S) -> TimedStream<S>
+    where
+        S: AsyncRead + AsyncWrite,
+    {
+        let mut stream = TimeoutStream::new(stream);
+        stream.set_read_timeout(self.idle);
+        stream.set_write_timeout(self.write);
+        TimedStream {
+            inner: Box::pin(stream),
+            switch: TimeoutSwitch::default(),
+        }
+    }
+
+    /// HTTP/1 builder с таймаутом чтения заголовков
+    pub fn http1_builder(&self) -> http1::Builder {
+        let mut builder = http1::Builder::new();
+        if let Some(header_read) = self.header_read {
+            builder
+                .timer(TokioTimer::new())
+                .header_read_timeout(header_read);
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::Instant;
+
+    #[tokio::test]
+    async fn test_idle_connection_closed() {
+        let timeouts = ConnectionTimeouts {
+            idle: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|_req| async {
+                Ok::<_, Infallible>(Response::new(Full::new(Bytes::new())))
+            });
+            let _ = timeouts
+                .http1_builder()
+                .serve_connection(TokioIo::new(timeouts.wrap(stream)), service)
+                .await;
+        });
+
+        // Клиент открывает соединение и ничего не отправляет
+        let started = Instant::now();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0u8; 16];
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+            .await
+            .expect("connection was not closed by idle timeout");
+
+        assert!(matches!(read, Ok(0) | Err(_)));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_not_applied_during_request() {
+        let timeouts = ConnectionTimeouts {
+            idle: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = timeouts.wrap(stream);
+            let switch = stream.switch();
+            // Обработка дольше idle: клиент ждет ответа молча
+            let service = service_fn(move |_req| {
+                let active = switch.request();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    let body = crate::flow::body::holding(Full::new(Bytes::from("done")), active);
+                    Ok::<_, Infallible>(Response::new(body))
+                }
+            });
+            let _ = timeouts
+                .http1_builder()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut client, b"GET / HTTP/1.1\r\nHost: dao\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        let started = Instant::now();
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut response))
+            .await
+            .expect("idle connection was not closed after the response");
+        assert!(read.is_ok());
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("done"), "{}", response);
+        // После ответа соединение закрывается по idle
+        assert!(started.elapsed() >= Duration::from_millis(700));
+    }
+
+    #[tokio::test]
+    async fn test_switch_disables_timeouts() {
+        let timeouts = ConnectionTimeouts {
+            idle: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let (client, server) = tokio::io::duplex(64);
+        let mut stream = timeouts.wrap(server);
+        stream.switch().disable();
+
+        // Молчание дольше idle после выключения — не ошибка
+        let mut client = client;
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            tokio::io::AsyncWriteExt::write_all(&mut client, b"late")
+                .await
+                .unwrap();
+        });
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"late");
+    }
+
+    #[test]
+    fn test_timeouts_from_config() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            bind = "127.0.0.1:0"
+            idle_timeout_secs = 60
+            read_timeout_secs = 10
+            "#,
+        )
+        .unwrap();
+
+        let timeouts = ConnectionTimeouts::from_config(&config);
+        assert_eq!(timeouts.idle, Some(Duration::from_secs(60)));
+        assert_eq!(timeouts.header_read, Some(Duration::from_secs(10)));
+        assert_eq!(timeouts.write, None);
+    }
+}
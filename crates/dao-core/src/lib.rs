@@ -9,21 +9,29 @@
 //! - **Flow**: Конвейер фильтров и трансформаций
 //! - **Memory**: Профили сервисов и горячая конфигурация
 
-pub mod gate;
-pub mod sense;
 pub mod align;
 pub mod flow;
+pub mod gate;
 pub mod memory;
+pub mod sense;
 
 pub mod config;
-pub mod upstream;
 pub mod error;
+pub mod upstream;
 
 pub use error::{DaoError, Result};
 
 /// Версия протокола DAO
 pub const DAO_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Сравнение секретов без раннего выхода по содержимому (токены, логины)
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 /// Маркер Intent — тег намерения трафика
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Intent(pub String);
@@ -36,6 +44,22 @@ impl Intent {
     pub fn matches(&self, other: &Intent) -> bool {
         self.0 == other.0
     }
+
+    /// Степень совпадения (0.0 - 1.0) с учетом иерархии через точку:
+    /// 1.0 — точное, `"realtime"` и `"realtime.chat"` — доля общих сегментов
+    /// (0.5), 0.0 — если ни один не является префиксом другого
+    pub fn match_score(&self, other: &Intent) -> f64 {
+        if self.0 == other.0 {
+            return 1.0;
+        }
+        let ours: Vec<_> = self.0.split('.').collect();
+        let theirs: Vec<_> = other.0.split('.').collect();
+        let shared = ours.len().min(theirs.len());
+        if ours[..shared] != theirs[..shared] {
+            return 0.0;
+        }
+        shared as f64 / ours.len().max(theirs.len()) as f64
+    }
 }
 
 impl From<&str> for Intent {
@@ -49,3 +73,44 @@ impl AsRef<str> for Intent {
         &self.0
     }
 }
+
+/// Intent с весом предпочтения upstream'а (1.0 — полное совпадение)
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedIntent {
+    pub intent: Intent,
+    pub affinity: f64,
+}
+
+impl WeightedIntent {
+    /// Разбор `"realtime"` или `"realtime:0.9"`
+    pub fn parse(s: &str) -> Result<Self> {
+        let Some((name, affinity)) = s.rsplit_once(':') else {
+            return Ok(Intent::new(s).into());
+        };
+
+        let affinity: f64 = affinity
+            .trim()
+            .parse()
+            .map_err(|_| DaoError::config(format!("Invalid intent affinity: {}", s)))?;
+        if !(0.0..=1.0).contains(&affinity) {
+            return Err(DaoError::config(format!(
+                "Intent affinity must be in [0, 1]: {}",
+                s
+            )));
+        }
+
+        Ok(Self {
+            intent: Intent::new(name.trim()),
+            affinity,
+        })
+    }
+}
+
+impl From<Intent> for WeightedIntent {
+    fn from(intent: Intent) -> Self {
+        Self {
+            intent,
+            affinity: 1.0,
+        }
+    }
+}
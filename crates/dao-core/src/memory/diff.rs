@@ -0,0 +1,294 @@
+//! Структурный diff конфигураций
+
+use crate::config::DaoConfig;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// Вид изменения
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// Изменение одного поля конфигурации
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigChange {
+    /// Путь поля, например `routes.rule[api].upstreams[backend-1].url`
+    pub path: String,
+    pub kind: ChangeKind,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// Разница между двумя конфигурациями
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConfigDiff {
+    pub changes: Vec<ConfigChange>,
+}
+
+impl ConfigDiff {
+    /// Diff `old` → `new`.
+    ///
+    /// Массивы объектов с полем `name` (маршруты, upstream'ы) сравниваются
+    /// по имени, остальные массивы — целиком. Секреты (токены, ключи, хеши
+    /// паролей) в изменениях маскируются `"***"`: diff уходит в лог и
+    /// admin API.
+    pub fn between(old: &DaoConfig, new: &DaoConfig) -> Self {
+        let mut diff = Self::default();
+        match (serde_json::to_value(old), serde_json::to_value(new)) {
+            (Ok(old), Ok(new)) => diff_values("", &old, &new, &mut diff.changes),
+            _ => tracing::warn!("Failed to serialize config for diff"),
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Человекочитаемое описание, по строке на изменение
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "no changes".to_string();
+        }
+        self.to_string()
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match change.kind {
+                ChangeKind::Added => write!(f, "+ {}", change.path)?,
+                ChangeKind::Removed => write!(f, "- {}", change.path)?,
+                ChangeKind::Changed => write!(
+                    f,
+                    "~ {}: {} -> {}",
+                    change.path,
+                    change.old.as_ref().unwrap_or(&Value::Null),
+                    change.new.as_ref().unwrap_or(&Value::Null)
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn diff_values(path: &str, old: &Value, new: &Value, changes: &mut Vec<ConfigChange>) {
+    if old == new {
+        return;
+    }
+
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            for (key, old_value) in old_map {
+                let field = join(path, key);
+                match new_map.get(key) {
+                    Some(new_value) => diff_values(&field, old_value, new_value, changes),
+                    None => changes.push(removed(field, old_value)),
+                }
+            }
+            for (key, new_value) in new_map {
+                if !old_map.contains_key(key) {
+                    changes.push(added(join(path, key), new_value));
+                }
+            }
+        }
+        (Value::Array(old_items), Value::Array(new_items))
+            if is_named(old_items) && is_named(new_items) =>
+        {
+            for old_item in old_items {
+                let name = item_name(old_item);
+                let field = format!("{}[{}]", path, name);
+                match new_items.iter().find(|n| item_name(n) == name) {
+                    Some(new_item) => diff_values(&field, old_item, new_item, changes),
+                    None => changes.push(removed(field, old_item)),
+                }
+            }
+            for new_item in new_items {
+                let name = item_name(new_item);
+                if !old_items.iter().any(|o| item_name(o) == name) {
+                    changes.push(added(format!("{}[{}]", path, name), new_item));
+                }
+            }
+        }
+        // null ↔ значение — опциональная секция появилась/исчезла
+        (Value::Null, _) => changes.push(added(path.to_string(), new)),
+        (_, Value::Null) => changes.push(removed(path.to_string(), old)),
+        _ => changes.push(ConfigChange {
+            path: path.to_string(),
+            kind: ChangeKind::Changed,
+            old: Some(masked(path, old)),
+            new: Some(masked(path, new)),
+        }),
+    }
+}
+
+/// Маска секрета
+const REDACTED: &str = "***";
+
+/// Поле с секретом: `admin.token`, `admin.auth.hmac_secret`, `jwt.secret`,
+/// хеши `basic_auth.users`
+fn is_secret(path: &str) -> bool {
+    let field = path.rsplit('.').next().unwrap_or(path);
+    ["token", "secret", "password", "hash"]
+        .iter()
+        .any(|marker| field.contains(marker))
+        || path.ends_with("basic_auth.users")
+}
+
+/// Значение с замаскированными секретами (в том числе во вложенных полях
+/// добавленной или удаленной секции)
+fn masked(path: &str, value: &Value) -> Value {
+    match value {
+        Value::Null => Value::Null,
+        _ if is_secret(path) => Value::String(REDACTED.to_string()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), masked(&join(path, key), value)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| masked(&format!("{}[{}]", path, item_name(item)), item))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+fn is_named(items: &[Value]) -> bool {
+    items
+        .iter()
+        .all(|item| item.get("name").is_some_and(Value::is_string))
+}
+
+fn item_name(item: &Value) -> &str {
+    item.get("name").and_then(Value::as_str).unwrap_or_default()
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn added(path: String, value: &Value) -> ConfigChange {
+    ConfigChange {
+        new: Some(masked(&path, value)),
+        path,
+        kind: ChangeKind::Added,
+        old: None,
+    }
+}
+
+fn removed(path: String, value: &Value) -> ConfigChange {
+    ConfigChange {
+        old: Some(masked(&path, value)),
+        path,
+        kind: ChangeKind::Removed,
+        new: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(url: &str) -> DaoConfig {
+        toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+            [routes.rule.match]
+            path_prefix = "/"
+            [[routes.rule.upstreams]]
+            name = "backend-1"
+            url = "{}"
+            [[routes.rule.upstreams]]
+            name = "backend-2"
+            url = "http://127.0.0.1:8082"
+            "#,
+            url
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff_upstream_url() {
+        let old = config("http://127.0.0.1:8081");
+        let new = config("http://127.0.0.1:9091");
+
+        let diff = ConfigDiff::between(&old, &new);
+        assert_eq!(diff.changes.len(), 1);
+
+        let change = &diff.changes[0];
+        assert_eq!(change.path, "routes.rule[api].upstreams[backend-1].url");
+        assert_eq!(change.kind, ChangeKind::Changed);
+        assert_eq!(
+            diff.summary(),
+            "~ routes.rule[api].upstreams[backend-1].url: \"http://127.0.0.1:8081\" -> \"http://127.0.0.1:9091\""
+        );
+
+        assert!(ConfigDiff::between(&old, &old).is_empty());
+        assert_eq!(ConfigDiff::between(&old, &old).summary(), "no changes");
+    }
+
+    #[test]
+    fn test_diff_added_and_removed() {
+        let old = config("http://127.0.0.1:8081");
+        let mut new = old.clone();
+        new.routes.rule[0].upstreams.remove(1);
+        let mut route = new.routes.rule[0].clone();
+        route.name = "batch".to_string();
+        new.routes.rule.push(route);
+
+        let diff = ConfigDiff::between(&old, &new);
+        let summary = diff.summary();
+        assert!(summary.contains("- routes.rule[api].upstreams[backend-2]"));
+        assert!(summary.contains("+ routes.rule[batch]"));
+    }
+
+    #[test]
+    fn test_diff_masks_secrets() {
+        let with_jwt = |secret: &str| {
+            let mut config = config("http://127.0.0.1:8081");
+            config.routes.rule[0].filters =
+                Some(toml::from_str(&format!("[jwt]\nsecret = \"{}\"", secret)).unwrap());
+            config
+        };
+        let old = with_jwt("old-signing-key");
+        let new = with_jwt("new-signing-key");
+
+        let diff = ConfigDiff::between(&old, &new);
+        assert_eq!(diff.changes.len(), 1);
+        assert_eq!(
+            diff.summary(),
+            "~ routes.rule[api].filters.jwt.secret: \"***\" -> \"***\""
+        );
+        let json = serde_json::to_string(&diff).unwrap();
+        assert!(!json.contains("signing-key"), "{}", json);
+
+        // Секрет во вложенной секции появившегося маршрута
+        let mut added = new.clone();
+        let mut route = added.routes.rule[0].clone();
+        route.name = "batch".to_string();
+        added.routes.rule.push(route);
+        let json = serde_json::to_string(&ConfigDiff::between(&old, &added)).unwrap();
+        assert!(!json.contains("signing-key"), "{}", json);
+    }
+}
@@ -7,21 +7,25 @@
 //! - Snapshot'ы
 
 use crate::config::DaoConfig;
-use crate::Result;
+use crate::{Intent, Result};
+use arc_swap::ArcSwap;
 use parking_lot::RwLock;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
+pub mod diff;
 pub mod profile;
 pub mod snapshot;
 
+pub use diff::{ChangeKind, ConfigChange, ConfigDiff};
 pub use profile::ServiceProfile;
 pub use snapshot::Snapshot;
 
 /// Memory — хранилище состояния системы
 #[derive(Clone)]
 pub struct Memory {
-    config: Arc<RwLock<DaoConfig>>,
+    /// Текущая конфигурация: чтение без блокировок, замена атомарная
+    config: Arc<ArcSwap<DaoConfig>>,
     profiles: Arc<RwLock<std::collections::HashMap<String, ServiceProfile>>>,
     snapshots: Arc<RwLock<Vec<Snapshot>>>,
 }
@@ -29,26 +33,28 @@ pub struct Memory {
 impl Memory {
     pub fn new(config: DaoConfig) -> Self {
         Self {
-            config: Arc::new(RwLock::new(config)),
+            config: Arc::new(ArcSwap::from_pointee(config)),
             profiles: Arc::new(RwLock::new(std::collections::HashMap::new())),
             snapshots: Arc::new(RwLock::new(Vec::new())),
         }
     }
 
-    /// Получение текущей конфигурации
-    pub fn get_config(&self) -> DaoConfig {
-        self.config.read().clone()
+    /// Текущая конфигурация (без копирования: общий `Arc` до следующего
+    /// обновления)
+    pub fn get_config(&self) -> Arc<DaoConfig> {
+        self.config.load_full()
     }
 
     /// Обновление конфигурации (hot-reload)
     pub fn update_config(&self, new_config: DaoConfig) -> Result<()> {
         new_config.validate()?;
 
-        let mut config = self.config.write();
-        *config = new_config;
+        let new_config = Arc::new(new_config);
+        let old_config = self.config.swap(new_config.clone());
+        let diff = ConfigDiff::between(&old_config, &new_config);
 
-        // Создание snapshot
         self.create_snapshot("config_update");
+        tracing::info!("Config updated:\n{}", diff.summary());
 
         Ok(())
     }
@@ -63,25 +69,71 @@ impl Memory {
         self.profiles.write().insert(service_name, profile);
     }
 
+    /// Учет результата запроса в профиле сервиса
+    pub fn observe(
+        &self,
+        service_name: &str,
+        intent: &Intent,
+        rps: f64,
+        latency_ms: f64,
+        success: bool,
+    ) {
+        let mut profiles = self.profiles.write();
+        let profile = profiles
+            .entry(service_name.to_string())
+            .or_insert_with(|| ServiceProfile::new(service_name.to_string()));
+        profile.learn_from_observation(intent, rps, latency_ms, success);
+    }
+
+    /// Забыть выученный профиль сервиса (запреты intent'ов, серии ошибок):
+    /// upstream сменил адрес, и его история относится к другому backend'у
+    pub fn forget_profile(&self, service_name: &str) {
+        self.profiles.write().remove(service_name);
+    }
+
+    /// Принимает ли сервис intent (нет профиля — принимает)
+    pub fn accepts_intent(&self, service_name: &str, intent: &Intent) -> bool {
+        self.profiles
+            .read()
+            .get(service_name)
+            .is_none_or(|profile| profile.accepts_intent(intent))
+    }
+
     /// Создание snapshot состояния
     pub fn create_snapshot(&self, reason: &str) {
         let snapshot = Snapshot {
             timestamp: SystemTime::now(),
             reason: reason.to_string(),
-            config: self.config.read().clone(),
+            config: DaoConfig::clone(&self.config.load()),
         };
 
+        let retention = &snapshot.config.snapshots;
+        let max_age = retention.max_age_secs.map(Duration::from_secs);
+        let max_count = retention.max_count;
+
         let mut snapshots = self.snapshots.write();
+        // Сначала по возрасту, затем по количеству
+        if let Some(max_age) = max_age {
+            snapshots.retain(|s| s.timestamp.elapsed().unwrap_or_default() <= max_age);
+        }
         snapshots.push(snapshot);
-
-        // Ограничение количества snapshot'ов
-        const MAX_SNAPSHOTS: usize = 100;
-        if snapshots.len() > MAX_SNAPSHOTS {
-            let excess = snapshots.len() - MAX_SNAPSHOTS;
+        if snapshots.len() > max_count {
+            let excess = snapshots.len() - max_count;
             snapshots.drain(0..excess);
         }
     }
 
+    /// Количество хранимых snapshot'ов
+    pub fn snapshot_count(&self) -> usize {
+        self.snapshots.read().len()
+    }
+
+    /// Diff между snapshot'ами по индексам истории
+    pub fn diff_snapshots(&self, from: usize, to: usize) -> Option<ConfigDiff> {
+        let snapshots = self.snapshots.read();
+        Some(snapshots.get(from)?.diff(snapshots.get(to)?))
+    }
+
     /// Получение истории snapshot'ов
     pub fn get_snapshots(&self) -> Vec<Snapshot> {
         self.snapshots.read().clone()
@@ -91,8 +143,7 @@ impl Memory {
     pub fn rollback_to_snapshot(&self, index: usize) -> Result<()> {
         let snapshots = self.snapshots.read();
         if let Some(snapshot) = snapshots.get(index) {
-            let mut config = self.config.write();
-            *config = snapshot.config.clone();
+            self.config.store(Arc::new(snapshot.config.clone()));
             Ok(())
         } else {
             Err(crate::DaoError::Internal("Snapshot not found".to_string()))
@@ -117,19 +168,156 @@ mod tests {
         assert_eq!(snapshots[0].reason, "test");
     }
 
+    #[test]
+    fn test_get_config_shares_snapshot() {
+        let memory = Memory::new(create_test_config());
+
+        // Чтение не копирует конфигурацию: тот же Arc
+        let first = memory.get_config();
+        for _ in 0..1000 {
+            assert!(Arc::ptr_eq(&first, &memory.get_config()));
+        }
+
+        // Hot-reload: новые читатели видят новую конфигурацию, старые
+        // дочитывают свою
+        let mut updated = create_test_config();
+        updated.server.health_path = "/healthz".to_string();
+        updated.routes = toml::from_str(
+            r#"
+            [[rule]]
+            name = "api"
+            policy = "resonant"
+            match = { path_prefix = "/" }
+            upstreams = [{ name = "backend-1", url = "http://127.0.0.1:8081" }]
+            "#,
+        )
+        .unwrap();
+        memory.update_config(updated).unwrap();
+
+        let second = memory.get_config();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(second.server.health_path, "/healthz");
+        assert_eq!(first.server.health_path, "/dao-health");
+        assert_eq!(memory.get_snapshots().len(), 1);
+    }
+
+    #[test]
+    fn test_update_config_snapshot_diff() {
+        let config_with_url = |url: &str| -> DaoConfig {
+            let mut config = create_test_config();
+            config.routes.rule = toml::from_str::<RoutesConfig>(&format!(
+                r#"
+                [[rule]]
+                name = "api"
+                policy = "resonant"
+                [rule.match]
+                path_prefix = "/"
+                [[rule.upstreams]]
+                name = "backend-1"
+                url = "{}"
+                "#,
+                url
+            ))
+            .unwrap()
+            .rule;
+            config
+        };
+
+        let memory = Memory::new(config_with_url("http://127.0.0.1:8081"));
+        memory.create_snapshot("initial");
+        memory
+            .update_config(config_with_url("http://127.0.0.1:9091"))
+            .unwrap();
+
+        let diff = memory.diff_snapshots(0, 1).unwrap();
+        assert_eq!(diff.changes.len(), 1);
+        assert_eq!(
+            diff.changes[0].path,
+            "routes.rule[api].upstreams[backend-1].url"
+        );
+        assert!(memory.diff_snapshots(0, 5).is_none());
+    }
+
+    #[test]
+    fn test_snapshot_retention_by_age() {
+        let mut config = create_test_config();
+        config.snapshots.max_age_secs = Some(7 * 24 * 3600);
+        let memory = Memory::new(config.clone());
+
+        let mut stale = Snapshot::new("stale".to_string(), config);
+        stale.timestamp = SystemTime::now() - Duration::from_secs(8 * 24 * 3600);
+        memory.snapshots.write().push(stale);
+
+        memory.create_snapshot("fresh");
+        let reasons: Vec<_> = memory
+            .get_snapshots()
+            .into_iter()
+            .map(|s| s.reason)
+            .collect();
+        assert_eq!(reasons, ["fresh"]);
+    }
+
+    #[test]
+    fn test_snapshot_retention_by_count() {
+        let mut config = create_test_config();
+        config.snapshots.max_count = 3;
+        config.snapshots.max_age_secs = Some(3600);
+        let memory = Memory::new(config);
+
+        for i in 0..5 {
+            memory.create_snapshot(&format!("s{}", i));
+        }
+        let reasons: Vec<_> = memory
+            .get_snapshots()
+            .into_iter()
+            .map(|s| s.reason)
+            .collect();
+        assert_eq!(reasons, ["s2", "s3", "s4"]);
+        assert_eq!(memory.snapshot_count(), 3);
+    }
+
     fn create_test_config() -> DaoConfig {
         DaoConfig {
+            include: vec![],
             server: ServerConfig {
-                bind: "0.0.0.0:8443".to_string(),
+                bind: Some("0.0.0.0:8443".to_string()),
                 tls_cert: None,
                 tls_key: None,
+                alpn: None,
+                alpn_strict: false,
+                tls_client_ca: None,
+                listen: vec![],
                 workers: 1,
+                idle_timeout_secs: None,
+                read_timeout_secs: None,
+                write_timeout_secs: None,
+                health_path: "/dao-health".to_string(),
+                slow_start_secs: None,
+                max_concurrent_requests: None,
+                max_connections: None,
+                connection_overflow: Default::default(),
+                allow_upstream_override: false,
+                expose_selection_headers: false,
+                tcp_nodelay: true,
+                tcp_keepalive: None,
+                rate_limit_max_keys: None,
+                upstream_pool: Default::default(),
+                max_headers: None,
+                max_header_bytes: None,
+                http1: Default::default(),
+                http2: Default::default(),
+                mode: Default::default(),
+                passthrough_upstream: None,
             },
             telemetry: None,
-            routes: RoutesConfig {
-                rule: vec![],
-            },
+            routes: RoutesConfig::default(),
             policies: None,
+            stats: StatsConfig::default(),
+            admin: None,
+            intent_rules: IntentRulesConfig::default(),
+            error_pages: ErrorPagesConfig::default(),
+            snapshots: SnapshotRetention::default(),
+            logging: Default::default(),
         }
     }
 }
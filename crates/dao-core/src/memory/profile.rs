@@ -1,7 +1,14 @@
 //! Service profiles — профили сервисов
 
 use crate::Intent;
-use std::time::SystemTime;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// Подряд идущих ошибок intent'а, после которых он запрещается
+pub const FORBID_AFTER_FAILURES: u32 = 5;
+
+/// Срок запрета intent'а: после него intent снова пробуется
+pub const FORBIDDEN_TTL: Duration = Duration::from_secs(60);
 
 /// Профиль сервиса — память о том, какой трафик "полезен"/"вреден"
 #[derive(Debug, Clone)]
@@ -12,6 +19,10 @@ pub struct ServiceProfile {
     pub optimal_rps_range: Option<(f64, f64)>,
     pub max_acceptable_latency_ms: Option<f64>,
     pub last_updated: SystemTime,
+    /// Подряд идущие ошибки по intent'у
+    pub failure_streaks: HashMap<String, u32>,
+    /// Момент запрета intent'а
+    pub forbidden_since: HashMap<String, SystemTime>,
 }
 
 impl ServiceProfile {
@@ -23,13 +34,19 @@ impl ServiceProfile {
             optimal_rps_range: None,
             max_acceptable_latency_ms: None,
             last_updated: SystemTime::now(),
+            failure_streaks: HashMap::new(),
+            forbidden_since: HashMap::new(),
         }
     }
 
     /// Проверка, подходит ли intent для этого сервиса
     pub fn accepts_intent(&self, intent: &Intent) -> bool {
-        // Запрещенные intent'ы имеют приоритет
-        if self.forbidden_intents.iter().any(|i| i.matches(intent)) {
+        // Запрещенные intent'ы имеют приоритет (пока не истек срок запрета)
+        if self
+            .forbidden_intents
+            .iter()
+            .any(|i| i.matches(intent) && !self.forbidden_expired(i))
+        {
             return false;
         }
 
@@ -42,7 +59,21 @@ impl ServiceProfile {
         self.preferred_intents.iter().any(|i| i.matches(intent))
     }
 
-    /// Обновление профиля на основе наблюдений
+    /// Истек ли срок запрета intent'а (запрет без отметки времени бессрочный)
+    fn forbidden_expired(&self, intent: &Intent) -> bool {
+        self.forbidden_since
+            .get(&intent.0)
+            .and_then(|since| since.elapsed().ok())
+            .is_some_and(|elapsed| elapsed >= FORBIDDEN_TTL)
+    }
+
+    /// Обновление профиля на основе наблюдений.
+    ///
+    /// Intent запрещается только после `FORBID_AFTER_FAILURES` ошибок подряд
+    /// и на `FORBIDDEN_TTL` — единичный сбой не блокирует его навсегда.
+    /// Успех сбрасывает серию и снимает запрет. Успешные intent'ы не
+    /// добавляются в `preferred_intents`: это сделало бы первый увиденный
+    /// intent единственным допустимым.
     pub fn learn_from_observation(
         &mut self,
         intent: &Intent,
@@ -51,15 +82,23 @@ impl ServiceProfile {
         success: bool,
     ) {
         if !success {
-            // Добавление в forbidden, если вызывает ошибки
-            if !self.forbidden_intents.contains(intent) {
-                self.forbidden_intents.push(intent.clone());
+            let streak = self.failure_streaks.entry(intent.0.clone()).or_insert(0);
+            *streak = streak.saturating_add(1);
+
+            if *streak >= FORBID_AFTER_FAILURES {
+                if !self.forbidden_intents.contains(intent) {
+                    self.forbidden_intents.push(intent.clone());
+                }
+                // Повторный запрет после истечения срока продлевает его
+                if !self.forbidden_since.contains_key(&intent.0) || self.forbidden_expired(intent) {
+                    self.forbidden_since
+                        .insert(intent.0.clone(), SystemTime::now());
+                }
             }
         } else {
-            // Обновление preferred, если успешен
-            if !self.preferred_intents.contains(intent) {
-                self.preferred_intents.push(intent.clone());
-            }
+            self.failure_streaks.remove(&intent.0);
+            self.forbidden_intents.retain(|i| !i.matches(intent));
+            self.forbidden_since.remove(&intent.0);
 
             // Обновление оптимального RPS range
             if let Some((min, max)) = self.optimal_rps_range {
@@ -79,3 +118,49 @@ impl ServiceProfile {
         self.last_updated = SystemTime::now();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_failures_forbid_intent() {
+        let mut profile = ServiceProfile::new("backend".to_string());
+        let realtime = Intent::new("realtime");
+        let batch = Intent::new("batch");
+
+        // Серия короче порога не запрещает intent
+        for _ in 0..FORBID_AFTER_FAILURES - 1 {
+            profile.learn_from_observation(&realtime, 10.0, 50.0, false);
+        }
+        assert!(profile.accepts_intent(&realtime));
+
+        // Успех сбрасывает серию
+        profile.learn_from_observation(&realtime, 10.0, 50.0, true);
+        for _ in 0..FORBID_AFTER_FAILURES - 1 {
+            profile.learn_from_observation(&realtime, 10.0, 50.0, false);
+        }
+        assert!(profile.accepts_intent(&realtime));
+
+        profile.learn_from_observation(&realtime, 10.0, 50.0, false);
+        assert!(!profile.accepts_intent(&realtime));
+        // Другие intent'ы не затронуты, успех не делает intent эксклюзивным
+        profile.learn_from_observation(&batch, 10.0, 50.0, true);
+        assert!(profile.accepts_intent(&batch));
+        assert!(profile.accepts_intent(&Intent::new("interactive")));
+    }
+
+    #[test]
+    fn test_forbidden_intent_expires() {
+        let mut profile = ServiceProfile::new("backend".to_string());
+        let realtime = Intent::new("realtime");
+        for _ in 0..FORBID_AFTER_FAILURES {
+            profile.learn_from_observation(&realtime, 10.0, 50.0, false);
+        }
+        assert!(!profile.accepts_intent(&realtime));
+
+        let expired = SystemTime::now() - FORBIDDEN_TTL;
+        profile.forbidden_since.insert(realtime.0.clone(), expired);
+        assert!(profile.accepts_intent(&realtime));
+    }
+}
@@ -1,5 +1,6 @@
 //! Configuration snapshots
 
+use super::ConfigDiff;
 use crate::config::DaoConfig;
 use std::time::SystemTime;
 
@@ -23,9 +24,11 @@ impl Snapshot {
 
     /// Возраст snapshot в секундах
     pub fn age_seconds(&self) -> u64 {
-        self.timestamp
-            .elapsed()
-            .unwrap_or_default()
-            .as_secs()
+        self.timestamp.elapsed().unwrap_or_default().as_secs()
+    }
+
+    /// Изменения конфигурации от этого snapshot'а к `other`
+    pub fn diff(&self, other: &Snapshot) -> ConfigDiff {
+        ConfigDiff::between(&self.config, &other.config)
     }
 }
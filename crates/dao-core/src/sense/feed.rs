@@ -0,0 +1,81 @@
+//! Поток снимков резонанс-метрик: один производитель, много подписчиков
+//! (SSE дашборды admin API)
+
+use super::{ResonanceMetrics, Sense};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Период публикации снимков по умолчанию
+pub const METRICS_FEED_PERIOD: Duration = Duration::from_secs(1);
+
+/// Снимков в очереди подписчика; отставший пропускает старые
+const FEED_CAPACITY: usize = 16;
+
+/// Снимок метрик всех upstream'ов
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    /// Время снимка (мс от UNIX epoch)
+    pub timestamp_ms: u64,
+    pub upstreams: Vec<ResonanceMetrics>,
+}
+
+/// Канал снимков метрик
+#[derive(Clone)]
+pub struct MetricsFeed {
+    sender: broadcast::Sender<Arc<MetricsSnapshot>>,
+}
+
+impl MetricsFeed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<MetricsSnapshot>> {
+        self.sender.subscribe()
+    }
+
+    /// Число активных подписчиков
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    pub fn publish(&self, snapshot: MetricsSnapshot) {
+        // Ошибка — подписчиков нет, снимок никому не нужен
+        let _ = self.sender.send(Arc::new(snapshot));
+    }
+}
+
+impl Default for MetricsFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sense {
+    /// Снимок резонанс-метрик всех upstream'ов
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        MetricsSnapshot {
+            timestamp_ms,
+            upstreams: self.get_resonance_metrics(),
+        }
+    }
+
+    /// Периодическая публикация снимков (фоновая задача); без подписчиков
+    /// снимки не собираются
+    pub async fn run_metrics_feed(self, period: Duration, feed: MetricsFeed) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if feed.subscribers() > 0 {
+                feed.publish(self.snapshot());
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Health check — состояние DAO для внешних балансировщиков
+
+use http::StatusCode;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Готовность DAO принимать трафик
+#[derive(Debug)]
+pub struct Health {
+    started_at: Instant,
+    ready: AtomicBool,
+}
+
+/// Тело ответа health endpoint'а
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub version: &'static str,
+    pub uptime_secs: u64,
+    pub ready: bool,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            ready: AtomicBool::new(false),
+        }
+    }
+
+    /// Переход в рабочий режим (после запуска listener'ов)
+    pub fn mark_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    pub fn report(&self) -> HealthReport {
+        HealthReport {
+            version: crate::DAO_VERSION,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            ready: self.is_ready(),
+        }
+    }
+
+    /// Статус и JSON тело ответа: 200 при готовности, иначе 503
+    pub fn response(&self) -> (StatusCode, String) {
+        let report = self.report();
+        let status = if report.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        let body = serde_json::to_string(&report).unwrap_or_default();
+        (status, body)
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_not_ready_during_startup() {
+        let health = Health::new();
+
+        let (status, body) = health.response();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["ready"], false);
+        assert_eq!(json["version"], crate::DAO_VERSION);
+    }
+
+    #[test]
+    fn test_health_ready() {
+        let health = Health::new();
+        health.mark_ready();
+
+        let (status, body) = health.response();
+        assert_eq!(status, StatusCode::OK);
+
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["ready"], true);
+        assert!(json["uptime_secs"].is_u64());
+    }
+}
@@ -5,32 +5,37 @@
 //! - Латентность, throughput, ошибки
 //! - Резонанс-метрики для политик
 
-use crate::upstream::UpstreamState;
+use crate::config::LatencyScale;
+use crate::upstream::{UpstreamRegistry, UpstreamState};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+/// load_resonance устаревшей статистики стремится к этому значению
+/// (≈ p95 100 мс без ошибок): устаревший "здоровый" upstream теряет
+/// преимущество, устаревший "больной" снова пробуется
+pub const STALE_LOAD_RESONANCE: f64 = 1.0;
+
+pub mod feed;
+pub mod health;
 pub mod metrics;
+pub use feed::{MetricsFeed, MetricsSnapshot, METRICS_FEED_PERIOD};
+pub use health::{Health, HealthReport};
 pub use metrics::{RequestMetrics, SystemMetrics};
 
 /// Sense — система телеметрии
 #[derive(Clone)]
 pub struct Sense {
-    upstreams: Arc<Vec<UpstreamState>>,
+    upstreams: Arc<UpstreamRegistry>,
 }
 
 impl Sense {
-    pub fn new(upstreams: Arc<Vec<UpstreamState>>) -> Self {
+    pub fn new(upstreams: Arc<UpstreamRegistry>) -> Self {
         Self { upstreams }
     }
 
     /// Запись результата запроса к upstream
-    pub fn record_upstream_request(
-        &self,
-        upstream_name: &str,
-        latency: Duration,
-        success: bool,
-    ) {
-        if let Some(upstream) = self.upstreams.iter().find(|u| u.name == upstream_name) {
+    pub fn record_upstream_request(&self, upstream_name: &str, latency: Duration, success: bool) {
+        if let Some(upstream) = self.upstreams.get(upstream_name) {
             upstream.record_request(latency, success);
         }
     }
@@ -38,29 +43,39 @@ impl Sense {
     /// Получение резонанс-метрик для всех upstream
     pub fn get_resonance_metrics(&self) -> Vec<ResonanceMetrics> {
         self.upstreams
+            .load()
             .iter()
-            .map(|u| {
-                let stats = u.get_stats();
-                ResonanceMetrics {
-                    upstream_name: u.name.clone(),
-                    load_resonance: calculate_load_resonance(&stats),
-                    tempo_spikiness: stats.tempo_spikiness(),
-                    p95_latency_ms: stats.p95_latency_ms(),
-                    error_rate: stats.error_rate(),
-                    current_rps: stats.current_rps(),
-                }
-            })
+            .map(ResonanceMetrics::of)
             .collect()
     }
 
+    /// Затухание статистики всех upstream'ов без недавних запросов
+    pub fn decay_idle_stats(&self) {
+        let now = Instant::now();
+        for upstream in self.upstreams.load().iter() {
+            if upstream.decay_idle_stats(now) {
+                tracing::debug!("Decayed idle stats of upstream {}", upstream.name);
+            }
+        }
+    }
+
+    /// Периодическое затухание статистики (фоновая задача)
+    pub async fn run_stats_decay(self, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            self.decay_idle_stats();
+        }
+    }
+
     /// Получение состояния конкретного upstream
-    pub fn get_upstream_state(&self, name: &str) -> Option<&UpstreamState> {
-        self.upstreams.iter().find(|u| u.name == name)
+    pub fn get_upstream_state(&self, name: &str) -> Option<UpstreamState> {
+        self.upstreams.get(name)
     }
 }
 
 /// Метрики резонанса для upstream
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct ResonanceMetrics {
     pub upstream_name: String,
     /// Совокупная "нагрузка" (latency + errors + queue)
@@ -69,19 +84,101 @@ pub struct ResonanceMetrics {
     pub tempo_spikiness: f64,
     /// P95 латентность в мс
     pub p95_latency_ms: f64,
-    /// Error rate (0.0 - 1.0)
+    /// Error rate за последнюю минуту (0.0 - 1.0)
     pub error_rate: f64,
+    /// Нормированная глубина очереди (0.0 - 1.0)
+    pub queue_depth: f64,
     /// Текущий RPS
     pub current_rps: f64,
+    /// Устаревание статистики (0.0 — свежая, 1.0 — полностью устарела)
+    pub staleness: f64,
+}
+
+impl ResonanceMetrics {
+    /// Резонанс-метрики одного upstream'а
+    pub fn of(upstream: &UpstreamState) -> Self {
+        let stats = upstream.get_stats();
+        let mut metrics = Self {
+            upstream_name: upstream.name.clone(),
+            load_resonance: 0.0,
+            staleness: stats.staleness(),
+            tempo_spikiness: stats.tempo_spikiness(),
+            p95_latency_ms: stats.p95_latency_ms(),
+            // Скользящее окно: давние ошибки не штрафуют восстановившийся upstream
+            error_rate: stats.windowed_error_rate(),
+            queue_depth: stats.queue_depth_norm(),
+            current_rps: stats.current_rps(),
+        };
+        metrics.load_resonance = metrics
+            .load_components(&LatencyNormalization::default())
+            .load_resonance;
+        metrics
+    }
+
+    /// Составляющие load_resonance при данном нормировании латентности
+    pub fn load_components(&self, latency: &LatencyNormalization) -> LoadComponents {
+        let latency = latency.component(self.p95_latency_ms);
+        let errors = self.error_rate * 10.0; // 0-10
+        let queue = self.queue_depth * 10.0; // 0-10
+
+        // По мере устаревания статистики смещается к STALE_LOAD_RESONANCE
+        let observed = latency + errors + queue;
+        LoadComponents {
+            latency,
+            errors,
+            queue,
+            load_resonance: observed * (1.0 - self.staleness)
+                + STALE_LOAD_RESONANCE * self.staleness,
+        }
+    }
+}
+
+/// Составляющие load_resonance = latency p95 + error_rate + queue_depth
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct LoadComponents {
+    /// Нормированная p95 латентность
+    pub latency: f64,
+    /// Error rate, 0-10
+    pub errors: f64,
+    /// Глубина очереди, 0-10
+    pub queue: f64,
+    /// Итог с учетом устаревания статистики
+    pub load_resonance: f64,
+}
+
+/// Нормирование p95 латентности в составляющую load_resonance.
+///
+/// По умолчанию — `p95 / 100` с пределом 10: различия выше 1000 мс не
+/// влияют на выбор.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyNormalization {
+    pub scale: LatencyScale,
+    /// p95 (мс), дающая составляющую 1.0
+    pub reference_ms: f64,
+    /// Верхняя граница составляющей
+    pub cap: f64,
 }
 
-/// Вычисление load_resonance = сглаженная функция: latency p95 + error_rate + queue_depth
-fn calculate_load_resonance(stats: &crate::upstream::UpstreamStats) -> f64 {
-    let latency_component = (stats.p95_latency_ms() / 100.0).min(10.0); // Нормализация до ~0-10
-    let error_component = stats.error_rate() * 10.0; // 0-10
-    let queue_component = stats.queue_depth_norm() * 10.0; // 0-10
+impl Default for LatencyNormalization {
+    fn default() -> Self {
+        Self {
+            scale: LatencyScale::Linear,
+            reference_ms: 100.0,
+            cap: 10.0,
+        }
+    }
+}
 
-    latency_component + error_component + queue_component
+impl LatencyNormalization {
+    /// Составляющая латентности для p95 (мс)
+    pub fn component(&self, p95_ms: f64) -> f64 {
+        let ratio = p95_ms / self.reference_ms;
+        let value = match self.scale {
+            LatencyScale::Linear => ratio,
+            LatencyScale::Log => (1.0 + ratio).log2(),
+        };
+        value.min(self.cap)
+    }
 }
 
 #[cfg(test)]
@@ -98,11 +195,73 @@ mod tests {
             1,
         )];
 
-        let sense = Sense::new(Arc::new(upstreams));
+        let sense = Sense::new(Arc::new(UpstreamRegistry::new(upstreams)));
         sense.record_upstream_request("test", Duration::from_millis(50), true);
 
         let metrics = sense.get_resonance_metrics();
         assert_eq!(metrics.len(), 1);
         assert_eq!(metrics[0].upstream_name, "test");
     }
+
+    #[test]
+    fn test_stale_upstream_loses_advantage() {
+        let upstream = UpstreamState::new("idle".to_string(), "http://idle".to_string(), vec![], 1);
+        upstream.record_request(Duration::from_millis(5), true);
+        let sense = Sense::new(Arc::new(UpstreamRegistry::new(vec![upstream.clone()])));
+
+        let fresh = &sense.get_resonance_metrics()[0];
+        assert_eq!(fresh.staleness, 0.0);
+        assert!(fresh.load_resonance < STALE_LOAD_RESONANCE);
+
+        // Последний запрос был давно
+        let long_ago = Instant::now() - Duration::from_secs(600);
+        upstream.stats.write().last_request_at = Some(long_ago);
+
+        let stale = &sense.get_resonance_metrics()[0];
+        assert_eq!(stale.staleness, 1.0);
+        assert!((stale.load_resonance - STALE_LOAD_RESONANCE).abs() < 1e-9);
+
+        sense.decay_idle_stats();
+        assert_eq!(upstream.get_stats().success_count, 0);
+    }
+
+    #[test]
+    fn test_latency_normalization_reference() {
+        let metrics = |p95_latency_ms| ResonanceMetrics {
+            upstream_name: "u".to_string(),
+            load_resonance: 0.0,
+            tempo_spikiness: 0.0,
+            p95_latency_ms,
+            error_rate: 0.0,
+            queue_depth: 0.0,
+            current_rps: 0.0,
+            staleness: 0.0,
+        };
+        let (fast, slow) = (metrics(50.0), metrics(500.0));
+        let load = |m: &ResonanceMetrics, latency| m.load_components(&latency).load_resonance;
+
+        // По умолчанию — p95 / 100
+        let default = LatencyNormalization::default();
+        assert!((load(&fast, default) - 0.5).abs() < 1e-9);
+        assert!((load(&slow, default) - 5.0).abs() < 1e-9);
+
+        // Опорные 50 мс: 1.0 против 10.0, медленный упирается в предел
+        let tight = LatencyNormalization {
+            reference_ms: 50.0,
+            cap: 4.0,
+            ..default
+        };
+        assert!((load(&fast, tight) - 1.0).abs() < 1e-9);
+        assert!((load(&slow, tight) - 4.0).abs() < 1e-9);
+
+        // Логарифм: log2(2) = 1, log2(11) ≈ 3.46 — разрыв меньше, чем линейный
+        let log = LatencyNormalization {
+            scale: LatencyScale::Log,
+            reference_ms: 50.0,
+            cap: 10.0,
+        };
+        assert!((load(&fast, log) - 1.0).abs() < 1e-9);
+        assert!((load(&slow, log) - 11f64.log2()).abs() < 1e-9);
+        assert!(load(&slow, log) / load(&fast, log) < load(&slow, default) / load(&fast, default));
+    }
 }
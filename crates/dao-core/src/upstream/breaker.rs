@@ -0,0 +1,348 @@
+//! Circuit breaker upstream'а
+//!
+//! Закрыт → после `failure_threshold` ошибок подряд открыт → через
+//! `open_secs` полуоткрыт: проходят только пробные запросы, не больше
+//! `half_open_probes` одновременно. Столько же успешных проб замыкают
+//! цепь, любая ошибка пробы снова ее размыкает; проба, брошенная без
+//! исхода (таймаут маршрута, обрыв), считается ошибкой. Ответы запросов,
+//! допущенных до размыкания, полуоткрытую цепь не замыкают и не размыкают.
+//!
+//! Размыкание — по ошибкам подряд, а не по оконной доле ошибок из
+//! статистики: та считает неуспехом и ответы 4xx, а breaker'у нужен только
+//! сигнал о здоровье upstream'а (соединение, таймаут, 5xx).
+
+use crate::config::CircuitBreakerConfig;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Состояние цепи
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Допуск запроса через breaker
+#[derive(Debug)]
+pub enum Admission {
+    /// Цепь замкнута; `generation` — период цепи для учета исхода
+    Pass { generation: u64 },
+    /// Пробный запрос полуоткрытой цепи
+    Probe(Probe),
+    /// Цепь разомкнута или пробы исчерпаны
+    Reject,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: BreakerState,
+    /// Ошибки подряд в замкнутом состоянии
+    failures: u32,
+    opened_at: Instant,
+    /// Успешные пробы текущего полуоткрытого периода
+    successes: u32,
+    /// Номер полуоткрытого периода: пробы прошлых периодов слот не возвращают
+    generation: u64,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    inner: Mutex<Inner>,
+    /// Свободные слоты проб полуоткрытого состояния
+    probes: AtomicU32,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            inner: Mutex::new(Inner {
+                state: BreakerState::Closed,
+                failures: 0,
+                opened_at: Instant::now(),
+                successes: 0,
+                generation: 0,
+            }),
+            probes: AtomicU32::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    /// Состояние на момент `now` (разомкнутая цепь по истечении
+    /// `open_secs` становится полуоткрытой)
+    pub fn state(&self, now: Instant) -> BreakerState {
+        let mut inner = self.inner.lock();
+        self.advance(&mut inner, now);
+        inner.state
+    }
+
+    /// Свободные пробы (только для полуоткрытой цепи)
+    pub fn remaining_probes(&self, now: Instant) -> Option<u32> {
+        (self.state(now) == BreakerState::HalfOpen).then(|| self.probes.load(Ordering::Acquire))
+    }
+
+    /// Пропустит ли цепь запрос — без занятия пробы (для выбора upstream'а)
+    pub fn admits(&self, now: Instant) -> bool {
+        match self.state(now) {
+            BreakerState::Closed => true,
+            BreakerState::Open => false,
+            BreakerState::HalfOpen => self.probes.load(Ordering::Acquire) > 0,
+        }
+    }
+
+    /// Допуск запроса; проба занимает слот до drop
+    pub fn admit(self: &Arc<Self>, now: Instant) -> Admission {
+        let mut inner = self.inner.lock();
+        self.advance(&mut inner, now);
+        match inner.state {
+            BreakerState::Closed => Admission::Pass {
+                generation: inner.generation,
+            },
+            BreakerState::Open => Admission::Reject,
+            BreakerState::HalfOpen => {
+                let taken = self
+                    .probes
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+                match taken {
+                    Ok(_) => Admission::Probe(Probe {
+                        breaker: self.clone(),
+                        generation: inner.generation,
+                        settled: false,
+                    }),
+                    Err(_) => Admission::Reject,
+                }
+            }
+        }
+    }
+
+    /// Исход запроса: `healthy` — без ошибки соединения и 5xx. В
+    /// полуоткрытой цепи учитываются только пробы
+    pub fn record(&self, healthy: bool, now: Instant) {
+        let mut inner = self.inner.lock();
+        self.advance(&mut inner, now);
+        if inner.state != BreakerState::HalfOpen {
+            self.apply(&mut inner, healthy, now);
+        }
+    }
+
+    /// Исход запроса, допущенного замкнутой цепью периода `generation`;
+    /// ответы прошлых периодов не учитываются
+    pub fn record_pass(&self, generation: u64, healthy: bool, now: Instant) {
+        let mut inner = self.inner.lock();
+        self.advance(&mut inner, now);
+        if inner.generation == generation && inner.state != BreakerState::HalfOpen {
+            self.apply(&mut inner, healthy, now);
+        }
+    }
+
+    /// Исход пробы; пробы прошлых полуоткрытых периодов не учитываются
+    fn record_probe(&self, generation: u64, healthy: bool, now: Instant) {
+        let mut inner = self.inner.lock();
+        self.advance(&mut inner, now);
+        if inner.generation == generation {
+            self.apply(&mut inner, healthy, now);
+        }
+    }
+
+    fn apply(&self, inner: &mut Inner, healthy: bool, now: Instant) {
+        match (inner.state, healthy) {
+            (BreakerState::Closed, true) => inner.failures = 0,
+            (BreakerState::Closed, false) => {
+                inner.failures += 1;
+                if inner.failures >= self.config.failure_threshold {
+                    self.open(inner, now);
+                }
+            }
+            (BreakerState::HalfOpen, true) => {
+                inner.successes += 1;
+                if inner.successes >= self.config.half_open_probes {
+                    inner.state = BreakerState::Closed;
+                    inner.failures = 0;
+                    self.probes.store(0, Ordering::Release);
+                }
+            }
+            (BreakerState::HalfOpen, false) => self.open(inner, now),
+            // Ответы запросов, начатых до размыкания
+            (BreakerState::Open, _) => {}
+        }
+    }
+
+    fn open(&self, inner: &mut Inner, now: Instant) {
+        inner.state = BreakerState::Open;
+        inner.opened_at = now;
+        inner.failures = 0;
+        self.probes.store(0, Ordering::Release);
+    }
+
+    fn advance(&self, inner: &mut Inner, now: Instant) {
+        let open_for = Duration::from_secs(self.config.open_secs);
+        if inner.state == BreakerState::Open
+            && now.saturating_duration_since(inner.opened_at) >= open_for
+        {
+            inner.state = BreakerState::HalfOpen;
+            inner.successes = 0;
+            inner.generation += 1;
+            self.probes
+                .store(self.config.half_open_probes, Ordering::Release);
+        }
+    }
+
+    fn release(&self, generation: u64) {
+        let inner = self.inner.lock();
+        if inner.state == BreakerState::HalfOpen && inner.generation == generation {
+            self.probes.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Слот пробного запроса; освобождается при drop. Проба без
+/// [`record`](Self::record) — ошибка
+#[derive(Debug)]
+pub struct Probe {
+    breaker: Arc<CircuitBreaker>,
+    generation: u64,
+    settled: bool,
+}
+
+impl Probe {
+    /// Исход пробы
+    pub fn record(&mut self, healthy: bool, now: Instant) {
+        self.settled = true;
+        self.breaker.record_probe(self.generation, healthy, now);
+    }
+}
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.breaker
+                .record_probe(self.generation, false, Instant::now());
+        }
+        self.breaker.release(self.generation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(probes: u32) -> Arc<CircuitBreaker> {
+        Arc::new(CircuitBreaker::new(&CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_secs: 10,
+            half_open_probes: probes,
+        }))
+    }
+
+    /// Размыкание; момент перехода в полуоткрытое состояние
+    fn half_open(breaker: &Arc<CircuitBreaker>, now: Instant) -> Instant {
+        breaker.record(false, now);
+        breaker.record(false, now);
+        assert_eq!(breaker.state(now), BreakerState::Open);
+        assert!(matches!(breaker.admit(now), Admission::Reject));
+        now + Duration::from_secs(10)
+    }
+
+    #[test]
+    fn test_half_open_probe_budget() {
+        let breaker = breaker(2);
+        let now = half_open(&breaker, Instant::now());
+        assert_eq!(breaker.state(now), BreakerState::HalfOpen);
+        assert_eq!(breaker.remaining_probes(now), Some(2));
+
+        // Две пробы в полете — третья отклоняется
+        let Admission::Probe(mut first) = breaker.admit(now) else {
+            panic!("first probe must be admitted");
+        };
+        let Admission::Probe(mut second) = breaker.admit(now) else {
+            panic!("second probe must be admitted");
+        };
+        assert_eq!(breaker.remaining_probes(now), Some(0));
+        assert!(!breaker.admits(now));
+        assert!(matches!(breaker.admit(now), Admission::Reject));
+
+        // Завершение пробы освобождает слот
+        first.record(true, now);
+        drop(first);
+        assert_eq!(breaker.remaining_probes(now), Some(1));
+
+        // Успешная пачка замыкает цепь
+        second.record(true, now);
+        drop(second);
+        assert_eq!(breaker.state(now), BreakerState::Closed);
+        assert_eq!(breaker.remaining_probes(now), None);
+        assert!(matches!(breaker.admit(now), Admission::Pass { .. }));
+    }
+
+    #[test]
+    fn test_failed_probe_reopens() {
+        let breaker = breaker(2);
+        let now = half_open(&breaker, Instant::now());
+        let probe = breaker.admit(now);
+        let Admission::Probe(mut first) = breaker.admit(now) else {
+            panic!("probe must be admitted");
+        };
+        first.record(true, now);
+        drop(first);
+        let Admission::Probe(mut second) = breaker.admit(now) else {
+            panic!("probe must be admitted");
+        };
+        second.record(false, now);
+        assert_eq!(breaker.state(now), BreakerState::Open);
+
+        // Проба прошлого периода ни слот, ни исход в новый не переносит
+        let later = now + Duration::from_secs(10);
+        assert_eq!(breaker.state(later), BreakerState::HalfOpen);
+        drop(probe);
+        assert_eq!(breaker.state(later), BreakerState::HalfOpen);
+        assert_eq!(breaker.remaining_probes(later), Some(2));
+    }
+
+    #[test]
+    fn test_abandoned_probe_reopens() {
+        let breaker = breaker(1);
+        let now = half_open(&breaker, Instant::now());
+        // Проба брошена без исхода (отмена по таймауту маршрута)
+        let probe = breaker.admit(now);
+        assert!(matches!(probe, Admission::Probe(_)));
+        drop(probe);
+        assert_eq!(breaker.state(Instant::now()), BreakerState::Open);
+    }
+
+    #[test]
+    fn test_stale_pass_ignored_when_half_open() {
+        let breaker = breaker(1);
+        let now = Instant::now();
+        // Запрос допущен замкнутой цепью и отвечает после размыкания
+        let Admission::Pass { generation } = breaker.admit(now) else {
+            panic!("closed circuit must pass");
+        };
+        let now = half_open(&breaker, now);
+        assert_eq!(breaker.state(now), BreakerState::HalfOpen);
+
+        breaker.record_pass(generation, true, now);
+        breaker.record(true, now);
+        assert_eq!(breaker.state(now), BreakerState::HalfOpen);
+        assert_eq!(breaker.remaining_probes(now), Some(1));
+
+        // Замыкает только проба; ответ прошлого периода ошибки не добавляет
+        let Admission::Probe(mut probe) = breaker.admit(now) else {
+            panic!("probe must be admitted");
+        };
+        probe.record(true, now);
+        drop(probe);
+        assert_eq!(breaker.state(now), BreakerState::Closed);
+        breaker.record_pass(generation, false, now);
+        breaker.record_pass(generation, false, now);
+        assert_eq!(breaker.state(now), BreakerState::Closed);
+    }
+}
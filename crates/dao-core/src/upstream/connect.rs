@@ -0,0 +1,125 @@
+//! Установка соединения с upstream'ом: таймаут и фиксированный адрес
+
+use hyper::Uri;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::Service;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Connector с таймаутом на все соединение: TCP connect и TLS handshake.
+///
+/// Истечение — `io::ErrorKind::TimedOut` (классифицируется как timeout).
+#[derive(Clone)]
+pub struct ConnectTimeout<C> {
+    inner: C,
+    timeout: Option<Duration>,
+}
+
+impl<C> ConnectTimeout<C> {
+    pub fn new(inner: C, timeout: Option<Duration>) -> Self {
+        Self { inner, timeout }
+    }
+}
+
+impl<C> Service<Uri> for ConnectTimeout<C>
+where
+    C: Service<Uri>,
+    C::Future: Send + 'static,
+    C::Error: Into<BoxError>,
+{
+    type Response = C::Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<C::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.inner.call(uri);
+        let timeout = self.timeout;
+        Box::pin(async move {
+            match timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, connecting).await {
+                    Ok(connected) => connected.map_err(Into::into),
+                    Err(_) => Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("connect timed out after {:?}", timeout),
+                    )) as BoxError),
+                },
+                None => connecting.await.map_err(Into::into),
+            }
+        })
+    }
+}
+
+/// Connector, всегда соединяющийся с адресом upstream'а.
+///
+/// Для HTTP/2 `:authority` берется из URI запроса — при переписанном
+/// Host URI указывает на другой хост, а соединение идет к upstream'у.
+#[derive(Clone)]
+pub struct FixedTarget<C> {
+    inner: C,
+    target: Option<Uri>,
+}
+
+impl<C> FixedTarget<C> {
+    /// `target: None` — соединение по URI запроса
+    pub fn new(inner: C, target: Option<Uri>) -> Self {
+        Self { inner, target }
+    }
+}
+
+impl<C: Service<Uri>> Service<Uri> for FixedTarget<C> {
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = C::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        self.inner.call(self.target.clone().unwrap_or(uri))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_timeout_elapses() {
+        let hanging =
+            tower::service_fn(|_uri: Uri| futures::future::pending::<Result<(), std::io::Error>>());
+        let mut connector = ConnectTimeout::new(hanging, Some(Duration::from_millis(50)));
+
+        let error = connector
+            .call(Uri::from_static("http://backend"))
+            .await
+            .unwrap_err();
+        let io = error.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(io.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn test_fixed_target_overrides_uri() {
+        let echo = tower::service_fn(|uri: Uri| async move { Ok::<_, std::io::Error>(uri) });
+        let mut connector = FixedTarget::new(echo, Some(Uri::from_static("http://10.0.0.1:8080")));
+        let dialed = connector
+            .call(Uri::from_static("http://api.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(dialed, Uri::from_static("http://10.0.0.1:8080"));
+
+        let mut passthrough = FixedTarget::new(echo, None);
+        let dialed = passthrough
+            .call(Uri::from_static("http://api.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(dialed, Uri::from_static("http://api.example.com"));
+    }
+}
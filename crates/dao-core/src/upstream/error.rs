@@ -0,0 +1,79 @@
+//! Классификация ошибок запроса к upstream'у
+
+use std::error::Error as StdError;
+use std::fmt;
+
+/// Вид сбоя запроса к upstream'у
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamErrorKind {
+    /// Соединение не установлено (refused, unreachable, DNS)
+    Connect,
+    /// Upstream не ответил за отведенное время
+    Timeout,
+    /// Ошибка TLS handshake (сертификат, протокол)
+    Tls,
+    /// Соединение оборвано или ответ не разобран
+    Protocol,
+}
+
+impl UpstreamErrorKind {
+    /// Классификация по цепочке источников ошибки клиента
+    pub fn classify(error: &hyper_util::client::legacy::Error) -> Self {
+        let mut source: Option<&(dyn StdError + 'static)> = Some(error);
+        while let Some(current) = source {
+            if current.is::<rustls::Error>() {
+                return Self::Tls;
+            }
+            source = match current.downcast_ref::<std::io::Error>() {
+                Some(io) if io.kind() == std::io::ErrorKind::TimedOut => return Self::Timeout,
+                // Ошибки TLS приходят завернутыми в (вложенные) io::Error,
+                // а `io::Error::source` пропускает вложенную ошибку
+                Some(io) => io.get_ref().map(|inner| inner as &(dyn StdError + 'static)),
+                None => current.source(),
+            };
+        }
+
+        if error.is_connect() {
+            Self::Connect
+        } else {
+            Self::Protocol
+        }
+    }
+
+    /// Статус ответа клиенту
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Timeout => 504,
+            Self::Connect | Self::Tls | Self::Protocol => 502,
+        }
+    }
+
+    /// Значение label'а `kind` в метриках
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::Timeout => "timeout",
+            Self::Tls => "tls",
+            Self::Protocol => "protocol",
+        }
+    }
+}
+
+impl fmt::Display for UpstreamErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Текст ошибки со всеми источниками (`client error (Connect)` сам по себе
+/// ничего не говорит)
+pub(crate) fn error_chain(error: &dyn StdError) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(current) = source {
+        message.push_str(": ");
+        message.push_str(&current.to_string());
+        source = current.source();
+    }
+    message
+}
@@ -0,0 +1,37 @@
+//! gRPC: исход вызова по `grpc-status`, а не по HTTP статусу
+
+use http::{HeaderMap, HeaderName};
+
+/// Статус gRPC вызова (в trailers или в заголовках trailers-only ответа)
+pub const GRPC_STATUS_HEADER: HeaderName = HeaderName::from_static("grpc-status");
+
+/// `grpc-status` успешного вызова
+pub const GRPC_OK: u32 = 0;
+
+/// Код `grpc-status` (None — нет или не число)
+pub fn grpc_status(headers: &HeaderMap) -> Option<u32> {
+    headers
+        .get(GRPC_STATUS_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grpc_status() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(grpc_status(&headers), None);
+
+        headers.insert(GRPC_STATUS_HEADER, "14".parse().unwrap());
+        assert_eq!(grpc_status(&headers), Some(14));
+
+        headers.insert(GRPC_STATUS_HEADER, "unavailable".parse().unwrap());
+        assert_eq!(grpc_status(&headers), None);
+    }
+}
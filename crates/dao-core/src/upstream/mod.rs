@@ -1,9 +1,34 @@
 //! Upstream management — работа с backend серверами
 
-pub mod state;
+pub mod breaker;
 pub mod client;
+pub mod connect;
+pub mod error;
+pub mod grpc;
 pub mod pool;
+pub mod registry;
+pub mod state;
 
-pub use state::{UpstreamState, UpstreamStats};
-pub use client::UpstreamClient;
+pub use breaker::{Admission, BreakerState, CircuitBreaker, Probe};
+pub use client::{UpstreamClient, UpstreamTls};
+pub use error::UpstreamErrorKind;
+pub use grpc::{grpc_status, GRPC_OK, GRPC_STATUS_HEADER};
 pub use pool::ConnectionPool;
+pub use registry::UpstreamRegistry;
+pub use state::{InFlightGuard, UpstreamState, UpstreamStats};
+
+/// Тестовые upstream'ы `http://<name>` с весом 1
+#[cfg(test)]
+pub(crate) fn test_upstreams(names: &[&str]) -> Vec<std::sync::Arc<UpstreamState>> {
+    names
+        .iter()
+        .map(|name| {
+            std::sync::Arc::new(UpstreamState::new(
+                name.to_string(),
+                format!("http://{}", name),
+                vec![],
+                1,
+            ))
+        })
+        .collect()
+}
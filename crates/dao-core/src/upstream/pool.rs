@@ -1,30 +1,83 @@
 //! Connection pooling для upstreams
 
-use super::client::UpstreamClient;
+use super::client::{UpstreamClient, UpstreamTls};
+use crate::gate::TcpOptions;
+use crate::Result;
 use dashmap::DashMap;
 use std::sync::Arc;
+use std::time::Duration;
+
+type ClientKey = (Option<String>, String, UpstreamTls, bool, Option<Duration>);
 
 /// Connection pool для upstreams
 #[derive(Clone)]
 pub struct ConnectionPool {
-    // URL -> Client
-    clients: Arc<DashMap<String, UpstreamClient>>,
+    // (имя upstream'а при изоляции, URL, TLS параметры, HTTP/2,
+    // таймаут соединения) -> Client
+    clients: Arc<DashMap<ClientKey, UpstreamClient>>,
+    /// Клиенты, заданные вручную по URL (приоритетнее созданных пулом)
+    custom: Arc<DashMap<String, UpstreamClient>>,
+    tcp: TcpOptions,
+    /// Свой клиент (и свои соединения) у каждого upstream'а, даже при
+    /// совпадающих URL
+    per_upstream: bool,
 }
 
 impl ConnectionPool {
     /// Создание нового пула
     pub fn new() -> Self {
+        Self::with_tcp_options(TcpOptions::default())
+    }
+
+    /// Пул с заданными параметрами сокетов к upstream'ам
+    pub fn with_tcp_options(tcp: TcpOptions) -> Self {
         Self {
             clients: Arc::new(DashMap::new()),
+            custom: Arc::new(DashMap::new()),
+            tcp,
+            per_upstream: false,
         }
     }
 
-    /// Получение клиента для upstream (или создание нового)
-    pub fn get_client(&self, upstream_url: &str) -> UpstreamClient {
-        self.clients
-            .entry(upstream_url.to_string())
-            .or_insert_with(UpstreamClient::new)
-            .clone()
+    /// Клиенты по имени upstream'а вместо общих по URL: соединения
+    /// нестабильного upstream'а не смешиваются с соседними на том же хосте
+    pub fn with_per_upstream_clients(mut self, enabled: bool) -> Self {
+        self.per_upstream = enabled;
+        self
+    }
+
+    /// Получение клиента для upstream (или создание нового).
+    ///
+    /// Connector выбирается по схеме URL: `https://` и `wss://` — TLS, иначе plaintext.
+    pub fn get_client(
+        &self,
+        upstream_name: &str,
+        upstream_url: &str,
+        tls: &UpstreamTls,
+        http2: bool,
+        connect_timeout: Option<Duration>,
+    ) -> Result<UpstreamClient> {
+        if let Some(client) = self.custom.get(upstream_url) {
+            return Ok(client.clone());
+        }
+        let key = (
+            self.per_upstream.then(|| upstream_name.to_string()),
+            upstream_url.to_string(),
+            tls.clone(),
+            http2,
+            connect_timeout,
+        );
+        if let Some(client) = self.clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let client = UpstreamClient::for_url(upstream_url, tls, http2, &self.tcp, connect_timeout)?;
+        Ok(self.clients.entry(key).or_insert(client).clone())
+    }
+
+    /// Свой клиент для upstream URL (например, при встраивании DAO)
+    pub fn insert_client(&self, upstream_url: impl Into<String>, client: UpstreamClient) {
+        self.custom.insert(upstream_url.into(), client);
     }
 
     /// Очистка пула
@@ -57,11 +110,58 @@ mod tests {
     #[test]
     fn test_pool_get_client() {
         let pool = ConnectionPool::new();
-        let _client = pool.get_client("http://localhost:8080");
+        let tls = UpstreamTls::default();
+        let client = pool
+            .get_client("a", "http://localhost:8080", &tls, false, None)
+            .unwrap();
+        assert!(!client.is_tls());
         assert_eq!(pool.size(), 1);
 
         // Повторный get должен вернуть того же клиента
-        let _client2 = pool.get_client("http://localhost:8080");
+        let _client2 = pool
+            .get_client("a", "http://localhost:8080", &tls, false, None)
+            .unwrap();
+        assert_eq!(pool.size(), 1);
+        // Другой upstream с тем же URL по умолчанию делит клиента
+        let _client3 = pool
+            .get_client("b", "http://localhost:8080", &tls, false, None)
+            .unwrap();
         assert_eq!(pool.size(), 1);
+
+        let client = pool
+            .get_client("c", "https://localhost:8443", &tls, false, None)
+            .unwrap();
+        assert!(client.is_tls());
+        assert_eq!(pool.size(), 2);
+    }
+
+    #[test]
+    fn test_pool_per_upstream_clients() {
+        let pool = ConnectionPool::new().with_per_upstream_clients(true);
+        let tls = UpstreamTls::default();
+        let url = "http://localhost:8080";
+        pool.get_client("stable", url, &tls, false, None).unwrap();
+        pool.get_client("flaky", url, &tls, false, None).unwrap();
+        assert_eq!(pool.size(), 2);
+
+        // Свои настройки у каждого upstream'а при общем URL
+        pool.get_client("flaky", url, &tls, false, Some(Duration::from_millis(200)))
+            .unwrap();
+        assert_eq!(pool.size(), 3);
+        pool.get_client("stable", url, &tls, false, None).unwrap();
+        assert_eq!(pool.size(), 3);
+    }
+
+    #[test]
+    fn test_pool_custom_client() {
+        let pool = ConnectionPool::new();
+        pool.insert_client("https://localhost:8443", UpstreamClient::new());
+
+        let tls = UpstreamTls::default();
+        let client = pool
+            .get_client("a", "https://localhost:8443", &tls, false, None)
+            .unwrap();
+        assert!(!client.is_tls());
+        assert_eq!(pool.size(), 0);
     }
 }
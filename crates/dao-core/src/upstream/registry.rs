@@ -0,0 +1,225 @@
+//! Upstream registry — актуальный набор upstream'ов с атомарной заменой
+
+use super::UpstreamState;
+use crate::config::{DaoConfig, RouteRule};
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+
+/// Реестр upstream'ов, общий для Sense, Admin и сервера.
+///
+/// При перезагрузке конфигурации набор пересобирается и подменяется
+/// атомарно. Upstream'ы, оставшиеся в конфиге (по имени), сохраняют
+/// статистику, счетчик in-flight и drain; при смене URL статистика
+/// начинается заново. Удаленные исчезают из выбора; запросы в полете
+/// держат свои клоны и завершаются штатно.
+#[derive(Debug)]
+pub struct UpstreamRegistry {
+    upstreams: ArcSwap<Vec<UpstreamState>>,
+}
+
+impl UpstreamRegistry {
+    pub fn new(upstreams: Vec<UpstreamState>) -> Self {
+        Self {
+            upstreams: ArcSwap::from_pointee(upstreams),
+        }
+    }
+
+    /// Построение из конфигурации (один upstream на имя)
+    pub fn from_config(config: &DaoConfig) -> Self {
+        Self::new(build(config, &[]))
+    }
+
+    /// Текущий набор upstream'ов
+    pub fn load(&self) -> Arc<Vec<UpstreamState>> {
+        self.upstreams.load_full()
+    }
+
+    /// Поиск upstream'а по имени
+    pub fn get(&self, name: &str) -> Option<UpstreamState> {
+        self.upstreams
+            .load()
+            .iter()
+            .find(|u| u.name == name)
+            .cloned()
+    }
+
+    /// Upstream'ы маршрута в порядке конфигурации
+    pub fn route_upstreams(&self, route: &RouteRule) -> Vec<Arc<UpstreamState>> {
+        let upstreams = self.upstreams.load();
+        route
+            .upstreams
+            .iter()
+            .filter_map(|uc| {
+                upstreams
+                    .iter()
+                    .find(|u| u.name == uc.name)
+                    .map(|u| Arc::new(u.clone()))
+            })
+            .collect()
+    }
+
+    /// Пересборка набора после перезагрузки конфигурации; возвращает
+    /// имена upstream'ов, сменивших URL (их статистика сброшена)
+    pub fn reload(&self, config: &DaoConfig) -> Vec<String> {
+        let current = self.load();
+        let rebuilt = build(config, &current);
+
+        let retargeted: Vec<String> = rebuilt
+            .iter()
+            .filter_map(|u| {
+                let old = current.iter().find(|old| old.name == u.name)?;
+                (old.url != u.url).then(|| {
+                    tracing::info!(
+                        "Upstream {} moved from {} to {}, stats reset",
+                        u.name,
+                        old.url,
+                        u.url
+                    );
+                    u.name.clone()
+                })
+            })
+            .collect();
+
+        for removed in current
+            .iter()
+            .filter(|old| !rebuilt.iter().any(|u| u.name == old.name))
+        {
+            tracing::info!(
+                "Upstream {} removed ({} requests in flight)",
+                removed.name,
+                removed.in_flight()
+            );
+        }
+
+        self.upstreams.store(Arc::new(rebuilt));
+        retargeted
+    }
+}
+
+fn build(config: &DaoConfig, previous: &[UpstreamState]) -> Vec<UpstreamState> {
+    let mut upstreams: Vec<UpstreamState> = Vec::new();
+
+    for upstream_cfg in config.routes.rule.iter().flat_map(|r| &r.upstreams) {
+        if upstreams.iter().any(|u| u.name == upstream_cfg.name) {
+            continue;
+        }
+
+        let fresh = UpstreamState::from_config(upstream_cfg, &config.stats);
+        let state = match previous.iter().find(|u| u.name == upstream_cfg.name) {
+            Some(old) => fresh.inherit_runtime(old),
+            None => fresh,
+        };
+        upstreams.push(state);
+    }
+
+    upstreams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(upstreams: &[(&str, &str)]) -> DaoConfig {
+        let mut toml_str = String::from(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+            [routes.rule.match]
+            path_prefix = "/"
+            "#,
+        );
+        for (name, url) in upstreams {
+            toml_str.push_str(&format!(
+                "[[routes.rule.upstreams]]\nname = \"{}\"\nurl = \"{}\"\n",
+                name, url
+            ));
+        }
+        toml::from_str(&toml_str).unwrap()
+    }
+
+    #[test]
+    fn test_reload_preserves_surviving_stats() {
+        let registry = UpstreamRegistry::from_config(&config(&[
+            ("keep", "http://127.0.0.1:8081"),
+            ("drop", "http://127.0.0.1:8082"),
+        ]));
+
+        let keep = registry.get("keep").unwrap();
+        keep.record_request(Duration::from_millis(40), true);
+        keep.set_draining(true);
+
+        // Запрос к удаляемому upstream'у в полете во время reload
+        let dropped = registry.get("drop").unwrap();
+        let in_flight = dropped.begin_request();
+
+        let retargeted = registry.reload(&config(&[
+            ("keep", "http://127.0.0.1:8081"),
+            ("new", "http://127.0.0.1:8083"),
+        ]));
+        assert!(retargeted.is_empty());
+
+        let names: Vec<_> = registry.load().iter().map(|u| u.name.clone()).collect();
+        assert_eq!(names, vec!["keep", "new"]);
+        assert!(registry.get("drop").is_none());
+
+        let keep = registry.get("keep").unwrap();
+        assert_eq!(keep.get_stats().success_count, 1);
+        assert!(keep.is_draining());
+        assert_eq!(registry.get("new").unwrap().get_stats().success_count, 0);
+
+        // Удаленный upstream доживает до завершения своих запросов
+        assert_eq!(dropped.in_flight(), 1);
+        drop(in_flight);
+        assert_eq!(dropped.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_reload_with_changed_url_resets_stats() {
+        let registry = UpstreamRegistry::from_config(&config(&[
+            ("moved", "http://127.0.0.1:8081"),
+            ("stays", "http://127.0.0.1:8082"),
+        ]));
+        for name in ["moved", "stays"] {
+            let upstream = registry.get(name).unwrap();
+            upstream.record_request(Duration::from_millis(40), false);
+            upstream.set_draining(true);
+        }
+        let in_flight = registry.get("moved").unwrap().begin_request();
+
+        let retargeted = registry.reload(&config(&[
+            ("moved", "http://127.0.0.1:9081"),
+            ("stays", "http://127.0.0.1:8082"),
+        ]));
+        assert_eq!(retargeted, vec!["moved"]);
+
+        // Новый адрес — другой backend: ошибки прежнего не учитываются
+        let moved = registry.get("moved").unwrap();
+        assert_eq!(moved.url, "http://127.0.0.1:9081");
+        assert_eq!(moved.get_stats().error_count, 0);
+        assert_eq!(moved.get_stats().windowed_error_rate(), 0.0);
+        // Drain и запросы к прежнему адресу переносятся
+        assert!(moved.is_draining());
+        assert_eq!(moved.in_flight(), 1);
+        drop(in_flight);
+        assert_eq!(moved.in_flight(), 0);
+
+        let stays = registry.get("stays").unwrap();
+        assert_eq!(stays.get_stats().error_count, 1);
+    }
+
+    #[test]
+    fn test_duplicate_upstream_names_share_state() {
+        let mut cfg = config(&[("shared", "http://127.0.0.1:8081")]);
+        let mut second = cfg.routes.rule[0].clone();
+        second.name = "other".to_string();
+        cfg.routes.rule.push(second);
+
+        let registry = UpstreamRegistry::from_config(&cfg);
+        assert_eq!(registry.load().len(), 1);
+    }
+}
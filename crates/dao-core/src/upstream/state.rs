@@ -1,45 +1,267 @@
 //! Upstream management — работа с backend серверами
 
-use crate::Intent;
+use super::breaker::{Admission, BreakerState, CircuitBreaker, Probe};
+use super::client::UpstreamTls;
+use crate::config::{CircuitBreakerConfig, StatsConfig, UpstreamConfig};
+use crate::{Intent, WeightedIntent};
+use hdrhistogram::serialization::{Serializer, V2DeflateSerializer};
 use hdrhistogram::Histogram;
 use parking_lot::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Минимальная доля трафика в начале slow start окна
+pub const SLOW_START_MIN_FACTOR: f64 = 0.05;
 
 /// Состояние upstream сервера
 #[derive(Debug, Clone)]
 pub struct UpstreamState {
     pub name: String,
     pub url: String,
-    pub intents: Vec<Intent>,
+    pub intents: Vec<WeightedIntent>,
     pub weight: u32,
+    /// TLS параметры для `https://` upstream'а
+    pub tls: UpstreamTls,
+    /// HTTP/2 к upstream'у (gRPC)
+    pub http2: bool,
+    /// Ожидание ответа upstream'а
+    pub timeout: Option<Duration>,
+    /// Установка соединения (TCP + TLS)
+    pub connect_timeout: Option<Duration>,
+    /// Предел запросов в полете (None — без ограничения)
+    pub max_concurrency: Option<usize>,
+    /// Host запроса к upstream'у вместо Host клиента
+    pub override_host: Option<http::HeaderValue>,
     pub stats: Arc<RwLock<UpstreamStats>>,
+    /// Количество запросов в полете (общий счетчик для всех клонов)
+    in_flight: Arc<AtomicUsize>,
+    /// Drain: новые запросы не направляются, текущие завершаются
+    draining: Arc<AtomicBool>,
+    /// Момент, с которого upstream доступен для выбора (slow start)
+    eligible_since: Arc<RwLock<Instant>>,
+    /// Circuit breaker (None — выключен)
+    breaker: Option<Arc<CircuitBreaker>>,
 }
 
 impl UpstreamState {
     pub fn new(name: String, url: String, intents: Vec<Intent>, weight: u32) -> Self {
+        Self::with_weighted_intents(
+            name,
+            url,
+            intents.into_iter().map(WeightedIntent::from).collect(),
+            weight,
+        )
+    }
+
+    /// Создание с весами intent'ов
+    pub fn with_weighted_intents(
+        name: String,
+        url: String,
+        intents: Vec<WeightedIntent>,
+        weight: u32,
+    ) -> Self {
         Self {
             name,
             url,
             intents,
             weight,
+            tls: UpstreamTls::default(),
+            http2: false,
+            timeout: None,
+            connect_timeout: None,
+            max_concurrency: None,
+            override_host: None,
             stats: Arc::new(RwLock::new(UpstreamStats::new())),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            draining: Arc::new(AtomicBool::new(false)),
+            eligible_since: Arc::new(RwLock::new(Instant::now())),
+            breaker: None,
         }
     }
 
-    /// Вычисление intent match score (0.0 = полное совпадение, 1.0 = нет совпадений)
-    pub fn intent_gap(&self, request_intent: &Intent) -> f64 {
-        if self.intents.is_empty() {
-            return 0.0; // No preferences
+    /// Создание из конфигурации upstream'а
+    pub fn from_config(config: &UpstreamConfig, stats_config: &StatsConfig) -> Self {
+        let mut state = Self::with_weighted_intents(
+            config.name.clone(),
+            config.url.clone(),
+            config.intents(),
+            config.weight,
+        )
+        .with_stats_config(stats_config);
+        state.tls = UpstreamTls {
+            insecure_skip_verify: config.insecure_skip_verify,
+            ca_cert: config.ca_cert.clone(),
+        };
+        state.http2 = config.http2;
+        state.timeout = config.timeout_secs.map(Duration::from_secs);
+        state.connect_timeout = config.connect_timeout_ms.map(Duration::from_millis);
+        state.max_concurrency = config.max_concurrency;
+        state.override_host = config
+            .override_host
+            .as_deref()
+            .and_then(|host| http::HeaderValue::from_str(host).ok());
+        match &config.circuit_breaker {
+            Some(breaker) => state.with_circuit_breaker(breaker),
+            None => state,
         }
+    }
+
+    /// Включение circuit breaker'а
+    pub fn with_circuit_breaker(mut self, config: &CircuitBreakerConfig) -> Self {
+        self.breaker = Some(Arc::new(CircuitBreaker::new(config)));
+        self
+    }
+
+    /// Замена статистики на пустую с заданными параметрами
+    pub fn with_stats_config(mut self, stats_config: &StatsConfig) -> Self {
+        self.stats = Arc::new(RwLock::new(UpstreamStats::with_config(stats_config)));
+        self
+    }
 
-        for intent in &self.intents {
-            if intent.matches(request_intent) {
-                return 0.0; // Perfect match
+    /// Перенос runtime состояния (статистика, in-flight, drain, slow start)
+    /// с прежнего экземпляра того же upstream'а — при перезагрузке конфигурации.
+    ///
+    /// Сменившийся URL — другой backend: статистика, slow start и circuit
+    /// breaker начинаются заново, in-flight (запросы к прежнему адресу
+    /// дозавершаются) и drain переносятся.
+    pub fn inherit_runtime(mut self, previous: &UpstreamState) -> Self {
+        self.in_flight = previous.in_flight.clone();
+        self.draining = previous.draining.clone();
+        if self.url == previous.url {
+            self.stats = previous.stats.clone();
+            self.eligible_since = previous.eligible_since.clone();
+            // Состояние цепи — только при тех же параметрах breaker'а
+            if let (Some(current), Some(previous)) = (&self.breaker, &previous.breaker) {
+                if current.config() == previous.config() {
+                    self.breaker = Some(previous.clone());
+                }
             }
         }
+        self
+    }
+
+    /// Начало запроса к upstream (без допуска circuit breaker'а);
+    /// счетчик уменьшается при drop guard'а
+    pub fn begin_request(&self) -> InFlightGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard {
+            in_flight: self.in_flight.clone(),
+            breaker: self.breaker.clone(),
+            probe: None,
+            generation: None,
+        }
+    }
+
+    /// Начало запроса через circuit breaker: `None` — цепь разомкнута или
+    /// пробы полуоткрытой цепи заняты. Проба занята, пока жив guard; guard
+    /// без [`InFlightGuard::record_breaker`] учитывается как ошибка пробы
+    pub fn try_begin_request(&self) -> Option<InFlightGuard> {
+        let mut guard = self.begin_request();
+        match self.breaker.as_ref().map(|b| b.admit(Instant::now())) {
+            None => {}
+            Some(Admission::Pass { generation }) => guard.generation = Some(generation),
+            Some(Admission::Probe(probe)) => guard.probe = Some(probe),
+            Some(Admission::Reject) => return None,
+        }
+        Some(guard)
+    }
+
+    /// Пропускает ли circuit breaker запросы (без занятия пробы)
+    pub fn breaker_admits(&self) -> bool {
+        self.breaker
+            .as_ref()
+            .is_none_or(|breaker| breaker.admits(Instant::now()))
+    }
+
+    /// Состояние цепи и свободные пробы (None — breaker выключен)
+    pub fn breaker_state(&self) -> Option<(BreakerState, Option<u32>)> {
+        let now = Instant::now();
+        self.breaker
+            .as_ref()
+            .map(|breaker| (breaker.state(now), breaker.remaining_probes(now)))
+    }
+
+    /// Исход запроса для circuit breaker'а (`healthy` — без ошибки
+    /// соединения и 5xx)
+    pub fn record_breaker(&self, healthy: bool) {
+        if let Some(breaker) = &self.breaker {
+            breaker.record(healthy, Instant::now());
+        }
+    }
+
+    /// Текущее количество запросов в полете
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Достигнут ли предел запросов в полете (`max_concurrency`)
+    pub fn at_capacity(&self) -> bool {
+        self.max_concurrency
+            .is_some_and(|max| self.in_flight() >= max)
+    }
+
+    /// Включение/выключение drain режима
+    pub fn set_draining(&self, draining: bool) {
+        let was_draining = self.draining.swap(draining, Ordering::Relaxed);
+        // Выход из drain — upstream снова проходит slow start
+        if was_draining && !draining {
+            self.mark_eligible_at(Instant::now());
+        }
+    }
+
+    /// Находится ли upstream в drain режиме
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Начало slow start окна
+    pub(crate) fn mark_eligible_at(&self, since: Instant) {
+        *self.eligible_since.write() = since;
+    }
+
+    /// Доля трафика в slow start окне: линейно от
+    /// `SLOW_START_MIN_FACTOR` до 1.0
+    pub fn slow_start_factor(&self, window: Duration, now: Instant) -> f64 {
+        if window.is_zero() {
+            return 1.0;
+        }
+        let elapsed = now.saturating_duration_since(*self.eligible_since.read());
+        (elapsed.as_secs_f64() / window.as_secs_f64()).clamp(SLOW_START_MIN_FACTOR, 1.0)
+    }
+
+    /// EWMA латентности в миллисекундах (без клонирования статистики)
+    pub fn ewma_latency_ms(&self) -> f64 {
+        self.stats.read().ewma_latency_ms()
+    }
+
+    /// Затухание статистики upstream'а без недавних запросов
+    pub fn decay_idle_stats(&self, now: Instant) -> bool {
+        self.stats.write().decay_if_idle(now)
+    }
+
+    /// Текущий RPS (без клонирования статистики)
+    pub fn current_rps(&self) -> f64 {
+        self.stats.read().current_rps()
+    }
+
+    /// p99 латентности (мс) без клонирования статистики
+    pub fn p99_latency_ms(&self) -> f64 {
+        self.stats.read().p99_latency_ms()
+    }
+
+    /// Вычисление intent match score: `1.0 - affinity` лучшего совпадения
+    /// (0.0 = полное совпадение, 1.0 = нет совпадений)
+    pub fn intent_gap(&self, request_intent: &Intent) -> f64 {
+        if self.intents.is_empty() {
+            return 0.0; // No preferences
+        }
 
-        1.0 // No match
+        // Частичное (иерархическое) совпадение снижает affinity пропорционально
+        self.intents
+            .iter()
+            .map(|w| 1.0 - w.affinity * w.intent.match_score(request_intent))
+            .fold(1.0, f64::min)
     }
 
     /// Запись результата запроса
@@ -54,12 +276,51 @@ impl UpstreamState {
     }
 }
 
+/// RAII guard запроса в полете
+#[derive(Debug)]
+pub struct InFlightGuard {
+    in_flight: Arc<AtomicUsize>,
+    breaker: Option<Arc<CircuitBreaker>>,
+    /// Слот пробы полуоткрытого circuit breaker'а
+    probe: Option<Probe>,
+    /// Период цепи, в котором запрос допущен без пробы
+    generation: Option<u64>,
+}
+
+impl InFlightGuard {
+    /// Исход запроса для circuit breaker'а (`healthy` — без ошибки
+    /// соединения и 5xx); для пробы — ее исход
+    pub fn record_breaker(&mut self, healthy: bool) {
+        let now = Instant::now();
+        match (&mut self.probe, &self.breaker, self.generation) {
+            (Some(probe), ..) => probe.record(healthy, now),
+            (None, Some(breaker), Some(generation)) => {
+                breaker.record_pass(generation, healthy, now)
+            }
+            (None, Some(breaker), None) => breaker.record(healthy, now),
+            (None, None, _) => {}
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 /// Статистика upstream сервера
 #[derive(Debug, Clone)]
 pub struct UpstreamStats {
     /// Гистограмма латентности (в микросекундах)
     latency_hist: Histogram<u64>,
 
+    /// Начало накопления гистограммы (сбрасывается при затухании)
+    pub histogram_since: SystemTime,
+
+    /// Количество значений выше верхней границы гистограммы (записаны как максимум)
+    pub saturated_count: u64,
+
     /// Количество успешных запросов
     pub success_count: u64,
 
@@ -69,59 +330,172 @@ pub struct UpstreamStats {
     /// Время последнего обновления
     pub last_update: Instant,
 
+    /// Время последнего запроса (None — запросов не было)
+    pub last_request_at: Option<Instant>,
+
+    /// Простой, после которого статистика устаревает
+    stale_after: Duration,
+
     /// Скользящий RPS за последнюю минуту
-    rps_window: Vec<(Instant, bool)>,
+    rps_window: RpsWindow,
+
+    /// Ошибки за последнюю минуту (для скользящего error rate)
+    error_window: RpsWindow,
+
+    /// EWMA латентности (в микросекундах), None до первого запроса
+    ewma_latency_us: Option<f64>,
+
+    /// Коэффициент сглаживания EWMA
+    ewma_alpha: f64,
 }
 
 impl UpstreamStats {
     pub fn new() -> Self {
+        Self::with_config(&StatsConfig::default())
+    }
+
+    pub fn with_config(config: &StatsConfig) -> Self {
+        let latency_hist = Histogram::<u64>::new_with_bounds(
+            config.histogram_min_us,
+            config.histogram_max_us,
+            config.histogram_sigfigs,
+        )
+        .unwrap_or_else(|e| {
+            tracing::warn!("Invalid histogram bounds ({}), using defaults", e);
+            Histogram::<u64>::new_with_bounds(1, 60_000_000, 3).unwrap()
+        });
+
         Self {
-            latency_hist: Histogram::<u64>::new_with_bounds(1, 60_000_000, 3).unwrap(),
+            latency_hist,
+            histogram_since: SystemTime::now(),
+            saturated_count: 0,
             success_count: 0,
             error_count: 0,
             last_update: Instant::now(),
-            rps_window: Vec::with_capacity(10000),
+            last_request_at: None,
+            stale_after: Duration::from_secs(config.stale_after_secs.max(1)),
+            rps_window: RpsWindow::new(Instant::now()),
+            error_window: RpsWindow::new(Instant::now()),
+            ewma_latency_us: None,
+            ewma_alpha: config.ewma_alpha,
         }
     }
 
     /// Запись результата запроса
     pub fn record(&mut self, latency: Duration, success: bool) {
+        self.record_at(latency, success, Instant::now());
+    }
+
+    /// Запись результата запроса, завершившегося в `now`
+    pub fn record_at(&mut self, latency: Duration, success: bool, now: Instant) {
         let micros = latency.as_micros() as u64;
-        let _ = self.latency_hist.record(micros);
+        if micros > self.latency_hist.high() {
+            self.saturated_count += 1;
+        }
+        // Значения за верхней границей насыщаются, а не теряются
+        self.latency_hist.saturating_record(micros);
+
+        self.ewma_latency_us = Some(match self.ewma_latency_us {
+            Some(ewma) => self.ewma_alpha * micros as f64 + (1.0 - self.ewma_alpha) * ewma,
+            None => micros as f64,
+        });
 
         if success {
             self.success_count += 1;
         } else {
             self.error_count += 1;
+            self.error_window.record(now);
         }
 
-        let now = Instant::now();
         self.last_update = now;
+        self.last_request_at = Some(now);
+
+        self.rps_window.record(now);
+    }
+
+    /// Степень устаревания (0.0 - 1.0): 0 при простое до `stale_after`,
+    /// линейно до 1 при простое `2 * stale_after`
+    pub fn staleness_at(&self, now: Instant) -> f64 {
+        let Some(last) = self.last_request_at else {
+            return 0.0;
+        };
+        let idle = now.saturating_duration_since(last);
+        let over = idle.saturating_sub(self.stale_after);
+        (over.as_secs_f64() / self.stale_after.as_secs_f64()).min(1.0)
+    }
+
+    /// Степень устаревания на текущий момент
+    pub fn staleness(&self) -> f64 {
+        self.staleness_at(Instant::now())
+    }
+
+    /// Затухание статистики при простое: счетчики делятся пополам, после
+    /// обнуления сбрасываются гистограмма и EWMA. `true` — затухание было.
+    pub fn decay_if_idle(&mut self, now: Instant) -> bool {
+        let idle = match self.last_request_at {
+            Some(last) => now.saturating_duration_since(last),
+            None => return false,
+        };
+        if idle < self.stale_after {
+            return false;
+        }
 
-        // Обновление RPS window
-        self.rps_window.push((now, success));
+        self.success_count /= 2;
+        self.error_count /= 2;
+        if self.success_count == 0 && self.error_count == 0 {
+            self.latency_hist.reset();
+            self.histogram_since = SystemTime::now();
+            self.saturated_count = 0;
+            self.ewma_latency_us = None;
+        }
+        true
+    }
 
-        // Очистка старых записей (> 60 секунд)
-        let cutoff = now - Duration::from_secs(60);
-        self.rps_window.retain(|(ts, _)| *ts > cutoff);
+    /// Гистограмма латентности (мкс) в формате HdrHistogram V2 + deflate —
+    /// для слияния гистограмм нескольких инстансов
+    pub fn encode_histogram(&self) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        if let Err(e) = V2DeflateSerializer::new().serialize(&self.latency_hist, &mut encoded) {
+            tracing::warn!("Failed to encode latency histogram: {:?}", e);
+            encoded.clear();
+        }
+        encoded
+    }
+
+    /// Количество значений в гистограмме
+    pub fn histogram_samples(&self) -> u64 {
+        self.latency_hist.len()
     }
 
     /// P95 латентность в миллисекундах
     pub fn p95_latency_ms(&self) -> f64 {
-        if self.latency_hist.len() == 0 {
+        if self.latency_hist.is_empty() {
             return 0.0;
         }
         self.latency_hist.value_at_quantile(0.95) as f64 / 1000.0
     }
 
+    /// P99 латентность в миллисекундах
+    pub fn p99_latency_ms(&self) -> f64 {
+        if self.latency_hist.is_empty() {
+            return 0.0;
+        }
+        self.latency_hist.value_at_quantile(0.99) as f64 / 1000.0
+    }
+
     /// P50 (медиана) латентность в миллисекундах
     pub fn p50_latency_ms(&self) -> f64 {
-        if self.latency_hist.len() == 0 {
+        if self.latency_hist.is_empty() {
             return 0.0;
         }
         self.latency_hist.value_at_quantile(0.50) as f64 / 1000.0
     }
 
+    /// EWMA латентности в миллисекундах (0.0 без наблюдений)
+    pub fn ewma_latency_ms(&self) -> f64 {
+        self.ewma_latency_us.unwrap_or(0.0) / 1000.0
+    }
+
     /// Error rate (0.0 - 1.0)
     pub fn error_rate(&self) -> f64 {
         let total = self.success_count + self.error_count;
@@ -131,19 +505,25 @@ impl UpstreamStats {
         self.error_count as f64 / total as f64
     }
 
-    /// Текущий RPS за последние 60 секунд
-    pub fn current_rps(&self) -> f64 {
-        if self.rps_window.is_empty() {
+    /// Error rate за последние `RPS_WINDOW_SECS` секунд (0.0 - 1.0):
+    /// давние ошибки не влияют на выбор, в отличие от [`error_rate`](Self::error_rate)
+    /// за все время (для отчетов)
+    pub fn windowed_error_rate(&self) -> f64 {
+        self.windowed_error_rate_at(Instant::now())
+    }
+
+    /// Скользящий error rate на момент `now`
+    pub fn windowed_error_rate_at(&self, now: Instant) -> f64 {
+        let total = self.rps_window.count_at(now);
+        if total == 0 {
             return 0.0;
         }
+        self.error_window.count_at(now) as f64 / total as f64
+    }
 
-        let now = Instant::now();
-        let window_start = now - Duration::from_secs(60);
-        let count = self.rps_window.iter()
-            .filter(|(ts, _)| *ts > window_start)
-            .count();
-
-        count as f64 / 60.0
+    /// Текущий RPS за последние 60 секунд
+    pub fn current_rps(&self) -> f64 {
+        self.rps_window.count_at(Instant::now()) as f64 / RPS_WINDOW_SECS as f64
     }
 
     /// Нормализованная глубина очереди (для будущей реализации)
@@ -154,20 +534,15 @@ impl UpstreamStats {
 
     /// Spikiness — вариативность RPS
     pub fn tempo_spikiness(&self) -> f64 {
-        if self.rps_window.len() < 10 {
+        let now = Instant::now();
+        if self.rps_window.count_at(now) < 10 {
             return 0.0;
         }
 
         // Простая метрика: стандартное отклонение RPS по 10-секундным бинам
-        let now = Instant::now();
-        let mut bins = vec![0u32; 6]; // 6 бинов по 10 секунд
-
-        for (ts, _) in &self.rps_window {
-            let age = now.duration_since(*ts).as_secs();
-            if age < 60 {
-                let bin = (age / 10) as usize;
-                bins[bin] += 1;
-            }
+        let mut bins = [0u32; 6]; // 6 бинов по 10 секунд
+        for (age, count) in self.rps_window.ages_at(now) {
+            bins[age / 10] += count;
         }
 
         let mean = bins.iter().sum::<u32>() as f64 / 6.0;
@@ -175,18 +550,80 @@ impl UpstreamStats {
             return 0.0;
         }
 
-        let variance = bins.iter()
+        let variance = bins
+            .iter()
             .map(|&x| {
                 let diff = x as f64 - mean;
                 diff * diff
             })
-            .sum::<f64>() / 6.0;
+            .sum::<f64>()
+            / 6.0;
 
         let std_dev = variance.sqrt();
         std_dev / mean.max(1.0) // Coefficient of variation
     }
 }
 
+/// Размер окна RPS (секунд)
+pub const RPS_WINDOW_SECS: usize = 60;
+
+/// Счетчик запросов по секундам в кольце: запись O(1), память фиксирована.
+/// Точность — до секунды на границе окна.
+#[derive(Debug, Clone)]
+struct RpsWindow {
+    origin: Instant,
+    buckets: [u32; RPS_WINDOW_SECS],
+    /// Секунда (от `origin`) последней записи
+    head: u64,
+}
+
+impl RpsWindow {
+    fn new(origin: Instant) -> Self {
+        Self {
+            origin,
+            buckets: [0; RPS_WINDOW_SECS],
+            head: 0,
+        }
+    }
+
+    fn second(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.origin).as_secs()
+    }
+
+    fn record(&mut self, at: Instant) {
+        let second = self.second(at).max(self.head);
+        // Бакеты пропущенных секунд обнуляются
+        let skipped = (second - self.head).min(RPS_WINDOW_SECS as u64);
+        for offset in 1..=skipped {
+            self.buckets[((self.head + offset) % RPS_WINDOW_SECS as u64) as usize] = 0;
+        }
+        self.head = second;
+        self.buckets[(second % RPS_WINDOW_SECS as u64) as usize] += 1;
+    }
+
+    /// Непустые секунды окна на момент `now`: (возраст в секундах, запросов)
+    fn ages_at(&self, now: Instant) -> impl Iterator<Item = (usize, u32)> + '_ {
+        let now = self.second(now).max(self.head);
+        (0..RPS_WINDOW_SECS as u64)
+            .filter_map(move |age| now.checked_sub(age).map(|second| (age, second)))
+            .filter(|&(_, second)| {
+                second <= self.head && self.head - second < RPS_WINDOW_SECS as u64
+            })
+            .map(|(age, second)| {
+                (
+                    age as usize,
+                    self.buckets[(second % RPS_WINDOW_SECS as u64) as usize],
+                )
+            })
+            .filter(|&(_, count)| count > 0)
+    }
+
+    /// Запросов за последние `RPS_WINDOW_SECS` секунд
+    fn count_at(&self, now: Instant) -> u64 {
+        self.ages_at(now).map(|(_, count)| u64::from(count)).sum()
+    }
+}
+
 impl Default for UpstreamStats {
     fn default() -> Self {
         Self::new()
@@ -211,6 +648,154 @@ mod tests {
         assert!(stats.error_rate() > 0.0 && stats.error_rate() < 1.0);
     }
 
+    #[test]
+    fn test_rps_window_matches_exact_count() {
+        let origin = Instant::now();
+        let mut window = RpsWindow::new(origin);
+        let mut exact: Vec<Instant> = Vec::new();
+
+        let check = |window: &RpsWindow, exact: &[Instant], seconds: u64| {
+            let now = origin + Duration::from_secs(seconds);
+            let cutoff = now - Duration::from_secs(60);
+            let expected = exact
+                .iter()
+                .filter(|at| **at > cutoff && **at <= now)
+                .count() as u64;
+            let counted = window.count_at(now);
+            // Расхождение — не больше одной секунды трафика на границе окна
+            let per_second = 1000 / 7 + 1;
+            assert!(
+                counted.abs_diff(expected) <= per_second,
+                "at {}s: {} vs exact {}",
+                seconds,
+                counted,
+                expected
+            );
+        };
+
+        // Синтетическая нагрузка: 90 секунд с меняющимся темпом
+        for ms in (0..90_000u64).step_by(7) {
+            if ms % 15_000 < 7 && ms > 0 {
+                check(&window, &exact, ms / 1000);
+            }
+            let rate = if (ms / 10_000) % 2 == 0 { 3 } else { 1 };
+            if (ms / 7) % rate == 0 {
+                let at = origin + Duration::from_millis(ms);
+                window.record(at);
+                exact.push(at);
+            }
+        }
+        for seconds in [90, 100, 120, 149] {
+            check(&window, &exact, seconds);
+        }
+        assert_eq!(window.count_at(origin + Duration::from_secs(200)), 0);
+    }
+
+    #[test]
+    fn test_windowed_error_rate_recovers() {
+        let mut stats = UpstreamStats::new();
+        let start = Instant::now();
+        for _ in 0..10 {
+            stats.record_at(Duration::from_millis(5), false, start);
+        }
+        assert_eq!(stats.windowed_error_rate_at(start), 1.0);
+
+        // Минуту спустя старые ошибки вышли из окна, а за все время — остались
+        let later = start + Duration::from_secs(RPS_WINDOW_SECS as u64 + 1);
+        for _ in 0..10 {
+            stats.record_at(Duration::from_millis(5), true, later);
+        }
+        assert_eq!(stats.windowed_error_rate_at(later), 0.0);
+        assert_eq!(stats.error_rate(), 0.5);
+
+        stats.record_at(Duration::from_millis(5), false, later);
+        assert!((stats.windowed_error_rate_at(later) - 1.0 / 11.0).abs() < 1e-9);
+        assert_eq!(
+            stats.windowed_error_rate_at(later + Duration::from_secs(120)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_ewma_latency() {
+        let mut stats = UpstreamStats::with_config(&StatsConfig {
+            ewma_alpha: 0.5,
+            ..Default::default()
+        });
+        assert_eq!(stats.ewma_latency_ms(), 0.0);
+
+        stats.record(Duration::from_millis(10), true);
+        assert!((stats.ewma_latency_ms() - 10.0).abs() < 1e-9);
+
+        stats.record(Duration::from_millis(30), true);
+        assert!((stats.ewma_latency_ms() - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_histogram_saturation() {
+        let mut stats = UpstreamStats::with_config(&StatsConfig {
+            histogram_min_us: 1,
+            histogram_max_us: 1_000_000, // 1s
+            histogram_sigfigs: 2,
+            ..Default::default()
+        });
+
+        stats.record(Duration::from_millis(10), true);
+        stats.record(Duration::from_secs(5), true);
+
+        assert_eq!(stats.saturated_count, 1);
+        assert_eq!(stats.success_count, 2);
+        // Насыщенное значение учитывается в хвосте как верхняя граница
+        let p95 = stats.p95_latency_ms();
+        assert!((990.0..=1010.0).contains(&p95), "p95 = {}", p95);
+    }
+
+    #[test]
+    fn test_encoded_histogram_roundtrip() {
+        let mut stats = UpstreamStats::new();
+        for ms in 1..=100 {
+            stats.record(Duration::from_millis(ms), true);
+        }
+
+        let encoded = stats.encode_histogram();
+        let decoded: Histogram<u64> = hdrhistogram::serialization::Deserializer::new()
+            .deserialize(&mut encoded.as_slice())
+            .unwrap();
+        assert_eq!(decoded.len(), stats.histogram_samples());
+        assert_eq!(decoded.len(), 100);
+        for quantile in [0.5, 0.95, 0.99] {
+            assert_eq!(
+                decoded.value_at_quantile(quantile),
+                stats.latency_hist.value_at_quantile(quantile)
+            );
+        }
+
+        // Слияние гистограмм двух инстансов
+        let mut merged = decoded.clone();
+        merged.add(&decoded).unwrap();
+        assert_eq!(merged.len(), 200);
+        assert_eq!(
+            merged.value_at_quantile(0.99),
+            decoded.value_at_quantile(0.99)
+        );
+    }
+
+    #[test]
+    fn test_in_flight_guard() {
+        let upstream = UpstreamState::new(
+            "test".to_string(),
+            "http://localhost:8080".to_string(),
+            vec![],
+            1,
+        );
+        let clone = upstream.clone();
+
+        let guard = upstream.begin_request();
+        assert_eq!(clone.in_flight(), 1);
+        drop(guard);
+        assert_eq!(clone.in_flight(), 0);
+    }
+
     #[test]
     fn test_intent_gap() {
         let upstream = UpstreamState::new(
@@ -226,4 +811,108 @@ mod tests {
         assert_eq!(upstream.intent_gap(&realtime_intent), 0.0);
         assert_eq!(upstream.intent_gap(&batch_intent), 1.0);
     }
+
+    #[test]
+    fn test_hierarchical_intent_gap() {
+        let upstream = UpstreamState::new(
+            "test".to_string(),
+            "http://localhost:8080".to_string(),
+            vec![Intent::new("realtime")],
+            1,
+        );
+
+        assert_eq!(
+            Intent::new("realtime").match_score(&Intent::new("realtime")),
+            1.0
+        );
+        assert_eq!(
+            Intent::new("realtime").match_score(&Intent::new("realtime.chat")),
+            0.5
+        );
+        assert_eq!(
+            Intent::new("realtime").match_score(&Intent::new("realtimechat")),
+            0.0
+        );
+        assert_eq!(
+            Intent::new("realtime.chat").match_score(&Intent::new("realtime.voice")),
+            0.0
+        );
+
+        assert_eq!(upstream.intent_gap(&Intent::new("realtime")), 0.0);
+        assert_eq!(upstream.intent_gap(&Intent::new("realtime.chat")), 0.5);
+        assert_eq!(
+            upstream.intent_gap(&Intent::new("realtime.chat.voice")),
+            1.0 - 1.0 / 3.0
+        );
+        assert_eq!(upstream.intent_gap(&Intent::new("batch")), 1.0);
+    }
+
+    #[test]
+    fn test_weighted_intent_gap() {
+        let config: UpstreamConfig = toml::from_str(
+            r#"
+            name = "mixed"
+            url = "http://localhost:8080"
+            intent = ["realtime:0.9", "batch:0.25", "realtime:0.5", "analytics"]
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let upstream = UpstreamState::from_config(&config, &StatsConfig::default());
+
+        // Лучшее совпадение среди нескольких записей одного intent'а
+        assert!((upstream.intent_gap(&Intent::new("realtime")) - 0.1).abs() < 1e-9);
+        assert!((upstream.intent_gap(&Intent::new("batch")) - 0.75).abs() < 1e-9);
+        // Без веса — полное совпадение
+        assert_eq!(upstream.intent_gap(&Intent::new("analytics")), 0.0);
+        assert_eq!(upstream.intent_gap(&Intent::new("stream")), 1.0);
+
+        // Без предпочтений — любой intent подходит
+        let any = UpstreamState::new("any".to_string(), "http://localhost".to_string(), vec![], 1);
+        assert_eq!(any.intent_gap(&Intent::new("realtime")), 0.0);
+
+        let invalid: UpstreamConfig = toml::from_str(
+            r#"
+            name = "bad"
+            url = "http://localhost:8080"
+            intent = ["realtime:1.5"]
+            "#,
+        )
+        .unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_stats_staleness_after_inactivity() {
+        let mut stats = UpstreamStats::with_config(&StatsConfig {
+            stale_after_secs: 60,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert_eq!(stats.staleness_at(now), 0.0);
+        assert!(!stats.decay_if_idle(now));
+
+        for _ in 0..8 {
+            stats.record(Duration::from_millis(10), true);
+        }
+        stats.record(Duration::from_millis(10), false);
+        let last = stats.last_request_at.unwrap();
+
+        // Недавняя статистика актуальна
+        assert_eq!(stats.staleness_at(last + Duration::from_secs(30)), 0.0);
+        assert!(!stats.decay_if_idle(last + Duration::from_secs(30)));
+
+        // Простой: устаревание растет, счетчики затухают
+        let idle = last + Duration::from_secs(90);
+        assert!((stats.staleness_at(idle) - 0.5).abs() < 1e-6);
+        assert_eq!(stats.staleness_at(last + Duration::from_secs(600)), 1.0);
+
+        assert!(stats.decay_if_idle(idle));
+        assert_eq!((stats.success_count, stats.error_count), (4, 0));
+        while stats.success_count > 0 {
+            stats.decay_if_idle(idle);
+        }
+        assert_eq!(stats.p95_latency_ms(), 0.0);
+        assert_eq!(stats.ewma_latency_ms(), 0.0);
+    }
 }
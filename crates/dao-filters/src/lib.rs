@@ -3,13 +3,12 @@
 //! Модуль для загрузки и выполнения WASM фильтров
 
 use wasmtime::*;
-use wasmtime_wasi::WasiCtxBuilder;
 
-pub mod runtime;
 pub mod abi;
+pub mod runtime;
 
-pub use runtime::WasmRuntime;
 pub use abi::FilterABI;
+pub use runtime::WasmRuntime;
 
 /// WASM фильтр
 pub struct WasmFilter {
@@ -26,6 +25,16 @@ impl WasmFilter {
         Ok(Self { engine, module })
     }
 
+    /// Engine, в котором скомпилирован модуль
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Скомпилированный WASM модуль
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
     /// Создание instance фильтра
     pub fn instantiate(&self) -> anyhow::Result<WasmFilterInstance> {
         // TODO: полная интеграция с WASM runtime
@@ -53,8 +62,7 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_wasm_filter_placeholder() {
-        // Placeholder test
-        assert!(true);
+    fn test_wasm_filter_missing_file() {
+        assert!(WasmFilter::from_file("does-not-exist.wasm").is_err());
     }
 }
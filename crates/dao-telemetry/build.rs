@@ -0,0 +1,35 @@
+//! Сведения о сборке для `dao_build_info`: версия rustc и git SHA
+
+use std::process::Command;
+
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output(Command::new(rustc).arg("--version"))
+        .and_then(|version| version.split_whitespace().nth(1).map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // Явный DAO_GIT_SHA (сборка из архива, CI) приоритетнее git
+    println!("cargo:rerun-if-env-changed=DAO_GIT_SHA");
+    let git_sha = std::env::var("DAO_GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| output(Command::new("git").args(["rev-parse", "--short=12", "HEAD"])))
+        .unwrap_or_else(|| "unknown".to_string());
+    if let Some(git_dir) = output(Command::new("git").args(["rev-parse", "--absolute-git-dir"])) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/refs", git_dir);
+    }
+
+    println!("cargo:rustc-env=DAO_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=DAO_GIT_SHA={}", git_sha);
+}
+
+/// Первая строка вывода успешной команды
+fn output(command: &mut Command) -> Option<String> {
+    let output = command
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    stdout.lines().next().map(|line| line.trim().to_string())
+}
@@ -2,15 +2,17 @@
 //!
 //! Prometheus metrics exporter и tracing для DAO
 
+use dao_core::config::TelemetryConfig;
 use metrics_exporter_prometheus::PrometheusBuilder;
 use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 pub mod exporter;
 pub mod metrics;
 
 pub use exporter::MetricsExporter;
-pub use metrics::{DaoMetrics, MetricsCollector};
+pub use metrics::{DaoMetrics, MetricsCollector, RequestCounts};
 
 /// Инициализация телеметрии
 pub fn init_telemetry() -> anyhow::Result<()> {
@@ -36,9 +38,80 @@ pub async fn start_prometheus_exporter(bind_addr: SocketAddr) -> anyhow::Result<
     Ok(())
 }
 
-/// Регистрация метрик DAO
+/// Попытки занять адрес exporter'а
+pub const EXPORTER_BIND_ATTEMPTS: u32 = 4;
+/// Пауза перед второй попыткой, дальше удваивается
+const EXPORTER_BIND_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Запуск exporter'а из `[telemetry]` с повторами bind.
+///
+/// Ошибка возвращается, только если `fail_on_bind_error`; иначе DAO
+/// продолжает работу без exporter'а.
+pub async fn start_telemetry_exporter(config: &TelemetryConfig) -> anyhow::Result<()> {
+    let bind_addr: SocketAddr = config.prometheus_bind.parse().map_err(|e| {
+        anyhow::anyhow!(
+            "Invalid prometheus_bind {:?}: {}",
+            config.prometheus_bind,
+            e
+        )
+    })?;
+
+    let mut backoff = EXPORTER_BIND_BACKOFF;
+    let mut attempt = 1;
+    let error = loop {
+        match start_prometheus_exporter(bind_addr).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= EXPORTER_BIND_ATTEMPTS => break e,
+            Err(e) => {
+                tracing::warn!(
+                    "Prometheus exporter attempt {}/{} failed, retrying in {:?}: {}",
+                    attempt,
+                    EXPORTER_BIND_ATTEMPTS,
+                    backoff,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    };
+
+    if config.fail_on_bind_error {
+        return Err(anyhow::anyhow!(
+            "Prometheus exporter could not bind {} after {} attempts: {}",
+            bind_addr,
+            EXPORTER_BIND_ATTEMPTS,
+            error
+        ));
+    }
+    tracing::error!("Running without Prometheus exporter: {}", error);
+    Ok(())
+}
+
+/// Версия rustc, которым собран DAO
+pub const RUSTC_VERSION: &str = env!("DAO_RUSTC_VERSION");
+
+/// Git SHA сборки (`unknown` вне git-репозитория)
+pub const GIT_SHA: &str = env!("DAO_GIT_SHA");
+
+/// Регистрация метрик DAO: `dao_build_info{version, rustc, git_sha} 1` и
+/// `dao_start_time_seconds`. Вызывается после установки exporter'а —
+/// значения, заданные до нее, теряются.
 pub fn register_dao_metrics() {
-    // Metrics будут автоматически регистрироваться при первом использовании
+    // Остальные метрики регистрируются при первом использовании
+    ::metrics::gauge!(
+        "dao_build_info",
+        "version" => dao_core::DAO_VERSION,
+        "rustc" => RUSTC_VERSION,
+        "git_sha" => GIT_SHA
+    )
+    .set(1.0);
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    ::metrics::gauge!("dao_start_time_seconds").set(started);
     tracing::debug!("DAO metrics registered");
 }
 
@@ -48,6 +121,47 @@ mod tests {
 
     #[test]
     fn test_metrics_registration() {
-        register_dao_metrics();
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        ::metrics::with_local_recorder(&recorder, register_dao_metrics);
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let (info, _, _, value) = snapshot
+            .iter()
+            .find(|(key, ..)| key.key().name() == "dao_build_info")
+            .expect("dao_build_info registered");
+        assert_eq!(*value, DebugValue::Gauge(1.0.into()));
+        let label = |name: &str| {
+            info.key()
+                .labels()
+                .find(|label| label.key() == name)
+                .map(|label| label.value().to_string())
+        };
+        assert_eq!(label("version").as_deref(), Some(dao_core::DAO_VERSION));
+        assert_eq!(label("rustc").as_deref(), Some(RUSTC_VERSION));
+        assert_eq!(label("git_sha").as_deref(), Some(GIT_SHA));
+        assert!(!RUSTC_VERSION.is_empty() && !GIT_SHA.is_empty());
+
+        let started = snapshot
+            .iter()
+            .find(|(key, ..)| key.key().name() == "dao_start_time_seconds")
+            .map(|(.., value)| value);
+        assert!(matches!(started, Some(DebugValue::Gauge(t)) if t.into_inner() > 0.0));
+    }
+
+    #[tokio::test]
+    async fn test_exporter_bind_error_is_fatal_when_configured() {
+        let occupied = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = TelemetryConfig {
+            prometheus_bind: occupied.local_addr().unwrap().to_string(),
+            fail_on_bind_error: true,
+        };
+        let error = start_telemetry_exporter(&config).await.unwrap_err();
+        assert!(error.to_string().contains("after 4 attempts"), "{}", error);
+
+        config.fail_on_bind_error = false;
+        assert!(start_telemetry_exporter(&config).await.is_ok());
     }
 }
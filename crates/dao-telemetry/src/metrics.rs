@@ -1,7 +1,13 @@
 //! DAO metrics collection
 
-use std::sync::Arc;
 use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Предел ключей в разбивке по маршрутам/upstream'ам; сверх него счет
+/// идет в [`OTHER_LABEL`]. Ключи — имена из конфигурации, не пути запросов.
+pub const MAX_BREAKDOWN_KEYS: usize = 1024;
+pub const OTHER_LABEL: &str = "_other";
 
 /// Коллектор метрик DAO
 #[derive(Clone)]
@@ -20,6 +26,11 @@ impl MetricsCollector {
     pub fn record_request(&self, route: &str, upstream: &str, duration_secs: f64, status: u16) {
         let mut m = self.metrics.write();
         m.total_requests += 1;
+        let route_counts = breakdown_entry(&mut m.routes, route);
+        route_counts.requests += 1;
+        if status >= 500 {
+            route_counts.errors += 1;
+        }
 
         // Prometheus metrics
         metrics::counter!("dao_requests_total", "route" => route.to_string()).increment(1);
@@ -38,6 +49,85 @@ impl MetricsCollector {
         .increment(1);
     }
 
+    /// Исход запроса к upstream'у (ошибка соединения, 5xx, gRPC-статус)
+    pub fn record_upstream_result(&self, upstream: &str, success: bool) {
+        let mut m = self.metrics.write();
+        let counts = breakdown_entry(&mut m.upstreams, upstream);
+        counts.requests += 1;
+        if !success {
+            counts.errors += 1;
+        }
+
+        metrics::counter!(
+            "dao_upstream_results_total",
+            "upstream" => upstream.to_string(),
+            "result" => if success { "success" } else { "error" }
+        )
+        .increment(1);
+    }
+
+    /// Назначение запроса в плечо A/B эксперимента маршрута
+    pub fn record_ab_assignment(&self, route: &str, arm: &str) {
+        metrics::counter!(
+            "dao_ab_assignment_total",
+            "route" => route.to_string(),
+            "arm" => arm.to_string()
+        )
+        .increment(1);
+    }
+
+    /// Режим observe: upstream, который выбрала бы политика маршрута
+    pub fn record_would_select(&self, route: &str, upstream: &str) {
+        metrics::counter!(
+            "dao_would_select",
+            "route" => route.to_string(),
+            "upstream" => upstream.to_string()
+        )
+        .increment(1);
+    }
+
+    /// Сбой upstream'а: вид ошибки (`timeout`, `tls`, ...) или ответ 5xx
+    pub fn record_upstream_error(&self, upstream: &str, kind: &str) {
+        metrics::counter!(
+            "dao_upstream_errors_total",
+            "upstream" => upstream.to_string(),
+            "kind" => kind.to_string()
+        )
+        .increment(1);
+    }
+
+    /// Размер тела запроса (байт, по факту переданного)
+    pub fn record_request_body_bytes(&self, route: &str, bytes: u64) {
+        self.metrics.write().request_body_bytes += bytes;
+        metrics::histogram!("dao_request_body_bytes", "route" => route.to_string())
+            .record(bytes as f64);
+    }
+
+    /// Размер тела ответа (байт, по факту переданного)
+    pub fn record_response_body_bytes(&self, route: &str, bytes: u64) {
+        self.metrics.write().response_body_bytes += bytes;
+        metrics::histogram!("dao_response_body_bytes", "route" => route.to_string())
+            .record(bytes as f64);
+    }
+
+    /// Счетчик байт от клиента (тела запросов, туннели) для маршрута и upstream'а
+    pub fn bytes_in_counter(&self, route: &str, upstream: &str) -> metrics::Counter {
+        metrics::counter!(
+            "dao_bytes_in_total",
+            "route" => route.to_string(),
+            "upstream" => upstream.to_string()
+        )
+    }
+
+    /// Счетчик байт клиенту (тела ответов, туннели) для маршрута и upstream'а
+    pub fn bytes_out_counter(&self, route: &str, upstream: &str) -> metrics::Counter {
+        metrics::counter!(
+            "dao_bytes_out_total",
+            "route" => route.to_string(),
+            "upstream" => upstream.to_string()
+        )
+    }
+
     /// Обновление счетчика активных соединений
     pub fn set_active_connections(&self, count: u64) {
         let mut m = self.metrics.write();
@@ -57,10 +147,134 @@ impl Default for MetricsCollector {
     }
 }
 
+/// Запись разбивки по имени с ограничением числа ключей
+fn breakdown_entry<'a>(
+    map: &'a mut HashMap<String, RequestCounts>,
+    name: &str,
+) -> &'a mut RequestCounts {
+    let key = if map.contains_key(name) || map.len() < MAX_BREAKDOWN_KEYS {
+        name
+    } else {
+        OTHER_LABEL
+    };
+    map.entry(key.to_string()).or_default()
+}
+
+/// Счетчики запросов и ошибок
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestCounts {
+    pub requests: u64,
+    pub errors: u64,
+}
+
 /// Метрики DAO
 #[derive(Debug, Clone, Default)]
 pub struct DaoMetrics {
     pub total_requests: u64,
     pub total_errors: u64,
     pub active_connections: u64,
+    /// Сумма размеров тел запросов
+    pub request_body_bytes: u64,
+    /// Сумма размеров тел ответов
+    pub response_body_bytes: u64,
+    /// По маршрутам: ответы клиентам (ошибки — 5xx)
+    pub routes: HashMap<String, RequestCounts>,
+    /// По upstream'ам: исходы запросов к ним
+    pub upstreams: HashMap<String, RequestCounts>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    #[test]
+    fn test_body_bytes_histograms() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let collector = MetricsCollector::new();
+
+        metrics::with_local_recorder(&recorder, || {
+            collector.record_response_body_bytes("api", 1024);
+            collector.record_response_body_bytes("api", 16);
+            collector.record_request_body_bytes("api", 5);
+        });
+
+        // Снимок забирает накопленные значения гистограмм — один на тест
+        let snapshot = snapshotter.snapshot().into_vec();
+        let histogram = |name: &str| {
+            snapshot
+                .iter()
+                .find(|(key, ..)| key.key().name() == name)
+                .map(|(.., value)| match value {
+                    DebugValue::Histogram(values) => {
+                        values.iter().map(|v| v.into_inner()).collect::<Vec<_>>()
+                    }
+                    other => panic!("unexpected metric value: {:?}", other),
+                })
+        };
+        assert_eq!(
+            histogram("dao_response_body_bytes"),
+            Some(vec![1024.0, 16.0])
+        );
+        assert_eq!(histogram("dao_request_body_bytes"), Some(vec![5.0]));
+
+        let totals = collector.get_metrics();
+        assert_eq!(totals.response_body_bytes, 1040);
+        assert_eq!(totals.request_body_bytes, 5);
+    }
+
+    #[test]
+    fn test_per_route_and_upstream_counts() {
+        let collector = MetricsCollector::new();
+        collector.record_request("api", "backend-1", 0.01, 200);
+        collector.record_request("api", "backend-2", 0.02, 502);
+        collector.record_request("web", "backend-1", 0.01, 404);
+        collector.record_upstream_result("backend-1", true);
+        collector.record_upstream_result("backend-2", false);
+        collector.record_upstream_result("backend-2", true);
+
+        let metrics = collector.get_metrics();
+        assert_eq!(metrics.total_requests, 3);
+        assert_eq!(metrics.total_errors, 1);
+        assert_eq!(
+            metrics.routes["api"],
+            RequestCounts {
+                requests: 2,
+                errors: 1
+            }
+        );
+        assert_eq!(
+            metrics.routes["web"],
+            RequestCounts {
+                requests: 1,
+                errors: 0
+            }
+        );
+        assert_eq!(
+            metrics.upstreams["backend-1"],
+            RequestCounts {
+                requests: 1,
+                errors: 0
+            }
+        );
+        assert_eq!(
+            metrics.upstreams["backend-2"],
+            RequestCounts {
+                requests: 2,
+                errors: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_breakdown_keys_bounded() {
+        let collector = MetricsCollector::new();
+        for i in 0..MAX_BREAKDOWN_KEYS + 10 {
+            collector.record_upstream_result(&format!("upstream-{}", i), true);
+        }
+        let metrics = collector.get_metrics();
+        assert_eq!(metrics.upstreams.len(), MAX_BREAKDOWN_KEYS + 1);
+        assert_eq!(metrics.upstreams[OTHER_LABEL].requests, 10);
+    }
 }
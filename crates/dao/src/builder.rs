@@ -0,0 +1,327 @@
+//! Сборка DAO из конфигурации в коде — для встраивания в другой бинарник
+
+use crate::server::DaoServer;
+use dao_core::{
+    align::{Align, PolicyWeights, SelectionStrategy},
+    config::{DaoConfig, UpstreamPool},
+    gate::{Gate, GateConfig, ListenerConfig, TcpOptions, TlsConfig},
+    memory::Memory,
+    sense::{LatencyNormalization, MetricsFeed, Sense, METRICS_FEED_PERIOD},
+    upstream::{ConnectionPool, UpstreamClient, UpstreamRegistry},
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Сборщик DAO сервера: конфигурация, политики и клиенты upstream'ов
+pub struct DaoServerBuilder {
+    config: DaoConfig,
+    policies: Vec<(String, PolicyWeights)>,
+    strategies: Vec<(String, Box<dyn SelectionStrategy>)>,
+    clients: Vec<(String, UpstreamClient)>,
+}
+
+impl DaoServerBuilder {
+    pub fn new(config: DaoConfig) -> Self {
+        Self {
+            config,
+            policies: Vec::new(),
+            strategies: Vec::new(),
+            clients: Vec::new(),
+        }
+    }
+
+    /// Политика в дополнение к `[policies]` конфигурации (одноименную заменяет)
+    pub fn policy(mut self, name: impl Into<String>, weights: PolicyWeights) -> Self {
+        self.policies.push((name.into(), weights));
+        self
+    }
+
+    /// Своя стратегия выбора upstream'а под именем политики маршрута
+    pub fn strategy(
+        mut self,
+        name: impl Into<String>,
+        strategy: impl SelectionStrategy + 'static,
+    ) -> Self {
+        self.strategies.push((name.into(), Box::new(strategy)));
+        self
+    }
+
+    /// Свой клиент для upstream'а с данным URL
+    pub fn upstream_client(
+        mut self,
+        upstream_url: impl Into<String>,
+        client: UpstreamClient,
+    ) -> Self {
+        self.clients.push((upstream_url.into(), client));
+        self
+    }
+
+    /// Валидация конфигурации, bind listener'ов и запуск в фоне
+    pub async fn start(self) -> anyhow::Result<DaoHandle> {
+        let config = self.config;
+        config.validate()?;
+
+        let memory = Arc::new(Memory::new(config.clone()));
+        let upstreams = Arc::new(UpstreamRegistry::from_config(&config));
+
+        // Sense — телеметрия
+        let sense = Sense::new(upstreams.clone());
+        let stats_decay = tokio::spawn(
+            sense
+                .clone()
+                .run_stats_decay(Duration::from_secs(config.stats.stale_after_secs)),
+        );
+        // Снимки метрик для SSE подписчиков admin API
+        let metrics_feed = MetricsFeed::new();
+        let metrics_feed_task = tokio::spawn(
+            sense
+                .clone()
+                .run_metrics_feed(METRICS_FEED_PERIOD, metrics_feed.clone()),
+        );
+
+        // Align — политики из конфигурации, затем заданные в коде
+        let mut align = Align::new(sense.clone());
+        for (name, policy_cfg) in config.policies.iter().flatten() {
+            let weights =
+                PolicyWeights::new(policy_cfg.w_load, policy_cfg.w_intent, policy_cfg.w_tempo)
+                    .with_epsilon(policy_cfg.epsilon)
+                    .with_p99_budget(policy_cfg.p99_budget_ms)
+                    .with_strict_intent(policy_cfg.strict_intent)
+                    .with_normalize(policy_cfg.normalize)
+                    .with_latency(LatencyNormalization {
+                        scale: policy_cfg.latency_scale,
+                        reference_ms: policy_cfg.latency_reference_ms,
+                        cap: policy_cfg.latency_cap,
+                    });
+            align.register_policy(name.clone(), weights);
+        }
+        for (name, weights) in self.policies {
+            align.register_policy(name, weights);
+        }
+        for (name, strategy) in self.strategies {
+            align.register_strategy(name, strategy);
+        }
+        align.set_memory((*memory).clone());
+        align.set_slow_start(config.server.slow_start_secs.map(Duration::from_secs));
+        let align = Arc::new(align);
+
+        // Gate — прием соединений
+        let gate = Gate::new(gate_config(&config)).await?;
+        let local_addrs = gate.local_addrs()?;
+        for local_addr in &local_addrs {
+            info!("DAO listening on: {}", local_addr);
+        }
+        // Обновленные сертификаты подхватываются без рестарта
+        dao_admin::start_tls_watch(gate.listeners().to_vec())?;
+
+        let pool = ConnectionPool::with_tcp_options(TcpOptions::from_config(&config.server))
+            .with_per_upstream_clients(config.server.upstream_pool == UpstreamPool::PerUpstream);
+        for (upstream_url, client) in self.clients {
+            pool.insert_client(upstream_url, client);
+        }
+
+        let server = DaoServer::new(
+            gate,
+            sense,
+            align.clone(),
+            memory.clone(),
+            upstreams.clone(),
+            pool,
+        );
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(server.run_until(shutdown.clone().cancelled_owned()));
+
+        Ok(DaoHandle {
+            local_addrs,
+            memory,
+            upstreams,
+            align,
+            shutdown,
+            task,
+            stats_decay,
+            metrics_feed,
+            metrics_feed_task,
+        })
+    }
+}
+
+/// Listener'ы из `[server]`
+fn gate_config(config: &DaoConfig) -> GateConfig {
+    GateConfig {
+        listeners: config
+            .server
+            .listeners()
+            .into_iter()
+            .map(|listen| ListenerConfig {
+                tls: listen
+                    .tls_cert
+                    .zip(listen.tls_key)
+                    .map(|(cert, key)| TlsConfig {
+                        cert_path: cert,
+                        key_path: key,
+                        alpn: listen.alpn,
+                        alpn_strict: listen.alpn_strict,
+                        client_ca: listen.tls_client_ca,
+                    }),
+                bind_addr: listen.bind,
+                tcp: TcpOptions::from_config(&config.server),
+                proxy_protocol: listen.proxy_protocol,
+            })
+            .collect(),
+    }
+}
+
+/// Запущенный DAO сервер
+pub struct DaoHandle {
+    local_addrs: Vec<SocketAddr>,
+    memory: Arc<Memory>,
+    upstreams: Arc<UpstreamRegistry>,
+    align: Arc<Align>,
+    shutdown: CancellationToken,
+    task: JoinHandle<anyhow::Result<()>>,
+    stats_decay: JoinHandle<()>,
+    metrics_feed: MetricsFeed,
+    metrics_feed_task: JoinHandle<()>,
+}
+
+impl DaoHandle {
+    /// Фактические адреса listener'ов (с портом при bind на `:0`)
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    pub fn memory(&self) -> &Arc<Memory> {
+        &self.memory
+    }
+
+    pub fn upstreams(&self) -> &Arc<UpstreamRegistry> {
+        &self.upstreams
+    }
+
+    pub fn align(&self) -> &Arc<Align> {
+        &self.align
+    }
+
+    /// Поток снимков метрик (для `AdminApi::with_metrics_feed`)
+    pub fn metrics_feed(&self) -> &MetricsFeed {
+        &self.metrics_feed
+    }
+
+    /// Триггер остановки, который можно передать в другую задачу
+    pub fn shutdown_trigger(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Ожидание завершения сервера
+    pub async fn wait(self) -> anyhow::Result<()> {
+        let result = self.task.await;
+        self.stats_decay.abort();
+        self.metrics_feed_task.abort();
+        result?
+    }
+
+    /// Остановка приема соединений и ожидание завершения
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        self.shutdown.cancel();
+        self.wait().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use dao_core::config::{MatchRule, RouteRule, RoutesConfig, ServerConfig, UpstreamConfig};
+    use http_body_util::Full;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn spawn_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|_req| async {
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"embedded"))))
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_embedded_server_proxies_request() {
+        let upstream_url = spawn_upstream().await;
+        let config = DaoConfig {
+            server: ServerConfig {
+                bind: Some("127.0.0.1:0".to_string()),
+                ..Default::default()
+            },
+            routes: RoutesConfig {
+                rule: vec![RouteRule {
+                    name: "embedded".to_string(),
+                    match_rule: MatchRule {
+                        path_prefix: Some("/".to_string()),
+                        ..Default::default()
+                    },
+                    policy: "local".to_string(),
+                    intent: None,
+                    upstreams: vec![UpstreamConfig {
+                        name: "backend".to_string(),
+                        url: upstream_url.clone(),
+                        intent: None,
+                        weight: 1,
+                        insecure_skip_verify: false,
+                        ca_cert: None,
+                        http2: false,
+                        timeout_secs: None,
+                        connect_timeout_ms: None,
+                        max_concurrency: None,
+                        override_host: None,
+                        circuit_breaker: None,
+                    }],
+                    upstreams_file: None,
+                    filters: None,
+                    fallback_route: None,
+                    deadline_ms: None,
+                    request_timeout_ms: None,
+                    selection_hold_ms: None,
+                    protocol: Default::default(),
+                    priority: None,
+                    ab_test: None,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let handle = DaoServerBuilder::new(config)
+            .policy("local", PolicyWeights::default())
+            .upstream_client(upstream_url, UpstreamClient::new())
+            .start()
+            .await
+            .unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("embedded"), "{}", response);
+
+        handle.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,10 @@
+//! DAO — Dynamic Awareness Orchestrator
+//!
+//! Библиотека для встраивания прокси в другой бинарник: сборка сервера из
+//! `DaoConfig` в коде, без файла конфигурации
+
+pub mod builder;
+pub mod server;
+
+pub use builder::{DaoHandle, DaoServerBuilder};
+pub use server::DaoServer;
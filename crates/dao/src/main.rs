@@ -2,30 +2,24 @@
 //!
 //! Лиминальный reverse-proxy с осознанной маршрутизацией
 
-use clap::Parser;
-use dao_admin::Admin;
-use dao_core::{
-    align::Align,
-    config::DaoConfig,
-    gate::{Gate, GateConfig, TlsConfig},
-    memory::Memory,
-    sense::Sense,
-    upstream::UpstreamState,
-};
-use dao_telemetry::{init_telemetry, register_dao_metrics, start_prometheus_exporter};
+use clap::{Parser, Subcommand};
+use dao::DaoServerBuilder;
+use dao_admin::{Admin, AdminApi};
+use dao_core::config::DaoConfig;
+use dao_telemetry::{init_telemetry, register_dao_metrics, start_telemetry_exporter};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tracing::{error, info};
 
-mod server;
-use server::DaoServer;
-
 #[derive(Parser, Debug)]
 #[command(name = "dao")]
 #[command(about = "Dynamic Awareness Orchestrator — лиминальный reverse-proxy", long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to configuration file
-    #[arg(short, long, default_value = "configs/dao.toml")]
+    #[arg(short, long, global = true, default_value = "configs/dao.toml")]
     config: PathBuf,
 
     /// Enable verbose logging
@@ -33,13 +27,34 @@ struct Args {
     verbose: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the proxy (default)
+    Run,
+    /// Load and validate the configuration without starting the proxy
+    Validate,
+    /// Print the JSON Schema of the configuration file
+    Schema,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
+    match args.command {
+        Some(Command::Validate) => return validate_config(&args.config),
+        Some(Command::Schema) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&DaoConfig::json_schema())?
+            );
+            return Ok(());
+        }
+        Some(Command::Run) | None => {}
+    }
+
     // Инициализация телеметрии
     init_telemetry()?;
-    register_dao_metrics();
 
     if args.verbose {
         info!("Verbose logging enabled");
@@ -52,74 +67,46 @@ async fn main() -> anyhow::Result<()> {
 
     info!("Configuration loaded successfully");
 
-    // Создание компонентов DAO
-    let memory = Arc::new(Memory::new(config.clone()));
-
-    // Создание upstream states
-    let mut all_upstreams = Vec::new();
-    for route in &config.routes.rule {
-        for upstream_cfg in &route.upstreams {
-            let upstream = UpstreamState::new(
-                upstream_cfg.name.clone(),
-                upstream_cfg.url.clone(),
-                upstream_cfg.intents(),
-                upstream_cfg.weight,
-            );
-            all_upstreams.push(upstream);
-        }
-    }
-    let upstreams = Arc::new(all_upstreams);
-
-    // Sense — телеметрия
-    let sense = Sense::new(upstreams.clone());
-
-    // Align — политики
-    let mut align = Align::new(sense.clone());
-
-    // Регистрация политик из конфига
-    if let Some(policies) = &config.policies {
-        for (name, policy_cfg) in policies {
-            use dao_core::align::PolicyWeights;
-            let weights = PolicyWeights::new(
-                policy_cfg.w_load,
-                policy_cfg.w_intent,
-                policy_cfg.w_tempo,
-            );
-            align.register_policy(name.clone(), weights);
-        }
+    // Prometheus exporter — до listener'ов, чтобы фатальная ошибка
+    // остановила запуск, пока ничего не принято
+    if let Some(telemetry_cfg) = &config.telemetry {
+        start_telemetry_exporter(telemetry_cfg).await?;
     }
+    // Информация о сборке — уже в установленный exporter
+    register_dao_metrics();
+
+    // Создание и запуск сервера
+    let handle = DaoServerBuilder::new(config.clone()).start().await?;
+    let (memory, upstreams, align) = (
+        handle.memory().clone(),
+        handle.upstreams().clone(),
+        handle.align().clone(),
+    );
 
     // Admin — управление
-    let admin = Arc::new(Admin::new(args.config.clone(), memory.clone()));
-
-    // Gate — прием соединений
-    let gate_config = GateConfig {
-        bind_addr: config.server.bind.clone(),
-        tls: config
-            .server
-            .tls_cert
-            .as_ref()
-            .zip(config.server.tls_key.as_ref())
-            .map(|(cert, key)| TlsConfig {
-                cert_path: cert.clone(),
-                key_path: key.clone(),
-            }),
-    };
-
-    let gate = Gate::new(gate_config).await?;
-    let local_addr = gate.local_addr()?;
-    info!("DAO listening on: {}", local_addr);
-
-    // Запуск Prometheus exporter
-    if let Some(telemetry_cfg) = &config.telemetry {
-        let prometheus_addr = telemetry_cfg.prometheus_bind.parse()?;
+    let admin = Arc::new(Admin::new(args.config.clone(), memory, upstreams));
+
+    // Запуск admin API
+    if let Some(admin_cfg) = &config.admin {
+        let listener = tokio::net::TcpListener::bind(&admin_cfg.bind).await?;
+        let mut api = AdminApi::new(admin.clone(), align.clone(), admin_cfg.token.clone())
+            .with_metrics_feed(handle.metrics_feed().clone());
+        if let Some(auth) = &admin_cfg.auth {
+            api = api.with_hmac_auth(auth);
+        }
+        let api = Arc::new(api);
         tokio::spawn(async move {
-            if let Err(e) = start_prometheus_exporter(prometheus_addr).await {
-                error!("Failed to start Prometheus exporter: {}", e);
+            if let Err(e) = api.serve(listener).await {
+                error!("Admin API failed: {}", e);
             }
         });
     }
 
+    // Файлы upstream'ов маршрутов перечитываются отдельно от конфигурации
+    if let Err(e) = admin.start_upstreams_watch() {
+        error!("Upstreams watch failed: {}", e);
+    }
+
     // Запуск config watch
     tokio::spawn({
         let admin = admin.clone();
@@ -130,13 +117,33 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    // Создание и запуск сервера
-    let server = DaoServer::new(gate, sense, align, memory, upstreams);
+    // `kill -HUP` — перезагрузка без ожидания событий файловой системы
+    #[cfg(unix)]
+    if let Err(e) = admin.start_sighup_reload() {
+        error!("SIGHUP handler failed: {}", e);
+    }
 
     info!("DAO started successfully");
     info!("Dynamic Awareness Orchestrator — врата сознания открыты");
 
-    server.run().await?;
+    handle.wait().await?;
 
     Ok(())
 }
+
+/// `dao validate`: все ошибки конфигурации, ненулевой код выхода при ошибке
+fn validate_config(path: &std::path::Path) -> anyhow::Result<()> {
+    match DaoConfig::check_file(path) {
+        Ok(_) => {
+            println!("{}: OK", path.display());
+            Ok(())
+        }
+        Err(errors) => {
+            eprintln!("{}: {} error(s)", path.display(), errors.len());
+            for error in &errors {
+                eprintln!("  - {}", error);
+            }
+            std::process::exit(1);
+        }
+    }
+}
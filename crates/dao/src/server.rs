@@ -1,22 +1,47 @@
 //! DAO Server — обработка запросов
 
 use dao_core::{
-    align::Align,
-    gate::{Connection, Gate, Protocol},
+    align::{
+        arm_upstreams, AbSplit, Align, IntentClassifier, SelectionHeaders, SelectionHold,
+        UpstreamOverride, UPSTREAM_OVERRIDDEN_HEADER, UPSTREAM_OVERRIDE_HEADER,
+    },
+    config::{
+        DaoConfig, MatchContext, RouteProtocol, RouteRule, RoutesConfig, ServerMode, Unmatched,
+    },
+    flow::{
+        body::{self, DeadlineBody},
+        copy_metered, has_idempotency_key, idempotency_key, is_upgrade_request, rate_limit_key,
+        request_id, shape_upstream_error, BasicAuthFilter, BodyBuffer, CacheRegistry, Claim,
+        ClientCertFilter, CorsFilter, ErrorPages, HeaderManipulator, IdempotencyRegistry,
+        IpAccessFilter, JwksCache, JwtFilter, ProxyBody, RateLimiter, RedactedHeaders, RequestKey,
+        TemplateVars, CACHE_STATUS_HEADER, DEFAULT_RATE_LIMIT_MAX_KEYS, REQUEST_ID_HEADER,
+    },
+    gate::{
+        ClientIdentity, ConcurrencyLimiter, Connection, ConnectionLimiter, ConnectionPermit,
+        ConnectionTimeouts, Gate, HeaderLimits, HttpOptions, Listener, Protocol, TimedStream,
+        TimeoutSwitch,
+    },
     memory::Memory,
-    sense::Sense,
-    upstream::{ConnectionPool, UpstreamState},
-    Intent, Result,
+    sense::{Health, Sense},
+    upstream::{
+        grpc_status, ConnectionPool, InFlightGuard, UpstreamErrorKind, UpstreamRegistry,
+        UpstreamState, GRPC_OK,
+    },
+    DaoError, Intent, Result,
 };
-use http_body_util::{combinators::BoxBody, BodyExt, Empty};
-use hyper::body::Incoming;
-use hyper::server::conn::{http1, http2};
+use dao_telemetry::MetricsCollector;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Incoming};
+use hyper::server::conn::http2;
 use hyper::service::service_fn;
-use hyper::{body::Bytes, Request, Response};
+use hyper::{Request, Response};
 use hyper_util::rt::TokioIo;
-use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 use tracing::{debug, error, info, warn};
 
 /// DAO Server
@@ -25,38 +50,193 @@ pub struct DaoServer {
     sense: Arc<Sense>,
     align: Arc<Align>,
     memory: Arc<Memory>,
-    upstreams: Arc<Vec<UpstreamState>>,
+    upstreams: Arc<UpstreamRegistry>,
     pool: Arc<ConnectionPool>,
+    jwks: Arc<JwksCache>,
+    rate_limiter: Arc<RateLimiter>,
+    caches: CacheRegistry,
+    idempotency: IdempotencyRegistry,
+    health: Arc<Health>,
+    limiter: ConcurrencyLimiter,
+    connections: ConnectionLimiter,
+    /// Туннели после upgrade: живут дольше запроса, закрываются при остановке
+    tunnels: TaskTracker,
+    tunnels_closed: CancellationToken,
+    metrics: MetricsCollector,
+}
+
+/// Маршрут запроса: совпавший, маршрут по умолчанию или — при методе,
+/// не разрешенном ни одним маршрутом, — маршрут пути с методами для 405
+struct Routing<'c> {
+    route: Option<&'c RouteRule>,
+    /// Методы для `Allow`; пусто — метод разрешен
+    allowed: Vec<String>,
+}
+
+impl<'c> Routing<'c> {
+    fn resolve<B>(routes: &'c RoutesConfig, req: &Request<B>) -> Self {
+        // Части запроса для матчинга — один раз на все маршруты
+        let ctx = MatchContext::from_request(req);
+        if let Some(route) = routes.find_route(&ctx) {
+            return Self {
+                route: Some(route),
+                allowed: Vec::new(),
+            };
+        }
+        // 405 — только после фильтров доступа и CORS preflight маршрута пути
+        let allowed = routes.allowed_methods(&ctx);
+        let route = if allowed.is_empty() {
+            match routes.unmatched() {
+                Unmatched::Route(route) => Some(route),
+                _ => None,
+            }
+        } else {
+            routes.find_route_any_method(&ctx)
+        };
+        Self { route, allowed }
+    }
+}
+
+/// Клиентское соединение: общее состояние его запросов
+struct ClientConnection {
+    peer_addr: SocketAddr,
+    /// Удержание выбора upstream'а — в пределах соединения
+    hold: SelectionHold,
+    /// Таймауты IO: idle — между запросами, все снимаются в туннеле
+    timeouts: TimeoutSwitch,
+    /// Слот `max_connections` — до закрытия соединения, в том числе туннеля
+    _permit: ConnectionPermit,
+}
+
+impl ClientConnection {
+    fn new<S>(
+        peer_addr: SocketAddr,
+        stream: &TimedStream<S>,
+        permit: ConnectionPermit,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            peer_addr,
+            hold: SelectionHold::new(),
+            timeouts: stream.switch(),
+            _permit: permit,
+        })
+    }
 }
 
 impl DaoServer {
     pub fn new(
         gate: Gate,
         sense: Sense,
-        align: Align,
+        align: Arc<Align>,
         memory: Arc<Memory>,
-        upstreams: Arc<Vec<UpstreamState>>,
+        upstreams: Arc<UpstreamRegistry>,
+        pool: ConnectionPool,
     ) -> Self {
+        let config = memory.get_config();
+        let limiter = ConcurrencyLimiter::new(config.server.max_concurrent_requests);
+        let connections = ConnectionLimiter::new(
+            config.server.max_connections,
+            config.server.connection_overflow,
+        );
+        let rate_limiter = RateLimiter::new(
+            config
+                .server
+                .rate_limit_max_keys
+                .unwrap_or(DEFAULT_RATE_LIMIT_MAX_KEYS),
+        );
         Self {
             gate: Arc::new(gate),
             sense: Arc::new(sense),
-            align: Arc::new(align),
+            align,
             memory,
             upstreams,
-            pool: Arc::new(ConnectionPool::new()),
+            pool: Arc::new(pool),
+            jwks: Arc::new(JwksCache::new()),
+            rate_limiter: Arc::new(rate_limiter),
+            caches: CacheRegistry::new(),
+            idempotency: IdempotencyRegistry::new(),
+            health: Arc::new(Health::new()),
+            limiter,
+            connections,
+            tunnels: TaskTracker::new(),
+            tunnels_closed: CancellationToken::new(),
+            metrics: MetricsCollector::new(),
         }
     }
 
-    /// Запуск сервера
+    /// Запуск сервера: accept loop на каждый listener
     pub async fn run(self) -> anyhow::Result<()> {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Запуск до сигнала `shutdown`: после него новые соединения не
+    /// принимаются, начатые обслуживаются до конца; туннели (WebSocket и др.)
+    /// закрываются
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> anyhow::Result<()> {
         let self_arc = Arc::new(self);
 
+        let accept_loops: Vec<_> = self_arc
+            .gate
+            .listeners()
+            .iter()
+            .map(|listener| tokio::spawn(self_arc.clone().accept_loop(listener.clone())))
+            .collect();
+
+        let eviction = tokio::spawn(
+            self_arc
+                .rate_limiter
+                .clone()
+                .run_eviction(std::time::Duration::from_secs(30)),
+        );
+
+        self_arc.health.mark_ready();
+
+        let aborts: Vec<_> = accept_loops
+            .iter()
+            .map(|task| task.abort_handle())
+            .collect();
+        tokio::select! {
+            results = futures::future::join_all(accept_loops) => {
+                for result in results {
+                    result?;
+                }
+            }
+            _ = shutdown => {
+                info!("Shutdown requested, no longer accepting connections");
+                for abort in aborts {
+                    abort.abort();
+                }
+            }
+        }
+        eviction.abort();
+
+        self_arc.tunnels_closed.cancel();
+        self_arc.tunnels.close();
+        self_arc.tunnels.wait().await;
+
+        Ok(())
+    }
+
+    /// Accept loop одного listener'а
+    async fn accept_loop(self: Arc<Self>, listener: Arc<Listener>) {
         loop {
-            match self_arc.gate.accept().await {
-                Ok(conn) => {
-                    let server = self_arc.clone();
+            match listener.accept().await {
+                Ok((stream, peer_addr)) => {
+                    // Слот — до handshake'а; в режиме wait accept ждет его
+                    let Some(permit) = self.connections.admit().await else {
+                        warn!("Connection limit reached, closing {}", peer_addr);
+                        continue;
+                    };
+                    let (server, listener) = (self.clone(), listener.clone());
                     tokio::spawn(async move {
-                        if let Err(e) = server.handle_connection(conn).await {
+                        let conn = match listener.handshake(stream, peer_addr).await {
+                            Ok(conn) => conn,
+                            Err(e) => {
+                                error!("Handshake with {} failed: {}", peer_addr, e);
+                                return;
+                            }
+                        };
+                        if let Err(e) = server.handle_connection(conn, permit).await {
                             error!("Connection error: {}", e);
                         }
                     });
@@ -69,7 +249,11 @@ impl DaoServer {
     }
 
     /// Обработка соединения
-    async fn handle_connection(self: Arc<Self>, conn: Connection) -> Result<()> {
+    async fn handle_connection(
+        self: Arc<Self>,
+        conn: Connection,
+        permit: ConnectionPermit,
+    ) -> Result<()> {
         let peer_addr = conn.peer_addr();
         let protocol = conn.protocol();
 
@@ -78,98 +262,152 @@ impl DaoServer {
             peer_addr, protocol
         );
 
-        match protocol {
-            Protocol::Http1 | Protocol::Http2 => {
-                self.handle_http_connection(conn).await?;
+        self.handle_http_connection(conn, permit).await
+    }
+
+    /// Обработка HTTP соединения
+    async fn handle_http_connection(
+        self: Arc<Self>,
+        conn: Connection,
+        permit: ConnectionPermit,
+    ) -> Result<()> {
+        // Таймауты читаются на каждое соединение — подхватывают hot-reload
+        let timeouts = ConnectionTimeouts::from_config(&self.memory.get_config().server);
+        let peer_addr = conn.peer_addr();
+
+        let client_identity = conn.client_identity().cloned();
+
+        match conn {
+            Connection::Plain {
+                stream, protocol, ..
+            } => {
+                let stream = timeouts.wrap(stream);
+                let client = ClientConnection::new(peer_addr, &stream, permit);
+                self.serve_http(stream, protocol, timeouts, client, None, "")
+                    .await;
             }
-            Protocol::WebSocket => {
-                self.handle_websocket_connection(conn).await?;
+            Connection::Tls {
+                stream, protocol, ..
+            } => {
+                let stream = timeouts.wrap(stream);
+                let client = ClientConnection::new(peer_addr, &stream, permit);
+                self.serve_http(stream, protocol, timeouts, client, client_identity, " TLS")
+                    .await;
             }
         }
 
         Ok(())
     }
 
-    /// Обработка HTTP соединения
-    async fn handle_http_connection(self: Arc<Self>, conn: Connection) -> Result<()> {
-        match conn {
-            Connection::Plain { stream, protocol, .. } => {
-                let io = TokioIo::new(stream);
-                let server = self.clone();
+    /// HTTP/1.1 или HTTP/2 поверх соединения с таймаутами
+    async fn serve_http<S>(
+        self: Arc<Self>,
+        stream: TimedStream<S>,
+        protocol: Protocol,
+        timeouts: ConnectionTimeouts,
+        client: Arc<ClientConnection>,
+        client_identity: Option<ClientIdentity>,
+        transport: &str,
+    ) where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+    {
+        let io = TokioIo::new(stream);
+        let server = self.clone();
 
-                let service = service_fn(move |req| {
-                    let server = server.clone();
-                    async move { server.handle_request(req).await }
-                });
+        let service = service_fn(move |mut req: Request<Incoming>| {
+            let (server, client) = (server.clone(), client.clone());
+            // Identity сертификата клиента — маршрутам и фильтрам
+            if let Some(identity) = &client_identity {
+                req.extensions_mut().insert(identity.clone());
+            }
+            // Idle-таймаут соединения не действует до конца тела ответа
+            let active = client.timeouts.request();
+            async move {
+                let response = server.handle_request(req, client).await?;
+                Ok::<_, hyper::Error>(response.map(|b| body::holding(b, active)))
+            }
+        });
 
-                match protocol {
-                    Protocol::Http1 => {
-                        if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
-                            error!("HTTP/1.1 connection error: {}", e);
-                        }
-                    }
-                    Protocol::Http2 => {
-                        if let Err(e) = http2::Builder::new(hyper_util::rt::TokioExecutor::new())
-                            .serve_connection(io, service)
-                            .await
-                        {
-                            error!("HTTP/2 connection error: {}", e);
-                        }
-                    }
-                    _ => {}
+        let server_config = &self.memory.get_config().server;
+        let limits = HeaderLimits::from_config(server_config);
+        let options = HttpOptions::from_config(server_config);
+        match protocol {
+            Protocol::Http1 => {
+                let mut builder = timeouts.http1_builder();
+                limits.apply_http1(&mut builder);
+                options.apply_http1(&mut builder);
+                // Upgrade (WebSocket и др.) — соединение переходит в туннель
+                if let Err(e) = builder.serve_connection(io, service).with_upgrades().await {
+                    error!("HTTP/1.1{} connection error: {}", transport, e);
                 }
             }
-            Connection::Tls { stream, protocol, .. } => {
-                let io = TokioIo::new(stream);
-                let server = self.clone();
-
-                let service = service_fn(move |req| {
-                    let server = server.clone();
-                    async move { server.handle_request(req).await }
-                });
-
-                match protocol {
-                    Protocol::Http1 => {
-                        if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
-                            error!("HTTP/1.1 TLS connection error: {}", e);
-                        }
-                    }
-                    Protocol::Http2 => {
-                        if let Err(e) = http2::Builder::new(hyper_util::rt::TokioExecutor::new())
-                            .serve_connection(io, service)
-                            .await
-                        {
-                            error!("HTTP/2 TLS connection error: {}", e);
-                        }
-                    }
-                    _ => {}
+            Protocol::Http2 => {
+                let mut builder = http2::Builder::new(hyper_util::rt::TokioExecutor::new());
+                limits.apply_http2(&mut builder);
+                options.apply_http2(&mut builder);
+                if let Err(e) = builder.serve_connection(io, service).await {
+                    error!("HTTP/2{} connection error: {}", transport, e);
                 }
             }
         }
-
-        Ok(())
-    }
-
-    /// Обработка WebSocket соединения
-    async fn handle_websocket_connection(&self, _conn: Connection) -> Result<()> {
-        info!("WebSocket connection handled (placeholder)");
-        // TODO: WebSocket proxying
-        Ok(())
     }
 
-
     /// Обработка HTTP запроса
     async fn handle_request(
         self: Arc<Self>,
         req: Request<Incoming>,
-    ) -> std::result::Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        client: Arc<ClientConnection>,
+    ) -> std::result::Result<Response<DeadlineBody<ProxyBody>>, hyper::Error> {
         let start = Instant::now();
         let method = req.method().clone();
         let uri = req.uri().clone();
+        let request_id = request_id(req.headers());
+
+        // Значения заголовков — только из allowlist, остальные маскируются
+        let config = self.memory.get_config();
+        debug!(
+            "Handling request {}: {} {} [{}]",
+            request_id,
+            method,
+            uri,
+            RedactedHeaders::new(req.headers(), &config.logging)
+        );
 
-        debug!("Handling request: {} {}", method, uri);
+        // Маршрут ищется один раз на запрос. Бюджет маршрута ограничивает
+        // обработку до заголовков ответа, таймаут запроса — и ее, и передачу тела
+        let routing = Routing::resolve(&config.routes, &req);
+        let (budget, request_timeout) = routing
+            .route
+            .map(|route| (route.deadline(), route.request_timeout()))
+            .unwrap_or_default();
+        let budget = match (budget, request_timeout) {
+            (Some(budget), Some(timeout)) => Some(budget.min(timeout)),
+            (budget, timeout) => budget.or(timeout),
+        };
+        let deadlines = Deadlines {
+            response: budget.map(|budget| start + budget),
+            body: request_timeout.map(|timeout| start + timeout),
+        };
+        let processed =
+            self.process_request(req, &client, &config, routing, &request_id, deadlines);
+        let result = match budget {
+            Some(budget) => match tokio::time::timeout(budget, processed).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!(
+                        "Request {} exceeded route deadline {:?}",
+                        request_id, budget
+                    );
+                    let response = self.error_response(504, &request_id).unwrap_or_else(|_| {
+                        Response::builder().status(504).body(body::empty()).unwrap()
+                    });
+                    return Ok(response.map(|b| body::with_deadline(b, None)));
+                }
+            },
+            None => processed.await,
+        };
 
-        match self.process_request(req).await {
+        let response = match result {
             Ok(response) => {
                 let status = response.status();
                 let latency = start.elapsed();
@@ -177,128 +415,2818 @@ impl DaoServer {
                     "Request completed: {} {} -> {} in {:?}",
                     method, uri, status, latency
                 );
-                Ok(response)
+                response
             }
             Err(e) => {
-                error!("Request processing failed: {}", e);
-                let response = Response::builder()
-                    .status(502)
-                    .body(
-                        Empty::<Bytes>::new()
-                            .map_err(|never: Infallible| match never {})
-                            .boxed(),
-                    )
-                    .unwrap();
-                Ok(response)
+                // Тело запроса сверх лимита буферизации — ошибка клиента
+                let status = match &e {
+                    DaoError::PayloadTooLarge(_) => 413,
+                    _ => 502,
+                };
+                error!("Request {} processing failed: {}", request_id, e);
+                self.error_response(status, &request_id)
+                    .unwrap_or_else(|_| {
+                        Response::builder()
+                            .status(status)
+                            .body(body::empty())
+                            .unwrap()
+                    })
             }
-        }
+        };
+        // Тело, не переданное до конца таймаута запроса, обрывается
+        Ok(response.map(|b| body::with_deadline(b, deadlines.body)))
     }
 
     /// Обработка запроса с маршрутизацией
-    async fn process_request(&self, req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
-        let config = self.memory.get_config();
+    async fn process_request(
+        &self,
+        mut req: Request<Incoming>,
+        client: &Arc<ClientConnection>,
+        config: &DaoConfig,
+        routing: Routing<'_>,
+        request_id: &str,
+        deadlines: Deadlines,
+    ) -> Result<Response<ProxyBody>> {
+        let (peer_addr, hold) = (client.peer_addr, &client.hold);
 
-        // Поиск подходящего маршрута
-        let route = config
-            .routes
-            .rule
-            .iter()
-            .find(|r| r.match_rule.matches(&req));
+        // Лимиты ниже буфера hyper проверяются по разобранным заголовкам
+        if HeaderLimits::from_config(&config.server).exceeded(req.headers()) {
+            debug!("Request headers over limit from {}", peer_addr);
+            return self.error_response(431, request_id);
+        }
 
+        // Health check обслуживается до таблицы маршрутов
+        if req.uri().path() == config.server.health_path {
+            return self.health_response();
+        }
+
+        // Лимит параллельных запросов; permit освобождается при любом выходе
+        let Some(mut permit) = self.limiter.try_acquire() else {
+            warn!("Concurrency limit reached, rejecting {}", req.uri());
+            return self.error_response(503, request_id);
+        };
+
+        let Routing { route, allowed } = routing;
         if let Some(route) = route {
             debug!("Matched route: {}", route.name);
+            permit.set_route(&route.name);
 
-            // Получение upstream'ов для маршрута
-            let route_upstreams: Vec<_> = route
-                .upstreams
-                .iter()
-                .filter_map(|uc| {
-                    self.upstreams
-                        .iter()
-                        .find(|u| u.name == uc.name)
-                        .map(|u| Arc::new(u.clone()))
-                })
-                .collect();
+            // Доступ по адресу клиента проверяется раньше остальных фильтров
+            if let Some(filters) = &route.filters {
+                if !IpAccessFilter::new(filters).is_allowed(peer_addr.ip()) {
+                    debug!("Client {} denied for route {}", peer_addr, route.name);
+                    return self.error_response(403, request_id);
+                }
+                if !ClientCertFilter::new(filters)
+                    .is_allowed(req.extensions().get::<ClientIdentity>())
+                {
+                    debug!(
+                        "Client certificate of {} denied for route {}",
+                        peer_addr, route.name
+                    );
+                    return self.error_response(403, request_id);
+                }
 
-            if route_upstreams.is_empty() {
-                warn!("No upstreams available for route: {}", route.name);
-                return self.error_response(503, "Service Unavailable");
+                if let Some(rps) = filters.rate_limit_rps {
+                    let key = rate_limit_key(filters, &req, peer_addr.ip());
+                    let burst = filters.rate_limit_burst.unwrap_or(rps);
+                    let decision =
+                        self.rate_limiter
+                            .check(&route.name, &key, rps, burst, Instant::now());
+                    if !decision.allowed {
+                        debug!("Rate limit exceeded for route {} ({})", route.name, key);
+                        let mut response = self.error_response(429, request_id)?;
+                        response.headers_mut().extend(decision.headers());
+                        return Ok(response);
+                    }
+                }
             }
 
+            // CORS preflight обрабатывается напрямую, без проксирования
+            let cors = route
+                .filters
+                .as_ref()
+                .and_then(|f| f.cors.as_ref())
+                .map(CorsFilter::new);
+            if let Some(cors) = &cors {
+                if CorsFilter::is_preflight(&req) {
+                    let mut response = self.empty_response(204)?;
+                    *response.headers_mut() = cors.preflight_headers(req.headers());
+                    return Ok(response);
+                }
+            }
+            let origin = req.headers().get(http::header::ORIGIN).cloned();
+            // Правка заголовков ответа (в том числе из кэша)
+            let response_headers = route.filters.as_ref().map(HeaderManipulator::for_response);
+
+            // Basic аутентификация до выбора upstream
+            if let Some(basic) = route.filters.as_ref().and_then(|f| f.basic_auth.as_ref()) {
+                let basic_filter = BasicAuthFilter::new(basic);
+                match basic_filter.authenticate(req.headers()).await {
+                    Ok(user) => BasicAuthFilter::inject_user(&user, req.headers_mut()),
+                    Err(e @ DaoError::Unauthorized(_)) => {
+                        debug!("Basic auth rejected for route {}: {}", route.name, e);
+                        return self.unauthorized_response(basic_filter.challenge(), request_id);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            // JWT аутентификация до выбора upstream
+            if let Some(jwt) = route.filters.as_ref().and_then(|f| f.jwt.as_ref()) {
+                let jwt_filter = JwtFilter::new(jwt, &self.jwks);
+                match jwt_filter.authenticate(req.headers()).await {
+                    Ok(claims) => jwt_filter.inject_claims(&claims, req.headers_mut()),
+                    Err(e @ DaoError::Unauthorized(_)) => {
+                        debug!("JWT rejected for route {}: {}", route.name, e);
+                        return self.unauthorized_response(JwtFilter::challenge(&e), request_id);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if !allowed.is_empty() {
+                debug!("Method {} not allowed for {}", req.method(), req.uri());
+                let mut response = self.error_response(405, request_id)?;
+                if let Ok(allow) = http::HeaderValue::from_str(&allowed.join(", ")) {
+                    response.headers_mut().insert(http::header::ALLOW, allow);
+                }
+                return Ok(response);
+            }
+
+            // Кэш ответов: свежий ответ отдается без проксирования
+            let cached = route
+                .filters
+                .as_ref()
+                .and_then(|f| f.cache.as_ref())
+                .and_then(|cache_config| {
+                    let key = RequestKey::from_request(&req)?;
+                    Some((self.caches.for_route(&route.name, cache_config), key))
+                });
+            if let Some((cache, key)) = &cached {
+                if let Some(hit) = cache.lookup(key, Instant::now()) {
+                    let (mut parts, cached_body) = hit.into_parts();
+                    if let Some(response_headers) = &response_headers {
+                        response_headers.apply_to_headers(&mut parts.headers)?;
+                    }
+                    if let Some(cors) = &cors {
+                        cors.apply_response_headers(origin.as_ref(), &mut parts.headers);
+                    }
+                    return Ok(Response::from_parts(parts, body::full(cached_body)));
+                }
+            }
+
+            // Повтор с тем же Idempotency-Key получает сохраненный ответ;
+            // пока первый запрос в работе, повторы ждут его. Тело запроса
+            // входит в ключ: читается целиком, к upstream'у уходит буфер
+            let mut req = req.map(body::passthrough);
+            let idempotency = match route.filters.as_ref().and_then(|f| f.idempotency.as_ref()) {
+                Some(idempotency_config) if has_idempotency_key(&req) => {
+                    let (parts, request_body) = req.into_parts();
+                    let request_body = BodyBuffer::new(idempotency_config.max_request_bytes)
+                        .collect(request_body)
+                        .await?;
+                    req = Request::from_parts(parts, body::full(request_body.clone()));
+                    idempotency_key(&req, peer_addr.ip(), &request_body)
+                        .map(|key| (idempotency_config, key))
+                }
+                _ => None,
+            };
+            let idempotent = match idempotency {
+                Some((idempotency_config, key)) => {
+                    let store = self.idempotency.for_route(&route.name, idempotency_config);
+                    match store.claim(key).await {
+                        Claim::Replay(replay) => {
+                            let (mut parts, stored_body) = replay.into_parts();
+                            if let Some(response_headers) = &response_headers {
+                                response_headers.apply_to_headers(&mut parts.headers)?;
+                            }
+                            if let Some(cors) = &cors {
+                                cors.apply_response_headers(origin.as_ref(), &mut parts.headers);
+                            }
+                            return Ok(Response::from_parts(parts, body::full(stored_body)));
+                        }
+                        Claim::Proceed(guard) => Some(guard),
+                        Claim::InProgress => {
+                            debug!("Idempotency key of route {} still in progress", route.name);
+                            return self.error_response(409, request_id);
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            // Получение upstream'ов для маршрута
+            let route_upstreams = self.upstreams.route_upstreams(route);
+
+            // Явный выбор upstream'а заголовком (если разрешен)
+            let pinned = match UpstreamOverride::new(config.server.allow_upstream_override)
+                .resolve(&req, &route_upstreams)
+            {
+                Ok(pinned) => pinned,
+                Err(e @ DaoError::InvalidRequest(_)) => {
+                    debug!("Upstream override rejected for route {}: {}", route.name, e);
+                    return self.error_response(400, request_id);
+                }
+                Err(e) => return Err(e),
+            };
+            req.headers_mut().remove(UPSTREAM_OVERRIDE_HEADER);
+
             // Выбор upstream через Align
-            let request_intent = route.intent();
-            let selected = self
-                .align
-                .select_upstream(&route.policy, &route_upstreams, request_intent.as_ref());
+            let request_intent =
+                IntentClassifier::new(&config.intent_rules).classify_or(&req, route.intent());
+            let overridden = pinned.is_some();
+            // Если в маршруте выбрать некого — fallback-маршруты по цепочке
+            // Плечо A/B эксперимента: выбор только среди его upstream'ов
+            let ab_arm = match (&pinned, &route.ab_test) {
+                (None, Some(ab_test)) => AbSplit::new(ab_test).assign(&req),
+                _ => None,
+            };
+            let observe = config.server.mode == ServerMode::Observe;
+            let selected = match (pinned, ab_arm) {
+                // Режим наблюдения: трафик не меняется, даже при override
+                _ if observe => self
+                    .observe_selection(
+                        route,
+                        &route_upstreams,
+                        request_intent.as_ref(),
+                        config.server.passthrough_upstream.as_deref(),
+                    )
+                    .map(|upstream| (upstream, route)),
+                (Some(upstream), _) => Some((upstream, route)),
+                (None, Some(arm)) => {
+                    self.metrics.record_ab_assignment(&route.name, &arm.name);
+                    // Плечу выбрать некого — fallback-маршруты, как без эксперимента
+                    let chain = config.routes.fallback_chain(route);
+                    let arm_upstreams = arm_upstreams(arm, &route_upstreams);
+                    self.align
+                        .select_with_fallback(
+                            &chain,
+                            Some(&arm_upstreams),
+                            &self.upstreams,
+                            request_intent.as_ref(),
+                        )
+                        .inspect(|(_, selected_route)| {
+                            if selected_route.name != route.name {
+                                info!(
+                                    "Route {} (arm {}) falls back to route {}",
+                                    route.name, arm.name, selected_route.name
+                                );
+                            }
+                        })
+                }
+                (None, None) => {
+                    // Удержанный на соединении upstream — без переоценки
+                    let now = Instant::now();
+                    let held = route.selection_hold().and_then(|_| {
+                        let name = hold.get(&route.name, now)?;
+                        self.align.held_upstream(
+                            &route.policy,
+                            &route_upstreams,
+                            request_intent.as_ref(),
+                            &name,
+                        )
+                    });
+                    match held {
+                        Some(upstream) => Some((upstream, route)),
+                        None => {
+                            let chain = config.routes.fallback_chain(route);
+                            self.align
+                                .select_with_fallback(
+                                    &chain,
+                                    None,
+                                    &self.upstreams,
+                                    request_intent.as_ref(),
+                                )
+                                .inspect(|(upstream, selected_route)| {
+                                    if selected_route.name != route.name {
+                                        info!(
+                                            "Route {} falls back to route {}",
+                                            route.name, selected_route.name
+                                        );
+                                    } else if let Some(window) = route.selection_hold() {
+                                        hold.hold(&route.name, &upstream.name, window, now);
+                                    }
+                                })
+                        }
+                    }
+                }
+            };
+
+            // Проигравший гонку за пробу полуоткрытого breaker'а запрос
+            // уходит к другому upstream'у маршрута (плеча) — не при override.
+            // Режим наблюдения breaker'ом трафик не отклоняет
+            let selected = match selected {
+                Some((upstream, selected_route)) if observe => {
+                    let in_flight = upstream.begin_request();
+                    Some((upstream, selected_route, in_flight))
+                }
+                Some((upstream, selected_route)) => {
+                    let candidates = match ab_arm {
+                        Some(arm) if selected_route.name == route.name => {
+                            arm_upstreams(arm, &route_upstreams)
+                        }
+                        _ => self.upstreams.route_upstreams(selected_route),
+                    };
+                    let admitted = self.align.admit(
+                        selected_route,
+                        upstream,
+                        candidates,
+                        request_intent.as_ref(),
+                        !overridden,
+                    );
+                    match admitted {
+                        Some((upstream, in_flight)) => Some((upstream, selected_route, in_flight)),
+                        None => {
+                            warn!("Circuit breakers of route {} reject request", route.name);
+                            return self.error_response(503, request_id);
+                        }
+                    }
+                }
+                None => None,
+            };
 
-            if let Some(upstream) = selected {
+            if let Some((upstream, selected_route, in_flight)) = selected {
                 info!(
                     "Selected upstream: {} for route: {}",
                     upstream.name, route.name
                 );
 
-                // Проксирование к upstream
-                match self.proxy_to_upstream(&upstream, req).await {
-                    Ok((response, latency)) => {
-                        let success = response.status().is_success();
-                        upstream.record_request(latency, success);
-                        self.sense
-                            .record_upstream_request(&upstream.name, latency, success);
-
-                        // Конвертация Response<Incoming> в Response<BoxBody>
-                        let (parts, body) = response.into_parts();
-                        let boxed_body = body.map_err(|e| hyper::Error::from(e)).boxed();
-                        Ok(Response::from_parts(parts, boxed_body))
-                    }
-                    Err(e) => {
-                        error!("Proxy to upstream {} failed: {}", upstream.name, e);
-                        upstream.record_request(std::time::Duration::from_secs(0), false);
-                        self.sense.record_upstream_request(
+                // Разбор решения — только если его отдадут клиенту
+                let selection_headers =
+                    SelectionHeaders::new(config.server.expose_selection_headers);
+                let explanation = selection_headers.enabled().then(|| {
+                    self.align.explain_route_selection(
+                        selected_route,
+                        &self.upstreams.route_upstreams(selected_route),
+                        request_intent.as_ref(),
+                    )
+                });
+
+                // Заголовки запроса к upstream'у с подстановкой переменных
+                if let Some(filters) = &route.filters {
+                    let vars = TemplateVars {
+                        route: &route.name,
+                        client_ip: peer_addr.ip(),
+                        request_id,
+                        upstream: &upstream.name,
+                    };
+                    HeaderManipulator::for_request(filters, &vars)
+                        .apply_to_headers(req.headers_mut())?;
+                }
+
+                // Размеры тел — по мере передачи, без буферизации
+                let bytes_in = self.metrics.bytes_in_counter(&route.name, &upstream.name);
+                let mut req = req.map(|request_body| {
+                    let (metrics, route) = (self.metrics.clone(), route.name.clone());
+                    body::counted(body::metered(request_body, bytes_in), move |bytes| {
+                        metrics.record_request_body_bytes(&route, bytes)
+                    })
+                });
+
+                // Смена протокола: клиентская сторона туннеля — после 101
+                let client_upgrade = is_upgrade_request(&req).then(|| hyper::upgrade::on(&mut req));
+
+                // Проксирование к upstream; слот (и проба breaker'а) занят до
+                // учета исхода и конца тела ответа. Запрос, брошенный по
+                // дедлайну маршрута, учитывается как ошибка upstream'а
+                let mut outcome = self.outcome(&upstream, request_intent.as_ref(), in_flight);
+                outcome.deadline = deadlines.response;
+                let result = self
+                    .proxy_to_upstream(&upstream, req, deadlines.response)
+                    .await;
+
+                match result {
+                    Ok((mut response, latency)) => {
+                        let status = response.status();
+                        let switched = status == http::StatusCode::SWITCHING_PROTOCOLS
+                            && client_upgrade.is_some();
+                        self.metrics.record_request(
+                            &route.name,
                             &upstream.name,
-                            std::time::Duration::from_secs(0),
-                            false,
+                            latency.as_secs_f64(),
+                            status.as_u16(),
                         );
-                        self.error_response(502, "Bad Gateway")
+                        // 5xx upstream'а отдается клиенту как есть
+                        if status.is_server_error() {
+                            self.metrics
+                                .record_upstream_error(&upstream.name, "status_5xx");
+                        }
+                        // Тело, не переданное к сроку запроса, — тоже ошибка upstream'а
+                        outcome.latency = latency;
+                        outcome.deadline = deadlines.body;
+                        // gRPC: исход по grpc-status — в заголовках trailers-only
+                        // ответа, иначе в trailers (учет откладывается до конца тела)
+                        let (pending, in_flight) =
+                            if route.protocol == RouteProtocol::Grpc && status.is_success() {
+                                match grpc_status(response.headers()) {
+                                    Some(code) => {
+                                        (None, outcome.record(code == GRPC_OK, code == GRPC_OK))
+                                    }
+                                    None => (Some(outcome), None),
+                                }
+                            } else {
+                                // В профиль — только ошибки upstream'а, не клиента (4xx)
+                                let verdict =
+                                    (status.is_success() || switched, !status.is_server_error());
+                                if deadlines.body.is_some() && !switched {
+                                    // Со сроком передачи тела исход учитывается в конце
+                                    // тела (или при уходе клиента)
+                                    outcome.verdict = Some(verdict);
+                                    (Some(outcome), None)
+                                } else {
+                                    (None, outcome.record(verdict.0, verdict.1))
+                                }
+                            };
+
+                        // Upstream согласился сменить протокол: дальше — туннель
+                        // байт в обе стороны, независимо от токена upgrade
+                        if let (true, Some(client_upgrade)) = (switched, client_upgrade) {
+                            let upstream_upgrade = hyper::upgrade::on(&mut response);
+                            self.spawn_tunnel(
+                                client.clone(),
+                                in_flight,
+                                client_upgrade,
+                                upstream_upgrade,
+                                &route.name,
+                                &upstream.name,
+                            );
+                            let (parts, _) = response.into_parts();
+                            return Ok(Response::from_parts(parts, body::empty()));
+                        }
+
+                        // Тело идет клиенту потоком, без буферизации
+                        let (mut parts, upstream_body) = response.into_parts();
+                        let upstream_body = match pending {
+                            Some(outcome) if outcome.verdict.is_some() => {
+                                body::holding(upstream_body, outcome).boxed()
+                            }
+                            Some(outcome) => body::with_trailers(upstream_body, move |trailers| {
+                                let ok = trailers.and_then(grpc_status) == Some(GRPC_OK);
+                                outcome.record(ok, ok);
+                            })
+                            .boxed(),
+                            None => upstream_body.boxed(),
+                        };
+                        let upstream_body = body::holding(upstream_body, in_flight).boxed();
+                        // В кэш — заголовки upstream'а, без добавленных DAO
+                        let cache_entry = cached.as_ref().and_then(|(cache, key)| {
+                            let ttl = cache.storable_ttl(parts.status, &parts.headers)?;
+                            Some((cache, key, ttl, parts.headers.clone()))
+                        });
+                        // Для повторов — тоже заголовки upstream'а
+                        let idempotent = idempotent.map(|guard| (guard, parts.headers.clone()));
+                        if cached.is_some() {
+                            parts.headers.insert(
+                                CACHE_STATUS_HEADER,
+                                http::HeaderValue::from_static("MISS"),
+                            );
+                        }
+                        if let Some(response_headers) = &response_headers {
+                            response_headers.apply_to_headers(&mut parts.headers)?;
+                        }
+                        if let Some(cors) = &cors {
+                            cors.apply_response_headers(origin.as_ref(), &mut parts.headers);
+                        }
+                        if overridden {
+                            if let Ok(name) = http::HeaderValue::from_str(&upstream.name) {
+                                parts.headers.insert(UPSTREAM_OVERRIDDEN_HEADER, name);
+                            }
+                        }
+                        if let Some(explanation) = &explanation {
+                            selection_headers.apply(
+                                &mut parts.headers,
+                                explanation,
+                                &upstream.name,
+                            );
+                        }
+                        // Ошибка upstream'а в конверте маршрута: тело upstream'а
+                        // не читается, статус сохраняется
+                        let shaped = route
+                            .filters
+                            .as_ref()
+                            .and_then(|f| f.error_shaping.as_ref())
+                            .and_then(|shaping| {
+                                shape_upstream_error(
+                                    shaping,
+                                    parts.status,
+                                    request_id,
+                                    &upstream.name,
+                                )
+                            });
+                        if let Some((content_type, shaped_body)) = shaped {
+                            drop(upstream_body);
+                            parts.headers.remove(http::header::CONTENT_LENGTH);
+                            parts.headers.remove(http::header::CONTENT_ENCODING);
+                            parts
+                                .headers
+                                .insert(http::header::CONTENT_TYPE, content_type);
+                            if let Ok(value) = http::HeaderValue::from_str(request_id) {
+                                parts.headers.insert(REQUEST_ID_HEADER, value);
+                            }
+                            self.metrics
+                                .record_response_body_bytes(&route.name, shaped_body.len() as u64);
+                            return Ok(Response::from_parts(parts, body::full(shaped_body)));
+                        }
+                        // Кэшируемый ответ ограничен по размеру и читается целиком
+                        if let Some((cache, key, ttl, headers)) = cache_entry {
+                            let cached_body =
+                                match body::buffer_limited(upstream_body, cache.max_entry_bytes())
+                                    .await
+                                {
+                                    Ok(cached_body) => cached_body,
+                                    Err(e) => {
+                                        error!("Reading cacheable response failed: {}", e);
+                                        return self.error_response(502, request_id);
+                                    }
+                                };
+                            self.metrics
+                                .record_response_body_bytes(&route.name, cached_body.len() as u64);
+                            self.metrics
+                                .bytes_out_counter(&route.name, &upstream.name)
+                                .increment(cached_body.len() as u64);
+                            cache.store(
+                                key,
+                                parts.status,
+                                &headers,
+                                cached_body.clone(),
+                                ttl,
+                                Instant::now(),
+                            );
+                            return Ok(Response::from_parts(parts, body::full(cached_body)));
+                        }
+
+                        let bytes_out = self.metrics.bytes_out_counter(&route.name, &upstream.name);
+                        let upstream_body = body::metered(upstream_body, bytes_out);
+                        let (metrics, route) = (self.metrics.clone(), route.name.clone());
+                        let upstream_body = body::counted(upstream_body, move |bytes| {
+                            metrics.record_response_body_bytes(&route, bytes)
+                        });
+                        let upstream_body = match idempotent {
+                            Some((guard, headers)) => {
+                                guard.record(parts.status, headers, upstream_body).boxed()
+                            }
+                            None => upstream_body.boxed(),
+                        };
+                        Ok(Response::from_parts(parts, upstream_body))
+                    }
+                    Err(e) => {
+                        let status = match &e {
+                            DaoError::UpstreamRequest(UpstreamErrorKind::Tls, message) => {
+                                error!(
+                                    "TLS handshake with upstream {} failed: {}",
+                                    upstream.name, message
+                                );
+                                UpstreamErrorKind::Tls.status()
+                            }
+                            DaoError::UpstreamRequest(kind, message) => {
+                                error!(
+                                    "Proxy to upstream {} failed ({}): {}",
+                                    upstream.name, kind, message
+                                );
+                                kind.status()
+                            }
+                            _ => {
+                                error!("Proxy to upstream {} failed: {}", upstream.name, e);
+                                502
+                            }
+                        };
+                        if let DaoError::UpstreamRequest(kind, _) = &e {
+                            self.metrics
+                                .record_upstream_error(&upstream.name, kind.as_str());
+                        }
+                        self.metrics
+                            .record_request(&route.name, &upstream.name, 0.0, status);
+                        outcome.record(false, false);
+                        self.error_response(status, request_id)
                     }
                 }
+            } else if self
+                .upstreams
+                .route_upstreams(route)
+                .iter()
+                .any(|u| u.at_capacity())
+            {
+                // Все доступные заняты до max_concurrency — клиенту повторить позже
+                warn!(
+                    "All upstreams of route {} are at max_concurrency",
+                    route.name
+                );
+                let mut response = self.error_response(503, request_id)?;
+                response.headers_mut().insert(
+                    http::header::RETRY_AFTER,
+                    http::HeaderValue::from_static("1"),
+                );
+                Ok(response)
             } else {
                 warn!("No suitable upstream selected for route: {}", route.name);
-                self.error_response(503, "Service Unavailable")
+                self.error_response(503, request_id)
             }
         } else {
             // Маршрут не найден
             debug!("No route matched for: {}", req.uri());
-            self.error_response(404, "Not Found")
+            match config.routes.unmatched() {
+                Unmatched::Redirect(status, location) => Response::builder()
+                    .status(status)
+                    .header(http::header::LOCATION, location)
+                    .header(REQUEST_ID_HEADER, request_id)
+                    .body(body::empty())
+                    .map_err(|e| DaoError::Internal(format!("Failed to build redirect: {}", e))),
+                Unmatched::Status(status) => self.error_response(status, request_id),
+                // Маршрут по умолчанию уже учтен в resolve
+                Unmatched::Route(_) => self.error_response(404, request_id),
+            }
+        }
+    }
+
+    /// Режим observe: выбор стратегии маршрута (тот же, что в enforce) —
+    /// в метрику, запрос — к passthrough upstream'у маршрута (по имени или
+    /// первому в списке)
+    fn observe_selection(
+        &self,
+        route: &RouteRule,
+        upstreams: &[Arc<UpstreamState>],
+        request_intent: Option<&Intent>,
+        passthrough: Option<&str>,
+    ) -> Option<Arc<UpstreamState>> {
+        if let Some(winner) = self
+            .align
+            .select_route_upstream(route, upstreams, request_intent)
+        {
+            debug!("Route {} would select upstream {}", route.name, winner.name);
+            self.metrics.record_would_select(&route.name, &winner.name);
+        }
+        upstreams
+            .iter()
+            .find(|u| Some(u.name.as_str()) == passthrough)
+            .or_else(|| upstreams.first())
+            .cloned()
+    }
+
+    /// Туннель между клиентом и upstream'ом после `101 Switching Protocols`.
+    ///
+    /// До закрытия туннеля держит слот соединения и in-flight upstream'а;
+    /// таймауты простоя HTTP к туннелю не применяются
+    fn spawn_tunnel(
+        &self,
+        connection: Arc<ClientConnection>,
+        in_flight: Option<InFlightGuard>,
+        client: hyper::upgrade::OnUpgrade,
+        upstream: hyper::upgrade::OnUpgrade,
+        route: &str,
+        upstream_name: &str,
+    ) {
+        let bytes_in = self.metrics.bytes_in_counter(route, upstream_name);
+        let bytes_out = self.metrics.bytes_out_counter(route, upstream_name);
+        let upstream_name = upstream_name.to_string();
+        let closed = self.tunnels_closed.clone();
+        self.tunnels.spawn(async move {
+            let _in_flight = in_flight;
+            let (client, upstream) = match tokio::try_join!(client, upstream) {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    warn!("Upgrade to upstream {} failed: {}", upstream_name, e);
+                    return;
+                }
+            };
+            connection.timeouts.disable();
+            let mut upstream = TokioIo::new(upstream);
+            let copy = copy_metered(TokioIo::new(client), &mut upstream, bytes_in, bytes_out);
+            tokio::select! {
+                result = copy => match result {
+                    Ok((sent, received)) => debug!(
+                        "Tunnel to upstream {} closed: {} bytes in, {} bytes out",
+                        upstream_name, sent, received
+                    ),
+                    Err(e) => debug!("Tunnel to upstream {} aborted: {}", upstream_name, e),
+                },
+                _ = closed.cancelled() => {
+                    debug!("Tunnel to upstream {} closed on shutdown", upstream_name)
+                }
+            }
+        });
+    }
+
+    /// Учет исхода запроса к upstream'у
+    fn outcome(
+        &self,
+        upstream: &Arc<UpstreamState>,
+        intent: Option<&Intent>,
+        in_flight: InFlightGuard,
+    ) -> UpstreamOutcome {
+        UpstreamOutcome {
+            upstream: upstream.clone(),
+            sense: self.sense.clone(),
+            memory: self.memory.clone(),
+            metrics: self.metrics.clone(),
+            intent: intent.cloned(),
+            latency: Duration::ZERO,
+            dispatched: Instant::now(),
+            in_flight: Some(in_flight),
+            deadline: None,
+            verdict: None,
         }
     }
 
-    /// Проксирование запроса к upstream
-    async fn proxy_to_upstream(
+    /// Проксирование запроса к upstream; таймаут не дольше остатка бюджета
+    async fn proxy_to_upstream<B>(
         &self,
         upstream: &UpstreamState,
-        req: Request<Incoming>,
-    ) -> Result<(Response<Incoming>, std::time::Duration)> {
-        let client = self.pool.get_client(&upstream.url);
+        req: Request<B>,
+        deadline: Option<Instant>,
+    ) -> Result<(Response<Incoming>, std::time::Duration)>
+    where
+        B: Body<Data = bytes::Bytes, Error = hyper::Error> + Send + Sync + 'static,
+    {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let timeout = match (upstream.timeout, remaining) {
+            (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+            (timeout, remaining) => timeout.or(remaining),
+        };
+        let client = self
+            .pool
+            .get_client(
+                &upstream.name,
+                &upstream.url,
+                &upstream.tls,
+                upstream.http2,
+                upstream.connect_timeout,
+            )?
+            .with_timeout(timeout)
+            .with_host(upstream.override_host.clone());
+
+        client.proxy_request(&upstream.url, req).await
+    }
 
-        // Конвертация запроса для проксирования
-        let (parts, body) = req.into_parts();
-        let new_req = Request::from_parts(parts, body);
+    /// Ответ с ошибкой: тело из `[error_pages]` или JSON по умолчанию
+    fn error_response(&self, status: u16, request_id: &str) -> Result<Response<ProxyBody>> {
+        let status = http::StatusCode::from_u16(status)
+            .map_err(|e| DaoError::Internal(format!("Invalid status: {}", e)))?;
+        let config = self.memory.get_config();
+        let (content_type, error_body) =
+            ErrorPages::new(&config.error_pages).render(status, request_id);
 
-        client.proxy_request(&upstream.url, new_req).await
+        let mut response = Response::new(body::full(error_body));
+        *response.status_mut() = status;
+        *response.headers_mut() = ErrorPages::headers(content_type, request_id);
+        Ok(response)
     }
 
-    /// Создание error response
-    fn error_response(&self, status: u16, _message: &str) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+    /// 401 с `WWW-Authenticate`
+    fn unauthorized_response(
+        &self,
+        challenge: http::HeaderValue,
+        request_id: &str,
+    ) -> Result<Response<ProxyBody>> {
+        let mut response = self.error_response(401, request_id)?;
+        response
+            .headers_mut()
+            .insert(http::header::WWW_AUTHENTICATE, challenge);
+        Ok(response)
+    }
+
+    /// Ответ health endpoint'а
+    fn health_response(&self) -> Result<Response<ProxyBody>> {
+        let (status, health_body) = self.health.response();
+        Response::builder()
+            .status(status)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(body::full(health_body))
+            .map_err(|e| DaoError::Internal(format!("Failed to build response: {}", e)))
+    }
+
+    /// Ответ с пустым телом
+    fn empty_response(&self, status: u16) -> Result<Response<ProxyBody>> {
         let response = Response::builder()
             .status(status)
-            .body(
-                Empty::<Bytes>::new()
-                    .map_err(|never: Infallible| match never {})
-                    .boxed(),
-            )
+            .body(body::empty())
             .map_err(|e| {
                 dao_core::DaoError::Internal(format!("Failed to build response: {}", e))
             })?;
         Ok(response)
     }
 }
+
+/// Сроки запроса: бюджет до заголовков ответа и таймаут передачи тела
+#[derive(Debug, Clone, Copy, Default)]
+struct Deadlines {
+    response: Option<Instant>,
+    body: Option<Instant>,
+}
+
+/// Исход запроса к upstream'у: статистика, Sense и профиль сервиса
+struct UpstreamOutcome {
+    upstream: Arc<UpstreamState>,
+    sense: Arc<Sense>,
+    memory: Arc<Memory>,
+    metrics: MetricsCollector,
+    intent: Option<Intent>,
+    latency: Duration,
+    /// Отправка запроса: от нее — латентность исхода, брошенного по сроку
+    dispatched: Instant,
+    /// Слот запроса; проба breaker'а получает исход отсюда
+    in_flight: Option<InFlightGuard>,
+    /// Срок: исход, брошенный неучтенным после него (дедлайн маршрута,
+    /// тело, оборванное таймаутом запроса), — ошибка upstream'а
+    deadline: Option<Instant>,
+    /// Исход, учитываемый при drop до срока (`success`, `healthy`)
+    verdict: Option<(bool, bool)>,
+}
+
+impl UpstreamOutcome {
+    /// `success` — для статистики выбора, `healthy` — для circuit breaker'а
+    /// и обучения профиля. Слот возвращается: он занят до конца тела
+    fn record(mut self, success: bool, healthy: bool) -> Option<InFlightGuard> {
+        let mut in_flight = self.in_flight.take();
+        if let Some(in_flight) = &mut in_flight {
+            self.account(in_flight, self.latency, success, healthy);
+        }
+        in_flight
+    }
+
+    fn account(
+        &self,
+        in_flight: &mut InFlightGuard,
+        latency: Duration,
+        success: bool,
+        healthy: bool,
+    ) {
+        self.upstream.record_request(latency, success);
+        in_flight.record_breaker(healthy);
+        self.metrics
+            .record_upstream_result(&self.upstream.name, success);
+        self.sense
+            .record_upstream_request(&self.upstream.name, latency, success);
+        if let Some(intent) = &self.intent {
+            self.memory.observe(
+                &self.upstream.name,
+                intent,
+                self.upstream.current_rps(),
+                latency.as_secs_f64() * 1000.0,
+                healthy,
+            );
+        }
+    }
+}
+
+impl Drop for UpstreamOutcome {
+    fn drop(&mut self) {
+        let Some(mut in_flight) = self.in_flight.take() else {
+            return;
+        };
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.metrics
+                .record_upstream_error(&self.upstream.name, "timeout");
+            self.account(&mut in_flight, self.dispatched.elapsed(), false, false);
+        } else if let Some((success, healthy)) = self.verdict {
+            self.account(&mut in_flight, self.latency, success, healthy);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DaoServerBuilder;
+    use bytes::Bytes;
+    use dao_core::config::DaoConfig;
+    use futures::StreamExt;
+    use http_body_util::{Full, StreamBody};
+    use hyper::body::Frame;
+    use hyper::server::conn::{http1, http2};
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Upstream, отвечающий через секунду
+    async fn spawn_slow_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|_req| async {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"late"))))
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_route_deadline_returns_504() {
+        let upstream_url = spawn_slow_upstream().await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "slow"
+            policy = "resonant"
+            deadline_ms = 100
+
+              [routes.rule.match]
+              path_prefix = "/"
+
+              [[routes.rule.upstreams]]
+              name = "slow-backend"
+              url = "{}"
+              timeout_secs = 30
+            "#,
+            upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let start = Instant::now();
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 504"), "{}", response);
+        assert!(start.elapsed() < Duration::from_millis(900));
+
+        // Брошенный по дедлайну запрос — ошибка upstream'а
+        let stats = handle.upstreams().get("slow-backend").unwrap().get_stats();
+        assert_eq!(stats.success_count, 0);
+        assert!(stats.error_count > 0);
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Upstream, сразу отдающий заголовки и первый фрагмент, остаток — через 2с
+    async fn spawn_slow_body_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|_req| async {
+                    let frames = futures::stream::iter([
+                        Bytes::from_static(b"first"),
+                        Bytes::from_static(b"late"),
+                    ])
+                    .then(|data| async move {
+                        if data == "late" {
+                            tokio::time::sleep(Duration::from_secs(2)).await;
+                        }
+                        Ok::<_, Infallible>(Frame::data(data))
+                    });
+                    Ok::<_, Infallible>(Response::new(StreamBody::new(frames)))
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_route_request_timeout_aborts_slow_body() {
+        let upstream_url = spawn_slow_body_upstream().await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "stream"
+            policy = "resonant"
+            request_timeout_ms = 300
+
+              [routes.rule.match]
+              path_prefix = "/"
+
+              [[routes.rule.upstreams]]
+              name = "stream-backend"
+              url = "{}"
+              timeout_secs = 30
+            "#,
+            upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let start = Instant::now();
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        // Соединение обрывается на сроке: заголовки и первый фрагмент уже
+        // отданы, завершающего chunk'а нет
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        let elapsed = start.elapsed();
+        let response = String::from_utf8_lossy(&response);
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("first"), "{}", response);
+        assert!(!response.contains("late"), "{}", response);
+        assert!(!response.ends_with("0\r\n\r\n"), "{}", response);
+        assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1500), "{:?}", elapsed);
+
+        // Ответ 200, но тело оборвано сроком — ошибка upstream'а
+        let stats = handle
+            .upstreams()
+            .get("stream-backend")
+            .unwrap()
+            .get_stats();
+        assert_eq!(stats.success_count, 0);
+        assert!(stats.error_count > 0);
+        handle.shutdown().await.unwrap();
+    }
+
+    /// h2c gRPC upstream: HTTP 200 и `grpc-status: 14` (UNAVAILABLE) в trailers
+    async fn spawn_failing_grpc_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|_req| async {
+                    let mut trailers = http::HeaderMap::new();
+                    trailers.insert("grpc-status", "14".parse().unwrap());
+                    let frames = futures::stream::iter([
+                        Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"\0\0\0\0\0"))),
+                        Ok(Frame::trailers(trailers)),
+                    ]);
+                    Ok::<_, Infallible>(Response::new(StreamBody::new(frames)))
+                });
+                tokio::spawn(
+                    http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service),
+                );
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_grpc_status_trailer_recorded_as_error() {
+        let upstream_url = spawn_failing_grpc_upstream().await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "grpc"
+            policy = "resonant"
+            protocol = "grpc"
+
+              [routes.rule.match]
+              path_prefix = "/"
+
+              [[routes.rule.upstreams]]
+              name = "grpc-backend"
+              url = "{}"
+              http2 = true
+            "#,
+            upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(
+                b"POST /echo.Echo/Say HTTP/1.1\r\nHost: dao\r\nContent-Type: application/grpc\r\n\
+                  TE: trailers\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let stats = handle.upstreams().get("grpc-backend").unwrap().get_stats();
+        assert_eq!(stats.success_count, 0);
+        assert!(stats.error_count > 0);
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Upstream, отвечающий фиксированным телом
+    async fn spawn_upstream(reply: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(move |_req| async move {
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(reply))))
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    /// Ответ на `GET /unknown` при маршрутах `/api` и `fallback` (`/fallback`)
+    async fn unmatched_response(defaults: &str) -> String {
+        let (api_url, fallback_url) = (
+            spawn_upstream(b"api").await,
+            spawn_upstream(b"fallback").await,
+        );
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [routes]
+            {}
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/api"
+              [[routes.rule.upstreams]]
+              name = "api-backend"
+              url = "{}"
+
+            [[routes.rule]]
+            name = "fallback"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/fallback"
+              [[routes.rule.upstreams]]
+              name = "fallback-backend"
+              url = "{}"
+            "#,
+            defaults, api_url, fallback_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(b"GET /unknown HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        handle.shutdown().await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_proxied_to_default_route() {
+        let response = unmatched_response(r#"default = "fallback""#).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("fallback"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_redirected() {
+        let response = unmatched_response(
+            r#"default_redirect = "https://example.com/"
+            default_status = 301"#,
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 301"), "{}", response);
+        assert!(
+            response
+                .to_lowercase()
+                .contains("location: https://example.com/"),
+            "{}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_default_404() {
+        let response = unmatched_response("").await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+        let response = unmatched_response("default_status = 410").await;
+        assert!(response.starts_with("HTTP/1.1 410"), "{}", response);
+    }
+
+    /// DAO с маршрутом только на `/api` (запросы к `/` получают 404) и
+    /// лимитом в 2 соединения на `listeners` listener'ов
+    async fn start_limited(overflow: &str, listeners: usize) -> crate::DaoHandle {
+        let upstream_url = spawn_upstream(b"ok").await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            max_connections = 2
+            connection_overflow = "{}"
+            {}
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/api"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{}"
+            "#,
+            overflow,
+            "[[server.listen]]\nbind = \"127.0.0.1:0\"\n".repeat(listeners),
+            upstream_url
+        ))
+        .unwrap();
+        DaoServerBuilder::new(config).start().await.unwrap()
+    }
+
+    /// Запрос на открытом соединении; `None` — сервер закрыл соединение
+    /// или не ответил за `wait`
+    async fn try_request(stream: &mut TcpStream, wait: Duration) -> Option<String> {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n")
+            .await
+            .ok()?;
+        let mut response = String::new();
+        tokio::time::timeout(wait, stream.read_to_string(&mut response))
+            .await
+            .ok()?
+            .ok()?;
+        (!response.is_empty()).then_some(response)
+    }
+
+    #[tokio::test]
+    async fn test_max_connections_rejects_third() {
+        let handle = start_limited("reject", 1).await;
+        let addr = handle.local_addrs()[0];
+
+        let held = [
+            TcpStream::connect(addr).await.unwrap(),
+            TcpStream::connect(addr).await.unwrap(),
+        ];
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        assert!(try_request(&mut third, Duration::from_secs(2))
+            .await
+            .is_none());
+
+        // Освободившийся слот принимает новое соединение
+        drop(held);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut fourth = TcpStream::connect(addr).await.unwrap();
+        let response = try_request(&mut fourth, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_connections_queues_third() {
+        let handle = start_limited("wait", 1).await;
+        let addr = handle.local_addrs()[0];
+
+        let first = TcpStream::connect(addr).await.unwrap();
+        let _second = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Третье ждет в очереди, пока держатся первые два
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        assert!(try_request(&mut third, Duration::from_millis(300))
+            .await
+            .is_none());
+
+        drop(first);
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(2), third.read_to_string(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_connections_wait_not_reserved_by_idle_listeners() {
+        // Второй listener простаивает и не занимает слот первого
+        let handle = start_limited("wait", 2).await;
+        let addr = handle.local_addrs()[0];
+
+        let _first = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        let response = try_request(&mut second, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_response_headers_stripped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|_req| async {
+                    let response = Response::builder()
+                        .header("server", "nginx/1.25")
+                        .header("x-powered-by", "PHP/8.3")
+                        .header("x-request-cost", "7")
+                        .body(Full::new(Bytes::from_static(b"ok")))
+                        .unwrap();
+                    Ok::<_, Infallible>(response)
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "http://{}"
+              [routes.rule.filters]
+              response_headers_remove = ["Server", "X-Powered-By"]
+              server_header = "dao"
+            "#,
+            upstream_addr
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let response = response.to_lowercase();
+
+        assert!(response.starts_with("http/1.1 200"), "{}", response);
+        assert!(!response.contains("x-powered-by"), "{}", response);
+        assert!(!response.contains("nginx"), "{}", response);
+        assert!(response.contains("server: dao\r\n"), "{}", response);
+        assert!(response.contains("x-request-cost: 7\r\n"), "{}", response);
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Upstream, отвечающий `:authority|Host` полученного запроса
+    async fn spawn_host_echo_upstream(h2: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|req: hyper::Request<hyper::body::Incoming>| async move {
+                    let authority = req
+                        .uri()
+                        .authority()
+                        .map(|a| a.to_string())
+                        .unwrap_or_default();
+                    let host = req
+                        .headers()
+                        .get(http::header::HOST)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default();
+                    let reply = format!("{}|{}", authority, host);
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(reply))))
+                });
+                let io = TokioIo::new(stream);
+                if h2 {
+                    tokio::spawn(
+                        http2::Builder::new(TokioExecutor::new()).serve_connection(io, service),
+                    );
+                } else {
+                    tokio::spawn(http1::Builder::new().serve_connection(io, service));
+                }
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    /// Тело ответа upstream'а на запрос с `Host: client.example`
+    async fn upstream_host(h2: bool, override_host: Option<&str>) -> String {
+        let upstream_url = spawn_host_echo_upstream(h2).await;
+        let override_host = override_host
+            .map(|host| format!("override_host = \"{}\"", host))
+            .unwrap_or_default();
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{}"
+              http2 = {}
+              {}
+            "#,
+            upstream_url, h2, override_host
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: client.example\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        handle.shutdown().await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        response.split("\r\n\r\n").nth(1).unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_host_preserved_by_default() {
+        // HTTP/1.1: Host клиента, в URI только путь
+        assert_eq!(upstream_host(false, None).await, "|client.example");
+        // h2: :authority — адрес upstream'а, Host клиента не передается
+        let reply = upstream_host(true, None).await;
+        assert!(reply.starts_with("127.0.0.1:"), "{}", reply);
+        assert!(reply.ends_with('|'), "{}", reply);
+    }
+
+    #[tokio::test]
+    async fn test_host_overridden() {
+        assert_eq!(
+            upstream_host(false, Some("api.internal")).await,
+            "|api.internal"
+        );
+        assert_eq!(
+            upstream_host(true, Some("api.internal:8443")).await,
+            "api.internal:8443|"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_error_shaped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|_req| async {
+                    let response = Response::builder()
+                        .status(503)
+                        .header("content-type", "text/html")
+                        .body(Full::new(Bytes::from_static(
+                            b"<h1>Service Unavailable</h1>",
+                        )))
+                        .unwrap();
+                    Ok::<_, Infallible>(response)
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "http://{}"
+              [routes.rule.filters.error_shaping]
+              statuses = [502, 503]
+              body = '{{"error":"unavailable","upstream":"{{upstream}}","request_id":"{{request_id}}"}}'
+            "#,
+            upstream_addr
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: dao\r\nX-Request-Id: req-7\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        handle.shutdown().await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert!(
+            response
+                .to_lowercase()
+                .contains("content-type: application/json\r\n"),
+            "{}",
+            response
+        );
+        assert!(
+            response
+                .ends_with(r#"{"error":"unavailable","upstream":"backend","request_id":"req-7"}"#),
+            "{}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn test_saturated_upstreams_reject_with_retry_after() {
+        // Upstream'ы отвечают, только когда тест выдаст разрешение
+        let release = std::sync::Arc::new(tokio::sync::Semaphore::new(0));
+        let mut urls = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            urls.push(format!("http://{}", listener.local_addr().unwrap()));
+            let release = release.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let release = release.clone();
+                    let service = service_fn(move |_req| {
+                        let release = release.clone();
+                        async move {
+                            release.acquire().await.unwrap().forget();
+                            Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"ok"))))
+                        }
+                    });
+                    tokio::spawn(
+                        http1::Builder::new().serve_connection(TokioIo::new(stream), service),
+                    );
+                }
+            });
+        }
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "backend-1"
+              url = "{}"
+              max_concurrency = 1
+              [[routes.rule.upstreams]]
+              name = "backend-2"
+              url = "{}"
+              max_concurrency = 1
+            "#,
+            urls[0], urls[1]
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        let addr = handle.local_addrs()[0];
+        let in_flight = || {
+            ["backend-1", "backend-2"]
+                .iter()
+                .map(|name| handle.upstreams().get(name).unwrap().in_flight())
+                .sum::<usize>()
+        };
+        let wait_in_flight = |expected: usize| async move {
+            for _ in 0..200 {
+                if in_flight() == expected {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("in flight {} != {}", in_flight(), expected);
+        };
+        let send = || async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            stream
+        };
+        let read = |mut stream: TcpStream| async move {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        // Оба upstream'а заняты — третий запрос отклоняется
+        let busy = [send().await, send().await];
+        wait_in_flight(2).await;
+        let response = read(send().await).await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert!(
+            response.to_lowercase().contains("retry-after: 1\r\n"),
+            "{}",
+            response
+        );
+
+        // Один освободился — следующий запрос принят
+        release.add_permits(1);
+        wait_in_flight(1).await;
+        let admitted = send().await;
+        wait_in_flight(2).await;
+        release.add_permits(10);
+        assert!(read(admitted).await.starts_with("HTTP/1.1 200"));
+        for stream in busy {
+            assert!(read(stream).await.starts_with("HTTP/1.1 200"));
+        }
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_method_not_allowed() {
+        let upstream_url = spawn_upstream(b"x").await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "x"
+            policy = "resonant"
+              [routes.rule.match]
+              path_exact = "/x"
+              methods = ["GET"]
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{}"
+              [routes.rule.filters.cors]
+              allowed_origins = ["https://app.example"]
+
+            [[routes.rule]]
+            name = "private"
+            policy = "resonant"
+              [routes.rule.match]
+              path_exact = "/private"
+              methods = ["GET"]
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{}"
+              [routes.rule.filters]
+              deny_cidrs = ["127.0.0.0/8"]
+            "#,
+            upstream_url, upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        let addr = handle.local_addrs()[0];
+
+        let request = |method: &'static str, path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let head = format!(
+                "{} {} HTTP/1.1\r\nHost: dao\r\nOrigin: https://app.example\r\n\
+                 Access-Control-Request-Method: GET\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                method, path
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = request("POST", "/x").await;
+        assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+        assert!(
+            response.to_lowercase().contains("allow: get\r\n"),
+            "{}",
+            response
+        );
+
+        assert!(request("GET", "/x").await.starts_with("HTTP/1.1 200"));
+        assert!(request("POST", "/y").await.starts_with("HTTP/1.1 404"));
+
+        // CORS preflight не получает 405
+        let response = request("OPTIONS", "/x").await;
+        assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+        // Фильтры доступа — раньше 405: закрытый маршрут не раскрывает методы
+        let response = request("POST", "/private").await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_headers_rejected_with_431() {
+        let upstream_url = spawn_upstream(b"ok").await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+            max_headers = 20
+            max_header_bytes = 4096
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{}"
+            "#,
+            upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        let addr = handle.local_addrs()[0];
+
+        let request = |headers: String| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let head = format!(
+                "GET / HTTP/1.1\r\nHost: dao\r\n{}Connection: close\r\n\r\n",
+                headers
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        // Число заголовков — отказ hyper, размер ниже его буфера — проверка DAO
+        let many: String = (0..30).map(|i| format!("X-H{}: v\r\n", i)).collect();
+        let response = request(many).await;
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+        let large = format!("X-Large: {}\r\n", "a".repeat(6000));
+        let response = request(large).await;
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+
+        let response = request("X-Small: v\r\n".to_string()).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_strict_intent_without_match_returns_503() {
+        let upstream_url = spawn_upstream(b"batch").await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [policies.strict]
+            strict_intent = true
+
+            [[routes.rule]]
+            name = "strict"
+            policy = "strict"
+            intent = "realtime"
+              [routes.rule.match]
+              path_prefix = "/strict"
+              [[routes.rule.upstreams]]
+              name = "batch-worker"
+              url = "{0}"
+              intent = ["batch"]
+
+            [[routes.rule]]
+            name = "lenient"
+            policy = "resonant"
+            intent = "realtime"
+              [routes.rule.match]
+              path_prefix = "/lenient"
+              [[routes.rule.upstreams]]
+              name = "batch-worker"
+              url = "{0}"
+              intent = ["batch"]
+            "#,
+            upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        let addr = handle.local_addrs()[0];
+
+        let request = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let head = format!(
+                "GET {} HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n",
+                path
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        // "realtime" запрос не уходит на "batch" upstream
+        let response = request("/strict").await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        let response = request("/lenient").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http1_keepalive_disabled() {
+        let upstream_url = spawn_upstream(b"ok").await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [server.http1]
+            keepalive = false
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{}"
+            "#,
+            upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        // Клиент просит keep-alive, но соединение закрывается после ответа
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: dao\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response))
+            .await
+            .expect("connection kept alive")
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(
+            response.to_lowercase().contains("connection: close\r\n"),
+            "{}",
+            response
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ab_test_sticky_arm() {
+        let (stable_url, next_url) = (
+            spawn_upstream(b"stable").await,
+            spawn_upstream(b"next").await,
+        );
+        let backup_url = spawn_upstream(b"backup").await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "backup"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/backup/"
+              [[routes.rule.upstreams]]
+              name = "backup"
+              url = "{}"
+
+            [[routes.rule]]
+            name = "checkout"
+            policy = "resonant"
+            fallback_route = "backup"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "stable"
+              url = "{}"
+              [[routes.rule.upstreams]]
+              name = "next"
+              url = "{}"
+              [routes.rule.ab_test]
+              key_header = "x-user-id"
+              [[routes.rule.ab_test.arms]]
+              name = "control"
+              buckets = [0, 49]
+              upstreams = ["stable"]
+              [[routes.rule.ab_test.arms]]
+              name = "variant"
+              buckets = [50, 99]
+              upstreams = ["next"]
+            "#,
+            backup_url, stable_url, next_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        let addr = handle.local_addrs()[0];
+
+        let request = |user: String| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let head = format!(
+                "GET / HTTP/1.1\r\nHost: dao\r\nX-User-Id: {}\r\nConnection: close\r\n\r\n",
+                user
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let mut seen = std::collections::HashSet::new();
+        for user in 0..20 {
+            let first = request(format!("user-{}", user)).await;
+            let backend = if first.ends_with("stable") {
+                "stable"
+            } else {
+                "next"
+            };
+            assert!(first.ends_with(backend), "{}", first);
+            for _ in 0..3 {
+                assert!(request(format!("user-{}", user)).await.ends_with(backend));
+            }
+            seen.insert(backend);
+        }
+        assert_eq!(seen.len(), 2);
+
+        // Плечу выбрать некого — fallback маршрута, а не другое плечо
+        handle.upstreams().get("next").unwrap().set_draining(true);
+        let mut seen = std::collections::HashSet::new();
+        for user in 0..20 {
+            let response = request(format!("user-{}", user)).await;
+            let backend = ["stable", "backup"]
+                .into_iter()
+                .find(|b| response.ends_with(b));
+            seen.insert(backend.expect(&response));
+        }
+        assert_eq!(seen.len(), 2);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_client_address() {
+        let upstream_url = spawn_upstream(b"ok").await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [[server.listen]]
+            bind = "127.0.0.1:0"
+            proxy_protocol = true
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{}"
+              [routes.rule.filters]
+              deny_cidrs = ["203.0.113.0/24"]
+            "#,
+            upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        let addr = handle.local_addrs()[0];
+        // Клиент без заголовка PROXY не задерживает прием остальных
+        let _silent = TcpStream::connect(addr).await.unwrap();
+
+        let request = |proxy_header: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let head = format!(
+                "{}GET / HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n",
+                proxy_header
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response).await;
+            response
+        };
+
+        // Фильтр видит адрес клиента из заголовка, а не loopback балансировщика
+        let denied = tokio::time::timeout(
+            Duration::from_secs(2),
+            request("PROXY TCP4 203.0.113.7 127.0.0.1 51234 80\r\n"),
+        )
+        .await
+        .expect("accept blocked by a silent client");
+        assert!(denied.starts_with("HTTP/1.1 403"), "{}", denied);
+        let allowed = request("PROXY TCP4 198.51.100.1 127.0.0.1 51234 80\r\n").await;
+        assert!(allowed.starts_with("HTTP/1.1 200"), "{}", allowed);
+
+        // Без заголовка соединение закрывается без ответа
+        assert!(request("").await.is_empty());
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_request_headers_templated() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|req: hyper::Request<hyper::body::Incoming>| async move {
+                    let header = |name: &str| {
+                        req.headers()
+                            .get(name)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or("-")
+                            .to_string()
+                    };
+                    let reply = ["x-route-name", "x-client-ip", "x-trace", "x-internal"]
+                        .map(header)
+                        .join("|");
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(reply))))
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "http://{}"
+              [routes.rule.filters]
+              request_headers_remove = ["x-internal"]
+              request_headers_add = {{ "x-route-name" = "{{route}}", "x-client-ip" = "{{client_ip}}", "x-trace" = "{{request_id}}@{{upstream}}" }}
+            "#,
+            upstream_addr
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: dao\r\nX-Request-Id: req-7\r\nX-Internal: 1\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(
+            response.ends_with("api|127.0.0.1|req-7@backend|-"),
+            "{}",
+            response
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Вывод tracing в общий буфер (для проверки строк лога)
+    #[derive(Clone, Default)]
+    struct CapturedLog(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_headers_redacted_in_logs() {
+        let log = CapturedLog::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer({
+                let log = log.clone();
+                move || log.clone()
+            })
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|req: hyper::Request<hyper::body::Incoming>| async move {
+                    let authorization = req.headers().get(http::header::AUTHORIZATION).cloned();
+                    let reply = Bytes::copy_from_slice(
+                        authorization.as_ref().map_or(&b""[..], |v| v.as_bytes()),
+                    );
+                    Ok::<_, Infallible>(Response::new(Full::new(reply)))
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "http://{}"
+            "#,
+            upstream_addr
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        stream
+            .write_all(
+                b"GET /private HTTP/1.1\r\nHost: dao\r\nAuthorization: Bearer top-secret\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        handle.shutdown().await.unwrap();
+
+        // Upstream получил настоящее значение, в логе — маска
+        assert!(response.ends_with("Bearer top-secret"), "{}", response);
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let line = log
+            .lines()
+            .find(|line| line.contains("Handling request") && line.contains("/private"))
+            .unwrap_or_else(|| panic!("no request log line in:\n{}", log));
+        assert!(line.contains("authorization: ***"), "{}", line);
+        assert!(!log.contains("top-secret"), "{}", log);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_replays_and_coalesces() {
+        // Upstream медленно создает заказ и нумерует их
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        let created = Arc::new(AtomicUsize::new(0));
+        let counter = created.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let counter = counter.clone();
+                let service = service_fn(move |_req| {
+                    let counter = counter.clone();
+                    async move {
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        let order = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                        let mut response =
+                            Response::new(Full::new(Bytes::from(format!("order-{}", order))));
+                        *response.status_mut() = hyper::StatusCode::CREATED;
+                        Ok::<_, Infallible>(response)
+                    }
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "orders"
+            policy = "resonant"
+
+              [routes.rule.match]
+              path_prefix = "/"
+
+              [routes.rule.filters.idempotency]
+              ttl_secs = 60
+
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "http://{}"
+            "#,
+            upstream_addr
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        let addr = handle.local_addrs()[0];
+        let post = |key: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!(
+                "POST /orders HTTP/1.1\r\nHost: dao\r\nIdempotency-Key: {}\r\n\
+                 Content-Length: 0\r\nConnection: close\r\n\r\n",
+                key
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        // Одновременные запросы с одним ключом — один заказ
+        let (first, concurrent) = tokio::join!(post("order-abc"), post("order-abc"));
+        for response in [&first, &concurrent] {
+            assert!(response.starts_with("HTTP/1.1 201"), "{}", response);
+            assert!(response.ends_with("order-1"), "{}", response);
+        }
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+
+        // Повтор получает сохраненный ответ, другой ключ — новый заказ
+        let retry = post("order-abc").await;
+        assert!(retry.starts_with("HTTP/1.1 201"), "{}", retry);
+        assert!(
+            retry.to_lowercase().contains("idempotent-replayed: true"),
+            "{}",
+            retry
+        );
+        assert!(retry.ends_with("order-1"), "{}", retry);
+        assert!(post("order-def").await.ends_with("order-2"));
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_idempotent_body_rejected_with_413() {
+        let upstream_url = spawn_upstream(b"created").await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "orders"
+            policy = "resonant"
+
+              [routes.rule.match]
+              path_prefix = "/"
+
+              [routes.rule.filters.idempotency]
+              max_request_bytes = 16
+
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{}"
+            "#,
+            upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        let addr = handle.local_addrs()[0];
+        let post = |body: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!(
+                "POST /orders HTTP/1.1\r\nHost: dao\r\nIdempotency-Key: k1\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        // Тело читается для отпечатка ключа; больше лимита — 413, не 502
+        let response = post(r#"{"amount": 100, "currency": "eur"}"#).await;
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        let response = post(r#"{"amount": 1}"#).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Upstream соглашается на любой upgrade и работает эхом по туннелю;
+    /// полученный `Connection` возвращает в `x-connection`
+    async fn spawn_upgrade_echo_upstream() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(
+                    |mut req: hyper::Request<hyper::body::Incoming>| async move {
+                        let upgrade = req.headers().get(http::header::UPGRADE).cloned();
+                        let connection = req.headers().get(http::header::CONNECTION).cloned();
+                        let on_upgrade = hyper::upgrade::on(&mut req);
+                        tokio::spawn(async move {
+                            let mut io = TokioIo::new(on_upgrade.await.unwrap());
+                            let mut buf = [0u8; 64];
+                            while let Ok(n @ 1..) = io.read(&mut buf).await {
+                                io.write_all(&buf[..n]).await.unwrap();
+                            }
+                        });
+                        let mut response = Response::new(Full::new(Bytes::new()));
+                        *response.status_mut() = hyper::StatusCode::SWITCHING_PROTOCOLS;
+                        response
+                            .headers_mut()
+                            .insert(http::header::CONNECTION, "upgrade".parse().unwrap());
+                        response
+                            .headers_mut()
+                            .insert(http::header::UPGRADE, upgrade.unwrap());
+                        response
+                            .headers_mut()
+                            .insert("x-connection", connection.unwrap());
+                        Ok::<_, Infallible>(response)
+                    },
+                );
+                tokio::spawn(
+                    http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .with_upgrades(),
+                );
+            }
+        });
+        upstream_addr
+    }
+
+    /// DAO с маршрутом-туннелем к `upstream_addr`
+    async fn start_tunnel(upstream_addr: SocketAddr, server: &str) -> crate::DaoHandle {
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+            {}
+
+            [[routes.rule]]
+            name = "tunnel"
+            policy = "resonant"
+
+              [routes.rule.match]
+              path_prefix = "/"
+              upgrade = "*"
+
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "http://{}"
+            "#,
+            server, upstream_addr
+        ))
+        .unwrap();
+        DaoServerBuilder::new(config).start().await.unwrap()
+    }
+
+    /// Запрос на upgrade; возвращает заголовки ответа в нижнем регистре
+    async fn request_upgrade(stream: &mut TcpStream, request: &[u8]) -> String {
+        stream.write_all(request).await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await.unwrap();
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap().to_ascii_lowercase()
+    }
+
+    #[tokio::test]
+    async fn test_custom_upgrade_tunneled() {
+        let handle = start_tunnel(spawn_upgrade_echo_upstream().await, "").await;
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        let head = request_upgrade(
+            &mut stream,
+            b"GET / HTTP/1.1\r\nHost: dao\r\nConnection: Upgrade\r\nUpgrade: custom-proto/1\r\n\r\n",
+        )
+        .await;
+        assert!(head.starts_with("http/1.1 101"), "{}", head);
+        assert!(head.contains("upgrade: custom-proto/1"), "{}", head);
+
+        // После 101 — произвольные байты в обе стороны
+        stream.write_all(b"ping\x00pong").await.unwrap();
+        let mut echo = [0u8; 9];
+        tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut echo))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&echo, b"ping\x00pong");
+
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_h2c_upgrade_tunnel_outlives_idle_timeout() {
+        let handle =
+            start_tunnel(spawn_upgrade_echo_upstream().await, "idle_timeout_secs = 1").await;
+
+        let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        let head = request_upgrade(
+            &mut stream,
+            b"GET / HTTP/1.1\r\nHost: dao\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+              Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\r\n",
+        )
+        .await;
+        assert!(head.starts_with("http/1.1 101"), "{}", head);
+        // Upstream получил токен HTTP2-Settings
+        assert!(
+            head.contains("x-connection: upgrade, http2-settings"),
+            "{}",
+            head
+        );
+
+        // Молчание дольше idle timeout туннель не закрывает
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        stream.write_all(b"ping").await.unwrap();
+        let mut echo = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut echo))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&echo, b"ping");
+
+        // Остановка закрывает туннель
+        handle.shutdown().await.unwrap();
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut echo))
+            .await
+            .unwrap();
+        assert!(matches!(read, Ok(0) | Err(_)));
+    }
+
+    #[tokio::test]
+    async fn test_selection_hold_keeps_upstream_per_connection() {
+        let (first_url, second_url) = (spawn_upstream(b"one").await, spawn_upstream(b"two").await);
+        // swrr без удержания чередует равные upstream'ы на каждом запросе
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "held"
+            policy = "swrr"
+            selection_hold_ms = 60000
+              [routes.rule.match]
+              path_prefix = "/held"
+              [[routes.rule.upstreams]]
+              name = "one"
+              url = "{first}"
+              [[routes.rule.upstreams]]
+              name = "two"
+              url = "{second}"
+
+            [[routes.rule]]
+            name = "free"
+            policy = "swrr"
+              [routes.rule.match]
+              path_prefix = "/free"
+              [[routes.rule.upstreams]]
+              name = "one"
+              url = "{first}"
+              [[routes.rule.upstreams]]
+              name = "two"
+              url = "{second}"
+            "#,
+            first = first_url,
+            second = second_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        // Несколько запросов по одному keep-alive соединению: тела ответов
+        let addr = handle.local_addrs()[0];
+        let bodies = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let mut bodies = Vec::new();
+            for _ in 0..4 {
+                let head = format!("GET {} HTTP/1.1\r\nHost: dao\r\n\r\n", path);
+                stream.write_all(head.as_bytes()).await.unwrap();
+                let mut response = Vec::new();
+                while !(response.ends_with(b"one") || response.ends_with(b"two")) {
+                    let mut chunk = [0u8; 512];
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    assert!(n > 0, "connection closed");
+                    response.extend_from_slice(&chunk[..n]);
+                }
+                bodies.push(String::from_utf8(response[response.len() - 3..].to_vec()).unwrap());
+            }
+            bodies
+        };
+
+        let held = bodies("/held").await;
+        assert!(held.iter().all(|b| *b == held[0]), "{:?}", held);
+        let free = bodies("/free").await;
+        assert!(free.iter().any(|b| *b != free[0]), "{:?}", free);
+
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_observe_mode_passes_through() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        // Глобальный recorder: метрики пишутся из задач сервера
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        recorder.install().unwrap();
+
+        let (scored_url, legacy_url) = (
+            spawn_upstream(b"scored").await,
+            spawn_upstream(b"legacy").await,
+        );
+        // Политика выбрала бы upstream с intent'ом запроса
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+            mode = "observe"
+            passthrough_upstream = "legacy"
+
+            [[routes.rule]]
+            name = "api"
+            policy = "resonant"
+            intent = "realtime"
+              [routes.rule.match]
+              path_prefix = "/"
+              [[routes.rule.upstreams]]
+              name = "realtime-pool"
+              url = "{}"
+              intent = ["realtime"]
+              [[routes.rule.upstreams]]
+              name = "legacy"
+              url = "{}"
+              intent = ["batch"]
+              circuit_breaker = {{ failure_threshold = 1, open_secs = 60 }}
+
+            # swrr чередует upstream'ы — в метрике тот же порядок
+            [[routes.rule]]
+            name = "weighted"
+            policy = "swrr"
+              [routes.rule.match]
+              path_prefix = "/weighted"
+              [[routes.rule.upstreams]]
+              name = "heavy"
+              url = "{}"
+              weight = 2
+              [[routes.rule.upstreams]]
+              name = "light"
+              url = "{}"
+            "#,
+            scored_url, legacy_url, legacy_url, scored_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        // Разомкнутая цепь passthrough upstream'а трафик не отклоняет
+        handle
+            .upstreams()
+            .get("legacy")
+            .unwrap()
+            .record_breaker(false);
+        assert!(!handle.upstreams().get("legacy").unwrap().breaker_admits());
+
+        for path in ["/", "/", "/", "/weighted", "/weighted", "/weighted"] {
+            let mut stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+            stream
+                .write_all(
+                    format!(
+                        "GET {} HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n",
+                        path
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            // Первый upstream "weighted" — тот же legacy
+            assert!(response.ends_with("legacy"), "{}", response);
+        }
+
+        let label = |key: &metrics_util::CompositeKey, name: &str| {
+            key.key()
+                .labels()
+                .find(|label| label.key() == name)
+                .map(|label| label.value().to_string())
+                .unwrap()
+        };
+        let mut would_select = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, ..)| key.key().name() == "dao_would_select")
+            .map(|(key, .., value)| (label(&key, "route"), label(&key, "upstream"), value))
+            .collect::<Vec<_>>();
+        would_select.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        assert_eq!(
+            would_select,
+            vec![
+                (
+                    "api".to_string(),
+                    "realtime-pool".to_string(),
+                    DebugValue::Counter(3)
+                ),
+                (
+                    "weighted".to_string(),
+                    "heavy".to_string(),
+                    DebugValue::Counter(2)
+                ),
+                (
+                    "weighted".to_string(),
+                    "light".to_string(),
+                    DebugValue::Counter(1)
+                ),
+            ]
+        );
+
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Сертификат клиента `cn`, подписанный CA
+    fn client_cert(
+        cn: &str,
+        ca: &rcgen::Certificate,
+        ca_key: &rcgen::KeyPair,
+    ) -> (
+        rustls::pki_types::CertificateDer<'static>,
+        rustls::pki_types::PrivateKeyDer<'static>,
+    ) {
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, cn);
+        params.subject_alt_names.push(rcgen::SanType::URI(
+            format!("spiffe://mesh/{}", cn).try_into().unwrap(),
+        ));
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, ca, ca_key).unwrap();
+        let key = rustls::pki_types::PrivatePkcs8KeyDer::from(key.serialize_der());
+        (cert.der().clone(), key.into())
+    }
+
+    #[tokio::test]
+    async fn test_client_cert_identity_authorizes_route() {
+        let dir = tempfile::tempdir().unwrap();
+        let server_cert =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.path().join("dao.crt"), server_cert.cert.pem()).unwrap();
+        std::fs::write(
+            dir.path().join("dao.key"),
+            server_cert.key_pair.serialize_pem(),
+        )
+        .unwrap();
+
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        std::fs::write(dir.path().join("clients-ca.crt"), ca.pem()).unwrap();
+
+        // /internal — только для svc-a (по CN), /mesh — по SAN
+        let upstream_url = spawn_upstream(b"internal").await;
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+            tls_cert = "{dir}/dao.crt"
+            tls_key = "{dir}/dao.key"
+            tls_client_ca = "{dir}/clients-ca.crt"
+
+            [[routes.rule]]
+            name = "internal"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/internal"
+              [routes.rule.filters]
+              client_cert_cn = ["svc-a"]
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{url}"
+
+            [[routes.rule]]
+            name = "mesh"
+            policy = "resonant"
+              [routes.rule.match]
+              path_prefix = "/mesh"
+              client_cert_san = "spiffe://mesh/svc-b"
+              [[routes.rule.upstreams]]
+              name = "backend"
+              url = "{url}"
+            "#,
+            dir = dir.path().display(),
+            url = upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+        let addr = handle.local_addrs()[0];
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(server_cert.cert.der().clone()).unwrap();
+        let request = |cert: Option<_>, path: &'static str| {
+            let roots = roots.clone();
+            async move {
+                let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+                let client_config = match cert {
+                    Some((chain, key)) => builder.with_client_auth_cert(vec![chain], key).unwrap(),
+                    None => builder.with_no_client_auth(),
+                };
+                let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+                let stream = TcpStream::connect(addr).await.unwrap();
+                let server_name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+                let mut tls = connector.connect(server_name, stream).await.unwrap();
+                let head = format!(
+                    "GET {} HTTP/1.1\r\nHost: dao\r\nConnection: close\r\n\r\n",
+                    path
+                );
+                tls.write_all(head.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                tls.read_to_string(&mut response).await.unwrap();
+                response
+            }
+        };
+
+        let response = request(Some(client_cert("svc-a", &ca, &ca_key)), "/internal").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = request(Some(client_cert("svc-b", &ca, &ca_key)), "/internal").await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        let response = request(None, "/internal").await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+
+        // Маршрут по SAN: другой identity до него не доходит
+        let response = request(Some(client_cert("svc-b", &ca, &ca_key)), "/mesh").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = request(Some(client_cert("svc-a", &ca, &ca_key)), "/mesh").await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+        handle.shutdown().await.unwrap();
+    }
+
+    /// Большое тело через DAO не собирается целиком: первый кадр клиент
+    /// получает раньше, чем upstream отдает весь payload, и кадры остаются
+    /// малыми
+    #[tokio::test]
+    async fn test_large_download_streams_through_dao() {
+        const CHUNK_SIZE: usize = 256 * 1024;
+        const CHUNKS: usize = 64; // 16 MiB
+
+        // Upstream отдает тело лениво, считая отправленные чанки
+        let produced = Arc::new(AtomicUsize::new(0));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn({
+            let produced = produced.clone();
+            async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = service_fn(move |_req| {
+                    let produced = produced.clone();
+                    async move {
+                        let chunks = futures::stream::iter(0..CHUNKS).map(move |_| {
+                            produced.fetch_add(1, Ordering::SeqCst);
+                            Ok::<_, Infallible>(Frame::data(Bytes::from(vec![0u8; CHUNK_SIZE])))
+                        });
+                        Ok::<_, Infallible>(Response::new(StreamBody::new(chunks)))
+                    }
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            }
+        });
+
+        let config: DaoConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            bind = "127.0.0.1:0"
+
+            [[routes.rule]]
+            name = "downloads"
+            policy = "resonant"
+            match = {{ path_prefix = "/" }}
+            upstreams = [{{ name = "files", url = "{}" }}]
+            "#,
+            upstream_url
+        ))
+        .unwrap();
+        let handle = DaoServerBuilder::new(config).start().await.unwrap();
+
+        let stream = TcpStream::connect(handle.local_addrs()[0]).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let req = http::Request::get("/download")
+            .header(http::header::HOST, "dao")
+            .body(http_body_util::Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(req).await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        let mut body = response.into_body();
+        let mut received = 0;
+        let mut max_frame = 0;
+        let mut produced_at_first_frame = None;
+        while let Some(frame) = http_body_util::BodyExt::frame(&mut body).await {
+            if let Ok(data) = frame.unwrap().into_data() {
+                produced_at_first_frame.get_or_insert(produced.load(Ordering::SeqCst));
+                max_frame = max_frame.max(data.len());
+                received += data.len();
+            }
+        }
+
+        assert_eq!(received, CHUNK_SIZE * CHUNKS);
+        assert!(
+            max_frame <= CHUNK_SIZE * 4,
+            "frame too large: {}",
+            max_frame
+        );
+        assert!(produced_at_first_frame.unwrap() < CHUNKS);
+
+        handle.shutdown().await.unwrap();
+    }
+}